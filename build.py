@@ -35,8 +35,8 @@ def run_command(args, **kwargs):
     return output.returncode, output.stdout, output.stderr
 
 
-def build_cargo_workspace(directory, command, args, cargo="cargo"):
-    code, _, _ = run_command([cargo, command, *args], cwd=directory)
+def build_cargo_workspace(directory, command, args, cargo="cargo", env=None):
+    code, _, _ = run_command([cargo, command, *args], cwd=directory, env=env)
 
     if code != 0:
         return None
@@ -46,6 +46,7 @@ def build_cargo_workspace(directory, command, args, cargo="cargo"):
         stdout=subprocess.PIPE,
         stderr=subprocess.DEVNULL,
         cwd=directory,
+        env=env,
     )
 
 
@@ -64,10 +65,16 @@ def build_kernel(args):
     elif args.document:
         command = "doc"
 
+    env = None
     if args.features:
         command_args += ["--features", ",".join(args.features)]
 
-    return build_cargo_workspace("src", command, command_args)
+        # Lockdep follows the frame pointers to print where locks were taken from.
+        if "lockdep" in args.features:
+            env = dict(os.environ)
+            env["RUSTFLAGS"] = env.get("RUSTFLAGS", "") + " -C force-frame-pointers=yes"
+
+    return build_cargo_workspace("src", command, command_args, env=env)
 
 
 def parse_args():
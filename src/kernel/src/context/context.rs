@@ -3,16 +3,24 @@ use alloc::collections::VecDeque;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
-use core::cmp::Ordering;
+use core::ptr::Unique;
 
-use crate::filesys::{Vnode, FileDescriptor};
-use crate::Error;
+use crate::context::scheduler::{Priority, SchedClass, Urgency};
+use crate::context::signal::SigAction;
+use crate::context::{AddressSpace, ContextId, CpuSet, ThreadGroup};
+#[cfg(not(test))]
+use crate::filesys::{FileDescriptor, Vnode};
+// The V-node layer is left out of host test builds, so the file fields hold nothing there.
+#[cfg(test)]
+type FileDescriptor = ();
+#[cfg(test)]
+type Vnode = ();
 
 use crate::machine;
-use crate::machine::context::{Context as MachineContext};
+use crate::machine::context::Context as MachineContext;
 use crate::machine::interrupt::InterruptStack;
-
-use spin::RwLock;
+use crate::sync::RwLock;
+use crate::utils::aligned_box::AlignedBox;
 
 /// Status of context. Used for scheduling.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -24,12 +32,14 @@ pub enum Status {
     Blocked,
     /// Process is stopped (because of the provided signal number).
     Stopped(usize),
-    /// The context has executed (with provided exit-code).
+    /// The context has executed (with provided wait status: the exit code in bits 8 to 15, or the
+    /// signal that terminated it in the low 7 bits, with bit 7 set if it dumped core).
     Exited(usize),
 }
 
 /// A context describes the executions state of a process or thread (or any execution image that
-/// can be loaded and saved on the CPU).
+/// can be loaded and saved on the CPU). Every context is a thread; the threads of a process are
+/// tied together by their [`ThreadGroup`].
 pub struct Context {
     /// Unique identifier.
     pub id: ContextId,
@@ -52,13 +62,43 @@ pub struct Context {
     pub status_reason: &'static str,
     /// Whether the context is running or not.
     pub running: bool,
-    /// The CPU that the context is running on (if locked).
+    /// Whether a CPU switched away from the context but may still be saving its registers. The
+    /// context cannot be switched to or removed until the switch is finished.
+    pub switching: bool,
+    /// The CPU whose run queue the context is on (or that it last ran on). `None` until it first
+    /// becomes runnable.
     pub cpu: Option<usize>,
+    /// CPUs that the context may run on. Change it through [`scheduler::set_affinity`], which
+    /// moves the context off CPUs that it may no longer run on.
+    ///
+    /// [`scheduler::set_affinity`]: crate::context::scheduler::set_affinity
+    pub affinity: CpuSet,
+    /// Scheduling priority. Change it through [`scheduler::set_priority`], so that the run queue
+    /// stays in order.
+    ///
+    /// [`scheduler::set_priority`]: crate::context::scheduler::set_priority
+    pub priority: Priority,
+    /// Urgency that the context inherited from contexts that wait for locks that it holds (their
+    /// priority, or their deadline if they are of the deadline class), or from the ceilings of the
+    /// locks. The scheduler uses it while it is higher than the context's own (see
+    /// [`Urgency::of`]). Change it through [`scheduler::set_boost`].
+    ///
+    /// [`scheduler::set_boost`]: crate::context::scheduler::set_boost
+    pub boost: Option<Urgency>,
+    /// Scheduling class. Change it through [`scheduler::set_deadline`] and
+    /// [`scheduler::clear_deadline`], which keep track of the reserved CPU time.
+    ///
+    /// [`scheduler::set_deadline`]: crate::context::scheduler::set_deadline
+    /// [`scheduler::clear_deadline`]: crate::context::scheduler::clear_deadline
+    pub class: SchedClass,
     /// Number of timer ticks that have passed while the process was executing (to measure how long
     /// the process has been on the CPU for).
     pub ticks: usize,
-    /// Context will wake up at this time.
-    pub wakeup_time: Option<(usize, usize)>,
+    /// Time (in scheduler ticks) at which the context is woken up while it is blocked, even if
+    /// what it waits for did not happen. See [`scheduler::block_until`].
+    ///
+    /// [`scheduler::block_until`]: crate::context::scheduler::block_until
+    pub wakeup_time: Option<u64>,
     /// Pending signals in the order that they will be handled.
     pub pending: VecDeque<u8>,
     /// Machine-specific data of the context (not including registers).
@@ -66,7 +106,7 @@ pub struct Context {
     /// Kernel stack.
     pub kernel_stack: Option<Box<[u8]>>,
     /// Kernel FX. Used to store SIMD and FPU registers.
-    pub kernel_fx: AlignedBox<[u8; machine::KERNFX_SIZE, {machine::KERNFX_ALIGN}]>,
+    pub kernel_fx: AlignedBox<[u8; machine::KERNFX_SIZE], { machine::KERNFX_ALIGN }>,
     /// Address space containing a page table lock, and grants. Normally this will have a value,
     /// but it can be None while the context is being reaped or when a new context is created but
     /// has not yet had its address space changed. Note that these are only for user mappings, as
@@ -75,23 +115,71 @@ pub struct Context {
     /// Name of this context (used mainly for debugging purposes).
     pub name: Arc<RwLock<Box<str>>>,
     /// Current working directory. Acts as a marker for where the process is currently in the
-    /// file-system. Used for relative paths.
-    pub current_dir: Arc<RwLock<Vnode>>,
+    /// file-system. Used for relative paths. Kernel contexts do not have one.
+    pub current_dir: Option<Arc<RwLock<Vnode>>>,
     /// Open file-descriptors.
     pub files: Arc<RwLock<Vec<Option<FileDescriptor>>>>,
     /// Pointer to user-space registers, saved after certain interrupts.
-    pub registers: Option<(usize, Unique<InterruptStack>)>
+    pub registers: Option<(usize, Unique<InterruptStack>)>,
     /// Signal action handlers.
     pub signal_actions: Arc<RwLock<Vec<(SigAction, usize)>>>,
+    /// Threads of the process that the context belongs to. Threads share the address space, the
+    /// open file-descriptors, the signal action handlers and the name.
+    pub thread_group: Arc<ThreadGroup>,
 }
 
 impl Context {
-    /// Construct a new [`Context`].
-    pub const fn new(id: ContextId) -> Self {
+    /// Construct a new [`Context`]. It is blocked until it is given something to run and woken
+    /// up by the scheduler.
+    pub fn new(id: ContextId) -> Self {
+        let mut context = Self {
+            id,
+            group_id: id,
+            parent_id: ContextId::default(),
+            real_user_id: 0,
+            real_group_id: 0,
+            effective_user_id: 0,
+            effective_group_id: 0,
+            signal_mask: [0; 2],
+            status: Status::Blocked,
+            status_reason: "",
+            running: false,
+            switching: false,
+            cpu: None,
+            affinity: CpuSet::all(),
+            priority: Priority::default(),
+            boost: None,
+            class: SchedClass::default(),
+            ticks: 0,
+            wakeup_time: None,
+            pending: VecDeque::new(),
+            machine: MachineContext::new(),
+            kernel_stack: None,
+            kernel_fx: AlignedBox::zeroed(),
+            addr_space: None,
+            name: Arc::new(RwLock::new(Box::from(""))),
+            current_dir: None,
+            files: Arc::new(RwLock::new(Vec::new())),
+            registers: None,
+            signal_actions: Arc::new(RwLock::new(Vec::new())),
+            thread_group: Arc::new(ThreadGroup::new(id)),
+        };
+
+        context.machine.set_fx(&mut context.kernel_fx);
+        context
+    }
+
+    /// Retrieve the priority that the context is scheduled with in the fixed-priority class: its
+    /// own priority, unless it inherited a higher one.
+    pub fn effective_priority(&self) -> Priority {
+        match self.boost {
+            Some(Urgency::Fixed(boost)) => boost.max(self.priority),
+            _ => self.priority,
+        }
     }
 
     /// Retrieve the context's address space.
-    pub fn addr_space(&self) -> Result<&Arc<RwLock<AddrSpace>>> {
-        self.addr_space.as_ref().ok_or(Error)
+    pub fn addr_space(&self) -> Option<&Arc<RwLock<AddressSpace>>> {
+        self.addr_space.as_ref()
     }
 }
@@ -0,0 +1,58 @@
+/// Maximum number of CPUs that contexts can be scheduled on.
+pub const MAX_CPUS: usize = 64;
+
+/// Set of CPUs, such as the CPUs that a context may run on.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct CpuSet(u64);
+
+impl CpuSet {
+    /// Construct a set of no CPUs.
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Construct a set of every CPU.
+    pub const fn all() -> Self {
+        Self(u64::MAX)
+    }
+
+    /// Construct a set of a single CPU.
+    pub const fn single(cpu: usize) -> Self {
+        Self(1 << cpu)
+    }
+
+    /// Construct a set from a bitmask, where bit `n` stands for CPU `n`.
+    pub const fn from_bits(bits: u64) -> Self {
+        Self(bits)
+    }
+
+    /// Retrieve the bitmask of the set.
+    pub const fn bits(&self) -> u64 {
+        self.0
+    }
+
+    /// Whether the set has no CPU.
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether a CPU is in the set.
+    pub const fn contains(&self, cpu: usize) -> bool {
+        cpu < MAX_CPUS && self.0 & (1 << cpu) != 0
+    }
+
+    /// Add a CPU to the set.
+    pub fn insert(&mut self, cpu: usize) {
+        self.0 |= 1 << cpu;
+    }
+
+    /// Remove a CPU from the set.
+    pub fn remove(&mut self, cpu: usize) {
+        self.0 &= !(1 << cpu);
+    }
+
+    /// Iterate through the CPUs of the set, in ascending order.
+    pub fn iter(self) -> impl Iterator<Item = usize> {
+        (0..MAX_CPUS).filter(move |&cpu| self.contains(cpu))
+    }
+}
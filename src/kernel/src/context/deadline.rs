@@ -0,0 +1,437 @@
+use alloc::collections::BTreeSet;
+
+use crate::context::trace::TimingStats;
+use crate::context::ContextId;
+
+/// Utilizations are fixed-point fractions, in parts per million.
+const PPM: u64 = 1_000_000;
+
+/// Utilization bound of the rate-monotonic test, `n * (2^(1/n) - 1)`, for `n` contexts (starting
+/// at one context). Beyond the table, its limit (`ln 2`) is used, which is lower than every entry.
+const RM_BOUNDS: [u64; 16] = [
+    1_000_000, 828_427, 779_763, 756_828, 743_491, 734_772, 728_626, 724_061, 720_537, 717_734,
+    715_451, 713_557, 711_958, 710_592, 709_411, 708_380,
+];
+
+/// Limit of the rate-monotonic bound as the number of contexts grows.
+const RM_BOUND_LIMIT: u64 = 693_147;
+
+/// Timing parameters of a periodic context, in scheduler ticks.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct DeadlineParams {
+    /// Time between two releases of the context's job.
+    pub period: u64,
+    /// Time after its release by which a job must be done. At most the period.
+    pub deadline: u64,
+    /// Worst-case execution time of a job. The context is never given more than this before its
+    /// deadline, so that it cannot starve other contexts if it overruns.
+    pub budget: u64,
+}
+
+impl DeadlineParams {
+    /// Construct new [`DeadlineParams`].
+    pub const fn new(period: u64, deadline: u64, budget: u64) -> Self {
+        Self {
+            period,
+            deadline,
+            budget,
+        }
+    }
+
+    /// Whether the parameters make sense: the budget fits before the deadline, which comes no
+    /// later than the next release.
+    pub fn is_valid(&self) -> bool {
+        self.budget > 0 && self.budget <= self.deadline && self.deadline <= self.period
+    }
+
+    /// Share of a CPU that the context needs, in parts per million. The budget is divided by the
+    /// deadline rather than the period, which is the safe side when the deadline is shorter, and
+    /// the share is rounded up for the same reason.
+    pub fn utilization(&self) -> u64 {
+        let utilization = (self.budget as u128 * PPM as u128).div_ceil(self.deadline as u128);
+        utilization.try_into().unwrap_or(u64::MAX)
+    }
+}
+
+/// Errors that can occur when a context asks for the deadline class.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AdmissionError {
+    /// The parameters are invalid (see [`DeadlineParams::is_valid`]).
+    InvalidParams,
+    /// Admitting the context would make the admitted contexts fail the rate-monotonic test, so
+    /// that their deadlines could no longer be guaranteed.
+    Overloaded,
+}
+
+/// Contexts that were admitted to the deadline class, and how much of the CPU they use together.
+pub struct Admission {
+    /// Number of admitted contexts.
+    count: usize,
+    /// Sum of the utilizations of the admitted contexts, in parts per million.
+    utilization: u64,
+}
+
+impl Admission {
+    /// Construct an empty admission set.
+    pub const fn new() -> Self {
+        Self {
+            count: 0,
+            utilization: 0,
+        }
+    }
+
+    /// Retrieve the number of admitted contexts.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Retrieve the total utilization of the admitted contexts, in parts per million.
+    pub fn utilization(&self) -> u64 {
+        self.utilization
+    }
+
+    /// Retrieve the utilization that `count` contexts may use together according to the
+    /// rate-monotonic test of Liu and Layland.
+    fn bound(count: usize) -> u64 {
+        match count {
+            0 => PPM,
+            count => RM_BOUNDS.get(count - 1).copied().unwrap_or(RM_BOUND_LIMIT),
+        }
+    }
+
+    /// Admit a context with the given parameters if the admitted contexts still pass the test.
+    /// `replaced` are the parameters that the context was admitted with before, if any, which
+    /// stop counting if the new ones are admitted.
+    pub fn admit(
+        &mut self,
+        params: &DeadlineParams,
+        replaced: Option<&DeadlineParams>,
+    ) -> Result<(), AdmissionError> {
+        if !params.is_valid() {
+            return Err(AdmissionError::InvalidParams);
+        }
+
+        let (count, utilization) = match replaced {
+            Some(replaced) => (self.count, self.utilization - replaced.utilization()),
+            None => (self.count + 1, self.utilization),
+        };
+        let utilization = utilization + params.utilization();
+
+        if utilization > Self::bound(count) {
+            return Err(AdmissionError::Overloaded);
+        }
+
+        self.count = count;
+        self.utilization = utilization;
+        Ok(())
+    }
+
+    /// Stop counting a context that leaves the deadline class.
+    pub fn remove(&mut self, params: &DeadlineParams) {
+        self.count -= 1;
+        self.utilization -= params.utilization();
+    }
+}
+
+/// CPU reservation of a context of the deadline class. It acts as a hard constant bandwidth server
+/// (CBS): the context is dispatched by the deadline of the server, and whenever it uses up its
+/// budget, it is throttled until the server deadline, at which point the deadline is pushed back
+/// by a relative deadline and the budget refilled. An overrunning context therefore only runs in
+/// the time that it reserved, so it can neither delay the deadlines of the others nor starve the
+/// fixed-priority contexts.
+#[derive(Clone, Debug)]
+pub struct Reservation {
+    /// Parameters that the context was admitted with.
+    pub params: DeadlineParams,
+    /// Time at which the current job was released.
+    pub release: u64,
+    /// Absolute deadline of the server, which orders the dispatch.
+    pub server_deadline: u64,
+    /// Budget left before the server deadline.
+    pub remaining: u64,
+    /// CPU time that the current job used so far.
+    pub used: u64,
+    /// Whether the server used up its budget, and may not run until it is replenished at its
+    /// deadline.
+    pub throttled: bool,
+    /// Timing of the completed jobs.
+    pub stats: TimingStats,
+}
+
+impl Reservation {
+    /// Construct a new [`Reservation`], whose first job is released at `now`.
+    pub fn new(params: DeadlineParams, now: u64) -> Self {
+        Self {
+            params,
+            release: now,
+            server_deadline: now + params.deadline,
+            remaining: params.budget,
+            used: 0,
+            throttled: false,
+            stats: TimingStats::new(),
+        }
+    }
+
+    /// Retrieve the absolute deadline of the current job.
+    pub fn job_deadline(&self) -> u64 {
+        self.release + self.params.deadline
+    }
+
+    /// Give the server a fresh deadline and budget when the context becomes runnable at `now`,
+    /// unless what is left of its budget can still be used before its deadline without going over
+    /// the reserved bandwidth. A throttled server waits for its replenishment instead.
+    pub fn wake(&mut self, now: u64) {
+        if self.throttled {
+            return;
+        }
+
+        // Both products can take up to 128 bits.
+        let params = &self.params;
+        let left = self.server_deadline.saturating_sub(now);
+        let density = self.remaining as u128 * params.deadline as u128;
+
+        if density >= left as u128 * params.budget as u128 {
+            self.server_deadline = now + params.deadline;
+            self.remaining = params.budget;
+        }
+    }
+
+    /// Charge a tick of CPU time to the server. Returns whether the budget was used up, in which
+    /// case the server is throttled until [`Reservation::replenish`] is called at its deadline.
+    pub fn charge(&mut self) -> bool {
+        self.used += 1;
+        self.remaining = self.remaining.saturating_sub(1);
+        if self.remaining > 0 {
+            return false;
+        }
+
+        self.throttled = true;
+        self.stats.overruns += 1;
+        true
+    }
+
+    /// Refill the budget of a throttled server once its deadline is reached, and push the deadline
+    /// back by a relative deadline.
+    pub fn replenish(&mut self) {
+        self.throttled = false;
+        self.server_deadline += self.params.deadline;
+        self.remaining = self.params.budget;
+    }
+
+    /// Mark the current job as done at `now`, and move on to the next one. Returns whether the
+    /// job missed its deadline.
+    pub fn complete(&mut self, now: u64) -> bool {
+        let deadline = self.job_deadline();
+        self.stats
+            .record(self.release, deadline, now, self.used, self.params.budget);
+
+        self.release += self.params.period;
+        self.used = 0;
+        now > deadline
+    }
+}
+
+/// Runnable contexts of the deadline class, sorted by their server deadlines.
+pub struct EdfQueue {
+    queue: BTreeSet<(u64, ContextId)>,
+}
+
+impl EdfQueue {
+    /// Construct an empty queue.
+    pub const fn new() -> Self {
+        Self {
+            queue: BTreeSet::new(),
+        }
+    }
+
+    /// Retrieve the number of queued contexts.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Whether no context is queued.
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Queue a context with the given server deadline.
+    pub fn push(&mut self, id: ContextId, deadline: u64) {
+        self.queue.insert((deadline, id));
+    }
+
+    /// Remove a context from the queue, returning whether it was queued.
+    pub fn remove(&mut self, id: ContextId, deadline: u64) -> bool {
+        self.queue.remove(&(deadline, id))
+    }
+
+    /// Retrieve the earliest deadline of the queued contexts.
+    pub fn earliest(&self) -> Option<u64> {
+        self.queue.first().map(|&(deadline, _)| deadline)
+    }
+
+    /// Take the context with the earliest deadline that `eligible` accepts out of the queue.
+    pub fn take(
+        &mut self,
+        mut eligible: impl FnMut(ContextId) -> bool,
+    ) -> Option<(ContextId, u64)> {
+        let entry = *self.queue.iter().find(|&&(_, id)| eligible(id))?;
+        self.queue.remove(&entry);
+        Some((entry.1, entry.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_params() {
+        assert!(DeadlineParams::new(10, 10, 5).is_valid());
+        assert!(DeadlineParams::new(10, 5, 5).is_valid());
+        assert!(!DeadlineParams::new(10, 5, 0).is_valid());
+        assert!(!DeadlineParams::new(10, 5, 6).is_valid());
+        assert!(!DeadlineParams::new(10, 20, 5).is_valid());
+    }
+
+    #[test]
+    fn measures_utilization_against_the_deadline() {
+        assert_eq!(DeadlineParams::new(10, 10, 5).utilization(), 500_000);
+        assert_eq!(DeadlineParams::new(100, 50, 5).utilization(), 100_000);
+
+        // A third is rounded up, so that three of them do not fit in the bound of one.
+        assert_eq!(DeadlineParams::new(3, 3, 1).utilization(), 333_334);
+        assert_eq!(
+            DeadlineParams::new(u64::MAX, u64::MAX, u64::MAX).utilization(),
+            PPM
+        );
+    }
+
+    #[test]
+    fn admits_up_to_the_rate_monotonic_bound() {
+        let mut admission = Admission::new();
+        let half = DeadlineParams::new(10, 10, 5);
+        let tenth = DeadlineParams::new(10, 10, 1);
+
+        // Two contexts may use 82.8% of the CPU together.
+        assert_eq!(admission.admit(&half, None), Ok(()));
+        assert_eq!(
+            admission.admit(&half, None),
+            Err(AdmissionError::Overloaded)
+        );
+        assert_eq!(admission.admit(&tenth, None), Ok(()));
+        assert_eq!(admission.count(), 2);
+        assert_eq!(admission.utilization(), 600_000);
+
+        // Three contexts may use 78%.
+        assert_eq!(admission.admit(&tenth, None), Ok(()));
+        assert_eq!(
+            admission.admit(&tenth, None),
+            Err(AdmissionError::Overloaded)
+        );
+
+        admission.remove(&half);
+        assert_eq!(admission.count(), 2);
+        assert_eq!(admission.utilization(), 200_000);
+    }
+
+    #[test]
+    fn replaces_admitted_params() {
+        let mut admission = Admission::new();
+        let half = DeadlineParams::new(10, 10, 5);
+        let most = DeadlineParams::new(10, 10, 9);
+
+        assert_eq!(admission.admit(&half, None), Ok(()));
+        assert_eq!(admission.admit(&most, Some(&half)), Ok(()));
+        assert_eq!(admission.count(), 1);
+        assert_eq!(admission.utilization(), 900_000);
+
+        let invalid = DeadlineParams::new(10, 10, 11);
+        assert_eq!(
+            admission.admit(&invalid, Some(&most)),
+            Err(AdmissionError::InvalidParams)
+        );
+        assert_eq!(admission.utilization(), 900_000);
+    }
+
+    #[test]
+    fn dispatches_by_earliest_deadline() {
+        let mut queue = EdfQueue::new();
+        queue.push(ContextId::new(1), 30);
+        queue.push(ContextId::new(2), 10);
+        queue.push(ContextId::new(3), 20);
+
+        assert_eq!(queue.len(), 3);
+        assert_eq!(queue.earliest(), Some(10));
+        assert_eq!(queue.take(|_| true), Some((ContextId::new(2), 10)));
+        assert_eq!(
+            queue.take(|id| id != ContextId::new(3)),
+            Some((ContextId::new(1), 30))
+        );
+
+        assert!(!queue.remove(ContextId::new(3), 30));
+        assert!(queue.remove(ContextId::new(3), 20));
+        assert!(queue.is_empty());
+        assert_eq!(queue.take(|_| true), None);
+    }
+
+    #[test]
+    fn throttles_overrunning_servers() {
+        let params = DeadlineParams::new(10, 10, 2);
+        let mut reservation = Reservation::new(params, 100);
+        assert_eq!(reservation.server_deadline, 110);
+
+        assert!(!reservation.charge());
+        assert!(reservation.charge());
+        assert!(reservation.throttled);
+        assert_eq!(reservation.stats.overruns, 1);
+
+        // Waking up does not lift the throttling.
+        reservation.wake(105);
+        assert!(reservation.throttled);
+        assert_eq!(reservation.server_deadline, 110);
+
+        reservation.replenish();
+        assert!(!reservation.throttled);
+        assert_eq!(reservation.server_deadline, 120);
+        assert_eq!(reservation.remaining, 2);
+    }
+
+    #[test]
+    fn keeps_the_server_deadline_while_bandwidth_is_left() {
+        let params = DeadlineParams::new(10, 10, 4);
+        let mut reservation = Reservation::new(params, 0);
+        reservation.charge();
+
+        // Three ticks of budget fit in the eight before the deadline.
+        reservation.wake(2);
+        assert_eq!(reservation.server_deadline, 10);
+        assert_eq!(reservation.remaining, 3);
+
+        // They would use more than the reserved bandwidth of the two ticks left.
+        reservation.wake(8);
+        assert_eq!(reservation.server_deadline, 18);
+        assert_eq!(reservation.remaining, 4);
+    }
+
+    #[test]
+    fn compares_bandwidth_without_overflowing() {
+        let params = DeadlineParams::new(u64::MAX / 2, u64::MAX / 2, u64::MAX / 4);
+        let mut reservation = Reservation::new(params, 0);
+        reservation.remaining = 1;
+
+        reservation.wake(1);
+        assert_eq!(reservation.server_deadline, u64::MAX / 2);
+        assert_eq!(reservation.remaining, 1);
+    }
+
+    #[test]
+    fn reports_missed_jobs() {
+        let params = DeadlineParams::new(10, 5, 2);
+        let mut reservation = Reservation::new(params, 0);
+
+        assert!(!reservation.complete(5));
+        assert_eq!(reservation.release, 10);
+        assert!(reservation.complete(16));
+        assert_eq!(reservation.job_deadline(), 25);
+    }
+}
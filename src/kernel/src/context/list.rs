@@ -0,0 +1,78 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec;
+
+use crate::context::{scheduler, Context, ContextId, Priority};
+use crate::sync::RwLock;
+
+/// Size of the kernel stack of every context, in bytes.
+pub const KERNEL_STACK_SIZE: usize = 64 * 1024;
+
+/// Every context in the system, indexed by their identifiers.
+pub struct ContextList {
+    /// Contexts, sorted by their identifiers.
+    map: BTreeMap<ContextId, Arc<RwLock<Context>>>,
+    /// Identifier that will be given to the next context.
+    next_id: usize,
+}
+
+impl ContextList {
+    /// Construct an empty context list.
+    pub const fn new() -> Self {
+        Self {
+            map: BTreeMap::new(),
+            next_id: 1,
+        }
+    }
+
+    /// Retrieve a context by its identifier.
+    pub fn get(&self, id: ContextId) -> Option<&Arc<RwLock<Context>>> {
+        self.map.get(&id)
+    }
+
+    /// Retrieve the context that is running on this CPU.
+    pub fn current(&self) -> Option<&Arc<RwLock<Context>>> {
+        self.get(super::context_id())
+    }
+
+    /// Iterate through every context, sorted by their identifiers.
+    pub fn iter(&self) -> impl Iterator<Item = (&ContextId, &Arc<RwLock<Context>>)> {
+        self.map.iter()
+    }
+
+    /// Reserve an identifier for a new context. Identifiers are never reused.
+    pub fn next_id(&mut self) -> ContextId {
+        let id = ContextId::new(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    /// Add a context to the list, and retrieve the shared handle to it.
+    pub fn insert(&mut self, context: Context) -> Arc<RwLock<Context>> {
+        let id = context.id;
+        let context = Arc::new(RwLock::new(context));
+        self.map.insert(id, Arc::clone(&context));
+        context
+    }
+
+    /// Create a kernel context that runs the given function on a stack of its own, with the given
+    /// priority, and make it runnable.
+    pub fn spawn(&mut self, priority: Priority, entry: extern "C" fn()) -> Arc<RwLock<Context>> {
+        let mut context = Context::new(self.next_id());
+        let stack = vec![0; KERNEL_STACK_SIZE].into_boxed_slice();
+
+        let stack_top = (stack.as_ptr() as usize + stack.len()) & !0xF;
+        unsafe { context.machine.set_entry(stack_top, entry) };
+        context.kernel_stack = Some(stack);
+        context.priority = priority;
+
+        let context = self.insert(context);
+        scheduler::wake(&mut context.write());
+        context
+    }
+
+    /// Remove a context from the list.
+    pub fn remove(&mut self, id: ContextId) -> Option<Arc<RwLock<Context>>> {
+        self.map.remove(&id)
+    }
+}
@@ -1,5 +1,1147 @@
+use alloc::collections::BTreeMap;
+#[cfg(feature = "swap")]
+use alloc::collections::BTreeSet;
+#[cfg(feature = "swap")]
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use core::ptr;
+
+use crate::context::{self, ContextId};
+use crate::machine::paging::{self, Mapper};
+use crate::memory::paging::{MapError, PageFlags, PageMapper, PageSize};
+#[cfg(feature = "swap")]
+use crate::memory::swap::{self, SwapError, SwapSlot};
+use crate::memory::tlb;
+use crate::memory::{self, Frame, PAGE_SIZE};
+#[cfg(feature = "swap")]
+use crate::sync::RwLock;
+
+/// Lowest address that can be mapped in user-space. The first page is never mapped, so that null
+/// pointer dereferences always fault.
+pub const USER_START: usize = PAGE_SIZE;
+
+/// Number of pages that are swapped out at once when physical memory runs out.
+#[cfg(feature = "swap")]
+const RECLAIM_BATCH: usize = 32;
+
+/// Representation of an error as the result of an operation on an address space.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MemoryError {
+    /// Physical memory or page table memory ran out.
+    OutOfMemory,
+    /// The range is not page-aligned, is empty, or is outside of user-space.
+    InvalidRange,
+    /// Part of the range is already mapped.
+    AddressInUse,
+    /// No part of the range is mapped.
+    NotMapped,
+    /// The access is not allowed by the permissions of the grant.
+    AccessDenied,
+    /// A page could not be read back from swap.
+    #[cfg(feature = "swap")]
+    Swap(SwapError),
+    /// The page is in swap, and has to be read back while the address space is not locked. A
+    /// reference to the slot was taken for the caller (see [`page_fault`]).
+    #[cfg(feature = "swap")]
+    SwappedOut(SwapSlot),
+}
+
+impl From<MapError> for MemoryError {
+    fn from(error: MapError) -> Self {
+        match error {
+            MapError::OutOfMemory => MemoryError::OutOfMemory,
+            MapError::AlreadyMapped => MemoryError::AddressInUse,
+            MapError::NotMapped => MemoryError::NotMapped,
+            MapError::Misaligned | MapError::InvalidAddress => MemoryError::InvalidRange,
+        }
+    }
+}
+
+#[cfg(feature = "swap")]
+impl From<SwapError> for MemoryError {
+    fn from(error: SwapError) -> Self {
+        MemoryError::Swap(error)
+    }
+}
+
+/// Kind of memory access that caused a page fault.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AccessKind {
+    /// Data was read.
+    Read,
+    /// Data was written.
+    Write,
+    /// An instruction was fetched.
+    Execute,
+}
+
+/// A page fault, as decoded by the architecture's exception handler.
+#[derive(Copy, Clone, Debug)]
+pub struct PageFault {
+    /// Virtual address that was accessed.
+    pub address: usize,
+    /// Kind of access that faulted.
+    pub access: AccessKind,
+    /// Whether the fault happened in user-mode.
+    pub user: bool,
+}
+
+/// Resolve a page fault using the address space of the context that is currently running.
+/// Faults in the kernel's half of the address space are never resolved, but faults caused by the
+/// kernel on user addresses (while copying from or to user-space) are.
 ///
-#[derive(Debug)]
+/// The address space is locked for writing while the fault is handled, so the kernel must not
+/// touch user memory while it holds that lock. The swap device is only used while no address
+/// space is locked, since it may block.
+pub fn page_fault(fault: &PageFault) -> Result<(), MemoryError> {
+    if fault.address >= paging::user_end() {
+        return Err(MemoryError::InvalidRange);
+    }
+
+    let context = context::current().ok_or(MemoryError::NotMapped)?;
+    let addr_space = context
+        .read()
+        .addr_space()
+        .cloned()
+        .ok_or(MemoryError::NotMapped)?;
+
+    // Without swapping, the fault is only handled once.
+    #[cfg_attr(not(feature = "swap"), allow(clippy::never_loop))]
+    loop {
+        let result = addr_space.write().handle_fault(fault.address, fault.access);
+
+        // The fault is handled again once the page is back in memory.
+        #[cfg(feature = "swap")]
+        let result = match result {
+            Err(MemoryError::SwappedOut(slot)) => match swap_in(&addr_space, fault.address, slot) {
+                Ok(()) => continue,
+                Err(error) => Err(error),
+            },
+            result => result,
+        };
+
+        #[cfg(feature = "swap")]
+        if result == Err(MemoryError::OutOfMemory) && reclaim(RECLAIM_BATCH) > 0 {
+            continue;
+        }
+
+        return result;
+    }
+}
+
+/// Allocate a frame for user memory. Pages are not swapped out to make room here, since an
+/// address space is usually locked; [`page_fault`] does it once it unlocked the address space.
+fn allocate_frame() -> Result<Frame, MemoryError> {
+    memory::allocate_frame().ok_or(MemoryError::OutOfMemory)
+}
+
+/// Read a page that [`AddressSpace::handle_fault`] found in swap back into memory, and map it
+/// unless it was mapped or unmapped in the meantime. The address space is not locked while the
+/// swap device is busy. Drops the reference to the slot that was taken for the caller.
+#[cfg(feature = "swap")]
+fn swap_in(
+    addr_space: &RwLock<AddressSpace>,
+    address: usize,
+    slot: SwapSlot,
+) -> Result<(), MemoryError> {
+    let page = address & !(PAGE_SIZE - 1);
+
+    let result = allocate_frame().and_then(|frame| match swap::read(slot, frame) {
+        Ok(()) => addr_space.write().finish_swap_in(page, slot, frame),
+        Err(error) => {
+            unsafe { memory::deallocate_frame(frame) };
+            Err(error.into())
+        }
+    });
+
+    swap::release(slot);
+    result
+}
+
+/// Keep every page of an address space in memory from now on, reading back the pages that were
+/// already swapped out. Used for real-time contexts, which cannot afford to wait for the swap
+/// device.
+#[cfg(feature = "swap")]
+pub fn pin(addr_space: &RwLock<AddressSpace>) -> Result<(), MemoryError> {
+    addr_space.write().pin();
+
+    loop {
+        let (address, slot) = {
+            let addr_space = addr_space.write();
+            match addr_space.swapped.iter().next() {
+                Some((&address, &slot)) => {
+                    swap::share(slot)?;
+                    (address, slot)
+                }
+                None => return Ok(()),
+            }
+        };
+
+        match swap_in(addr_space, address, slot) {
+            Ok(()) => (),
+            Err(MemoryError::OutOfMemory) if reclaim(RECLAIM_BATCH) > 0 => (),
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Swap out up to `count` cold pages from the address spaces of all contexts, and return how many
+/// were swapped out. Address spaces that are pinned or locked are skipped. The address spaces are
+/// only locked to pick the pages, not while they are written to swap.
+#[cfg(feature = "swap")]
+pub fn reclaim(count: usize) -> usize {
+    // Threads share their address space, which is only scanned once.
+    let mut addr_spaces: Vec<Arc<RwLock<AddressSpace>>> = Vec::new();
+    for (_, context) in context::contexts().iter() {
+        let addr_space = context
+            .try_read()
+            .and_then(|context| context.addr_space().cloned());
+        if let Some(addr_space) = addr_space {
+            if !addr_spaces
+                .iter()
+                .any(|other| Arc::ptr_eq(other, &addr_space))
+            {
+                addr_spaces.push(addr_space);
+            }
+        }
+    }
+
+    let mut evicted = 0;
+    for addr_space in addr_spaces {
+        if evicted == count {
+            break;
+        }
+
+        let pages = match addr_space.try_write() {
+            Some(mut guard) => guard.evict(count - evicted),
+            None => continue,
+        };
+
+        for (address, frame) in pages {
+            let result = swap::write(frame);
+            if addr_space.write().finish_eviction(address, frame, result) {
+                evicted += 1;
+            }
+        }
+    }
+
+    evicted
+}
+
+/// What a grant's pages are backed by.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum GrantKind {
+    /// Private, zero-initialized memory. The frames belong to the grant and are freed with it,
+    /// unless they are still shared with a forked address space.
+    Anonymous,
+    /// A fixed range of physical memory (usually a device's registers or a DMA buffer). The frames
+    /// do not belong to the grant and are never freed by it.
+    Physical {
+        /// Physical address that the start of the grant is mapped to.
+        base: usize,
+    },
+}
+
+/// A grant is a contiguous range of virtual memory in an address space, along with what it is
+/// backed by and how it may be accessed. Every user mapping belongs to exactly one grant.
+#[derive(Clone, Debug)]
+pub struct Grant {
+    /// Virtual address of the start of the grant.
+    start: usize,
+    /// Size of the grant, in bytes.
+    size: usize,
+    /// Permissions of the grant's pages.
+    flags: PageFlags,
+    /// What the grant's pages are backed by.
+    kind: GrantKind,
+    /// The context that created the grant (if it was not created by the kernel itself).
+    owner: Option<ContextId>,
+}
+
+impl Grant {
+    /// Retrieve the virtual address of the start of the grant.
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Retrieve the virtual address of the end of the grant (exclusive).
+    pub fn end(&self) -> usize {
+        self.start + self.size
+    }
+
+    /// Retrieve the size of the grant, in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Retrieve the permissions of the grant's pages.
+    pub fn flags(&self) -> PageFlags {
+        self.flags
+    }
+
+    /// Retrieve what the grant's pages are backed by.
+    pub fn kind(&self) -> GrantKind {
+        self.kind
+    }
+
+    /// Retrieve the context that created the grant.
+    pub fn owner(&self) -> Option<ContextId> {
+        self.owner
+    }
+
+    /// Whether the grant contains the given virtual address.
+    pub fn contains(&self, address: usize) -> bool {
+        address >= self.start && address < self.end()
+    }
+
+    /// Iterate through the virtual addresses of the grant's pages.
+    pub fn pages(&self) -> impl Iterator<Item = usize> {
+        (self.start..self.end()).step_by(PAGE_SIZE)
+    }
+
+    /// Retrieve the physical address that a page of a physical grant is mapped to.
+    fn phys_for(&self, address: usize) -> Option<usize> {
+        match self.kind {
+            GrantKind::Physical { base } => Some(base + (address - self.start)),
+            GrantKind::Anonymous => None,
+        }
+    }
+
+    /// Split the grant in two at the given (page-aligned) address.
+    fn split_at(self, address: usize) -> (Grant, Grant) {
+        let offset = address - self.start;
+        let kind = match self.kind {
+            GrantKind::Physical { base } => GrantKind::Physical {
+                base: base + offset,
+            },
+            kind => kind,
+        };
+
+        let right = Grant {
+            start: address,
+            size: self.size - offset,
+            kind,
+            ..self.clone()
+        };
+        let left = Grant {
+            size: offset,
+            ..self
+        };
+
+        (left, right)
+    }
+}
+
+/// An address space holds the user half of a page table, along with the grants that describe
+/// what is mapped in it. Kernel mappings are shared between all address spaces, and are not
+/// managed here.
 pub struct AddressSpace {
-    
+    /// Page table of the address space.
+    mapper: Mapper,
+    /// Grants of the address space, sorted by their start address. Grants never overlap.
+    grants: BTreeMap<usize, Grant>,
+    /// Slots holding the anonymous pages that were swapped out, by their virtual addresses.
+    #[cfg(feature = "swap")]
+    swapped: BTreeMap<usize, SwapSlot>,
+    /// Frames of the anonymous pages that were unmapped to be written to swap, by their virtual
+    /// addresses. A fault on one of them maps the frame again, which cancels the eviction.
+    #[cfg(feature = "swap")]
+    evicting: BTreeMap<usize, Frame>,
+    /// Frames of the pages that were unmapped while they were written to swap, which are freed
+    /// once they are written.
+    #[cfg(feature = "swap")]
+    discarded: BTreeSet<Frame>,
+    /// Whether the pages of the address space must stay in memory.
+    #[cfg(feature = "swap")]
+    pinned: bool,
+    /// Address at which the next scan for pages to swap out starts.
+    #[cfg(feature = "swap")]
+    clock: usize,
+}
+
+impl AddressSpace {
+    /// Create an empty address space.
+    pub fn new() -> Result<Self, MemoryError> {
+        Ok(Self {
+            mapper: Mapper::new()?,
+            grants: BTreeMap::new(),
+            #[cfg(feature = "swap")]
+            swapped: BTreeMap::new(),
+            #[cfg(feature = "swap")]
+            evicting: BTreeMap::new(),
+            #[cfg(feature = "swap")]
+            discarded: BTreeSet::new(),
+            #[cfg(feature = "swap")]
+            pinned: false,
+            #[cfg(feature = "swap")]
+            clock: USER_START,
+        })
+    }
+
+    /// Retrieve the page table of the address space.
+    pub fn mapper(&self) -> &Mapper {
+        &self.mapper
+    }
+
+    /// Iterate through the grants of the address space, in order.
+    pub fn grants(&self) -> impl Iterator<Item = &Grant> {
+        self.grants.values()
+    }
+
+    /// Retrieve the grant that contains the given address.
+    pub fn grant_at(&self, address: usize) -> Option<&Grant> {
+        self.grants
+            .range(..=address)
+            .next_back()
+            .map(|(_, grant)| grant)
+            .filter(|grant| grant.contains(address))
+    }
+
+    /// Whether any grant overlaps the given range.
+    fn is_used(&self, start: usize, end: usize) -> bool {
+        self.grants
+            .range(..end)
+            .next_back()
+            .map_or(false, |(_, grant)| grant.end() > start)
+    }
+
+    /// Check that a range is page-aligned, non-empty and inside user-space, and retrieve its end.
+    fn check_range(start: usize, size: usize) -> Result<usize, MemoryError> {
+        let end = start.checked_add(size).ok_or(MemoryError::InvalidRange)?;
+        if size == 0 || start % PAGE_SIZE != 0 || size % PAGE_SIZE != 0 {
+            return Err(MemoryError::InvalidRange);
+        }
+
+        if start < USER_START || end > paging::user_end() {
+            return Err(MemoryError::InvalidRange);
+        }
+
+        Ok(end)
+    }
+
+    /// Find the lowest free range of the given size.
+    fn find_free(&self, size: usize) -> Result<usize, MemoryError> {
+        let mut start = USER_START;
+
+        for grant in self.grants.values() {
+            if grant.start() >= start + size {
+                break;
+            }
+            start = start.max(grant.end());
+        }
+
+        Self::check_range(start, size).map(|_| start)
+    }
+
+    /// Pick the range for a new grant, either at the requested address or at any free address.
+    fn place(&self, address: Option<usize>, size: usize) -> Result<usize, MemoryError> {
+        match address {
+            Some(start) => {
+                let end = Self::check_range(start, size)?;
+                if self.is_used(start, end) {
+                    return Err(MemoryError::AddressInUse);
+                }
+                Ok(start)
+            }
+            None => self.find_free(size),
+        }
+    }
+
+    /// Allocate a zeroed frame for a page of an anonymous grant.
+    unsafe fn allocate_page() -> Result<Frame, MemoryError> {
+        let frame = allocate_frame()?;
+        ptr::write_bytes(frame.virt_address() as *mut u8, 0, PAGE_SIZE);
+        Ok(frame)
+    }
+
+    /// Map a single page of a grant. Pages of anonymous grants are zeroed, so pages that are in
+    /// swap must be read back with [`swap_in`] instead.
+    unsafe fn map_page(&mut self, grant: &Grant, address: usize) -> Result<(), MemoryError> {
+        let phys = match grant.phys_for(address) {
+            Some(phys) => phys,
+            None => Self::allocate_page()?.start_address(),
+        };
+
+        let result = self.mapper.map(
+            address,
+            phys,
+            PageSize::Small,
+            grant.flags | PageFlags::USER,
+        );
+
+        if result.is_err() && grant.kind == GrantKind::Anonymous {
+            memory::deallocate_frame(Frame::containing_address(phys));
+        }
+
+        result.map_err(MemoryError::from)
+    }
+
+    /// Unmap every page of a grant and release the swap slots that belong to it. The frames that
+    /// belong to it are returned instead, since other CPUs may still access them through their
+    /// TLBs until [`tlb::shootdown`] is called.
+    #[must_use]
+    unsafe fn unmap_pages(&mut self, grant: &Grant) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        for address in grant.pages() {
+            if let Ok((phys, _)) = self.mapper.unmap(address) {
+                if grant.kind == GrantKind::Anonymous {
+                    frames.push(Frame::containing_address(phys));
+                }
+            }
+        }
+
+        #[cfg(feature = "swap")]
+        {
+            let mut swapped = self.swapped.split_off(&grant.start);
+            let mut after = swapped.split_off(&grant.end());
+            self.swapped.append(&mut after);
+
+            for slot in swapped.into_values() {
+                swap::release(slot);
+            }
+
+            // Pages that are being written to swap are dropped once they are written, since the
+            // write still reads their frames (see `finish_eviction`).
+            let mut evicting = self.evicting.split_off(&grant.start);
+            let mut after = evicting.split_off(&grant.end());
+            self.evicting.append(&mut after);
+            self.discarded.extend(evicting.into_values());
+        }
+
+        frames
+    }
+
+    /// Release the frames returned by [`AddressSpace::unmap_pages`].
+    ///
+    /// # Safety
+    /// No TLB may still hold the pages that the frames were mapped at.
+    unsafe fn release_frames(frames: Vec<Frame>) {
+        for frame in frames {
+            memory::release_frame(frame);
+        }
+    }
+
+    /// Retrieve the flags that a mapped page of a grant should have. Anonymous pages that are
+    /// shared with another address space are kept read-only, so that the first write to them
+    /// faults and can be resolved by [`AddressSpace::copy_on_write`].
+    fn page_flags(grant: &Grant, phys: usize) -> PageFlags {
+        let mut flags = grant.flags | PageFlags::USER;
+        if grant.kind == GrantKind::Anonymous
+            && memory::frame_refcount(Frame::containing_address(phys)) > 1
+        {
+            flags.remove(PageFlags::WRITE);
+        }
+        flags
+    }
+
+    /// Add a new grant to the address space. Physical grants are mapped right away, while the
+    /// pages of anonymous grants are only allocated when they are first accessed (see
+    /// [`AddressSpace::handle_fault`]). Nothing is left mapped if this fails.
+    fn insert(&mut self, grant: Grant) -> Result<usize, MemoryError> {
+        let eager = match grant.kind {
+            GrantKind::Physical { .. } => true,
+            GrantKind::Anonymous => false,
+        };
+
+        for address in grant.pages().filter(|_| eager) {
+            if let Err(error) = unsafe { self.map_page(&grant, address) } {
+                let mapped = Grant {
+                    size: address - grant.start,
+                    ..grant
+                };
+                // Physical grants have no frames of their own.
+                let _ = unsafe { self.unmap_pages(&mapped) };
+                return Err(error);
+            }
+        }
+
+        let start = grant.start;
+        self.grants.insert(start, grant);
+        Ok(start)
+    }
+
+    /// Map zero-initialized memory at the given address (or anywhere, if no address is given),
+    /// and return the address it was mapped at. No memory is allocated until the pages are used.
+    pub fn map_anonymous(
+        &mut self,
+        address: Option<usize>,
+        size: usize,
+        flags: PageFlags,
+        owner: Option<ContextId>,
+    ) -> Result<usize, MemoryError> {
+        let start = self.place(address, size)?;
+
+        self.insert(Grant {
+            start,
+            size,
+            flags,
+            kind: GrantKind::Anonymous,
+            owner,
+        })
+    }
+
+    /// Map a range of physical memory at the given address (or anywhere, if no address is given),
+    /// and return the address it was mapped at. This is used to give drivers access to device
+    /// memory.
+    ///
+    /// # Safety
+    /// The physical memory must not be in use by the kernel.
+    pub unsafe fn map_physical(
+        &mut self,
+        address: Option<usize>,
+        phys: usize,
+        size: usize,
+        flags: PageFlags,
+        owner: Option<ContextId>,
+    ) -> Result<usize, MemoryError> {
+        if phys % PAGE_SIZE != 0 {
+            return Err(MemoryError::InvalidRange);
+        }
+
+        let start = self.place(address, size)?;
+
+        self.insert(Grant {
+            start,
+            size,
+            flags,
+            kind: GrantKind::Physical { base: phys },
+            owner,
+        })
+    }
+
+    /// Remove the parts of all grants that overlap the given range from the address space and
+    /// return them. The parts of the grants outside of the range are kept.
+    fn take_range(&mut self, start: usize, end: usize) -> Vec<Grant> {
+        let starts: Vec<usize> = self
+            .grants
+            .range(..end)
+            .rev()
+            .take_while(|(_, grant)| grant.end() > start)
+            .map(|(&start, _)| start)
+            .collect();
+
+        let mut taken = Vec::with_capacity(starts.len());
+        for grant_start in starts.into_iter().rev() {
+            let mut grant = self.grants.remove(&grant_start).unwrap();
+
+            if grant.start < start {
+                let (left, right) = grant.split_at(start);
+                self.grants.insert(left.start, left);
+                grant = right;
+            }
+
+            if grant.end() > end {
+                let (left, right) = grant.split_at(end);
+                self.grants.insert(right.start, right);
+                grant = left;
+            }
+
+            taken.push(grant);
+        }
+
+        taken
+    }
+
+    /// Unmap the given range. Grants that are only partly inside of the range are split.
+    pub fn unmap(&mut self, start: usize, size: usize) -> Result<(), MemoryError> {
+        let end = Self::check_range(start, size)?;
+
+        let taken = self.take_range(start, end);
+        if taken.is_empty() {
+            return Err(MemoryError::NotMapped);
+        }
+
+        let mut frames = Vec::new();
+        for grant in taken {
+            frames.append(&mut unsafe { self.unmap_pages(&grant) });
+        }
+
+        tlb::shootdown();
+        unsafe { Self::release_frames(frames) };
+        Ok(())
+    }
+
+    /// Change the permissions of the given range. Grants that are only partly inside of the range
+    /// are split. If a page cannot be changed, the range keeps its previous permissions.
+    pub fn protect(
+        &mut self,
+        start: usize,
+        size: usize,
+        flags: PageFlags,
+    ) -> Result<(), MemoryError> {
+        let end = Self::check_range(start, size)?;
+
+        let taken = self.take_range(start, end);
+        if taken.is_empty() {
+            return Err(MemoryError::NotMapped);
+        }
+
+        let protected: Vec<Grant> = taken
+            .iter()
+            .map(|grant| Grant {
+                flags,
+                ..grant.clone()
+            })
+            .collect();
+
+        let result = protected
+            .iter()
+            .try_for_each(|grant| unsafe { self.protect_pages(grant) });
+
+        // The pages that were already changed get their previous permissions back. Changing the
+        // permissions of a page that is mapped does not allocate, so this only fails if the page
+        // tables are broken.
+        let grants = match result {
+            Ok(()) => protected,
+            Err(_) => {
+                for grant in &taken {
+                    let _ = unsafe { self.protect_pages(grant) };
+                }
+                taken
+            }
+        };
+
+        for grant in grants {
+            self.grants.insert(grant.start, grant);
+        }
+
+        tlb::shootdown();
+        result
+    }
+
+    /// Give the mapped pages of a grant the permissions of the grant. Pages that are not mapped
+    /// pick up the permissions when they are mapped.
+    unsafe fn protect_pages(&mut self, grant: &Grant) -> Result<(), MemoryError> {
+        for address in grant.pages() {
+            if let Some(translation) = self.mapper.translate(address) {
+                let flags = Self::page_flags(grant, translation.phys);
+                self.mapper.protect(address, flags)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Create a copy of the address space. Anonymous memory is copied into new frames, while
+    /// physical grants map the same physical memory.
+    pub fn try_clone(&mut self) -> Result<Self, MemoryError> {
+        #[cfg(feature = "swap")]
+        self.cancel_evictions();
+
+        let mut clone = Self::new()?;
+
+        for grant in self.grants.values() {
+            // The grant is added first, so that dropping the clone on failure unmaps the pages
+            // that were already copied.
+            clone.grants.insert(grant.start, grant.clone());
+
+            for address in grant.pages() {
+                let translation = match self.mapper.translate(address) {
+                    Some(translation) => translation,
+                    None => continue,
+                };
+
+                unsafe {
+                    clone.map_page(grant, address)?;
+
+                    if grant.kind == GrantKind::Anonymous {
+                        let copy = clone.mapper.translate(address).unwrap().phys;
+                        ptr::copy_nonoverlapping(
+                            memory::phys_to_virt(translation.phys) as *const u8,
+                            memory::phys_to_virt(copy) as *mut u8,
+                            PAGE_SIZE,
+                        );
+                    }
+                }
+            }
+        }
+
+        // Swapped out pages are never written to in swap, so the slots can be shared. Each side
+        // reads its own copy back in when it first accesses the page.
+        #[cfg(feature = "swap")]
+        clone.share_swapped(&self.swapped)?;
+
+        Ok(clone)
+    }
+
+    /// Create a copy-on-write copy of the address space, as done by `fork`. Anonymous pages are not
+    /// copied; instead, both address spaces map the same frames read-only, and a page is only
+    /// copied once either side writes to it (see [`AddressSpace::copy_on_write`]). Physical grants
+    /// map the same physical memory.
+    pub fn fork(&mut self) -> Result<Self, MemoryError> {
+        #[cfg(feature = "swap")]
+        self.cancel_evictions();
+
+        let mut child = Self::new()?;
+
+        // Dropping the child on failure unmaps what was shared with it, but the pages that became
+        // read-only here stay so until they are written to.
+        let result = self.share_with(&mut child);
+        tlb::shootdown();
+        result?;
+
+        #[cfg(feature = "swap")]
+        child.share_swapped(&self.swapped)?;
+
+        Ok(child)
+    }
+
+    /// Map the pages of every grant in another address space, sharing anonymous pages
+    /// copy-on-write. Used by [`AddressSpace::fork`].
+    fn share_with(&mut self, child: &mut Self) -> Result<(), MemoryError> {
+        for grant in self.grants.values() {
+            child.grants.insert(grant.start, grant.clone());
+
+            for address in grant.pages() {
+                let translation = match self.mapper.translate(address) {
+                    Some(translation) => translation,
+                    None => continue,
+                };
+
+                if grant.kind != GrantKind::Anonymous {
+                    unsafe { child.map_page(grant, address)? };
+                    continue;
+                }
+
+                let frame = Frame::containing_address(translation.phys);
+                memory::share_frame(frame);
+
+                let flags = Self::page_flags(grant, translation.phys);
+                unsafe {
+                    if let Err(error) =
+                        child
+                            .mapper
+                            .map(address, translation.phys, PageSize::Small, flags)
+                    {
+                        memory::release_frame(frame);
+                        return Err(error.into());
+                    }
+
+                    self.mapper.protect(address, flags)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Take a reference to the swap slots of another address space.
+    #[cfg(feature = "swap")]
+    fn share_swapped(&mut self, swapped: &BTreeMap<usize, SwapSlot>) -> Result<(), MemoryError> {
+        for (&address, &slot) in swapped {
+            swap::share(slot)?;
+            self.swapped.insert(address, slot);
+        }
+
+        Ok(())
+    }
+
+    /// Resolve a write to a page that is read-only because it is shared with another address
+    /// space. If no other address space still uses the frame, the page is simply made writable
+    /// again; otherwise the page is copied into a new frame.
+    ///
+    /// Fails with [`MemoryError::NotMapped`] if no grant contains the address and with
+    /// [`MemoryError::AccessDenied`] if the grant does not allow writes.
+    pub fn copy_on_write(&mut self, address: usize) -> Result<(), MemoryError> {
+        let page = address & !(PAGE_SIZE - 1);
+        let grant = self.grant_at(page).ok_or(MemoryError::NotMapped)?;
+        if !grant.flags.contains(PageFlags::WRITE) || grant.kind != GrantKind::Anonymous {
+            return Err(MemoryError::AccessDenied);
+        }
+
+        let flags = grant.flags | PageFlags::USER;
+        let translation = self.mapper.translate(page).ok_or(MemoryError::NotMapped)?;
+        let frame = Frame::containing_address(translation.phys);
+
+        unsafe {
+            if memory::frame_refcount(frame) == 1 {
+                self.mapper.protect(page, flags)?;
+                return Ok(());
+            }
+
+            let copy = allocate_frame()?;
+            ptr::copy_nonoverlapping(
+                frame.virt_address() as *const u8,
+                copy.virt_address() as *mut u8,
+                PAGE_SIZE,
+            );
+
+            // Unmapping may free the page's table, so mapping the copy can still run out of memory.
+            // The page is lost in that case, and the faulting context has to be killed anyway.
+            // Other CPUs running the address space must stop reading the shared frame before
+            // writes go to the copy.
+            self.mapper.unmap(page)?;
+            tlb::shootdown();
+            memory::release_frame(frame);
+
+            if let Err(error) = self
+                .mapper
+                .map(page, copy.start_address(), PageSize::Small, flags)
+            {
+                memory::deallocate_frame(copy);
+                return Err(error.into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolve a page fault at the given address. Pages of anonymous grants that were never
+    /// accessed are allocated and zeroed, and writes to pages shared by [`AddressSpace::fork`] are
+    /// given a private copy.
+    ///
+    /// Fails with [`MemoryError::NotMapped`] if no grant contains the address and with
+    /// [`MemoryError::AccessDenied`] if the grant does not allow the access. Pages that are in swap
+    /// are left to the caller, with [`MemoryError::SwappedOut`].
+    pub fn handle_fault(&mut self, address: usize, access: AccessKind) -> Result<(), MemoryError> {
+        let page = address & !(PAGE_SIZE - 1);
+        let grant = self.grant_at(page).ok_or(MemoryError::NotMapped)?.clone();
+
+        let required = match access {
+            AccessKind::Read => PageFlags::READ,
+            AccessKind::Write => PageFlags::WRITE,
+            AccessKind::Execute => PageFlags::EXECUTE,
+        };
+        if !grant.flags.contains(required) {
+            return Err(MemoryError::AccessDenied);
+        }
+
+        #[cfg(feature = "swap")]
+        if let Some(frame) = self.evicting.remove(&page) {
+            return self.restore_evicted(page, frame);
+        }
+
+        #[cfg(feature = "swap")]
+        if let Some(&slot) = self.swapped.get(&page) {
+            swap::share(slot)?;
+            return Err(MemoryError::SwappedOut(slot));
+        }
+
+        match self.mapper.translate(page) {
+            None => unsafe { self.map_page(&grant, page) },
+            Some(translation)
+                if access == AccessKind::Write && !translation.flags.contains(PageFlags::WRITE) =>
+            {
+                self.copy_on_write(page)
+            }
+            // Either another thread sharing the address space resolved the fault first, or the
+            // accessed bit was cleared by a scan for pages to swap out (which the CPU does not set
+            // again by itself on RISC-V). Rewriting the entry takes care of both.
+            Some(translation) => unsafe {
+                let flags = Self::page_flags(&grant, translation.phys);
+                self.mapper.protect(page, flags)?;
+                Ok(())
+            },
+        }
+    }
+
+    /// Pick up to `count` anonymous pages that were not accessed since the last scan to be swapped
+    /// out, and unmap them. Pages are scanned in address order, resuming where the last scan
+    /// stopped, and their accessed bits are cleared along the way, so a page is only picked if it
+    /// stayed unused for a full round. Frames that are shared with a forked address space are
+    /// skipped, and so are pinned address spaces.
+    ///
+    /// The pages are returned with their frames, which the caller writes to swap without the
+    /// address space locked and then hands back to [`AddressSpace::finish_eviction`].
+    #[cfg(feature = "swap")]
+    fn evict(&mut self, count: usize) -> Vec<(usize, Frame)> {
+        let mut picked = Vec::new();
+        if self.pinned || !swap::is_enabled() {
+            return picked;
+        }
+
+        let clock = self.clock;
+        let grants = &self.grants;
+        let pages = || {
+            grants
+                .values()
+                .filter(|grant| grant.kind == GrantKind::Anonymous)
+                .flat_map(|grant| grant.pages())
+        };
+        let round = || {
+            pages()
+                .filter(|&address| address >= clock)
+                .chain(pages().filter(|&address| address < clock))
+        };
+
+        let stats = swap::stats();
+        let free = stats.total - stats.used;
+
+        // The first round may only clear accessed bits, so the pages are scanned twice.
+        for address in round().chain(round()) {
+            if picked.len() == count.min(free) {
+                break;
+            }
+            self.clock = address + PAGE_SIZE;
+
+            let translation = match self.mapper.translate(address) {
+                Some(translation) => translation,
+                None => continue,
+            };
+            let frame = Frame::containing_address(translation.phys);
+            if memory::frame_refcount(frame) > 1 {
+                continue;
+            }
+
+            match unsafe { self.mapper.clear_accessed(address) } {
+                Ok(false) => (),
+                _ => continue,
+            }
+
+            // The page is unmapped before it is written, so that other threads sharing the address
+            // space cannot change it behind our back.
+            if unsafe { self.mapper.unmap(address) }.is_err() {
+                continue;
+            }
+            self.evicting.insert(address, frame);
+            picked.push((address, frame));
+        }
+
+        // Other CPUs may still write to the pages through their TLBs until this returns.
+        if !picked.is_empty() {
+            tlb::shootdown();
+        }
+
+        picked
+    }
+
+    /// Finish swapping out a page picked by [`AddressSpace::evict`], once the result of writing it
+    /// to swap is known, and return whether the page is now in swap. Pages that were faulted in
+    /// or unmapped in the meantime drop their slot, and the frames of unmapped pages are freed.
+    /// Pages that could not be written are mapped again.
+    #[cfg(feature = "swap")]
+    fn finish_eviction(
+        &mut self,
+        address: usize,
+        frame: Frame,
+        result: Result<SwapSlot, SwapError>,
+    ) -> bool {
+        if self.evicting.get(&address) != Some(&frame) {
+            if let Ok(slot) = result {
+                swap::release(slot);
+            }
+            if self.discarded.remove(&frame) {
+                unsafe { memory::release_frame(frame) };
+            }
+            return false;
+        }
+        self.evicting.remove(&address);
+
+        match result {
+            Ok(slot) => {
+                self.swapped.insert(address, slot);
+                unsafe { memory::release_frame(frame) };
+                true
+            }
+            Err(error) => {
+                log::warn!("Unable to swap out page {:#x}: {:?}", address, error);
+                let _ = self.restore_evicted(address, frame);
+                false
+            }
+        }
+    }
+
+    /// Map a page that was picked by [`AddressSpace::evict`] again, with its frame. Unmapping may
+    /// have freed the page's table, in which case mapping the page again can fail. The page is
+    /// lost then, like in `copy_on_write`.
+    #[cfg(feature = "swap")]
+    fn restore_evicted(&mut self, address: usize, frame: Frame) -> Result<(), MemoryError> {
+        let grant = self.grant_at(address).ok_or(MemoryError::NotMapped)?;
+        let flags = grant.flags | PageFlags::USER;
+
+        unsafe {
+            let result = self
+                .mapper
+                .map(address, frame.start_address(), PageSize::Small, flags);
+            if result.is_err() {
+                memory::release_frame(frame);
+            }
+            result.map_err(MemoryError::from)
+        }
+    }
+
+    /// Map every page that is being written to swap again.
+    #[cfg(feature = "swap")]
+    fn cancel_evictions(&mut self) {
+        for (address, frame) in core::mem::take(&mut self.evicting) {
+            let _ = self.restore_evicted(address, frame);
+        }
+    }
+
+    /// Map a page that [`swap_in`] read back from swap into a frame, unless the page was mapped or
+    /// unmapped while it was read, in which case the frame is freed.
+    #[cfg(feature = "swap")]
+    fn finish_swap_in(
+        &mut self,
+        address: usize,
+        slot: SwapSlot,
+        frame: Frame,
+    ) -> Result<(), MemoryError> {
+        let flags = match self.grant_at(address) {
+            Some(grant) if self.swapped.get(&address) == Some(&slot) => {
+                grant.flags | PageFlags::USER
+            }
+            _ => {
+                unsafe { memory::deallocate_frame(frame) };
+                return Ok(());
+            }
+        };
+
+        unsafe {
+            if let Err(error) =
+                self.mapper
+                    .map(address, frame.start_address(), PageSize::Small, flags)
+            {
+                memory::deallocate_frame(frame);
+                return Err(error.into());
+            }
+        }
+
+        // The page is back in memory, so its copy in swap is no longer needed.
+        self.swapped.remove(&address);
+        swap::release(slot);
+        Ok(())
+    }
+
+    /// Keep every page of the address space in memory from now on. Pages that are being written
+    /// to swap are mapped again, but the ones that are already in swap are left to [`pin`].
+    #[cfg(feature = "swap")]
+    fn pin(&mut self) {
+        self.pinned = true;
+        self.cancel_evictions();
+    }
+
+    /// Allow the pages of the address space to be swapped out again.
+    #[cfg(feature = "swap")]
+    pub fn unpin(&mut self) {
+        self.pinned = false;
+    }
+
+    /// Whether the pages of the address space are kept in memory.
+    #[cfg(feature = "swap")]
+    pub fn is_pinned(&self) -> bool {
+        self.pinned
+    }
+
+    /// Make the CPU use this address space.
+    ///
+    /// # Safety
+    /// Nothing may still be using the previous address space's user mappings.
+    pub unsafe fn activate(&self) {
+        self.mapper.activate();
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        let grants = core::mem::take(&mut self.grants);
+        // The address space is no longer in use on any CPU, so no TLB holds its pages.
+        for grant in grants.values() {
+            unsafe { Self::release_frames(self.unmap_pages(grant)) };
+        }
+
+        // Every intermediate table was freed as it became empty, so only the root is left.
+        unsafe { memory::deallocate_frame(self.mapper.root()) };
+    }
 }
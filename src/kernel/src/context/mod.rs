@@ -0,0 +1,108 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+
+use crate::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+pub use self::context::*;
+pub use self::cpu_set::{CpuSet, MAX_CPUS};
+pub use self::list::ContextList;
+pub use self::memory::AddressSpace;
+pub(crate) use self::percpu::set_context_id;
+pub use self::percpu::{context_id, cpu_id};
+pub use self::scheduler::{Priority, SchedClass};
+pub use self::sleep_queue::{SleepQueue, WaitError, WakeupPolicy};
+pub use self::switch::switch;
+pub use self::thread::ThreadGroup;
+
+pub mod context;
+pub mod cpu_set;
+pub mod deadline;
+pub mod list;
+pub mod memory;
+pub mod percpu;
+pub mod process;
+pub mod scheduler;
+pub mod signal;
+pub mod sleep_queue;
+pub mod switch;
+pub mod thread;
+pub mod trace;
+pub mod worker;
+
+/// Unique identifier of a context.
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ContextId(usize);
+
+impl ContextId {
+    /// Construct a new [`ContextId`].
+    pub const fn new(id: usize) -> Self {
+        Self(id)
+    }
+
+    /// Retrieve the numerical value of the identifier.
+    pub const fn get(&self) -> usize {
+        self.0
+    }
+}
+
+/// Every context in the system.
+static CONTEXTS: RwLock<ContextList> = RwLock::new(ContextList::new());
+
+/// Lock the context list for reading.
+pub fn contexts() -> RwLockReadGuard<'static, ContextList> {
+    CONTEXTS.read()
+}
+
+/// Lock the context list for reading, unless it is locked for writing. Used by code that runs in
+/// interrupt handlers, where the interrupted code may hold the lock.
+pub(crate) fn try_contexts() -> Option<RwLockReadGuard<'static, ContextList>> {
+    CONTEXTS.try_read()
+}
+
+/// Lock the context list for writing.
+pub fn contexts_mut() -> RwLockWriteGuard<'static, ContextList> {
+    CONTEXTS.write()
+}
+
+/// Retrieve the context that is currently running.
+pub fn current() -> Option<Arc<RwLock<Context>>> {
+    contexts().current().cloned()
+}
+
+/// Retrieve the number of CPUs that contexts are scheduled on.
+pub fn cpu_count() -> usize {
+    scheduler::cpu_count()
+}
+
+/// Set up scheduling on the given number of CPUs, turn the code that is running on the boot CPU
+/// into its idle context, and start the kernel's workers. Called once, by the boot CPU, after
+/// [`percpu::init`].
+pub fn init(cpus: usize) {
+    #[cfg(feature = "lockdep")]
+    crate::sync::lockdep::enable();
+
+    scheduler::init(cpus);
+    init_cpu();
+    worker::init();
+}
+
+/// Turn the code that is running on this CPU into the CPU's idle context, so that other contexts
+/// can be switched to. The idle context only runs when nothing else can. Called by every other CPU
+/// once it is started and called [`percpu::init`], after the boot CPU called [`init`].
+pub fn init_cpu() {
+    let cpu = cpu_id();
+    let mut contexts = contexts_mut();
+    let mut context = Context::new(contexts.next_id());
+
+    context.priority = Priority::IDLE;
+    context.cpu = Some(cpu);
+    context.affinity = CpuSet::single(cpu);
+    context.status = Status::Runnable;
+    context.running = true;
+    *context.name.write() = Box::from("idle");
+
+    let id = context.id;
+    contexts.insert(context);
+    set_context_id(id);
+    scheduler::init_cpu(cpu, id);
+}
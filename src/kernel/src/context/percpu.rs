@@ -0,0 +1,166 @@
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::context::{ContextId, MAX_CPUS};
+use crate::machine::{self, irq};
+
+/// Data that belongs to a CPU, which the CPU reaches through a register rather than by looking up
+/// its identifier: the GS base on x86_64, and `tp` on RISC-V (see [`machine::percpu`]). It holds
+/// what is needed the most often, and other per-CPU data is declared with [`percpu!`], indexed by
+/// the identifier of the CPU.
+#[repr(C)]
+pub struct Area {
+    /// State of the machine's entry code, which has to come first.
+    machine: machine::percpu::Area,
+    /// Identifier of the CPU.
+    cpu: AtomicUsize,
+    /// Identifier of the context that the CPU is running. Zero when no context is running yet.
+    context: AtomicUsize,
+    /// How many times preemption was disabled on the CPU (see [`preempt_disable`]).
+    preempt: AtomicUsize,
+}
+
+/// Data of which every CPU has its own copy, declared with [`percpu!`].
+pub struct PerCpu<T> {
+    values: [T; MAX_CPUS],
+}
+
+/// Declare a static that every CPU has its own copy of, as a [`PerCpu`]. The initial value has to
+/// be a constant expression.
+macro_rules! percpu {
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr;) => {
+        $(#[$attr])*
+        $vis static $name: $crate::context::percpu::PerCpu<$ty> = {
+            #[allow(clippy::declare_interior_mutable_const)]
+            const INIT: $ty = $init;
+            $crate::context::percpu::PerCpu::new([INIT; $crate::context::MAX_CPUS])
+        };
+    };
+}
+
+pub(crate) use percpu;
+
+/// Whether the boot CPU called [`init`]. Nothing but the boot CPU runs before, so it uses the first
+/// area until then.
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Areas of the CPUs, indexed by CPU.
+static AREAS: [Area; MAX_CPUS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NONE: Area = Area::new();
+    [NONE; MAX_CPUS]
+};
+
+impl Area {
+    /// Construct the area of a CPU that is not started yet.
+    const fn new() -> Self {
+        Self {
+            machine: machine::percpu::Area::new(),
+            cpu: AtomicUsize::new(0),
+            context: AtomicUsize::new(0),
+            preempt: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl<T> PerCpu<T> {
+    /// Construct per-CPU data from the copies of the CPUs, indexed by CPU.
+    pub const fn new(values: [T; MAX_CPUS]) -> Self {
+        Self { values }
+    }
+
+    /// Retrieve the copy of the running CPU. The running context may be moved to another CPU
+    /// right after, unless interrupts are disabled, so it may end up using the copy of another CPU.
+    pub fn get(&self) -> &T {
+        &self.values[cpu_id()]
+    }
+
+    /// Retrieve the copy of the given CPU.
+    pub fn of(&self, cpu: usize) -> &T {
+        &self.values[cpu]
+    }
+}
+
+/// Point this CPU to its area. Must be called by every CPU as soon as it knows its identifier,
+/// since nothing can tell which CPU is running before.
+///
+/// # Safety
+/// Must be called once per CPU, with an identifier that no other CPU has, and before anything can
+/// interrupt the CPU.
+pub unsafe fn init(cpu: usize) {
+    assert!(cpu < MAX_CPUS, "Unsupported CPU identifier: {}", cpu);
+
+    let area = &AREAS[cpu];
+    area.cpu.store(cpu, Ordering::Relaxed);
+    machine::percpu::init(&area.machine);
+
+    // Preemption may have been disabled while the first area stood in for this one.
+    if !READY.swap(true, Ordering::Relaxed) {
+        area.preempt.store(
+            AREAS[0].preempt.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+    }
+}
+
+/// Retrieve the area of the running CPU.
+fn current() -> &'static Area {
+    if !READY.load(Ordering::Relaxed) {
+        return &AREAS[0];
+    }
+
+    // The machine's part comes first, so it has the same address as the area.
+    unsafe { &*(machine::percpu::base() as *const Area) }
+}
+
+/// Retrieve the identifier of the CPU that is running.
+pub fn cpu_id() -> usize {
+    current().cpu.load(Ordering::Relaxed)
+}
+
+/// Retrieve the identifier of the context that is running on this CPU.
+pub fn context_id() -> ContextId {
+    ContextId::new(current().context.load(Ordering::SeqCst))
+}
+
+/// Set the identifier of the context that is running on this CPU. Called when switching contexts.
+pub(crate) fn set_context_id(id: ContextId) {
+    current().context.store(id.get(), Ordering::SeqCst);
+}
+
+/// Forbid preempting the running context until [`preempt_enable`] was called as many times. Spin
+/// locks do it while they are held: a context that spins for a lock held by a preempted one would
+/// spin until that one runs again. Interrupts are still taken, but the pending switch waits for
+/// the next interrupt or system call once preemption is enabled again.
+pub fn preempt_disable() {
+    // The context must not move to another CPU between finding the area and counting in it.
+    let enabled = irq::enabled();
+    unsafe { irq::disable() };
+    current().preempt.fetch_add(1, Ordering::Relaxed);
+    if enabled {
+        unsafe { irq::enable() };
+    }
+}
+
+/// Undo a call to [`preempt_disable`].
+pub fn preempt_enable() {
+    // The context cannot have moved since it disabled preemption.
+    let previous = current().preempt.fetch_sub(1, Ordering::Relaxed);
+    debug_assert!(previous > 0, "Preemption enabled more often than disabled");
+}
+
+/// Whether the running context may be preempted.
+pub fn preemptible() -> bool {
+    current().preempt.load(Ordering::Relaxed) == 0
+}
+
+/// Retrieve how many times the running context disabled preemption, and reset the count of the
+/// CPU for the context that is switched to. Called when switching contexts, with interrupts
+/// disabled.
+pub(crate) fn take_preempt_count() -> usize {
+    current().preempt.swap(0, Ordering::Relaxed)
+}
+
+/// Restore the count that [`take_preempt_count`] retrieved, once the context was switched back to.
+pub(crate) fn restore_preempt_count(count: usize) {
+    current().preempt.store(count, Ordering::Relaxed);
+}
@@ -0,0 +1,312 @@
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use core::mem;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::context::list::KERNEL_STACK_SIZE;
+use crate::context::memory::{MemoryError, USER_START};
+use crate::context::signal::{self, SIGCHLD};
+use crate::context::worker::SYSTEM;
+use crate::context::{self, scheduler, switch, thread, Context, ContextId, ContextList, Status};
+use crate::context::{SleepQueue, WaitError, WakeupPolicy};
+use crate::machine::interrupt::{self, InterruptStack};
+use crate::machine::irq;
+use crate::machine::paging;
+use crate::sync::RwLock;
+use crate::syscall::sync::destroy_objects;
+
+/// Identifier of the init process, which adopts the children of contexts that exit. Zero until
+/// it is set with [`set_init`].
+static INIT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Contexts that wait for a child to exit, which are all woken up whenever a process ends and
+/// whenever an exited context switches away for the last time.
+static WAITERS: SleepQueue = SleepQueue::new(WakeupPolicy::All);
+
+/// Children that [`wait`] waits for.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WaitTarget {
+    /// A child with the given identifier.
+    Child(ContextId),
+    /// Any child in the given process group.
+    Group(ContextId),
+    /// Any child.
+    Any,
+}
+
+/// Errors that can occur when waiting for a child.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WaitPidError {
+    /// The calling context has no child that matches the target.
+    NoChild,
+    /// A signal was sent to the calling context while it waited.
+    Interrupted,
+}
+
+/// Encode the exit code of a context into a wait status (see [`Status::Exited`]).
+pub const fn exit_status(code: usize) -> usize {
+    (code & 0xFF) << 8
+}
+
+/// Make a context the init process, which adopts orphaned contexts.
+pub fn set_init(id: ContextId) {
+    INIT_ID.store(id.get(), Ordering::SeqCst);
+}
+
+/// Retrieve the identifier of the init process (zero if there is none).
+pub fn init_id() -> ContextId {
+    ContextId::new(INIT_ID.load(Ordering::SeqCst))
+}
+
+/// Wake up the contexts waiting for a child, once an exited context switched away for the last
+/// time and can be collected. Called by the switch code, which cannot wait for locks.
+pub(super) fn switched_away() {
+    WAITERS.wake_up_from_interrupt();
+}
+
+/// Retrieve where the user registers of a context are saved at the top of its kernel stack.
+pub(super) fn user_registers(kernel_stack: &[u8]) -> *mut InterruptStack {
+    let top = (kernel_stack.as_ptr() as usize + kernel_stack.len()) & !0xF;
+    (top - mem::size_of::<InterruptStack>()) as *mut InterruptStack
+}
+
+/// Entry point of contexts created by [`spawn`] and [`thread::spawn`], which start in user-space
+/// with the registers at the top of their kernel stack.
+pub(super) extern "C" fn user_entry() {
+    let registers = {
+        let context = context::current().expect("User context without a context");
+        let context = context.read();
+        user_registers(
+            context
+                .kernel_stack
+                .as_ref()
+                .expect("User context without a stack"),
+        )
+    };
+
+    unsafe {
+        irq::disable();
+        interrupt::return_to_user(registers);
+    }
+}
+
+/// Create a child of the current process that is a copy of it, like `fork`. The child gets a
+/// copy-on-write copy of the address space, a copy of the file table and of the signal actions,
+/// and resumes user-space with the given registers, with the system call returning zero. Only the
+/// current thread is copied.
+pub fn spawn(registers: &InterruptStack) -> Result<ContextId, MemoryError> {
+    let current = context::current().expect("Spawning outside of a context");
+
+    // The parent is not locked while the child is added to the list, since contexts are locked
+    // after the list elsewhere.
+    let id = context::contexts_mut().next_id();
+    let (mut child, tls) = {
+        let parent = current.read();
+        let addr_space = match parent.addr_space() {
+            Some(addr_space) => addr_space.write().fork()?,
+            None => return Err(MemoryError::NotMapped),
+        };
+
+        let mut child = Context::new(id);
+        child.group_id = parent.group_id;
+        child.parent_id = parent.thread_group.leader();
+        child.real_user_id = parent.real_user_id;
+        child.real_group_id = parent.real_group_id;
+        child.effective_user_id = parent.effective_user_id;
+        child.effective_group_id = parent.effective_group_id;
+        child.signal_mask = parent.signal_mask;
+        child.priority = parent.priority;
+        child.affinity = parent.affinity;
+        child.addr_space = Some(Arc::new(RwLock::new(addr_space)));
+        child.name = Arc::new(RwLock::new(parent.name.read().clone()));
+        child.current_dir = parent.current_dir.clone();
+        child.files = Arc::new(RwLock::new(parent.files.read().clone()));
+        child.signal_actions = Arc::new(RwLock::new(parent.signal_actions.read().clone()));
+        (child, parent.machine.tls(registers))
+    };
+
+    let stack = vec![0; KERNEL_STACK_SIZE].into_boxed_slice();
+    let frame = user_registers(&stack);
+    unsafe {
+        frame.write(registers.clone());
+        (*frame).set_syscall_result(0);
+        child.machine.set_tls(&mut *frame, tls);
+        child.machine.set_entry(frame as usize, user_entry);
+    }
+    child.kernel_stack = Some(stack);
+
+    let child = context::contexts_mut().insert(child);
+    scheduler::wake(&mut child.write());
+    Ok(id)
+}
+
+/// End the current process with the given wait status (see [`Status::Exited`]): every one of its
+/// threads is killed, its files are closed, its memory and synchronization objects are released,
+/// its children are given to the init process, and its parent is told with `SIGCHLD`. The leader of
+/// the process stays in the list as a zombie until the parent collects its status with [`wait`].
+pub fn exit(status: usize) -> ! {
+    terminate(status, true)
+}
+
+/// Terminate the current thread with the given wait status, and the whole process if `group` is
+/// set. The process ends once its last thread exits.
+pub(super) fn terminate(status: usize, group: bool) -> ! {
+    if let Some(current) = context::current() {
+        let (id, thread_group) = {
+            let context = current.read();
+            (context.id, Arc::clone(&context.thread_group))
+        };
+        let leader = thread_group.leader();
+
+        if group {
+            thread_group.end(status);
+            thread::kill_group(&thread_group, id);
+        }
+        let last = thread_group.leave();
+        if last {
+            thread_group.end(status);
+        }
+
+        {
+            let mut context = current.write();
+            scheduler::clear_deadline(&mut context);
+
+            if last {
+                // Dropping the last reference to a file descriptor closes it.
+                context.files = Arc::new(RwLock::new(Vec::new()));
+
+                // The page table stays until the leader is reaped, since it is still active.
+                if let Some(addr_space) = context.addr_space() {
+                    let _ = addr_space
+                        .write()
+                        .unmap(USER_START, paging::user_end() - USER_START);
+                }
+            }
+
+            context.status = Status::Exited(thread_group.status().unwrap_or(status));
+        }
+
+        let contexts = context::contexts();
+        let parent = contexts.get(leader).map(|leader| leader.read().parent_id);
+        if last {
+            reparent_children(&contexts, leader);
+        }
+        drop(contexts);
+
+        if last {
+            destroy_objects(leader);
+            if let Some(parent) = parent {
+                signal::notify_parent(parent, false);
+            }
+
+            WAITERS.wake_up();
+        }
+
+        // Nothing waits for threads other than the leader, nor for contexts without a parent.
+        if id != leader || parent.map_or(true, |parent| parent == ContextId::default()) {
+            SYSTEM.queue(move || thread::reap(id));
+        }
+    }
+
+    loop {
+        unsafe { switch() };
+    }
+}
+
+/// Give the children of a context that exits to the init process. Children that already exited
+/// are reported to it, so that it collects them.
+fn reparent_children(contexts: &ContextList, id: ContextId) {
+    let init = init_id();
+    let mut zombies = false;
+
+    for (_, context) in contexts.iter() {
+        let mut context = context.write();
+        if context.parent_id == id {
+            context.parent_id = init;
+            zombies |= matches!(context.status, Status::Exited(_));
+        }
+    }
+
+    if zombies {
+        signal::send_to(init, SIGCHLD);
+    }
+}
+
+/// Whether a context is the leader of a child process of `parent` that matches a wait target.
+fn matches(context: &Context, parent: ContextId, target: WaitTarget) -> bool {
+    context.parent_id == parent
+        && context.thread_group.leader() == context.id
+        && match target {
+            WaitTarget::Child(id) => context.id == id,
+            WaitTarget::Group(group) => context.group_id == group,
+            WaitTarget::Any => true,
+        }
+}
+
+/// Wait for a child process of the current process that matches `target` to end, and collect it:
+/// retrieve its identifier and wait status, and remove its leader from the list. If `nohang` is
+/// set, `None` is returned right away if no such child ended yet.
+pub fn wait(target: WaitTarget, nohang: bool) -> Result<Option<(ContextId, usize)>, WaitPidError> {
+    let current = context::current().expect("Waiting outside of a context");
+    let id = current.read().thread_group.leader();
+
+    if nohang {
+        return collect(id, target).transpose();
+    }
+
+    let ready = || !matches!(find_exited(id, target), Children::Running);
+    match WAITERS.wait_until(None, || collect(id, target), ready) {
+        Ok(result) => result.map(Some),
+        Err(WaitError::Interrupted) => Err(WaitPidError::Interrupted),
+        Err(WaitError::TimedOut) => unreachable!(),
+    }
+}
+
+/// What [`find_exited`] found among the children of a process.
+enum Children {
+    /// No child matches.
+    None,
+    /// No matching child can be collected yet.
+    Running,
+    /// The leader of a matching child that can be collected, with its wait status.
+    Exited(ContextId, usize),
+}
+
+/// Look for a child process of `parent` that matches `target` and can be collected: all its
+/// threads exited, and its leader switched away for the last time.
+fn find_exited(parent: ContextId, target: WaitTarget) -> Children {
+    let mut children = Children::None;
+    for (_, context) in context::contexts().iter() {
+        let context = context.read();
+        if !matches(&context, parent, target) {
+            continue;
+        }
+
+        children = Children::Running;
+        let group = &context.thread_group;
+        let running = context.running || context.switching;
+        if let (Status::Exited(status), 0, false) = (context.status, group.live(), running) {
+            return Children::Exited(context.id, group.status().unwrap_or(status));
+        }
+    }
+    children
+}
+
+/// Collect a child process of `parent` that matches `target`, if one can be. Returns `None` if the
+/// matching children are all still running.
+fn collect(
+    parent: ContextId,
+    target: WaitTarget,
+) -> Option<Result<(ContextId, usize), WaitPidError>> {
+    match find_exited(parent, target) {
+        Children::None => Some(Err(WaitPidError::NoChild)),
+        Children::Running => None,
+        Children::Exited(child, status) => {
+            // Another thread of the process may have collected it in between.
+            context::contexts_mut().remove(child)?;
+            Some(Ok((child, status)))
+        }
+    }
+}
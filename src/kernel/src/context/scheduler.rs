@@ -0,0 +1,1278 @@
+use alloc::collections::{BTreeSet, VecDeque};
+
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use core::{cmp, mem};
+
+use crate::context::deadline::{Admission, AdmissionError, DeadlineParams, EdfQueue, Reservation};
+use crate::context::percpu::percpu;
+use crate::context::{self, switch, Context, ContextId, ContextList, CpuSet, Status};
+use crate::machine::{self, irq};
+use crate::sync::IrqMutex;
+
+/// Number of priority levels.
+pub const PRIORITY_LEVELS: usize = 256;
+
+/// Frequency of the scheduler's timer, in hertz.
+pub const TICK_RATE: usize = 1000;
+
+/// Number of ticks that a context may run for before other contexts of the same priority get a
+/// turn.
+pub const TIME_SLICE: usize = 10;
+
+/// Number of ticks between two runs of the load balancer on a CPU. Idle CPUs run it at every tick.
+pub const BALANCE_INTERVAL: usize = 50;
+
+/// Highest number of wake-ups that interrupt handlers can defer to the next tick (see
+/// [`wake_from_interrupt`]).
+const MAX_DEFERRED_WAKEUPS: usize = 64;
+
+/// Scheduling priority of a context. A runnable context always preempts contexts of a lower
+/// priority, and contexts of the same priority take turns (round-robin).
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Priority(u8);
+
+impl Priority {
+    /// Priority of the idle contexts, which only run when nothing else can.
+    pub const IDLE: Self = Self(0);
+    /// Lowest priority that is given to contexts other than idle contexts.
+    pub const LOWEST: Self = Self(1);
+    /// Priority that contexts are created with.
+    pub const DEFAULT: Self = Self(128);
+    /// Highest priority.
+    pub const HIGHEST: Self = Self(u8::MAX);
+
+    /// Construct a new [`Priority`].
+    pub const fn new(level: u8) -> Self {
+        Self(level)
+    }
+
+    /// Retrieve the numerical level of the priority (higher is more urgent).
+    pub const fn get(&self) -> u8 {
+        self.0
+    }
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Runnable contexts, in one first-in first-out queue per priority level. A bitmap of the levels
+/// that are not empty lets the most urgent context be found in constant time.
+pub struct RunQueue {
+    /// Queues of the priority levels.
+    levels: [VecDeque<ContextId>; PRIORITY_LEVELS],
+    /// Bit `n` is set if level `n` is not empty.
+    bitmap: [u64; PRIORITY_LEVELS / 64],
+    /// Number of queued contexts.
+    len: usize,
+}
+
+impl RunQueue {
+    /// Construct an empty run queue.
+    pub const fn new() -> Self {
+        const EMPTY: VecDeque<ContextId> = VecDeque::new();
+
+        Self {
+            levels: [EMPTY; PRIORITY_LEVELS],
+            bitmap: [0; PRIORITY_LEVELS / 64],
+            len: 0,
+        }
+    }
+
+    /// Retrieve the number of queued contexts.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether no context is queued.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Update the bitmap after a level changed.
+    fn update(&mut self, priority: Priority) {
+        let level = priority.0 as usize;
+        let bit = 1 << (level % 64);
+
+        if self.levels[level].is_empty() {
+            self.bitmap[level / 64] &= !bit;
+        } else {
+            self.bitmap[level / 64] |= bit;
+        }
+    }
+
+    /// Queue a context behind the others of the same priority.
+    pub fn push_back(&mut self, id: ContextId, priority: Priority) {
+        self.levels[priority.0 as usize].push_back(id);
+        self.len += 1;
+        self.update(priority);
+    }
+
+    /// Queue a context in front of the others of the same priority. Used for contexts that were
+    /// preempted, so that they pick up where they left off.
+    pub fn push_front(&mut self, id: ContextId, priority: Priority) {
+        self.levels[priority.0 as usize].push_front(id);
+        self.len += 1;
+        self.update(priority);
+    }
+
+    /// Remove a context from the queue, returning whether it was queued.
+    pub fn remove(&mut self, id: ContextId, priority: Priority) -> bool {
+        let level = &mut self.levels[priority.0 as usize];
+        let index = match level.iter().position(|&queued| queued == id) {
+            Some(index) => index,
+            None => return false,
+        };
+
+        level.remove(index);
+        self.len -= 1;
+        self.update(priority);
+        true
+    }
+
+    /// Retrieve the highest priority that has a queued context.
+    pub fn highest(&self) -> Option<Priority> {
+        self.below(PRIORITY_LEVELS).next()
+    }
+
+    /// Iterate through the priorities that have queued contexts and are lower than `limit`, from
+    /// the highest to the lowest.
+    fn below(&self, limit: usize) -> impl Iterator<Item = Priority> + '_ {
+        let mut limit = limit;
+
+        core::iter::from_fn(move || {
+            while limit > 0 {
+                let word = (limit - 1) / 64;
+                // Keep the bits of the levels below the limit.
+                let mask = u64::MAX >> (63 - (limit - 1) % 64);
+                let bits = self.bitmap[word] & mask;
+
+                if bits != 0 {
+                    let level = word * 64 + 63 - bits.leading_zeros() as usize;
+                    limit = level;
+                    return Some(Priority(level as u8));
+                }
+                limit = word * 64;
+            }
+            None
+        })
+    }
+
+    /// Take the first context of the highest priority that `eligible` accepts out of the queue.
+    /// Contexts that are not eligible (because they are pinned to another CPU) stay queued.
+    pub fn take(
+        &mut self,
+        mut eligible: impl FnMut(ContextId) -> bool,
+    ) -> Option<(ContextId, Priority)> {
+        let mut found = None;
+
+        for priority in self.below(PRIORITY_LEVELS) {
+            let level = &self.levels[priority.0 as usize];
+            if let Some(index) = level.iter().position(|&id| eligible(id)) {
+                found = Some((index, priority));
+                break;
+            }
+        }
+
+        let (index, priority) = found?;
+        let id = self.levels[priority.0 as usize].remove(index)?;
+        self.len -= 1;
+        self.update(priority);
+        Some((id, priority))
+    }
+}
+
+/// Scheduling class of a context, which decides how it is picked to run.
+#[derive(Clone, Debug)]
+pub enum SchedClass {
+    /// The context runs by its priority, taking turns with the contexts of the same priority.
+    Fixed,
+    /// The context runs periodically within a CPU reservation, and is picked by earliest deadline
+    /// first (EDF). Contexts of this class are more urgent than every fixed-priority context.
+    Deadline(Reservation),
+}
+
+impl Default for SchedClass {
+    fn default() -> Self {
+        Self::Fixed
+    }
+}
+
+/// Errors that can occur when changing the affinity of a context.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AffinityError {
+    /// None of the CPUs of the set exist.
+    NoCpu,
+    /// The context is of the deadline class, and its CPU time is reserved on a CPU that is not in
+    /// the set.
+    Reserved,
+}
+
+/// How urgent a context is, which decides whether it preempts the running context. Contexts that
+/// hold mutexes inherit the urgency of the contexts that wait for them (see [`Context::boost`]).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Urgency {
+    /// A fixed-priority context with the given priority.
+    Fixed(Priority),
+    /// A context of the deadline class with the given server deadline.
+    Deadline(u64),
+}
+
+impl Urgency {
+    /// Retrieve the urgency of a context: the one of its class, unless it inherited a higher one.
+    /// A fixed-priority context that inherits a deadline is scheduled by it like the contexts of
+    /// the deadline class, though without a budget.
+    pub fn of(context: &Context) -> Self {
+        let own = match &context.class {
+            SchedClass::Fixed => Self::Fixed(context.priority),
+            SchedClass::Deadline(reservation) => Self::Deadline(reservation.server_deadline),
+        };
+        context.boost.map_or(own, |boost| own.max(boost))
+    }
+}
+
+impl Ord for Urgency {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        match (self, other) {
+            (Self::Fixed(a), Self::Fixed(b)) => a.cmp(b),
+            // The earlier deadline is the more urgent.
+            (Self::Deadline(a), Self::Deadline(b)) => b.cmp(a),
+            (Self::Fixed(_), Self::Deadline(_)) => cmp::Ordering::Less,
+            (Self::Deadline(_), Self::Fixed(_)) => cmp::Ordering::Greater,
+        }
+    }
+}
+
+impl PartialOrd for Urgency {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// State of the scheduler of a CPU.
+struct Scheduler {
+    /// Runnable fixed-priority contexts that are not running.
+    queue: RunQueue,
+    /// Runnable contexts of the deadline class that are not running.
+    deadlines: EdfQueue,
+    /// Contexts of the deadline class that wait for the release of their next job, sorted by the
+    /// time of the release.
+    releases: BTreeSet<(u64, ContextId)>,
+    /// Runnable contexts of the deadline class that used up their budget, sorted by the time at
+    /// which it is replenished.
+    throttled: BTreeSet<(u64, ContextId)>,
+    /// Contexts of the deadline class whose CPU time is reserved on the CPU.
+    admission: Admission,
+    /// Context that the CPU is running.
+    running: ContextId,
+    /// Urgency of the running context.
+    current: Urgency,
+    /// Number of ticks left in the running context's time slice.
+    slice: usize,
+    /// Number of ticks left until the load balancer runs.
+    balance: usize,
+    /// Whether the running context used up its time slice, in which case it goes behind the
+    /// other contexts of its priority when it is switched away from.
+    expired: bool,
+    /// Whether a context should be switched to as soon as possible.
+    pending: bool,
+    /// Number of quiescent states that the CPU went through: points where it cannot be inside an
+    /// RCU read-side critical section (see [`quiescent_states`]).
+    quiescent: u64,
+}
+
+impl Scheduler {
+    /// Construct the scheduler of a CPU that does not run anything yet.
+    const fn new() -> Self {
+        Self {
+            queue: RunQueue::new(),
+            deadlines: EdfQueue::new(),
+            releases: BTreeSet::new(),
+            throttled: BTreeSet::new(),
+            admission: Admission::new(),
+            running: ContextId::new(0),
+            current: Urgency::Fixed(Priority::IDLE),
+            slice: TIME_SLICE,
+            balance: BALANCE_INTERVAL,
+            expired: false,
+            pending: false,
+            quiescent: 0,
+        }
+    }
+
+    /// Queue a runnable context by its urgency. Fixed-priority contexts go behind the others of
+    /// their priority, and throttled contexts of the deadline class wait for their budget to be
+    /// replenished (even if they inherited an earlier deadline).
+    fn push(&mut self, context: &Context) {
+        if let SchedClass::Deadline(reservation) = &context.class {
+            if reservation.throttled {
+                self.throttled
+                    .insert((reservation.server_deadline, context.id));
+                return;
+            }
+        }
+
+        match Urgency::of(context) {
+            Urgency::Fixed(priority) => self.queue.push_back(context.id, priority),
+            Urgency::Deadline(deadline) => self.deadlines.push(context.id, deadline),
+        }
+    }
+
+    /// Take a context out of the queues, returning whether it was queued.
+    fn remove(&mut self, context: &Context) -> bool {
+        if let SchedClass::Deadline(reservation) = &context.class {
+            if reservation.throttled {
+                return self
+                    .throttled
+                    .remove(&(reservation.server_deadline, context.id));
+            }
+        }
+
+        match Urgency::of(context) {
+            Urgency::Fixed(priority) => self.queue.remove(context.id, priority),
+            Urgency::Deadline(deadline) => self.deadlines.remove(context.id, deadline),
+        }
+    }
+
+    /// Retrieve the urgency of the most urgent queued context.
+    fn highest(&self) -> Option<Urgency> {
+        match self.deadlines.earliest() {
+            Some(deadline) => Some(Urgency::Deadline(deadline)),
+            None => self.queue.highest().map(Urgency::Fixed),
+        }
+    }
+
+    /// Request a switch if a context of the given urgency should preempt the running context,
+    /// returning whether it should.
+    fn preempt_for(&mut self, urgency: Urgency) -> bool {
+        if urgency > self.current {
+            self.pending = true;
+        }
+        self.pending
+    }
+
+    /// Retrieve the number of contexts that want the CPU: the queued ones, and the running one
+    /// unless the CPU is idle.
+    fn load(&self) -> usize {
+        let running = self.current != Urgency::Fixed(Priority::IDLE);
+        self.queue.len() + self.deadlines.len() + running as usize
+    }
+
+    /// Take the first release that is due by `now` out of the releases.
+    fn pop_release(&mut self, now: u64) -> Option<(u64, ContextId)> {
+        pop_due(&mut self.releases, now)
+    }
+
+    /// Take the first throttled context whose budget is due to be replenished by `now`.
+    fn pop_throttled(&mut self, now: u64) -> Option<(u64, ContextId)> {
+        pop_due(&mut self.throttled, now)
+    }
+}
+
+/// Take the first entry of a set sorted by time out of it, if the time is `now` or earlier.
+fn pop_due(set: &mut BTreeSet<(u64, ContextId)>, now: u64) -> Option<(u64, ContextId)> {
+    let &(time, id) = set.first()?;
+    if time > now {
+        return None;
+    }
+
+    set.remove(&(time, id));
+    Some((time, id))
+}
+
+/// Wake-ups that interrupt handlers deferred to the next tick, which are kept without allocating.
+struct DeferredWakeups {
+    ids: [ContextId; MAX_DEFERRED_WAKEUPS],
+    count: usize,
+    /// Whether a wake-up did not fit, in which case every blocked context is woken up at the next
+    /// tick instead.
+    overflowed: bool,
+}
+
+impl DeferredWakeups {
+    /// Construct an empty [`DeferredWakeups`].
+    const fn new() -> Self {
+        Self {
+            ids: [ContextId::new(0); MAX_DEFERRED_WAKEUPS],
+            count: 0,
+            overflowed: false,
+        }
+    }
+
+    /// Remember a wake-up, unless it already is.
+    fn push(&mut self, id: ContextId) {
+        if self.ids[..self.count].contains(&id) {
+            return;
+        }
+
+        match self.ids.get_mut(self.count) {
+            Some(slot) => {
+                *slot = id;
+                self.count += 1;
+            }
+            None => self.overflowed = true,
+        }
+    }
+}
+
+percpu! {
+    /// Schedulers of the CPUs, which hold their run queues. Interrupts are disabled while one is
+    /// locked, since the timer interrupt needs them too.
+    static SCHEDULERS: IrqMutex<Scheduler> = IrqMutex::new(Scheduler::new());
+}
+
+/// Number of CPUs that contexts are scheduled on. Zero until the schedulers are set up.
+static CPU_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether contexts of the deadline class stay on the CPU that they were admitted on, rather than
+/// being moved around by the load balancer.
+static PARTITIONED: AtomicBool = AtomicBool::new(false);
+
+/// Blocked contexts that are woken up once a time passes, even if what they wait for did not
+/// happen, sorted by the time. Entries of contexts that were woken up before are left in, and
+/// skipped once they expire.
+static TIMEOUTS: IrqMutex<BTreeSet<(u64, ContextId)>> = IrqMutex::new(BTreeSet::new());
+
+/// Contexts that interrupt handlers woke up while they could not be locked, which are woken up at
+/// the next tick instead.
+static DEFERRED_WAKEUPS: IrqMutex<DeferredWakeups> = IrqMutex::new(DeferredWakeups::new());
+
+/// Number of timer ticks since the scheduler's timer was started.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Set up the schedulers of the given number of CPUs.
+pub fn init(cpus: usize) {
+    assert!(
+        cpus > 0 && cpus <= context::MAX_CPUS,
+        "Unsupported number of CPUs: {}",
+        cpus
+    );
+    CPU_COUNT.store(cpus, Ordering::Release);
+}
+
+/// Start scheduling on a CPU, which runs its idle context.
+pub(super) fn init_cpu(cpu: usize, idle: ContextId) {
+    with_scheduler(cpu, |scheduler| scheduler.running = idle);
+}
+
+/// Retrieve the number of CPUs that contexts are scheduled on.
+pub fn cpu_count() -> usize {
+    match CPU_COUNT.load(Ordering::Acquire) {
+        0 => 1,
+        cpus => cpus,
+    }
+}
+
+/// Run a function with interrupts disabled. Data that interrupt handlers use too is better kept in
+/// an [`IrqMutex`], which does this while it is locked.
+pub(crate) fn without_interrupts<T>(function: impl FnOnce() -> T) -> T {
+    let enabled = irq::enabled();
+    unsafe { irq::disable() };
+
+    let result = function();
+
+    if enabled {
+        unsafe { irq::enable() };
+    }
+    result
+}
+
+/// Run a function with the scheduler of a CPU locked.
+fn with_scheduler<T>(cpu: usize, function: impl FnOnce(&mut Scheduler) -> T) -> T {
+    function(&mut SCHEDULERS.of(cpu).lock())
+}
+
+/// Run a function with the schedulers of two different CPUs locked. The lower CPU is always locked
+/// first, so that two CPUs doing this at once cannot deadlock.
+fn with_schedulers<T>(
+    a: usize,
+    b: usize,
+    function: impl FnOnce(&mut Scheduler, &mut Scheduler) -> T,
+) -> T {
+    debug_assert_ne!(a, b);
+
+    // Interrupts stay disabled until both are unlocked, whichever is unlocked first.
+    without_interrupts(|| {
+        let first = SCHEDULERS.of(a.min(b)).lock();
+        let second = SCHEDULERS.of(a.max(b)).lock();
+
+        let (mut a, mut b) = if a < b {
+            (first, second)
+        } else {
+            (second, first)
+        };
+        function(&mut a, &mut b)
+    })
+}
+
+/// Retrieve the number of quiescent states that a CPU went through. RCU read-side critical
+/// sections run with interrupts disabled and do not switch away, so every tick of the timer and
+/// every context switch is one. A grace period is over once every other CPU went through one.
+pub fn quiescent_states(cpu: usize) -> u64 {
+    with_scheduler(cpu, |scheduler| scheduler.quiescent)
+}
+
+/// Retrieve the number of timer ticks since the scheduler's timer was started.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Choose whether contexts of the deadline class stay on the CPU that they were admitted on
+/// (partitioned scheduling), or may be moved to other CPUs by the load balancer. Contexts that
+/// are moved take their reservation along, so they are only moved to CPUs that can admit them.
+pub fn set_partitioned(partitioned: bool) {
+    PARTITIONED.store(partitioned, Ordering::Relaxed);
+}
+
+/// Whether contexts of the deadline class stay on the CPU that they were admitted on.
+pub fn is_partitioned() -> bool {
+    PARTITIONED.load(Ordering::Relaxed)
+}
+
+/// Retrieve the CPUs that exist out of a set.
+fn online(affinity: CpuSet) -> impl Iterator<Item = usize> {
+    let count = cpu_count();
+    affinity.iter().take_while(move |&cpu| cpu < count)
+}
+
+/// Pick the CPU whose run queue a context goes on: the one that it was on if it may still run
+/// there (which keeps its caches warm), or else the least loaded CPU that it may run on. Contexts
+/// of the deadline class stay on the CPU that their time is reserved on.
+fn place(context: &mut Context) {
+    match context.cpu {
+        Some(cpu) if context.affinity.contains(cpu) => return,
+        Some(_) if matches!(context.class, SchedClass::Deadline(_)) => return,
+        _ => (),
+    }
+
+    let cpu = online(context.affinity)
+        .min_by_key(|&cpu| with_scheduler(cpu, |scheduler| scheduler.load()))
+        .unwrap_or_else(context::cpu_id);
+    context.cpu = Some(cpu);
+}
+
+/// Make a CPU act on a switch that was requested, by interrupting it if it is another CPU.
+fn reschedule(cpu: usize) {
+    if cpu != context::cpu_id() {
+        machine::ipi::reschedule(cpu);
+    }
+}
+
+/// Queue a runnable context on the run queue of its CPU, and make the CPU switch to it if it is
+/// more urgent than the context that the CPU runs.
+fn enqueue(context: &Context) {
+    let cpu = context.cpu.expect("Runnable context without a CPU");
+    let urgency = Urgency::of(context);
+
+    if with_scheduler(cpu, |scheduler| {
+        scheduler.push(context);
+        scheduler.preempt_for(urgency)
+    }) {
+        reschedule(cpu);
+    }
+}
+
+/// Queue a runnable context that was switched away from on a CPU that it may no longer run on.
+/// Called by the switch, which keeps the context from being switched to before its registers are
+/// saved.
+pub(super) fn migrate(context: &mut Context) {
+    place(context);
+    enqueue(context);
+}
+
+/// Make a blocked context runnable. If it is more urgent than the context that its CPU runs, a
+/// switch is requested, which happens on the way out of the current interrupt (or at the next
+/// one).
+pub fn wake(context: &mut Context) {
+    if context.status != Status::Blocked {
+        return;
+    }
+
+    context.status = Status::Runnable;
+    context.status_reason = "";
+
+    if let SchedClass::Deadline(reservation) = &mut context.class {
+        reservation.wake(ticks());
+    }
+
+    // The context may have been waiting for its next release.
+    if let (SchedClass::Deadline(reservation), Some(cpu)) = (&context.class, context.cpu) {
+        let release = (reservation.release, context.id);
+        with_scheduler(cpu, |scheduler| scheduler.releases.remove(&release));
+    }
+
+    // A context that has not finished switching away is put back in a queue by the switch.
+    if context.running {
+        return;
+    }
+
+    place(context);
+    enqueue(context);
+}
+
+/// Wake up a blocked context from an interrupt handler, which must not wait for locks that the
+/// interrupted code may hold. If the context cannot be locked right away, it is woken up at the
+/// next tick instead, so it may have blocked again for another reason by then; contexts have to
+/// cope with such spurious wake-ups anyway, since signals cause them too. Nothing is allocated: if
+/// more than [`MAX_DEFERRED_WAKEUPS`] are deferred, every blocked context is woken up at the next
+/// tick.
+pub fn wake_from_interrupt(id: ContextId) {
+    let woken = context::try_contexts().map_or(false, |contexts| {
+        match contexts.get(id).map(|context| context.try_write()) {
+            Some(Some(mut context)) => {
+                wake(&mut context);
+                true
+            }
+            Some(None) => false,
+            // The context exited.
+            None => true,
+        }
+    });
+
+    if !woken {
+        DEFERRED_WAKEUPS.lock().push(id);
+    }
+}
+
+/// Wake up the contexts that interrupt handlers could not wake up, leaving the ones that still
+/// cannot be locked for the next tick.
+fn wake_deferred(contexts: &ContextList) {
+    let deferred = mem::replace(&mut *DEFERRED_WAKEUPS.lock(), DeferredWakeups::new());
+
+    for &id in &deferred.ids[..deferred.count] {
+        match contexts.get(id).map(|context| context.try_write()) {
+            Some(Some(mut context)) => wake(&mut context),
+            Some(None) => DEFERRED_WAKEUPS.lock().push(id),
+            None => (),
+        }
+    }
+
+    // The wake-ups that did not fit are not known, so every blocked context is woken up.
+    if deferred.overflowed {
+        for (&id, context) in contexts.iter() {
+            match context.try_write() {
+                Some(mut context) => wake(&mut context),
+                None => DEFERRED_WAKEUPS.lock().push(id),
+            }
+        }
+    }
+}
+
+/// Block a context for the given reason, taking it out of the run queue. A running context keeps
+/// running until it switches away.
+pub fn block(context: &mut Context, reason: &'static str) {
+    if context.status == Status::Runnable && !context.running {
+        if let Some(cpu) = context.cpu {
+            with_scheduler(cpu, |scheduler| scheduler.remove(context));
+        }
+    }
+
+    context.status = Status::Blocked;
+    context.status_reason = reason;
+}
+
+/// Stop a context because of a signal, like [`block`]. It does not run again until [`resume`] is
+/// called.
+pub fn stop(context: &mut Context, signal: u8) {
+    block(context, "Stopped by a signal");
+    context.status = Status::Stopped(signal as usize);
+}
+
+/// Let a stopped context run again.
+pub fn resume(context: &mut Context) {
+    if let Status::Stopped(_) = context.status {
+        context.status = Status::Blocked;
+        wake(context);
+    }
+}
+
+/// Block a context like [`block`], but wake it up at the given time (in ticks) if nothing else
+/// does by then. The time is kept in [`Context::wakeup_time`] until [`cancel_timeout`] is called.
+pub fn block_until(context: &mut Context, reason: &'static str, time: u64) {
+    block(context, reason);
+
+    context.wakeup_time = Some(time);
+    TIMEOUTS.lock().insert((time, context.id));
+}
+
+/// Forget the time at which a context was to be woken up. Called by the context once it is done
+/// waiting.
+pub fn cancel_timeout(context: &mut Context) {
+    if let Some(time) = context.wakeup_time.take() {
+        TIMEOUTS.lock().remove(&(time, context.id));
+    }
+}
+
+/// Whether the time at which a context was to be woken up has passed.
+pub fn timed_out(context: &Context) -> bool {
+    context.wakeup_time.map_or(false, |time| time <= ticks())
+}
+
+/// Apply a change to how or where a context is scheduled, keeping the run queues in order and
+/// requesting switches where the change calls for them.
+fn update<T>(context: &mut Context, change: impl FnOnce(&mut Context) -> T) -> T {
+    let queued = context.status == Status::Runnable
+        && !context.running
+        && context.cpu.map_or(false, |cpu| {
+            with_scheduler(cpu, |scheduler| scheduler.remove(context))
+        });
+
+    let result = change(context);
+
+    if queued {
+        place(context);
+        enqueue(context);
+    } else if context.running {
+        // The CPU that runs the context tracks how urgent it is, and has to switch away from it if
+        // it should run elsewhere.
+        let urgency = Urgency::of(context);
+        for cpu in 0..cpu_count() {
+            let moved = context.cpu != Some(cpu) || !context.affinity.contains(cpu);
+            let preempt = with_scheduler(cpu, |scheduler| {
+                if scheduler.running != context.id {
+                    return false;
+                }
+
+                scheduler.current = urgency;
+                if moved || scheduler.highest() > Some(urgency) {
+                    scheduler.pending = true;
+                }
+                scheduler.pending
+            });
+
+            if preempt {
+                reschedule(cpu);
+            }
+        }
+    }
+    result
+}
+
+/// Change the priority of a context, moving it to its new place in the run queue. The priority of
+/// a context of the deadline class only matters once it leaves the class.
+///
+/// If the context waits for a mutex, call [`mutex::urgency_changed`] once it is unlocked, so that
+/// the owner inherits the change.
+///
+/// [`mutex::urgency_changed`]: crate::sync::mutex::urgency_changed
+pub fn set_priority(context: &mut Context, priority: Priority) {
+    update(context, |context| context.priority = priority);
+}
+
+/// Change the urgency that a context inherited from the contexts that wait for it (see
+/// [`Context::boost`]), moving it to its new place in the run queue.
+pub fn set_boost(context: &mut Context, boost: Option<Urgency>) {
+    update(context, |context| context.boost = boost);
+}
+
+/// Change the CPUs that a context may run on. If it is on another CPU, it is moved to one of them.
+/// The CPU that a context of the deadline class has its time reserved on cannot be left out.
+pub fn set_affinity(context: &mut Context, affinity: CpuSet) -> Result<(), AffinityError> {
+    if online(affinity).next().is_none() {
+        return Err(AffinityError::NoCpu);
+    }
+
+    if let (SchedClass::Deadline(_), Some(cpu)) = (&context.class, context.cpu) {
+        if !affinity.contains(cpu) {
+            return Err(AffinityError::Reserved);
+        }
+    }
+
+    update(context, |context| context.affinity = affinity);
+    Ok(())
+}
+
+/// Move a context to the deadline class with the given parameters, or change its parameters if it
+/// already is of the class. The context is admitted on the first CPU that it may run on (starting
+/// with its own) where the contexts of the class can all still meet their deadlines, and it is
+/// moved there. Its first job is released right away.
+///
+/// This is meant to be called by the context itself, or before it first runs.
+pub fn set_deadline(context: &mut Context, params: DeadlineParams) -> Result<(), AdmissionError> {
+    if !params.is_valid() {
+        return Err(AdmissionError::InvalidParams);
+    }
+
+    let now = ticks();
+    let replaced = match &context.class {
+        SchedClass::Deadline(reservation) => Some(reservation.params),
+        SchedClass::Fixed => None,
+    };
+
+    let home = context.cpu.filter(|&cpu| context.affinity.contains(cpu));
+    let others = online(context.affinity).filter(|&cpu| Some(cpu) != home);
+    let cpu = home
+        .into_iter()
+        .chain(others)
+        .find(|&cpu| {
+            // The old parameters only count on the CPU that they were admitted on.
+            let replaced = replaced.filter(|_| context.cpu == Some(cpu));
+            with_scheduler(cpu, |scheduler| {
+                scheduler.admission.admit(&params, replaced.as_ref())
+            })
+            .is_ok()
+        })
+        .ok_or(AdmissionError::Overloaded)?;
+
+    if let (Some(old), Some(replaced)) = (context.cpu, replaced) {
+        if old != cpu {
+            with_scheduler(old, |scheduler| scheduler.admission.remove(&replaced));
+        }
+    }
+
+    update(context, |context| {
+        context.cpu = Some(cpu);
+        context.class = SchedClass::Deadline(Reservation::new(params, now));
+    });
+    Ok(())
+}
+
+/// Move a context back to the fixed-priority class, giving up its reservation.
+///
+/// This is meant to be called by the context itself.
+pub fn clear_deadline(context: &mut Context) {
+    if let (SchedClass::Deadline(reservation), Some(cpu)) = (&context.class, context.cpu) {
+        let release = (reservation.release, context.id);
+        with_scheduler(cpu, |scheduler| {
+            scheduler.admission.remove(&reservation.params);
+            scheduler.releases.remove(&release);
+        });
+    } else {
+        return;
+    }
+
+    update(context, |context| context.class = SchedClass::Fixed);
+}
+
+/// Mark the current job of a context of the deadline class as done. Unless its next job was
+/// already released, the context is blocked until it is, and it should switch away. Returns whether
+/// the context was blocked.
+pub fn end_job(context: &mut Context) -> bool {
+    let now = ticks();
+    let id = context.id;
+    let cpu = context.cpu.unwrap_or_else(context::cpu_id);
+
+    let reservation = match &mut context.class {
+        SchedClass::Deadline(reservation) => reservation,
+        SchedClass::Fixed => return false,
+    };
+
+    let deadline = reservation.job_deadline();
+    if reservation.complete(now) {
+        log::warn!(
+            "Context {} missed its deadline by {} ticks ({} misses)",
+            id.get(),
+            now - deadline,
+            reservation.stats.misses
+        );
+    }
+
+    let release = reservation.release;
+    if release <= now {
+        return false;
+    }
+
+    block(context, "Waiting for the next period");
+    with_scheduler(cpu, |scheduler| scheduler.releases.insert((release, id)));
+    true
+}
+
+/// Finish the current job of the running context and wait for its next job to be released.
+pub fn wait_next_period() {
+    let blocked = match context::current() {
+        Some(context) => end_job(&mut context.write()),
+        None => false,
+    };
+
+    if blocked {
+        unsafe { switch::switch() };
+    }
+}
+
+/// Account for a tick of the scheduler's timer. Called by the timer interrupt of every CPU. The
+/// running context is charged for the tick, and once it has used up its time slice, a switch is
+/// requested if another context is at least as urgent. A context that used up the budget of its
+/// reservation is switched away from until the budget is replenished. Contexts whose next job is
+/// due are released, and the load is balanced between the CPUs from time to time.
+pub fn tick() {
+    let cpu = context::cpu_id();
+
+    // The boot CPU keeps the time.
+    let now = if cpu == 0 {
+        TICKS.fetch_add(1, Ordering::Relaxed) + 1
+    } else {
+        ticks()
+    };
+
+    // The contexts may be locked by the code that was interrupted, in which case the tick is simply
+    // not accounted for.
+    let contexts = match context::try_contexts() {
+        Some(contexts) => contexts,
+        None => return,
+    };
+
+    let mut throttled = false;
+    if let Some(mut context) = contexts.current().and_then(|context| context.try_write()) {
+        context.ticks += 1;
+        if let SchedClass::Deadline(reservation) = &mut context.class {
+            throttled = reservation.charge();
+        }
+    }
+
+    let balance = with_scheduler(cpu, |scheduler| {
+        scheduler.quiescent += 1;
+
+        if throttled {
+            scheduler.pending = true;
+        }
+
+        if let Urgency::Fixed(_) = scheduler.current {
+            scheduler.slice = scheduler.slice.saturating_sub(1);
+            if scheduler.slice == 0 {
+                scheduler.slice = TIME_SLICE;
+                if scheduler.highest() >= Some(scheduler.current) {
+                    scheduler.expired = true;
+                    scheduler.pending = true;
+                }
+            }
+        }
+
+        scheduler.balance = scheduler.balance.saturating_sub(1);
+        if scheduler.balance == 0 || scheduler.load() == 0 {
+            scheduler.balance = BALANCE_INTERVAL;
+            true
+        } else {
+            false
+        }
+    });
+
+    if cpu == 0 {
+        expire(&contexts, now);
+        wake_deferred(&contexts);
+    }
+    release(&contexts, cpu, now);
+    replenish(&contexts, cpu, now);
+    if balance && cpu_count() > 1 {
+        self::balance(&contexts, cpu);
+    }
+}
+
+/// Wake the contexts of a CPU whose next job is released by `now`.
+fn release(contexts: &ContextList, cpu: usize, now: u64) {
+    while let Some((time, id)) = with_scheduler(cpu, |scheduler| scheduler.pop_release(now)) {
+        let context = match contexts.get(id) {
+            Some(context) => context,
+            None => continue,
+        };
+
+        match context.try_write() {
+            Some(mut context) => wake(&mut context),
+            None => {
+                // Try again at the next tick.
+                with_scheduler(cpu, |scheduler| scheduler.releases.insert((time, id)));
+                break;
+            }
+        }
+    }
+}
+
+/// Refill the budgets of the throttled contexts of a CPU that are due by `now`, and queue them
+/// again.
+fn replenish(contexts: &ContextList, cpu: usize, now: u64) {
+    while let Some((time, id)) = with_scheduler(cpu, |scheduler| scheduler.pop_throttled(now)) {
+        let context = match contexts.get(id) {
+            Some(context) => context,
+            None => continue,
+        };
+
+        let mut context = match context.try_write() {
+            Some(context) => context,
+            None => {
+                // Try again at the next tick.
+                with_scheduler(cpu, |scheduler| scheduler.throttled.insert((time, id)));
+                break;
+            }
+        };
+
+        // The context may have left the class, or been moved to another CPU, since.
+        match &mut context.class {
+            SchedClass::Deadline(reservation) if reservation.throttled => reservation.replenish(),
+            _ => continue,
+        }
+
+        if context.status == Status::Runnable && !context.running {
+            enqueue(&context);
+        }
+    }
+}
+
+/// Wake the blocked contexts whose timeout expired by `now`.
+fn expire(contexts: &ContextList, now: u64) {
+    loop {
+        let timeout = {
+            let mut timeouts = TIMEOUTS.lock();
+            match timeouts.first() {
+                Some(&(time, _)) if time <= now => timeouts.pop_first(),
+                _ => None,
+            }
+        };
+        let (time, id) = match timeout {
+            Some(timeout) => timeout,
+            None => break,
+        };
+
+        let context = match contexts.get(id) {
+            Some(context) => context,
+            None => continue,
+        };
+
+        match context.try_write() {
+            // The context may have been woken up and be waiting for something else since.
+            Some(mut context) if context.wakeup_time == Some(time) => wake(&mut context),
+            Some(_) => (),
+            None => {
+                // Try again at the next tick.
+                TIMEOUTS.lock().insert((time, id));
+                break;
+            }
+        }
+    }
+}
+
+/// Pull a context from the busiest CPU to this one, if the busiest CPU has at least two contexts
+/// more. Only queued contexts that may run on this CPU are pulled, and contexts of the deadline
+/// class are only pulled if they are not partitioned and this CPU can admit them.
+fn balance(contexts: &ContextList, cpu: usize) {
+    let load = with_scheduler(cpu, |scheduler| scheduler.load());
+    let busiest = (0..cpu_count())
+        .filter(|&other| other != cpu)
+        .map(|other| (other, with_scheduler(other, |scheduler| scheduler.load())))
+        .max_by_key(|&(_, load)| load);
+
+    let busiest = match busiest {
+        Some((busiest, busiest_load)) if busiest_load >= load + 2 => busiest,
+        _ => return,
+    };
+
+    let partitioned = is_partitioned();
+    // This CPU switches to the pulled context on the way out of the timer interrupt if it is more
+    // urgent.
+    with_schedulers(cpu, busiest, |this, other| {
+        let mut pulled = None;
+        let mut eligible = |id| {
+            let context = match contexts.get(id).and_then(|context| context.try_write()) {
+                Some(context) => context,
+                None => return false,
+            };
+            if context.running || !context.affinity.contains(cpu) {
+                return false;
+            }
+
+            if let SchedClass::Deadline(reservation) = &context.class {
+                if partitioned || this.admission.admit(&reservation.params, None).is_err() {
+                    return false;
+                }
+            }
+
+            pulled = Some(context);
+            true
+        };
+
+        if other.deadlines.take(&mut eligible).is_none() {
+            other.queue.take(&mut eligible);
+        }
+
+        let mut context = match pulled {
+            Some(context) => context,
+            None => return,
+        };
+
+        if let SchedClass::Deadline(reservation) = &context.class {
+            other.admission.remove(&reservation.params);
+        }
+        context.cpu = Some(cpu);
+        this.push(&context);
+        this.preempt_for(Urgency::of(&context));
+    });
+}
+
+/// Give up the rest of the running context's time slice, so that the next switch puts it behind the
+/// other contexts of its priority instead of in front of them.
+pub fn expire_slice() {
+    without_interrupts(|| {
+        with_scheduler(context::cpu_id(), |scheduler| {
+            scheduler.slice = TIME_SLICE;
+            scheduler.expired = true;
+        })
+    });
+}
+
+/// Whether a switch was requested on this CPU.
+pub fn switch_pending() -> bool {
+    with_scheduler(context::cpu_id(), |scheduler| scheduler.pending)
+}
+
+/// Put the context that is being switched away from back in the run queue of the CPU (if it is
+/// still runnable and may stay on the CPU), and take the most urgent context that `eligible`
+/// accepts out of it. `prev` is locked by the caller, so it is checked separately through
+/// `prev_eligible`.
+pub(super) fn select(
+    cpu: usize,
+    prev: &Context,
+    prev_eligible: bool,
+    mut eligible: impl FnMut(ContextId) -> bool,
+) -> Option<ContextId> {
+    with_scheduler(cpu, |scheduler| {
+        scheduler.quiescent += 1;
+
+        if prev_eligible {
+            match Urgency::of(prev) {
+                Urgency::Fixed(priority) if scheduler.expired => {
+                    scheduler.queue.push_back(prev.id, priority)
+                }
+                Urgency::Fixed(priority) => scheduler.queue.push_front(prev.id, priority),
+                Urgency::Deadline(_) => scheduler.push(prev),
+            }
+        }
+
+        let mut eligible = |id| {
+            if id == prev.id {
+                prev_eligible
+            } else {
+                eligible(id)
+            }
+        };
+
+        let taken = match scheduler.deadlines.take(&mut eligible) {
+            Some((next, deadline)) => Some((next, Urgency::Deadline(deadline))),
+            None => scheduler
+                .queue
+                .take(&mut eligible)
+                .map(|(next, priority)| (next, Urgency::Fixed(priority))),
+        };
+
+        let (next, urgency) = match taken {
+            Some(taken) => taken,
+            None => {
+                // Nothing can run here, so the previous context keeps running.
+                if prev_eligible {
+                    scheduler.remove(prev);
+                }
+                return None;
+            }
+        };
+
+        scheduler.pending = false;
+        scheduler.expired = false;
+        scheduler.running = next;
+        scheduler.current = urgency;
+        if next != prev.id {
+            scheduler.slice = TIME_SLICE;
+        }
+
+        Some(next)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    fn id(id: usize) -> ContextId {
+        ContextId::new(id)
+    }
+
+    /// Take every context out of the queue, in the order that they would run.
+    fn drain(queue: &mut RunQueue) -> Vec<(usize, u8)> {
+        core::iter::from_fn(|| queue.take(|_| true))
+            .map(|(id, priority)| (id.get(), priority.get()))
+            .collect()
+    }
+
+    #[test]
+    fn runs_higher_priorities_first() {
+        let mut queue = RunQueue::new();
+        queue.push_back(id(1), Priority::new(10));
+        queue.push_back(id(2), Priority::new(200));
+        queue.push_back(id(3), Priority::new(64));
+        queue.push_back(id(4), Priority::new(63));
+        queue.push_back(id(5), Priority::IDLE);
+
+        assert_eq!(queue.len(), 5);
+        assert_eq!(queue.highest(), Some(Priority::new(200)));
+        assert_eq!(
+            drain(&mut queue),
+            vec![(2, 200), (3, 64), (4, 63), (1, 10), (5, 0)]
+        );
+        assert!(queue.is_empty());
+        assert_eq!(queue.highest(), None);
+    }
+
+    #[test]
+    fn takes_turns_within_a_priority() {
+        let mut queue = RunQueue::new();
+        queue.push_back(id(1), Priority::DEFAULT);
+        queue.push_back(id(2), Priority::DEFAULT);
+        queue.push_front(id(3), Priority::DEFAULT);
+
+        assert_eq!(drain(&mut queue), vec![(3, 128), (1, 128), (2, 128)]);
+    }
+
+    #[test]
+    fn removes_queued_contexts() {
+        let mut queue = RunQueue::new();
+        queue.push_back(id(1), Priority::HIGHEST);
+        queue.push_back(id(2), Priority::LOWEST);
+
+        assert!(!queue.remove(id(1), Priority::LOWEST));
+        assert!(queue.remove(id(1), Priority::HIGHEST));
+        assert!(!queue.remove(id(1), Priority::HIGHEST));
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.highest(), Some(Priority::LOWEST));
+    }
+
+    #[test]
+    fn leaves_ineligible_contexts_queued() {
+        let mut queue = RunQueue::new();
+        queue.push_back(id(1), Priority::HIGHEST);
+        queue.push_back(id(2), Priority::DEFAULT);
+        queue.push_back(id(3), Priority::DEFAULT);
+
+        assert_eq!(
+            queue.take(|id| id.get() != 1),
+            Some((id(2), Priority::DEFAULT))
+        );
+        assert_eq!(queue.take(|_| false), None);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(drain(&mut queue), vec![(1, 255), (3, 128)]);
+    }
+
+    #[test]
+    fn orders_urgencies() {
+        // Contexts that inherit a deadline get ahead of every fixed priority.
+        assert!(Urgency::Deadline(u64::MAX) > Urgency::Fixed(Priority::HIGHEST));
+        assert!(Urgency::Deadline(10) > Urgency::Deadline(50));
+        assert!(Urgency::Fixed(Priority::DEFAULT) > Urgency::Fixed(Priority::LOWEST));
+    }
+
+    #[test]
+    fn defers_wakeups_without_allocating() {
+        let mut deferred = DeferredWakeups::new();
+        deferred.push(id(1));
+        deferred.push(id(2));
+        deferred.push(id(1));
+        assert_eq!(&deferred.ids[..deferred.count], &[id(1), id(2)]);
+        assert!(!deferred.overflowed);
+
+        for i in 3..=MAX_DEFERRED_WAKEUPS + 1 {
+            deferred.push(id(i));
+        }
+        assert_eq!(deferred.count, MAX_DEFERRED_WAKEUPS);
+        assert!(deferred.overflowed);
+    }
+}
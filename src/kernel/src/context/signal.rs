@@ -0,0 +1,409 @@
+use core::mem;
+
+use crate::context::{self, process, scheduler, switch, Context, ContextId, Status};
+use crate::machine::interrupt::InterruptStack;
+use crate::syscall::validate::write_user;
+
+/// Number of signals. Signal numbers go from one up to (but not including) this, which matches the
+/// size of signal masks.
+pub const NSIG: usize = 128;
+
+/// Hangup.
+pub const SIGHUP: u8 = 1;
+/// Interrupt from the keyboard.
+pub const SIGINT: u8 = 2;
+/// Quit from the keyboard.
+pub const SIGQUIT: u8 = 3;
+/// Illegal instruction.
+pub const SIGILL: u8 = 4;
+/// Breakpoint trap.
+pub const SIGTRAP: u8 = 5;
+/// Abort.
+pub const SIGABRT: u8 = 6;
+/// Bus error (bad memory access).
+pub const SIGBUS: u8 = 7;
+/// Floating-point exception.
+pub const SIGFPE: u8 = 8;
+/// Kill. Cannot be caught or ignored.
+pub const SIGKILL: u8 = 9;
+/// User-defined signal 1.
+pub const SIGUSR1: u8 = 10;
+/// Invalid memory reference.
+pub const SIGSEGV: u8 = 11;
+/// User-defined signal 2.
+pub const SIGUSR2: u8 = 12;
+/// Write to a pipe with no readers.
+pub const SIGPIPE: u8 = 13;
+/// Timer signal.
+pub const SIGALRM: u8 = 14;
+/// Termination.
+pub const SIGTERM: u8 = 15;
+/// Stack fault on the coprocessor (unused).
+pub const SIGSTKFLT: u8 = 16;
+/// Child stopped or exited.
+pub const SIGCHLD: u8 = 17;
+/// Continue if stopped.
+pub const SIGCONT: u8 = 18;
+/// Stop. Cannot be caught or ignored.
+pub const SIGSTOP: u8 = 19;
+/// Stop typed at the terminal.
+pub const SIGTSTP: u8 = 20;
+/// Terminal input for a background process.
+pub const SIGTTIN: u8 = 21;
+/// Terminal output for a background process.
+pub const SIGTTOU: u8 = 22;
+/// Urgent condition on a socket.
+pub const SIGURG: u8 = 23;
+/// CPU time limit exceeded.
+pub const SIGXCPU: u8 = 24;
+/// File size limit exceeded.
+pub const SIGXFSZ: u8 = 25;
+/// Virtual alarm clock.
+pub const SIGVTALRM: u8 = 26;
+/// Profiling timer expired.
+pub const SIGPROF: u8 = 27;
+/// Window resize.
+pub const SIGWINCH: u8 = 28;
+/// I/O is possible.
+pub const SIGIO: u8 = 29;
+/// Power failure.
+pub const SIGPWR: u8 = 30;
+/// Bad system call.
+pub const SIGSYS: u8 = 31;
+
+/// Handler that takes the default action of a signal.
+pub const SIG_DFL: usize = 0;
+/// Handler that ignores a signal.
+pub const SIG_IGN: usize = 1;
+
+/// `SYS_SIGPROCMASK` adds the signals of the set to the mask.
+pub const SIG_BLOCK: usize = 0;
+/// `SYS_SIGPROCMASK` removes the signals of the set from the mask.
+pub const SIG_UNBLOCK: usize = 1;
+/// `SYS_SIGPROCMASK` replaces the mask with the set.
+pub const SIG_SETMASK: usize = 2;
+
+/// Bit of a wait status that tells that the context dumped core.
+const CORE_DUMPED: usize = 0x80;
+
+bitflags::bitflags! {
+    /// Flags of a [`SigAction`].
+    #[derive(Default)]
+    #[repr(transparent)]
+    pub struct SigActionFlags: usize {
+        /// Do not send `SIGCHLD` when a child context stops.
+        const NOCLDSTOP = 0x0000_0001;
+        /// Restart system calls that the signal interrupts, rather than failing them with `EINTR`.
+        const RESTART = 0x1000_0000;
+        /// Do not block the signal while its handler runs.
+        const NODEFER = 0x4000_0000;
+        /// Go back to the default action once the handler is called.
+        const RESETHAND = 0x8000_0000;
+    }
+}
+
+/// Action taken when a signal is received. The layout is shared with user-space, which passes it to
+/// `SYS_SIGACTION`.
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+pub struct SigAction {
+    /// Address of the handler, or [`SIG_DFL`] or [`SIG_IGN`].
+    pub handler: usize,
+    /// Signals that are blocked while the handler runs, on top of the ones that already are.
+    pub mask: [u64; 2],
+    /// See [`SigActionFlags`].
+    pub flags: SigActionFlags,
+}
+
+/// What happens when a signal whose action is [`SIG_DFL`] is received.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DefaultAction {
+    /// The context is terminated.
+    Terminate,
+    /// The context is terminated, and it is reported to have dumped core.
+    Core,
+    /// The context stops until it receives [`SIGCONT`].
+    Stop,
+    /// The context continues if it was stopped.
+    Continue,
+    /// Nothing happens.
+    Ignore,
+}
+
+/// Retrieve the default action of a signal.
+pub fn default_action(signal: u8) -> DefaultAction {
+    match signal {
+        SIGQUIT | SIGILL | SIGTRAP | SIGABRT | SIGBUS | SIGFPE | SIGSEGV | SIGXCPU | SIGXFSZ
+        | SIGSYS => DefaultAction::Core,
+        SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+        SIGCONT => DefaultAction::Continue,
+        SIGCHLD | SIGURG | SIGWINCH => DefaultAction::Ignore,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+/// Saved state of the interrupted code, pushed on the user stack when a signal handler is called.
+/// `SYS_SIGRETURN` restores it once the handler returns.
+#[derive(Clone)]
+#[repr(C)]
+pub struct SignalFrame {
+    /// Address that the handler returns to, which calls `SYS_SIGRETURN`.
+    pub restorer: usize,
+    /// Signal that the handler was called for.
+    pub signal: usize,
+    /// Signal mask from before the handler was called.
+    pub mask: [u64; 2],
+    /// Registers of the interrupted code.
+    pub registers: InterruptStack,
+}
+
+/// Retrieve the index of the word of a signal mask that holds a signal, and the bit of the signal
+/// in it.
+fn mask_bit(signal: u8) -> (usize, u64) {
+    let signal = signal as usize;
+    (signal / 64, 1 << (signal % 64))
+}
+
+/// Add a signal to a signal mask.
+pub fn add_to_mask(mask: &mut [u64; 2], signal: u8) {
+    let (index, bit) = mask_bit(signal);
+    mask[index] |= bit;
+}
+
+/// Remove the signals that cannot be blocked ([`SIGKILL`] and [`SIGSTOP`]) from a signal mask.
+pub fn sanitize_mask(mut mask: [u64; 2]) -> [u64; 2] {
+    for signal in [SIGKILL, SIGSTOP] {
+        let (index, bit) = mask_bit(signal);
+        mask[index] &= !bit;
+    }
+    mask
+}
+
+/// Whether a signal is blocked by the signal mask of a context. [`SIGKILL`] and [`SIGSTOP`] cannot
+/// be blocked.
+pub fn is_masked(context: &Context, signal: u8) -> bool {
+    if signal == SIGKILL || signal == SIGSTOP {
+        return false;
+    }
+
+    let (index, bit) = mask_bit(signal);
+    context.signal_mask[index] & bit != 0
+}
+
+/// Whether a context has a pending signal that is not blocked, which interrupts what it waits for.
+pub fn interrupted(context: &Context) -> bool {
+    next(context).is_some()
+}
+
+/// Retrieve the first pending signal of a context that is not blocked.
+fn next(context: &Context) -> Option<u8> {
+    context
+        .pending
+        .iter()
+        .copied()
+        .find(|&signal| !is_masked(context, signal))
+}
+
+/// Retrieve the action of a context for a signal, along with the address that its handler returns
+/// to.
+pub fn action(context: &Context, signal: u8) -> (SigAction, usize) {
+    context
+        .signal_actions
+        .read()
+        .get(signal as usize)
+        .copied()
+        .unwrap_or_default()
+}
+
+/// Change the action of a context for a signal. The actions are shared by the threads of a
+/// process.
+pub fn set_action(context: &Context, signal: u8, action: SigAction, restorer: usize) {
+    let mut actions = context.signal_actions.write();
+    if actions.len() <= signal as usize {
+        actions.resize(signal as usize + 1, Default::default());
+    }
+    actions[signal as usize] = (action, restorer);
+}
+
+/// Queue a signal on a context. A signal that is already pending is not queued twice. Unless the
+/// signal is blocked, a blocked context is woken up to handle it.
+///
+/// [`SIGCONT`] makes a stopped context continue and discards its pending stop signals, while stop
+/// signals discard a pending [`SIGCONT`]. [`SIGKILL`] makes a stopped context continue too, so
+/// that it can be terminated.
+pub fn send(context: &mut Context, signal: u8) {
+    match default_action(signal) {
+        DefaultAction::Continue => {
+            context
+                .pending
+                .retain(|&pending| default_action(pending) != DefaultAction::Stop);
+            scheduler::resume(context);
+        }
+        DefaultAction::Stop => context.pending.retain(|&pending| pending != SIGCONT),
+        _ if signal == SIGKILL => scheduler::resume(context),
+        _ => (),
+    }
+
+    if !context.pending.contains(&signal) {
+        context.pending.push_back(signal);
+    }
+
+    if context.status == Status::Blocked && !is_masked(context, signal) {
+        scheduler::wake(context);
+    }
+}
+
+/// Queue a signal on the context that is currently running. Used by exception handlers to report
+/// faults caused by user code. Since returning to the faulting code would fault again, the signal
+/// takes its default action if it is blocked or ignored.
+pub fn raise(signal: u8) {
+    if let Some(context) = context::current() {
+        let mut context = context.write();
+
+        let (action, _) = action(&context, signal);
+        if is_masked(&context, signal) || action.handler == SIG_IGN {
+            set_action(&context, signal, SigAction::default(), 0);
+            let (index, bit) = mask_bit(signal);
+            context.signal_mask[index] &= !bit;
+        }
+
+        send(&mut context, signal);
+    }
+}
+
+/// Send a signal to a context by its identifier, returning whether the context exists.
+pub fn send_to(id: ContextId, signal: u8) -> bool {
+    let contexts = context::contexts();
+    match contexts.get(id) {
+        Some(context) => {
+            send(&mut context.write(), signal);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Tell the parent of a context that the context stopped (`stopped` is set) or exited, with
+/// `SIGCHLD`. Parents that asked not to be told about stops are not.
+pub fn notify_parent(parent: ContextId, stopped: bool) {
+    let contexts = context::contexts();
+    let mut parent = match contexts.get(parent) {
+        Some(parent) => parent.write(),
+        None => return,
+    };
+
+    let (action, _) = action(&parent, SIGCHLD);
+    if !(stopped && action.flags.contains(SigActionFlags::NOCLDSTOP)) {
+        send(&mut parent, SIGCHLD);
+    }
+}
+
+/// Whether a system call that was interrupted by a signal is made again once the signal is
+/// handled. This is the case unless the signal is caught by a handler without
+/// [`SigActionFlags::RESTART`]. Calls that were interrupted without a signal to handle fail.
+pub fn restart_syscall() -> bool {
+    let context = match context::current() {
+        Some(context) => context,
+        None => return false,
+    };
+    let context = context.read();
+
+    let signal = match next(&context) {
+        Some(signal) => signal,
+        None => return false,
+    };
+
+    let (action, _) = action(&context, signal);
+    match action.handler {
+        SIG_DFL | SIG_IGN => true,
+        _ => action.flags.contains(SigActionFlags::RESTART),
+    }
+}
+
+/// Handle the pending signals of the current context that are not blocked, on the way back to
+/// user-space. Signals are taken in the order that they were sent: default actions are carried out
+/// here, until a signal is caught by a handler, which the context is made to return to instead of
+/// the interrupted code.
+pub fn deliver(stack: &mut InterruptStack) {
+    let current = match context::current() {
+        Some(context) => context,
+        None => return,
+    };
+
+    loop {
+        let mut context = current.write();
+        let signal = match next(&context) {
+            Some(signal) => signal,
+            None => return,
+        };
+        context.pending.retain(|&pending| pending != signal);
+
+        let (action, restorer) = action(&context, signal);
+        match action.handler {
+            SIG_IGN => (),
+            SIG_DFL => match default_action(signal) {
+                DefaultAction::Ignore | DefaultAction::Continue => (),
+                DefaultAction::Stop => {
+                    let parent = context.parent_id;
+                    scheduler::stop(&mut context, signal);
+                    drop(context);
+
+                    notify_parent(parent, true);
+                    while let Status::Stopped(_) = current.read().status {
+                        unsafe { switch() };
+                    }
+                }
+                DefaultAction::Terminate => {
+                    drop(context);
+                    process::exit(signal as usize);
+                }
+                DefaultAction::Core => {
+                    drop(context);
+                    process::exit(signal as usize | CORE_DUMPED);
+                }
+            },
+            handler => {
+                let mask = context.signal_mask;
+                context.signal_mask[0] |= action.mask[0];
+                context.signal_mask[1] |= action.mask[1];
+                if !action.flags.contains(SigActionFlags::NODEFER) {
+                    add_to_mask(&mut context.signal_mask, signal);
+                }
+                context.signal_mask = sanitize_mask(context.signal_mask);
+
+                if action.flags.contains(SigActionFlags::RESETHAND) {
+                    set_action(&context, signal, SigAction::default(), 0);
+                }
+                drop(context);
+
+                // The frame cannot be pushed if the stack is unusable, which leaves nothing to do
+                // but to terminate the context.
+                if push_frame(stack, handler, signal, restorer, mask).is_err() {
+                    process::exit(SIGSEGV as usize | CORE_DUMPED);
+                }
+                return;
+            }
+        }
+    }
+}
+
+/// Save the interrupted code's state in a signal frame on the user stack, and make the context
+/// return to a handler instead.
+fn push_frame(
+    stack: &mut InterruptStack,
+    handler: usize,
+    signal: u8,
+    restorer: usize,
+    mask: [u64; 2],
+) -> Result<(), ()> {
+    let address = stack.signal_frame(mem::size_of::<SignalFrame>());
+    let frame = SignalFrame {
+        restorer,
+        signal: signal as usize,
+        mask,
+        registers: stack.clone(),
+    };
+    write_user(address as *mut SignalFrame, &frame).map_err(|_| ())?;
+    stack.enter_signal_handler(handler, signal as usize, address, restorer);
+    Ok(())
+}
@@ -1,15 +1,16 @@
 use alloc::collections::vec_deque::VecDeque;
-use alloc::sync::Arc;
 use alloc::vec::Vec;
 
-use crate::context::{ContextId, Context};
-use spin::RwLock;
+use crate::context::scheduler::{self, without_interrupts};
+use crate::context::{self, signal, switch, Context, ContextId, Status};
+use crate::sync::Mutex;
 
 /// Allows sleep-queue managers to pick context to wake up manually, for special cases.
 /// The boolean the routine returns is whether the context should be woken up or not.
-pub type MatcherRoutine = fn(context_id: ContextId) -> bool;
+pub type MatcherRoutine = fn(context: &Context) -> bool;
 
 /// Describes how contexts should be waken up when a wake-up occurs.
+#[derive(Copy, Clone)]
 pub enum WakeupPolicy {
     /// Wake up a specific number of contexts from the front.
     Front(usize),
@@ -19,45 +20,287 @@ pub enum WakeupPolicy {
     All,
     /// Custom matching routine.
     /// See [`MatcherRoutine`]
-    Custom(MatcherRoutine)
+    Custom(MatcherRoutine),
+}
+
+/// Reasons for a wait on a [`SleepQueue`] to end before the context was woken up.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WaitError {
+    /// A signal that the context does not block was sent to it.
+    Interrupted,
+    /// The timeout of the wait expired.
+    TimedOut,
 }
 
 /// Sleep queues allow contexts to go to sleep in a queue to wait for an event to happen. Upon a
 /// wakeup occuring, they are brought up in the order they were put in the queue in the manor that
 /// is specified.
 pub struct SleepQueue {
-    /// Queue of contexts that need to be waken up (FIFO).
-    queue: Arc<RwLock<VecDeque<ContextId>>>,
+    /// Queue of contexts that need to be waken up (FIFO). Interrupts are disabled while it is
+    /// locked, since interrupt handlers wake contexts up.
+    queue: Mutex<VecDeque<ContextId>>,
     /// See [`WakeupPolicy`]
     policy: WakeupPolicy,
 }
 
 impl SleepQueue {
-    /// Remove contexts from the queue and return them.
-    pub(crate) fn get_contexts(&mut self) -> Option<Vec<ContextId>> {
-        let contexts = Arc::try_unwrap().map(RwLock::into_inner).ok()?;
+    /// Construct an empty [`SleepQueue`], which wakes contexts up according to `policy` in
+    /// [`SleepQueue::wake_up`].
+    pub const fn new(policy: WakeupPolicy) -> Self {
+        Self {
+            queue: Mutex::new(VecDeque::new()),
+            policy,
+        }
+    }
+
+    /// Retrieve the number of contexts in the queue.
+    pub fn len(&self) -> usize {
+        without_interrupts(|| self.queue.lock().len())
+    }
+
+    /// Whether no context is in the queue.
+    pub fn is_empty(&self) -> bool {
+        without_interrupts(|| self.queue.lock().is_empty())
+    }
+
+    /// Block the current context until it is woken up. See [`SleepQueue::wait_with`].
+    pub fn wait(&self) -> Result<(), WaitError> {
+        self.wait_with(None, || ())
+    }
+
+    /// Block the current context until it is woken up, or for at most `timeout` scheduler ticks.
+    /// See [`SleepQueue::wait_with`].
+    pub fn wait_timeout(&self, timeout: u64) -> Result<(), WaitError> {
+        self.wait_with(Some(timeout), || ())
+    }
+
+    /// Block the current context until it is woken up, until `timeout` scheduler ticks passed (if
+    /// there is a timeout), or until a signal that it does not block is sent to it. `release` is
+    /// called once the context is in the queue, before it switches away, which lets callers drop
+    /// the locks that protect the condition that they wait for without missing a wake-up.
+    ///
+    /// Do not call this when holding locks (other than the ones that `release` drops).
+    pub fn wait_with(&self, timeout: Option<u64>, release: impl FnOnce()) -> Result<(), WaitError> {
+        let current = context::current().expect("Waiting outside of a context");
+        let time = timeout.map(|timeout| scheduler::ticks() + timeout);
+
+        // The context is locked while it is queued and blocked, so that a context that wakes it up
+        // in between waits for it to be blocked first.
+        {
+            let mut context = current.write();
+            if signal::interrupted(&context) {
+                drop(context);
+                release();
+                return Err(WaitError::Interrupted);
+            }
+
+            without_interrupts(|| self.queue.lock().push_back(context.id));
+            Self::sleep(&mut context, time);
+        }
+        release();
+
+        loop {
+            while current.read().status == Status::Blocked {
+                unsafe { switch() };
+            }
+
+            let mut context = current.write();
+            let id = context.id;
+            let queued = without_interrupts(|| self.queue.lock().contains(&id));
+            if !queued {
+                scheduler::cancel_timeout(&mut context);
+                return Ok(());
+            }
+
+            let result = if signal::interrupted(&context) {
+                Err(WaitError::Interrupted)
+            } else if scheduler::timed_out(&context) {
+                Err(WaitError::TimedOut)
+            } else {
+                // Something else woke the context up, so it goes back to sleep.
+                Self::sleep(&mut context, time);
+                continue;
+            };
+
+            self.remove(id);
+            scheduler::cancel_timeout(&mut context);
+            return result;
+        }
+    }
+
+    /// Block the current context until `take` succeeds, for at most `timeout` scheduler ticks (if
+    /// there is a timeout), or until a signal that it does not block is sent to it. `take` is tried
+    /// first, and again whenever the context is woken up. `ready` tells whether `take` would
+    /// succeed without taking anything; it is checked once the context is in the queue, and the
+    /// queue is woken up according to its policy if it would, so that a wake-up that happened in
+    /// between is not missed.
+    ///
+    /// This is what kernel objects use to wait for their state to change. The code that changes it
+    /// wakes up the queue afterwards.
+    pub fn wait_until<T>(
+        &self,
+        timeout: Option<u64>,
+        mut take: impl FnMut() -> Option<T>,
+        ready: impl Fn() -> bool,
+    ) -> Result<T, WaitError> {
+        let deadline = timeout.map(|timeout| scheduler::ticks() + timeout);
+
+        loop {
+            if let Some(value) = take() {
+                return Ok(value);
+            }
 
+            let timeout = match deadline {
+                Some(deadline) => match deadline.checked_sub(scheduler::ticks()) {
+                    Some(left) if left > 0 => Some(left),
+                    _ => return Err(WaitError::TimedOut),
+                },
+                None => None,
+            };
+
+            self.wait_with(timeout, || {
+                if ready() {
+                    self.wake_up();
+                }
+            })?;
+        }
+    }
+
+    /// Block a context that was put in the queue, until `time` if there is one.
+    fn sleep(context: &mut Context, time: Option<u64>) {
+        let reason = "Waiting on a sleep queue";
+        match time {
+            Some(time) => scheduler::block_until(context, reason, time),
+            None => scheduler::block(context, reason),
+        }
     }
 
-    /// Remove a context from the sleep-queue and make it runnable.
-    fn resume(context: &Context) {
+    /// Remove a context from the queue, returning whether it was in it.
+    fn remove(&self, id: ContextId) -> bool {
+        without_interrupts(|| {
+            let mut queue = self.queue.lock();
+            match queue.iter().position(|&queued| queued == id) {
+                Some(index) => queue.remove(index).is_some(),
+                None => false,
+            }
+        })
+    }
+
+    /// Remove contexts from the queue according to a policy and return them. Custom policies need
+    /// to look at the contexts, which cannot be locked while the queue is, so every context in the
+    /// queue is returned for them instead, and left in it.
+    pub(crate) fn get_contexts(&self, policy: &WakeupPolicy) -> Vec<ContextId> {
+        without_interrupts(|| {
+            let mut queue = self.queue.lock();
+            let queue_len = queue.len();
+
+            match *policy {
+                WakeupPolicy::Front(count) => queue.drain(..count.min(queue_len)).collect(),
+                WakeupPolicy::Back(count) => {
+                    queue.drain(queue_len - count.min(queue_len)..).collect()
+                }
+                WakeupPolicy::All => queue.drain(..).collect(),
+                WakeupPolicy::Custom(_) => queue.iter().copied().collect(),
+            }
+        })
     }
 
-    /// Wake up contexts in the queue.
-    fn wake_up(&mut self) -> usize {
-        let queue = Arc::try_unwrap(self.queue).map(RwLock::into_inner)?;
-        let queue_len = queue.len();
+    /// Remove a context from the sleep-queue and make it runnable. Returns whether it was in the
+    /// queue.
+    pub fn resume(&self, context: &mut Context) -> bool {
+        if !self.remove(context.id) {
+            return false;
+        }
 
-        let contexts = match self.policy {
-            WakeupPolicy::Front(count) => queue.drain(0..count),
-            WakeupPolicy::Back(count) => queue.drain(queue_len .. (queue_len - count)),
-            WakeupPolicy::All => queue.drain(0..queue_len),
-            WakeupPolicy::Custom(matcher) => 
-                iter
-                    .filter(matcher)
-                    .enumerate()
-                    .map(|(&index, &context_id)| queue.remove(index))
-                    .collect(),
+        scheduler::wake(context);
+        true
+    }
+
+    /// Wake up contexts in the queue according to the queue's policy. Returns the number of
+    /// contexts that were woken up.
+    pub fn wake_up(&self) -> usize {
+        self.wake(&self.policy)
+    }
+
+    /// Wake up contexts in the queue according to the queue's policy, from an interrupt handler.
+    /// See [`SleepQueue::wake_from_interrupt`].
+    pub fn wake_up_from_interrupt(&self) -> usize {
+        self.wake_from_interrupt(&self.policy)
+    }
+
+    /// Wake up contexts in the queue according to the given policy, without waiting for locks that
+    /// the interrupted code may hold, and without allocating. Contexts that cannot be locked right
+    /// away are woken up at the next tick (see [`scheduler::wake_from_interrupt`]), except for
+    /// custom policies, which leave them in the queue since they cannot be matched. Returns the
+    /// number of contexts that were taken out of the queue.
+    pub fn wake_from_interrupt(&self, policy: &WakeupPolicy) -> usize {
+        // Contexts that queue again right away cannot keep the handler going.
+        let count = match *policy {
+            WakeupPolicy::Front(count) | WakeupPolicy::Back(count) => count.min(self.len()),
+            WakeupPolicy::All | WakeupPolicy::Custom(_) => self.len(),
         };
+
+        let mut woken = 0;
+        while woken < count {
+            let id = match self.take_one(policy) {
+                Some(id) => id,
+                None => break,
+            };
+
+            scheduler::wake_from_interrupt(id);
+            woken += 1;
+        }
+        woken
+    }
+
+    /// Take the next context that a policy wakes up out of the queue, one at a time so that
+    /// nothing is allocated. Contexts that custom policies cannot lock right away are skipped.
+    fn take_one(&self, policy: &WakeupPolicy) -> Option<ContextId> {
+        without_interrupts(|| {
+            let mut queue = self.queue.lock();
+
+            match *policy {
+                WakeupPolicy::Front(_) | WakeupPolicy::All => queue.pop_front(),
+                WakeupPolicy::Back(_) => queue.pop_back(),
+                WakeupPolicy::Custom(matcher) => {
+                    // Contexts can only be tried while the queue is locked (see `get_contexts`).
+                    let contexts = context::try_contexts()?;
+                    let index = queue.iter().position(|&id| {
+                        let context = contexts.get(id).and_then(|context| context.try_read());
+                        context.map_or(false, |context| matcher(&context))
+                    })?;
+                    queue.remove(index)
+                }
+            }
+        })
+    }
+
+    /// Wake up contexts in the queue according to the given policy. Returns the number of
+    /// contexts that were woken up.
+    pub fn wake(&self, policy: &WakeupPolicy) -> usize {
+        let ids = self.get_contexts(policy);
+        let contexts = context::contexts();
+        let mut woken = 0;
+
+        for id in ids {
+            // The context may have exited since.
+            let mut context = match contexts.get(id) {
+                Some(context) => context.write(),
+                None => continue,
+            };
+
+            match policy {
+                WakeupPolicy::Custom(matcher) => {
+                    if matcher(&context) && self.resume(&mut context) {
+                        woken += 1;
+                    }
+                }
+                _ => {
+                    scheduler::wake(&mut context);
+                    woken += 1;
+                }
+            }
+        }
+        woken
     }
 }
@@ -1,16 +1,170 @@
-use crate::context::{Context, Status};
-use crate::machine;
+use alloc::sync::Arc;
+
+#[cfg(feature = "lockdep")]
+use core::panic::Location;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::context::percpu::{self, percpu};
+use crate::context::{self, process, scheduler, Context, ContextId, Status};
+use crate::machine::context::Context as MachineContext;
+use crate::machine::{self, irq};
+#[cfg(feature = "lockdep")]
+use crate::sync::lockdep;
+
+percpu! {
+    /// Identifier of the context that each CPU is switching away from, until the context that is
+    /// switched to finishes the switch. Zero when no switch is in progress.
+    static SWITCHED_FROM: AtomicUsize = AtomicUsize::new(0);
+}
 
 /// Determines if a context is able to be run by the specified CPU.
-unsafe fn runnable(context; &Context, cpu_id: usize) -> bool {
-    !context.running && context.status == Status::Runnable && context.cpu_id == Some(cpu_id)
+fn runnable(context: &Context, cpu_id: usize) -> bool {
+    !context.running
+        && !context.switching
+        && context.status == Status::Runnable
+        && context.cpu.map_or(true, |cpu| cpu == cpu_id)
+        && context.affinity.contains(cpu_id)
+}
+
+/// Pick the next context and update the state of both contexts, returning where their registers
+/// are saved. Nothing can be locked here, since the locks may be held by the code that was
+/// interrupted; in that case, the switch is given up on.
+unsafe fn prepare(cpu_id: usize) -> Option<(*mut MachineContext, *const MachineContext)> {
+    let contexts = context::try_contexts()?;
+    let prev_lock = contexts.current()?;
+    let mut prev = prev_lock.try_write()?;
+
+    let prev_eligible = prev.status == Status::Runnable
+        && prev.cpu.map_or(true, |cpu| cpu == cpu_id)
+        && prev.affinity.contains(cpu_id);
+
+    let mut next = None;
+    let next_id = scheduler::select(cpu_id, &prev, prev_eligible, |id| {
+        match contexts.get(id).and_then(|context| context.try_write()) {
+            Some(context) if runnable(&context, cpu_id) => {
+                next = Some(context);
+                true
+            }
+            _ => false,
+        }
+    })?;
+
+    let mut next = match next {
+        Some(next) => next,
+        // The previous context was picked again.
+        None => return None,
+    };
+    debug_assert_eq!(next.id, next_id);
+
+    // No other CPU may switch to the previous context before its registers are saved, which only
+    // the context that is switched to can tell.
+    prev.running = false;
+    prev.switching = true;
+    next.running = true;
+    SWITCHED_FROM.get().store(prev.id.get(), Ordering::Relaxed);
+    context::set_context_id(next.id);
+
+    // A context that may no longer run on this CPU goes to the run queue of another one. It cannot
+    // be switched to there before its registers are saved, since it is switching until then.
+    if prev.status == Status::Runnable && !prev_eligible {
+        scheduler::migrate(&mut prev);
+    }
+
+    // Kernel contexts keep using whichever address space was active. The root table of an
+    // address space never changes, so it can be activated without taking its lock (which may be
+    // held by the context that was interrupted).
+    if let Some(addr_space) = next.addr_space() {
+        let same = prev
+            .addr_space()
+            .map_or(false, |prev_space| Arc::ptr_eq(prev_space, addr_space));
+        if !same {
+            (*addr_space.as_mut_ptr()).activate();
+        }
+    }
+
+    Some((&mut prev.machine, &next.machine))
 }
 
 /// Switch to the next context. By the time this function will return, the current context will
-/// have been restored by someone else and a lot of time might have passed.
+/// have been restored by someone else and a lot of time might have passed. Returns whether a
+/// switch happened.
 ///
 /// # Safety
 /// Do not call this when holding locks.
+#[cfg_attr(feature = "lockdep", track_caller)]
 pub unsafe fn switch() -> bool {
+    #[cfg(feature = "lockdep")]
+    lockdep::check_switch(Location::caller());
+
+    let enabled = irq::enabled();
+    irq::disable();
+
+    // The preemption count belongs to this context, and comes back with it. The one that is
+    // switched to restores its own.
+    let preempt = percpu::take_preempt_count();
+
+    let switched = match prepare(context::cpu_id()) {
+        Some((prev, next)) => {
+            machine::switch::switch(prev, next);
+            true
+        }
+        None => false,
+    };
+
+    // Either nothing happened, or this context was just switched back to.
+    finish();
+    percpu::restore_preempt_count(preempt);
+
+    if enabled {
+        irq::enable();
+    }
+    switched
+}
+
+/// Switch to a more urgent context if one became runnable, unless the interrupted code holds a spin
+/// lock (see [`percpu::preempt_disable`]). Called on the way out of interrupt handlers.
+///
+/// # Safety
+/// Interrupts must be disabled, and the interrupted code must be able to be resumed later.
+pub unsafe fn preempt() {
+    if scheduler::switch_pending() && percpu::preemptible() {
+        switch();
+    }
+}
+
+/// Let other CPUs switch to the previous context once a switch is over, since its registers are
+/// saved, and wake up the contexts waiting for it if it exited. Called with interrupts disabled.
+fn finish() {
+    let id = SWITCHED_FROM.get().swap(0, Ordering::Relaxed);
+    if id == 0 {
+        return;
+    }
+
+    // The context that was switched to holds no locks, since it switched away itself or was
+    // preempted, so the previous context can be waited for.
+    let exited = match context::contexts().get(ContextId::new(id)) {
+        Some(prev) => {
+            let mut prev = prev.write();
+            prev.switching = false;
+            matches!(prev.status, Status::Exited(_))
+        }
+        None => false,
+    };
+
+    if exited {
+        process::switched_away();
+    }
+}
+
+/// Entry point of new kernel contexts, called by the architecture's trampoline the first time the
+/// context is switched to.
+pub extern "C" fn kernel_entry(entry: extern "C" fn()) -> ! {
+    finish();
+    unsafe { irq::enable() };
+
+    entry();
 
+    // Kernel contexts have no parent to wait for them, so exiting reaps the context once it
+    // switched away for the last time, which frees its stack.
+    process::exit(0);
 }
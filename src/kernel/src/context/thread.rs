@@ -0,0 +1,204 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::context::list::KERNEL_STACK_SIZE;
+use crate::context::memory::MemoryError;
+use crate::context::signal::{self, SIGKILL};
+use crate::context::{self, process, scheduler, Context, ContextId, Priority, Status};
+use crate::machine::interrupt::InterruptStack;
+use crate::sync::Mutex;
+
+/// Functions that kernel threads created by [`spawn_kernel`] run, until the threads take them.
+static KERNEL_FUNCTIONS: Mutex<BTreeMap<ContextId, Box<dyn FnOnce() + Send>>> =
+    Mutex::new(BTreeMap::new());
+
+/// Threads that make up a process. They share the address space, the file table and the signal
+/// actions of the process, and the process ends when its last thread exits. The identifier of the
+/// process is the one of the thread that created the group, its leader.
+pub struct ThreadGroup {
+    /// Identifier of the thread that created the group.
+    leader: ContextId,
+    /// Number of threads in the group that did not exit yet.
+    live: AtomicUsize,
+    /// Wait status of the process, once a thread ended it (or its last thread exited).
+    status: Mutex<Option<usize>>,
+}
+
+impl ThreadGroup {
+    /// Construct a group of one thread, its leader.
+    pub fn new(leader: ContextId) -> Self {
+        Self {
+            leader,
+            live: AtomicUsize::new(1),
+            status: Mutex::new(None),
+        }
+    }
+
+    /// Retrieve the identifier of the leader, which is the identifier of the process.
+    pub fn leader(&self) -> ContextId {
+        self.leader
+    }
+
+    /// Retrieve the number of threads in the group that did not exit yet.
+    pub fn live(&self) -> usize {
+        self.live.load(Ordering::SeqCst)
+    }
+
+    /// Retrieve the wait status of the process, if it ended.
+    pub fn status(&self) -> Option<usize> {
+        *self.status.lock()
+    }
+
+    /// Record the wait status of the process, unless another thread already did. Returns the
+    /// status that is kept.
+    pub(super) fn end(&self, status: usize) -> usize {
+        *self.status.lock().get_or_insert(status)
+    }
+
+    /// Count a thread that exits. Returns whether it was the last one.
+    pub(super) fn leave(&self) -> bool {
+        self.live.fetch_sub(1, Ordering::SeqCst) == 1
+    }
+}
+
+/// Create a thread in the process of the current context, which resumes user-space at `entry`
+/// with the given stack pointer and thread-local storage pointer, and is passed `arg`. It gets a
+/// kernel stack of its own, and shares everything else with the process.
+pub fn spawn(
+    registers: &InterruptStack,
+    entry: usize,
+    stack: usize,
+    tls: usize,
+    arg: usize,
+) -> Result<ContextId, MemoryError> {
+    let current = context::current().expect("Spawning outside of a context");
+
+    let id = context::contexts_mut().next_id();
+    let mut thread = {
+        let parent = current.read();
+        let addr_space = parent.addr_space().ok_or(MemoryError::NotMapped)?;
+
+        let mut thread = Context::new(id);
+        thread.group_id = parent.group_id;
+        thread.parent_id = parent.parent_id;
+        thread.real_user_id = parent.real_user_id;
+        thread.real_group_id = parent.real_group_id;
+        thread.effective_user_id = parent.effective_user_id;
+        thread.effective_group_id = parent.effective_group_id;
+        thread.signal_mask = parent.signal_mask;
+        thread.priority = parent.priority;
+        thread.affinity = parent.affinity;
+        thread.addr_space = Some(Arc::clone(addr_space));
+        thread.name = Arc::clone(&parent.name);
+        thread.current_dir = parent.current_dir.clone();
+        thread.files = Arc::clone(&parent.files);
+        thread.signal_actions = Arc::clone(&parent.signal_actions);
+        thread.thread_group = Arc::clone(&parent.thread_group);
+        thread
+    };
+
+    let stack_area = vec![0; KERNEL_STACK_SIZE].into_boxed_slice();
+    let frame = process::user_registers(&stack_area);
+    unsafe {
+        frame.write(registers.clone());
+        (*frame).enter_thread(entry, stack, arg);
+        thread.machine.set_tls(&mut *frame, tls);
+        thread
+            .machine
+            .set_entry(frame as usize, process::user_entry);
+    }
+    thread.kernel_stack = Some(stack_area);
+
+    // A thread that ends the process looks for the threads to kill in the list, so the thread is
+    // killed right away if the process ended before it was added.
+    let mut contexts = context::contexts_mut();
+    let group = Arc::clone(&thread.thread_group);
+    group.live.fetch_add(1, Ordering::SeqCst);
+    let thread = contexts.insert(thread);
+    let mut thread = thread.write();
+    if group.status().is_some() {
+        signal::send(&mut thread, SIGKILL);
+    }
+    scheduler::wake(&mut thread);
+    Ok(id)
+}
+
+/// Terminate the current thread with the given wait status. The process goes on unless it was its
+/// last thread. See [`process::exit`] for ending every thread of the process.
+pub fn exit(status: usize) -> ! {
+    process::terminate(status, false)
+}
+
+/// Kill every thread of a group other than `except`, which ends the process.
+pub(super) fn kill_group(group: &ThreadGroup, except: ContextId) {
+    let contexts = context::contexts();
+    for (&id, context) in contexts.iter() {
+        if id == except {
+            continue;
+        }
+
+        let mut context = context.write();
+        let member = context.thread_group.leader() == group.leader();
+        if member && !matches!(context.status, Status::Exited(_)) {
+            signal::send(&mut context, SIGKILL);
+        }
+    }
+}
+
+/// Remove a thread that exited from the list once it stopped running, which frees its kernel stack.
+/// Threads other than the leader of their group are not waited for, so they are removed by a
+/// worker instead.
+pub(super) fn reap(id: ContextId) {
+    loop {
+        let mut contexts = context::contexts_mut();
+        let running = match contexts.get(id) {
+            Some(context) => {
+                let context = context.read();
+                context.running || context.switching
+            }
+            None => return,
+        };
+
+        if !running {
+            contexts.remove(id);
+            return;
+        }
+
+        // The thread has not switched away for the last time yet.
+        drop(contexts);
+        unsafe { context::switch() };
+    }
+}
+
+/// Create a kernel thread that runs `function` with the given priority, and make it runnable. It
+/// exits once the function returns.
+pub fn spawn_kernel(
+    name: &str,
+    priority: Priority,
+    function: impl FnOnce() + Send + 'static,
+) -> ContextId {
+    // The list stays locked until the function is stored, which the thread waits for by locking it.
+    let mut contexts = context::contexts_mut();
+    let context = contexts.spawn(priority, kernel_thread_entry);
+    let context = context.read();
+    *context.name.write() = Box::from(name);
+    KERNEL_FUNCTIONS
+        .lock()
+        .insert(context.id, Box::new(function));
+    context.id
+}
+
+/// Entry point of kernel threads created by [`spawn_kernel`].
+extern "C" fn kernel_thread_entry() {
+    drop(context::contexts());
+
+    let function = KERNEL_FUNCTIONS
+        .lock()
+        .remove(&context::context_id())
+        .expect("Kernel thread without a function");
+    function();
+}
@@ -0,0 +1,211 @@
+use core::fmt;
+
+use crate::context::{self, ContextId, SchedClass};
+use crate::machine;
+
+/// Number of buckets of a [`Histogram`].
+pub const HISTOGRAM_BUCKETS: usize = 16;
+
+/// Histogram of durations, in scheduler ticks. Bucket zero counts durations of zero, bucket `n`
+/// counts durations from `2^(n - 1)` up to (but not including) `2^n`, and the last bucket counts
+/// everything above.
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+pub struct Histogram {
+    /// Number of durations in each bucket.
+    pub buckets: [u64; HISTOGRAM_BUCKETS],
+    /// Number of recorded durations.
+    pub count: u64,
+    /// Sum of the recorded durations.
+    pub sum: u64,
+    /// Shortest recorded duration.
+    pub min: u64,
+    /// Longest recorded duration.
+    pub max: u64,
+}
+
+impl Histogram {
+    /// Construct an empty histogram.
+    pub const fn new() -> Self {
+        Self {
+            buckets: [0; HISTOGRAM_BUCKETS],
+            count: 0,
+            sum: 0,
+            min: 0,
+            max: 0,
+        }
+    }
+
+    /// Retrieve the index of the bucket that counts a duration.
+    pub fn bucket(value: u64) -> usize {
+        (u64::BITS - value.leading_zeros()).min(HISTOGRAM_BUCKETS as u32 - 1) as usize
+    }
+
+    /// Retrieve the shortest duration that a bucket counts.
+    pub fn bucket_start(index: usize) -> u64 {
+        match index {
+            0 => 0,
+            index => 1 << (index - 1),
+        }
+    }
+
+    /// Record a duration.
+    pub fn record(&mut self, value: u64) {
+        self.buckets[Self::bucket(value)] += 1;
+        self.min = if self.count == 0 {
+            value
+        } else {
+            self.min.min(value)
+        };
+        self.max = self.max.max(value);
+        self.count += 1;
+        self.sum += value;
+    }
+
+    /// Retrieve the mean of the recorded durations (rounded down).
+    pub fn mean(&self) -> u64 {
+        self.sum.checked_div(self.count).unwrap_or(0)
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "count {} min {} mean {} max {}",
+            self.count,
+            self.min,
+            self.mean(),
+            self.max
+        )?;
+
+        for (index, &count) in self.buckets.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+
+            let start = Self::bucket_start(index);
+            if index == HISTOGRAM_BUCKETS - 1 {
+                writeln!(f, "    {:>5}+      {}", start, count)?;
+            } else {
+                let end = Self::bucket_start(index + 1);
+                writeln!(f, "    {:>5}-{:<5} {}", start, end - 1, count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Timing of the jobs of a context of the deadline class, in scheduler ticks. The layout is shared
+/// with user-space, which reads it with `SYS_SCHED_STATS`.
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+pub struct TimingStats {
+    /// Number of jobs that were completed.
+    pub jobs: u64,
+    /// Number of jobs that were completed after their deadline.
+    pub misses: u64,
+    /// Number of times that the budget of the reservation was used up.
+    pub overruns: u64,
+    /// Release time of the last completed job.
+    pub last_release: u64,
+    /// Completion time of the last completed job.
+    pub last_completion: u64,
+    /// Longest time that a job was completed after its deadline.
+    pub max_lateness: u64,
+    /// Time from the release of each job to its completion.
+    pub response: Histogram,
+    /// Difference between the response times of consecutive jobs.
+    pub jitter: Histogram,
+    /// CPU time that each job used beyond the budget (zero for jobs that stayed within it).
+    pub overrun: Histogram,
+}
+
+impl TimingStats {
+    /// Construct empty statistics.
+    pub const fn new() -> Self {
+        Self {
+            jobs: 0,
+            misses: 0,
+            overruns: 0,
+            last_release: 0,
+            last_completion: 0,
+            max_lateness: 0,
+            response: Histogram::new(),
+            jitter: Histogram::new(),
+            overrun: Histogram::new(),
+        }
+    }
+
+    /// Record the completion of a job that was released at `release`, had to be done by
+    /// `deadline`, and used `used` ticks of CPU time out of `budget`.
+    pub fn record(&mut self, release: u64, deadline: u64, completion: u64, used: u64, budget: u64) {
+        let response = completion.saturating_sub(release);
+        if self.jobs > 0 {
+            let previous = self.last_completion.saturating_sub(self.last_release);
+            self.jitter.record(response.abs_diff(previous));
+        }
+        self.response.record(response);
+        self.overrun.record(used.saturating_sub(budget));
+
+        if completion > deadline {
+            self.misses += 1;
+            self.max_lateness = self.max_lateness.max(completion - deadline);
+        }
+        self.jobs += 1;
+        self.last_release = release;
+        self.last_completion = completion;
+    }
+}
+
+impl fmt::Display for TimingStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "  jobs {} misses {} overruns {} max lateness {}",
+            self.jobs, self.misses, self.overruns, self.max_lateness
+        )?;
+        write!(f, "  response: {}", self.response)?;
+        write!(f, "  jitter: {}", self.jitter)?;
+        write!(f, "  overrun: {}", self.overrun)
+    }
+}
+
+/// Retrieve the timing statistics of a context, if it is of the deadline class.
+pub fn stats(id: ContextId) -> Option<TimingStats> {
+    let context = context::contexts().get(id)?.clone();
+    let context = context.read();
+
+    match &context.class {
+        SchedClass::Deadline(reservation) => Some(reservation.stats),
+        SchedClass::Fixed => None,
+    }
+}
+
+/// Write the timing statistics of every context of the deadline class to the debugging console.
+pub fn dump() {
+    let contexts = context::contexts();
+
+    for (id, context) in contexts.iter() {
+        // Copy the statistics out, so that the context is not locked while they are written.
+        let (name, params, stats) = {
+            let context = context.read();
+            match &context.class {
+                SchedClass::Deadline(reservation) => {
+                    (context.name.clone(), reservation.params, reservation.stats)
+                }
+                SchedClass::Fixed => continue,
+            }
+        };
+
+        machine::debug::print(format_args!(
+            "context {} ({}): period {} deadline {} budget {}\n{}",
+            id.get(),
+            name.read(),
+            params.period,
+            params.deadline,
+            params.budget,
+            stats
+        ));
+    }
+}
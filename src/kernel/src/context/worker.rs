@@ -0,0 +1,206 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+use crate::context::{self, scheduler, switch, thread, ContextId, Priority, Status};
+use crate::sync::IrqMutex;
+
+/// Number of scheduler ticks after which an idle worker looks for work again, in case it missed a
+/// wake-up (see [`WorkQueue::queue`]).
+const POLL_TICKS: u64 = 10;
+
+/// Closure that is deferred to a worker.
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Work that is allocated ahead of time, usually in a static, so that it can be queued without
+/// allocating (see [`WorkQueue::queue_work`]). It is in a queue at most once: queuing it again
+/// before a worker takes it does nothing, and the function then runs once for both.
+pub struct Work {
+    /// Function that does the work.
+    function: fn(),
+    /// Whether the work waits for a worker.
+    queued: AtomicBool,
+    /// Next work in the queue, which is only touched with the queue locked.
+    next: AtomicPtr<Work>,
+}
+
+/// Intrusive list of [`Work`] that waits for a worker, oldest first.
+struct WorkList {
+    head: Option<&'static Work>,
+    tail: Option<&'static Work>,
+}
+
+/// Queue of work shared by every part of the kernel. It is served by one worker.
+pub static SYSTEM: WorkQueue = WorkQueue::new();
+
+/// Work queues let code defer work to kernel threads, its workers. Drivers use them to do the
+/// part of handling an interrupt that cannot be done in the handler, since it blocks or takes
+/// long. Work of each kind is done in the order that it was queued, by whichever worker is free;
+/// preallocated [`Work`] goes before closures.
+pub struct WorkQueue {
+    /// Preallocated work that no worker took yet, which interrupt handlers queue.
+    work: IrqMutex<WorkList>,
+    /// Closures that no worker took yet, oldest first.
+    jobs: IrqMutex<VecDeque<Job>>,
+    /// Identifiers of the workers.
+    workers: IrqMutex<Vec<ContextId>>,
+}
+
+impl Work {
+    /// Construct [`Work`] that runs the given function, which is not queued yet.
+    pub const fn new(function: fn()) -> Self {
+        Self {
+            function,
+            queued: AtomicBool::new(false),
+            next: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+}
+
+impl WorkList {
+    /// Append work, which must not be in a list.
+    fn push(&mut self, work: &'static Work) {
+        work.next.store(ptr::null_mut(), Ordering::Relaxed);
+        match self.tail {
+            Some(tail) => tail
+                .next
+                .store(work as *const Work as *mut Work, Ordering::Relaxed),
+            None => self.head = Some(work),
+        }
+        self.tail = Some(work);
+    }
+
+    /// Remove the oldest work.
+    fn pop(&mut self) -> Option<&'static Work> {
+        let work = self.head?;
+        // The pointer came from a `&'static Work` in `push`.
+        self.head = unsafe { work.next.load(Ordering::Relaxed).as_ref() };
+        if self.head.is_none() {
+            self.tail = None;
+        }
+        Some(work)
+    }
+}
+
+impl WorkQueue {
+    /// Construct an empty [`WorkQueue`], without workers.
+    pub const fn new() -> Self {
+        Self {
+            work: IrqMutex::new(WorkList {
+                head: None,
+                tail: None,
+            }),
+            jobs: IrqMutex::new(VecDeque::new()),
+            workers: IrqMutex::new(Vec::new()),
+        }
+    }
+
+    /// Create `count` workers for the queue, which run with the given priority.
+    pub fn start(&'static self, name: &str, priority: Priority, count: usize) {
+        for _ in 0..count {
+            let id = thread::spawn_kernel(name, priority, move || self.run());
+            self.workers.lock().push(id);
+        }
+    }
+
+    /// Retrieve the number of closures that no worker took yet.
+    pub fn len(&self) -> usize {
+        self.jobs.lock().len()
+    }
+
+    /// Whether no work is waiting for a worker.
+    pub fn is_empty(&self) -> bool {
+        self.work.lock().head.is_none() && self.len() == 0
+    }
+
+    /// Queue a closure, and wake up an idle worker to run it. The closure is allocated, so this
+    /// must not be called from interrupt handlers; they queue preallocated work with
+    /// [`WorkQueue::queue_work`].
+    pub fn queue(&self, work: impl FnOnce() + Send + 'static) {
+        self.jobs.lock().push_back(Box::new(work));
+        self.wake_worker();
+    }
+
+    /// Queue preallocated work, unless it is already queued, and wake up an idle worker to do it.
+    /// This does not allocate, and may be called from interrupt handlers.
+    pub fn queue_work(&self, work: &'static Work) {
+        if work.queued.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        self.work.lock().push(work);
+        self.wake_worker();
+    }
+
+    /// Wake up an idle worker, if that can be done without waiting for a lock (since this may be
+    /// called from interrupt handlers). Otherwise, the work is done once a worker polls the queue.
+    fn wake_worker(&self) {
+        let contexts = match context::try_contexts() {
+            Some(contexts) => contexts,
+            None => return,
+        };
+        let workers = match self.workers.try_lock() {
+            Some(workers) => workers,
+            None => return,
+        };
+
+        for &id in workers.iter() {
+            let context = contexts.get(id).and_then(|context| context.try_write());
+            if let Some(mut context) = context {
+                if context.status == Status::Blocked {
+                    scheduler::wake(&mut context);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Do queued work forever. Run by the workers.
+    fn run(&self) {
+        loop {
+            let work = self.work.lock().pop();
+            if let Some(work) = work {
+                // The work may be queued again while it runs, to run once more.
+                work.queued.store(false, Ordering::Release);
+                (work.function)();
+                continue;
+            }
+
+            let job = self.jobs.lock().pop_front();
+            match job {
+                Some(job) => job(),
+                None => self.idle(),
+            }
+        }
+    }
+
+    /// Block the current worker until work is queued, or for at most [`POLL_TICKS`].
+    fn idle(&self) {
+        let current = context::current().expect("Worker without a context");
+
+        // The worker is locked while it looks for work and blocks, so a context that queues work
+        // in between either sees it blocked, or fails to lock it and leaves the work to the poll.
+        {
+            let mut context = current.write();
+            if !self.is_empty() {
+                return;
+            }
+
+            let time = scheduler::ticks() + POLL_TICKS;
+            scheduler::block_until(&mut context, "Waiting for work", time);
+        }
+
+        while current.read().status == Status::Blocked {
+            unsafe { switch() };
+        }
+        scheduler::cancel_timeout(&mut current.write());
+    }
+}
+
+/// Start the workers of the [`SYSTEM`] queue.
+pub fn init() {
+    SYSTEM.start("worker", Priority::DEFAULT, 1);
+}
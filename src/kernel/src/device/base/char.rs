@@ -1,6 +1,6 @@
-use crate::device::{Device, DeviceError, DeviceSwitch};
-use crate::sync::rwlock::RwLock;
-use crate::sync::Yield;
+use crate::device::{Device, DeviceError, DeviceOpers, DeviceSwitch};
+use crate::sync::{RwLock, Yield};
+use alloc::boxed::Box;
 use alloc::collections::vec_deque::VecDeque;
 use alloc::sync::Arc;
 use core::fmt;
@@ -17,19 +17,19 @@ pub trait CharDeviceSwitch: DeviceSwitch {
 /// both character and block devices).
 pub struct CharDevice {
     /// Inner character device switch.
-    inner: dyn CharDeviceSwitch,
+    inner: Box<dyn CharDeviceSwitch + Send + Sync>,
     /// Queue for characters to be written to the device.
     queue: Arc<RwLock<VecDeque<u8>, Yield>>,
 }
 
 impl Device for CharDevice {
     /// Read the given number of bytes (based on the size of the buffer array).
-    fn read(&self, position: usize, buffer: &[u8]) -> Result<usize, DeviceError> {
+    fn read(&mut self, _position: usize, buffer: &mut [u8]) -> Result<usize, DeviceError> {
         // We can ignore the position parameter, which is better than reading them just to skip
         // over them.
-        let mut queue = Arc::try_unwrap(self.queue).map(RwLock::into_inner)?;
+        let mut queue = self.queue.write();
         for byte in buffer.iter_mut() {
-            *byte = match self.queue.pop_front() {
+            *byte = match queue.pop_front() {
                 Some(character) => character,
                 None => self.inner.get_char()?,
             };
@@ -39,11 +39,10 @@ impl Device for CharDevice {
     }
 
     /// Write all the given bytes to the device.
-    fn write(&mut self, position: usize, buffer: &[u8]) -> Result<usize, DeviceError> {
-        let mut queue = Arc::try_unwrap(self.queue).map(RwLock::into_inner)?;
-        if queue.len() > 0 {
-            let mut to_append: VecDeque<u8> = buffer.into();
-            queue.append(to_append);
+    fn write(&mut self, _position: usize, buffer: &[u8]) -> Result<usize, DeviceError> {
+        let mut queue = self.queue.write();
+        if !queue.is_empty() {
+            queue.extend(buffer);
         } else {
             for &byte in buffer {
                 self.inner.put_char(byte)?;
@@ -54,28 +53,36 @@ impl Device for CharDevice {
     }
 
     /// Perform an I/O control operation.
-    fn io_control(&mut self, command: usize, buffer: &[u8]) -> Result<usize, DeviceError> {
+    fn io_control(&mut self, command: usize, buffer: &[u8]) -> Result<(), DeviceError> {
         self.inner.io_control(command, buffer)
     }
 }
 
+impl DeviceSwitch for CharDevice {
+    /// Wrapper for DeviceSwitch::io_control.
+    fn io_control(&mut self, command: usize, buffer: &[u8]) -> Result<(), DeviceError> {
+        self.inner.io_control(command, buffer)
+    }
+
+    /// Wrapper for DeviceSwitch::poll.
+    fn poll(&self) -> DeviceOpers {
+        self.inner.poll()
+    }
+}
+
 impl CharDeviceSwitch for CharDevice {
     /// Wrapper for CharDeviceSwitch::get_char.
     fn get_char(&self) -> Result<u8, DeviceError> {
-        let mut queue = Arc::try_unwrap(self.queue).map(RwLock::into_inner)?;
-
-        Ok(match queue.pop_front() {
-            Some(queued) => queued,
+        match self.queue.write().pop_front() {
+            Some(queued) => Ok(queued),
             None => self.inner.get_char(),
-        })
+        }
     }
 
     /// Wrapper for CharDeviceSwitch::put_char.
     fn put_char(&mut self, byte: u8) -> Result<(), DeviceError> {
-        if let Err(_) = self.inner.put_char(byte) {
-            Arc::try_unwrap(self.queue)
-                .map(RwLock::into_inner)?
-                .push_back(byte);
+        if self.inner.put_char(byte).is_err() {
+            self.queue.write().push_back(byte);
         }
 
         Ok(())
@@ -88,11 +95,11 @@ impl fmt::Write for CharDevice {
     fn write_str(&mut self, string: &str) -> fmt::Result {
         // TODO: Match result of [`CharDevice::put_char`] to handle any device errors.
         for byte in string.bytes() {
-            if byte == '\n' as u8 {
-                self.put_char('\r' as u8);
+            if byte == b'\n' {
+                let _ = self.put_char(b'\r');
             }
 
-            self.put_char(byte);
+            let _ = self.put_char(byte);
         }
 
         Ok(())
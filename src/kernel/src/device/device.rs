@@ -19,7 +19,7 @@ pub trait DeviceSwitch {
 
     /// Poll the device for I/O readiness.
     fn poll(&self) -> DeviceOpers {
-        0.into()
+        DeviceOpers::empty()
     }
 }
 
@@ -31,7 +31,7 @@ pub trait DeviceSwitch {
 pub trait Device {
     /// Read the given number of bytes (through the buffer length) into the provided buffer, from
     /// the given location.
-    fn read(&self, position: usize, buffer: &[u8]) -> Result<usize, DeviceError>;
+    fn read(&mut self, position: usize, buffer: &mut [u8]) -> Result<usize, DeviceError>;
     /// Write the given buffer of points at the given location.
     fn write(&mut self, position: usize, buffer: &[u8]) -> Result<usize, DeviceError>;
     /// Perform an I/O control operation. This is for operations that are specific to the device
@@ -36,7 +36,7 @@ bitflags::bitflags! {
 }
 
 /// One allocated per parallel port.
-#[repr(C, packed)]
+#[repr(C)]
 pub struct ParallelPort<T: IoVec> {
     /// Data register. Read to recieve and write to send.
     data: T,
@@ -77,22 +77,22 @@ where
 
     /// Retrieve the value of the control register.
     fn control(&self) -> ControlFlags {
-        ControlFlags::from_bits_truncate((self.status.read() & 0xff.into()).try_into().unwrap_or(0))
+        ControlFlags::from_bits_truncate(
+            (self.control.read() & 0xFF.into()).try_into().unwrap_or(0),
+        )
     }
 }
 
+impl<T: IoVec> device::DeviceSwitch for ParallelPort<T> {}
+
 impl<T: IoVec> device::base::char::CharDeviceSwitch for ParallelPort<T>
 where
     T::Value: From<u8> + TryInto<u8>,
 {
-    /// Read a byte from the parallel port.
+    /// Read a byte from the parallel port. This only returns what the printer sent if the port is
+    /// in bi-directional mode.
     fn get_char(&self) -> Result<u8, DeviceError> {
-        if self.line_status().contains(StatusFlags::INPUT_FULL) {
-            return Ok((self.data.read() & 0xFF.into()).try_into().unwrap_or(0));
-        }
-
-        // TODO: This should be an error here, but I have yet to implement devices::DeviceError.
-        Ok(0)
+        Ok((self.data.read() & 0xFF.into()).try_into().unwrap_or(0))
     }
 
     fn put_char(&mut self, byte: u8) -> Result<(), DeviceError> {
@@ -102,8 +102,8 @@ where
         // Pulse the strobe to tell the printer to read the data.
         let control = self.control();
         self.control
-            .write((control | ControlFlags::NO_STROBE).into());
-        self.control.write(control.into());
+            .write((control | ControlFlags::NO_STROBE).bits().into());
+        self.control.write(control.bits().into());
 
         Ok(())
     }
@@ -14,7 +14,7 @@ bitflags::bitflags! {
 }
 
 /// One allocated per serial port.
-#[repr(C, packed)]
+#[repr(C)]
 pub struct SerialPort<T: IoVec> {
     /// Data register. Read to recieve and write to send.
     data: T,
@@ -79,6 +79,8 @@ where
     }
 }
 
+impl<T: IoVec> device::DeviceSwitch for SerialPort<T> {}
+
 impl<T: IoVec> device::base::char::CharDeviceSwitch for SerialPort<T>
 where
     T::Value: From<u8> + TryInto<u8>,
@@ -1,9 +1,10 @@
+use crate::context::WaitError;
 use crate::device::DeviceError;
 use core::convert::From;
 
 /// Representation of an error as the result of an file-operation on a file-system. Provided through
 /// all I/O routines of file-systems (since they all return a `Result`).
-#[derive(Copy, Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum FileSystemError {
     NotSupported,
     EntryExists,
@@ -19,10 +20,21 @@ pub enum FileSystemError {
     ConnectionRefused,
     NotConnected,
     WouldBlock,
+    Device,
 }
 
 impl From<DeviceError> for FileSystemError {
-    fn from(device_error: DeviceError) -> Self {
-        match device_error {}
+    fn from(_: DeviceError) -> Self {
+        Self::Device
+    }
+}
+
+impl From<WaitError> for FileSystemError {
+    fn from(wait_error: WaitError) -> Self {
+        match wait_error {
+            // The system call is restarted or fails with `EINTR`, depending on the signal.
+            WaitError::Interrupted => Self::Interrupted,
+            WaitError::TimedOut => Self::WouldBlock,
+        }
     }
 }
@@ -1,6 +1,38 @@
+use alloc::collections::BTreeMap;
+
 use core::mem::{self, MaybeUninit};
+use core::ops::{Deref, DerefMut};
 use core::{ptr, slice};
 
+use crate::machine::paging::Mapper;
+use crate::machine::{DMA_OFFSET, DMA_SIZE};
+use crate::memory::paging::{PageFlags, PageMapper, PageSize};
+use crate::memory::{self, Frame, PAGE_SIZE};
+use crate::sync::Mutex;
+
+/// How the CPU caches memory that is shared with a device.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CacheMode {
+    /// Normal cached memory. Only suitable for devices that snoop the CPU's caches (which is the
+    /// case for PCI devices on x86-64).
+    WriteBack,
+    /// Reads are cached, but writes go straight to memory.
+    WriteThrough,
+    /// Memory is never cached. Needed for devices that do not snoop the CPU's caches.
+    Uncached,
+}
+
+impl CacheMode {
+    /// Retrieve the page flags that select the cache mode.
+    fn flags(&self) -> PageFlags {
+        match self {
+            CacheMode::WriteBack => PageFlags::empty(),
+            CacheMode::WriteThrough => PageFlags::WRITE_THROUGH,
+            CacheMode::Uncached => PageFlags::NO_CACHE,
+        }
+    }
+}
+
 /// A RAII guard of a physical memory allocation. Usually all physically allocated memory is page
 /// aligned and will take up at least 4k of space on x86-64.
 #[derive(Debug)]
@@ -12,10 +44,332 @@ pub struct PhysBox {
 }
 
 impl PhysBox {
-    /// Construct a physical box from a given address and size.
+    /// Construct a physical box from a given address and size. The memory is freed when the box
+    /// is dropped, so it must have been allocated from the frame allocator with the order that
+    /// fits the size.
     pub unsafe fn from_raw(address: usize, size: usize) -> Self {
         Self { address, size }
     }
 
-    
+    /// Allocate physically contiguous memory of (at least) the given size. The memory is aligned
+    /// to its size, rounded up to a power of two pages. Returns `None` if there is not enough
+    /// contiguous memory.
+    pub fn new(size: usize) -> Option<Self> {
+        let size = size.max(1);
+        let frame = memory::allocate_frames(memory::order_for_size(size))?;
+        let size = (size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+
+        Some(unsafe { Self::from_raw(frame.start_address(), size) })
+    }
+
+    /// Allocate physically contiguous memory of (at least) the given size, and fill it with zeros.
+    pub fn new_zeroed(size: usize) -> Option<Self> {
+        let phys = Self::new(size)?;
+        unsafe { ptr::write_bytes(memory::phys_to_virt(phys.address) as *mut u8, 0, phys.size) };
+        Some(phys)
+    }
+
+    /// Retrieve the physical address of the start of the memory.
+    pub fn address(&self) -> usize {
+        self.address
+    }
+
+    /// Retrieve the size of the memory, in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Give up ownership of the memory, and retrieve its address and size.
+    pub fn into_raw(self) -> (usize, usize) {
+        let raw = (self.address, self.size);
+        mem::forget(self);
+        raw
+    }
+}
+
+impl Drop for PhysBox {
+    fn drop(&mut self) {
+        unsafe {
+            memory::deallocate_frames(
+                Frame::containing_address(self.address),
+                memory::order_for_size(self.size),
+            );
+        }
+    }
+}
+
+/// View the bytes of a physical box. Used by drivers that fill buffers before handing them to a
+/// device.
+impl Deref for PhysBox {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(memory::phys_to_virt(self.address) as *const u8, self.size) }
+    }
+}
+
+impl DerefMut for PhysBox {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            slice::from_raw_parts_mut(memory::phys_to_virt(self.address) as *mut u8, self.size)
+        }
+    }
+}
+
+/// Free ranges of the DMA window, as a map of their start addresses to their sizes.
+static DMA_WINDOW: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+
+/// Create the page tables of the DMA window, so that they are shared by every address space that
+/// is created afterwards. The window fits in a single entry of the root table, so only that entry
+/// needs to exist up front.
+///
+/// # Safety
+/// Must be called once, after the frame allocator is initialized and before any address space is
+/// created.
+pub unsafe fn init() {
+    let mut mapper = Mapper::current();
+    mapper
+        .map(
+            DMA_OFFSET,
+            0,
+            PageSize::Small,
+            PageFlags::READ | PageFlags::GLOBAL,
+        )
+        .expect("Unable to create the DMA window");
+    mapper
+        .unmap(DMA_OFFSET)
+        .expect("Unable to create the DMA window");
+}
+
+/// Allocate a range of the DMA window.
+fn allocate_window(size: usize) -> Option<usize> {
+    let mut window = DMA_WINDOW.lock();
+    if window.is_empty() {
+        window.insert(DMA_OFFSET, DMA_SIZE);
+    }
+
+    let (&start, &free) = window.iter().find(|(_, &free)| free >= size)?;
+    window.remove(&start);
+    if free > size {
+        window.insert(start + size, free - size);
+    }
+
+    Some(start)
+}
+
+/// Give a range back to the DMA window, merging it with the free ranges around it.
+fn deallocate_window(mut start: usize, mut size: usize) {
+    let mut window = DMA_WINDOW.lock();
+
+    if let Some((&before, &free)) = window.range(..start).next_back() {
+        if before + free == start {
+            window.remove(&before);
+            start = before;
+            size += free;
+        }
+    }
+
+    if let Some(free) = window.remove(&(start + size)) {
+        size += free;
+    }
+
+    window.insert(start, size);
+}
+
+/// Map physical memory in the kernel's half of the address space with the given cache mode.
+/// Write-back memory is reached through the linear mapping of physical memory, and everything
+/// else is mapped in the DMA window.
+fn map(phys: &PhysBox, mode: CacheMode) -> Option<usize> {
+    if mode == CacheMode::WriteBack {
+        return Some(memory::phys_to_virt(phys.address));
+    }
+
+    let start = allocate_window(phys.size)?;
+    let flags = PageFlags::READ | PageFlags::WRITE | PageFlags::GLOBAL | mode.flags();
+
+    // The kernel's half is shared by every page table, so any of them can be used.
+    let mut mapper = unsafe { Mapper::current() };
+    for offset in (0..phys.size).step_by(PAGE_SIZE) {
+        let result = unsafe {
+            mapper.map(
+                start + offset,
+                phys.address + offset,
+                PageSize::Small,
+                flags,
+            )
+        };
+
+        if result.is_err() {
+            unmap(start, offset);
+            deallocate_window(start, phys.size);
+            return None;
+        }
+    }
+
+    Some(start)
 }
+
+/// Unmap the given range of the DMA window.
+fn unmap(start: usize, size: usize) {
+    let mut mapper = unsafe { Mapper::current() };
+    for offset in (0..size).step_by(PAGE_SIZE) {
+        let _ = unsafe { mapper.unmap(start + offset) };
+    }
+}
+
+/// Allocate zeroed physical memory and map it with the given cache mode. The memory is zeroed
+/// through the new mapping, so that no dirty cache lines are left behind for uncached memory.
+fn allocate(size: usize, mode: CacheMode) -> Option<(PhysBox, usize)> {
+    let phys = PhysBox::new(size)?;
+    let virt = map(&phys, mode)?;
+    unsafe { ptr::write_bytes(virt as *mut u8, 0, phys.size()) };
+    Some((phys, virt))
+}
+
+/// A value in physically contiguous memory, which can be handed to devices for direct memory
+/// access (DMA). The value can be used through its virtual address like a [`Box`], while
+/// [`Dma::physical`] gives the address that devices must be given. The memory is freed when the
+/// value is dropped.
+///
+/// [`Box`]: alloc::boxed::Box
+pub struct Dma<T: ?Sized> {
+    /// Physical memory holding the value.
+    phys: PhysBox,
+    /// Virtual address of the value.
+    virt: *mut T,
+    /// How the CPU caches the memory.
+    mode: CacheMode,
+}
+
+impl<T> Dma<T> {
+    /// Move a value into write-back cached DMA memory.
+    pub fn new(value: T) -> Option<Self> {
+        Self::with_mode(value, CacheMode::WriteBack)
+    }
+
+    /// Move a value into DMA memory with the given cache mode.
+    pub fn with_mode(value: T, mode: CacheMode) -> Option<Self> {
+        let mut dma = Dma::<T>::zeroed_with_mode(mode)?;
+        dma.write(value);
+        Some(unsafe { dma.assume_init() })
+    }
+
+    /// Allocate zeroed, write-back cached DMA memory for a value.
+    pub fn zeroed() -> Option<Dma<MaybeUninit<T>>> {
+        Self::zeroed_with_mode(CacheMode::WriteBack)
+    }
+
+    /// Allocate zeroed DMA memory for a value, with the given cache mode.
+    pub fn zeroed_with_mode(mode: CacheMode) -> Option<Dma<MaybeUninit<T>>> {
+        assert!(mem::align_of::<T>() <= PAGE_SIZE);
+
+        let (phys, virt) = allocate(mem::size_of::<T>(), mode)?;
+        Some(Dma {
+            phys,
+            virt: virt as *mut MaybeUninit<T>,
+            mode,
+        })
+    }
+}
+
+impl<T> Dma<[T]> {
+    /// Allocate zeroed, write-back cached DMA memory for a slice of the given length.
+    pub fn zeroed_slice(len: usize) -> Option<Dma<[MaybeUninit<T>]>> {
+        Self::zeroed_slice_with_mode(len, CacheMode::WriteBack)
+    }
+
+    /// Allocate zeroed DMA memory for a slice of the given length, with the given cache mode.
+    pub fn zeroed_slice_with_mode(len: usize, mode: CacheMode) -> Option<Dma<[MaybeUninit<T>]>> {
+        assert!(mem::align_of::<T>() <= PAGE_SIZE);
+
+        let (phys, virt) = allocate(mem::size_of::<T>().checked_mul(len)?, mode)?;
+        Some(Dma {
+            phys,
+            virt: ptr::slice_from_raw_parts_mut(virt as *mut MaybeUninit<T>, len),
+            mode,
+        })
+    }
+}
+
+impl<T> Dma<MaybeUninit<T>> {
+    /// Assume that the value has been initialized.
+    ///
+    /// # Safety
+    /// The memory must hold a valid value of `T` (which the zeroed memory might already be).
+    pub unsafe fn assume_init(self) -> Dma<T> {
+        let (phys, virt, mode) = self.into_parts();
+        Dma {
+            phys,
+            virt: virt as *mut T,
+            mode,
+        }
+    }
+}
+
+impl<T> Dma<[MaybeUninit<T>]> {
+    /// Assume that every element of the slice has been initialized.
+    ///
+    /// # Safety
+    /// The memory must hold valid values of `T` (which the zeroed memory might already be).
+    pub unsafe fn assume_init(self) -> Dma<[T]> {
+        let (phys, virt, mode) = self.into_parts();
+        Dma {
+            phys,
+            virt: virt as *mut [T],
+            mode,
+        }
+    }
+}
+
+impl<T: ?Sized> Dma<T> {
+    /// Retrieve the physical address of the value, which is what devices must be given.
+    pub fn physical(&self) -> usize {
+        self.phys.address()
+    }
+
+    /// Retrieve the size of the physical memory holding the value, in bytes.
+    pub fn size(&self) -> usize {
+        self.phys.size()
+    }
+
+    /// Retrieve how the CPU caches the memory.
+    pub fn cache_mode(&self) -> CacheMode {
+        self.mode
+    }
+
+    /// Take the allocation apart without dropping the value or unmapping it.
+    fn into_parts(self) -> (PhysBox, *mut T, CacheMode) {
+        let this = mem::ManuallyDrop::new(self);
+        let phys = unsafe { ptr::read(&this.phys) };
+        (phys, this.virt, this.mode)
+    }
+}
+
+impl<T: ?Sized> Deref for Dma<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.virt }
+    }
+}
+
+impl<T: ?Sized> DerefMut for Dma<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.virt }
+    }
+}
+
+impl<T: ?Sized> Drop for Dma<T> {
+    fn drop(&mut self) {
+        unsafe { ptr::drop_in_place(self.virt) };
+
+        if self.mode != CacheMode::WriteBack {
+            let start = self.virt as *mut u8 as usize;
+            unmap(start, self.phys.size());
+            deallocate_window(start, self.phys.size());
+        }
+    }
+}
+
+unsafe impl<T: ?Sized + Send> Send for Dma<T> {}
+unsafe impl<T: ?Sized + Sync> Sync for Dma<T> {}
@@ -1,9 +1,11 @@
+pub use self::dma::*;
 pub use self::io::*;
 pub use self::memmapped::*;
 
 #[cfg(target_arch = "x86_64")]
 pub use self::port::*;
 
+pub mod dma;
 mod io;
 mod memmapped;
 mod port;
@@ -4,10 +4,10 @@ pub mod x86_64;
 #[cfg(target_arch = "x86_64")]
 pub use self::x86_64::*;
 
-#[cfg(target_arch = "riscv")]
+#[cfg(target_arch = "riscv64")]
 #[macro_use]
 pub mod riscv;
-#[cfg(target_arch = "riscv")]
+#[cfg(target_arch = "riscv64")]
 pub use self::riscv::*;
 
 #[cfg(target_arch = "aarch64")]
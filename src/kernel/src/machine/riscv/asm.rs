@@ -0,0 +1,109 @@
+use core::arch::asm;
+
+/// Read the supervisor address translation and protection (satp) register.
+#[inline(always)]
+pub fn read_satp() -> usize {
+    let value: usize;
+    unsafe {
+        asm!("csrr {0}, satp", out(reg) value, options(nomem, nostack));
+    }
+    value
+}
+
+/// Write to the supervisor address translation and protection (satp) register. This changes the
+/// paging mode and the root page table, but does not flush the TLB.
+///
+/// # Safety
+/// The new page table must map the code that is currently running.
+#[inline(always)]
+pub unsafe fn write_satp(value: usize) {
+    asm!("csrw satp, {0}", in(reg) value, options(nostack));
+}
+
+/// Flush the TLB entries for the given virtual address, in every address space.
+#[inline(always)]
+pub unsafe fn sfence_vma(address: usize) {
+    asm!("sfence.vma {0}, zero", in(reg) address, options(nostack));
+}
+
+/// Flush the entire TLB.
+#[inline(always)]
+pub unsafe fn sfence_vma_all() {
+    asm!("sfence.vma zero, zero", options(nostack));
+}
+
+/// Write to the supervisor trap vector base address (stvec) register.
+///
+/// # Safety
+/// The address must point to a valid trap vector, aligned to four bytes.
+#[inline(always)]
+pub unsafe fn write_stvec(value: usize) {
+    asm!("csrw stvec, {0}", in(reg) value, options(nostack));
+}
+
+/// Write to the supervisor scratch (sscratch) register, which tells the trap vector where it was
+/// taken from (see [`super::percpu`]).
+///
+/// # Safety
+/// The value must be what the trap vector expects: zero while the kernel runs.
+#[inline(always)]
+pub unsafe fn write_sscratch(value: usize) {
+    asm!("csrw sscratch, {0}", in(reg) value, options(nostack));
+}
+
+/// Read the time CSR, which counts at the platform's timebase frequency.
+#[inline(always)]
+pub fn read_time() -> usize {
+    let value: usize;
+    unsafe {
+        asm!("rdtime {0}", out(reg) value, options(nomem, nostack));
+    }
+    value
+}
+
+/// Set bits of the supervisor interrupt enable (sie) register.
+///
+/// # Safety
+/// The interrupts that are enabled must have handlers.
+#[inline(always)]
+pub unsafe fn set_sie(bits: usize) {
+    asm!("csrs sie, {0}", in(reg) bits, options(nostack));
+}
+
+/// Set bits of the sstatus register.
+///
+/// # Safety
+/// Changing the status can break the assumptions of the code that runs afterwards.
+#[inline(always)]
+pub unsafe fn set_sstatus(bits: usize) {
+    asm!("csrs sstatus, {0}", in(reg) bits, options(nostack));
+}
+
+/// Read the thread pointer (tp) register, which holds the address of the per-CPU area of the
+/// running hart while in the kernel.
+#[inline(always)]
+pub fn read_tp() -> usize {
+    let value: usize;
+    unsafe {
+        asm!("mv {0}, tp", out(reg) value, options(nomem, nostack, preserves_flags));
+    }
+    value
+}
+
+/// Write to the thread pointer (tp) register.
+///
+/// # Safety
+/// The kernel expects `tp` to hold the address of the per-CPU area of the running hart.
+#[inline(always)]
+pub unsafe fn write_tp(value: usize) {
+    asm!("mv tp, {0}", in(reg) value, options(nomem, nostack, preserves_flags));
+}
+
+/// Clear bits of the supervisor interrupt pending (sip) register.
+///
+/// # Safety
+/// Clearing an interrupt that was not handled loses it.
+#[inline(always)]
+pub unsafe fn clear_sip(bits: usize) {
+    asm!("csrc sip, {0}", in(reg) bits, options(nostack));
+}
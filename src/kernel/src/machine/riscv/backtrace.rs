@@ -0,0 +1,38 @@
+use core::arch::asm;
+
+/// Largest frame that is followed, in bytes. Frame pointers further apart than this (or that do not
+/// go up the stack) are taken for something else, which ends the trace.
+const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/// Fill `frames` with the return addresses of the functions that called the current one, innermost
+/// first, by following the frame pointers. Returns how many were found. The kernel has to be built
+/// with frame pointers (`-C force-frame-pointers=yes`) for the trace to go further than the
+/// functions that keep one anyway.
+#[inline(always)]
+pub fn trace(frames: &mut [usize]) -> usize {
+    let mut frame: usize;
+    unsafe {
+        asm!("mv {0}, s0", out(reg) frame, options(nomem, nostack, preserves_flags));
+    }
+
+    let mut count = 0;
+    while count < frames.len() && frame > 16 && frame & 7 == 0 {
+        // The frame pointer points past the return address, followed (downwards) by the frame
+        // pointer of the caller.
+        let (next, address) = unsafe {
+            let frame = frame as *const usize;
+            (*frame.sub(2), *frame.sub(1))
+        };
+        if address == 0 {
+            break;
+        }
+
+        frames[count] = address;
+        count += 1;
+        if next <= frame || next - frame > MAX_FRAME_SIZE {
+            break;
+        }
+        frame = next;
+    }
+    count
+}
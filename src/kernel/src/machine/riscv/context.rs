@@ -0,0 +1,71 @@
+use crate::machine::interrupt::InterruptStack;
+
+/// Size of the area that the floating-point registers (`f0` to `f31`, and `fcsr`) are saved to,
+/// in bytes.
+pub const KERNFX_SIZE: usize = 33 * 8;
+
+/// Alignment of the area.
+pub const KERNFX_ALIGN: usize = 8;
+
+/// Registers of a context that are saved when switching away from it. Only callee-saved registers
+/// need to be saved, since switching is a function call as far as the compiler is concerned. The
+/// layout is relied on by `switch.S`.
+#[derive(Clone, Debug)]
+#[repr(C)]
+pub struct Context {
+    /// Return address.
+    pub ra: usize,
+    /// Stack pointer.
+    pub sp: usize,
+    /// Saved registers (`s0` to `s11`).
+    pub s: [usize; 12],
+    /// Address of the area that the floating-point registers are saved to.
+    pub fx: usize,
+}
+
+impl Context {
+    /// Construct an empty context.
+    pub const fn new() -> Self {
+        Self {
+            ra: 0,
+            sp: 0,
+            s: [0; 12],
+            fx: 0,
+        }
+    }
+
+    /// Set the area that the floating-point registers are saved to, and give the registers their
+    /// initial values (zero, which also selects round-to-nearest in `fcsr`).
+    pub fn set_fx(&mut self, fx: &mut [u8; KERNFX_SIZE]) {
+        fx.fill(0);
+        self.fx = fx.as_mut_ptr() as usize;
+    }
+
+    /// Make the context start running the given function on the given stack the first time it is
+    /// switched to. The function is called through [`crate::context::switch::kernel_entry`].
+    ///
+    /// # Safety
+    /// The stack must be valid for as long as the context runs, and the top must be 16-byte
+    /// aligned.
+    pub unsafe fn set_entry(&mut self, stack_top: usize, entry: extern "C" fn()) {
+        self.ra = context_entry as *const () as usize;
+        self.sp = stack_top;
+        self.s[0] = entry as usize;
+    }
+
+    /// Set the thread-local storage pointer of the context. User-space keeps it in `tp`, which is
+    /// restored from the saved registers when returning to it.
+    pub fn set_tls(&mut self, registers: &mut InterruptStack, tls: usize) {
+        registers.registers[4] = tls;
+    }
+
+    /// Retrieve the thread-local storage pointer of the context.
+    pub fn tls(&self, registers: &InterruptStack) -> usize {
+        registers.registers[4]
+    }
+}
+
+extern "C" {
+    /// Trampoline that new contexts start in. Calls the entry function that was left in `s0`.
+    fn context_entry();
+}
@@ -0,0 +1,37 @@
+use core::arch::asm;
+use core::fmt::{self, Write};
+
+/// Extension ID of the legacy SBI console putchar call.
+const SBI_CONSOLE_PUTCHAR: usize = 1;
+
+/// Writes debugging output to the SBI firmware's console. This needs no set-up and takes no locks,
+/// so that output can still be written while panicking, no matter what state the kernel is in.
+pub struct DebugWriter;
+
+impl DebugWriter {
+    /// Write a single byte to the console.
+    fn put_char(&mut self, byte: u8) {
+        unsafe {
+            asm!(
+                "ecall",
+                inout("a0") byte as usize => _,
+                in("a7") SBI_CONSOLE_PUTCHAR,
+                options(nostack)
+            );
+        }
+    }
+}
+
+impl Write for DebugWriter {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        for byte in string.bytes() {
+            self.put_char(byte);
+        }
+        Ok(())
+    }
+}
+
+/// Write formatted debugging output.
+pub fn print(args: fmt::Arguments) {
+    let _ = DebugWriter.write_fmt(args);
+}
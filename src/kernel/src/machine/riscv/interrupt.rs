@@ -0,0 +1,287 @@
+use core::arch::{asm, global_asm};
+use core::fmt;
+
+/// Bit of the sstatus register that holds the privilege mode the trap was taken from (set for
+/// supervisor-mode, clear for user-mode).
+pub const SSTATUS_SPP: usize = 1 << 8;
+
+/// Bit of the sstatus register that permits supervisor-mode to access user memory.
+pub const SSTATUS_SUM: usize = 1 << 18;
+
+/// Bit of the scause register that is set for interrupts, and clear for exceptions.
+pub const SCAUSE_INTERRUPT: usize = 1 << 63;
+
+/// Registers saved by the trap vector when a trap occurs.
+#[derive(Clone, Default)]
+#[repr(C)]
+pub struct InterruptStack {
+    /// General purpose registers, indexed by their number. `x0` is never saved, and `x2` holds the
+    /// stack pointer of the interrupted code.
+    pub registers: [usize; 32],
+    /// Address of the interrupted instruction.
+    pub sepc: usize,
+    /// Status of the interrupted code.
+    pub sstatus: usize,
+    /// Cause of the trap.
+    pub scause: usize,
+    /// Extra information about the trap (the faulting address for page faults).
+    pub stval: usize,
+}
+
+impl InterruptStack {
+    /// Whether the interrupted code was running in user-mode.
+    pub fn is_user(&self) -> bool {
+        self.sstatus & SSTATUS_SPP == 0
+    }
+
+    /// Retrieve the address of the interrupted instruction.
+    pub fn instruction_pointer(&self) -> usize {
+        self.sepc
+    }
+
+    /// Retrieve the stack pointer of the interrupted code.
+    pub fn stack_pointer(&self) -> usize {
+        self.registers[2]
+    }
+
+    /// Retrieve the number and the arguments of a system call.
+    pub fn syscall_args(&self) -> (usize, [usize; 5]) {
+        let registers = &self.registers;
+        (
+            registers[17],
+            [
+                registers[10],
+                registers[11],
+                registers[12],
+                registers[13],
+                registers[14],
+            ],
+        )
+    }
+
+    /// Set the value that a system call returns.
+    pub fn set_syscall_result(&mut self, value: usize) {
+        self.registers[10] = value;
+    }
+
+    /// Make the system call be made again once the interrupted code resumes, by going back to the
+    /// `ecall` instruction. The number and arguments are still in their registers.
+    pub fn restart_syscall(&mut self) {
+        self.sepc -= 4;
+    }
+
+    /// Retrieve the address where a signal frame of the given size is pushed on the stack.
+    pub fn signal_frame(&self, size: usize) -> usize {
+        self.registers[2].wrapping_sub(size) & !0xF
+    }
+
+    /// Retrieve the address of the signal frame once its handler returned.
+    pub fn returned_signal_frame(&self) -> usize {
+        self.registers[2]
+    }
+
+    /// Make the interrupted code resume in a signal handler, which is passed the signal, with the
+    /// signal frame at the top of the stack. The handler returns to `restorer`.
+    pub fn enter_signal_handler(
+        &mut self,
+        handler: usize,
+        signal: usize,
+        frame: usize,
+        restorer: usize,
+    ) {
+        self.sepc = handler;
+        self.registers[1] = restorer;
+        self.registers[2] = frame;
+        self.registers[10] = signal;
+    }
+
+    /// Make the interrupted code resume at the entry point of a new thread, which is passed `arg`,
+    /// with the given stack pointer.
+    pub fn enter_thread(&mut self, entry: usize, stack: usize, arg: usize) {
+        self.sepc = entry;
+        self.registers[1] = 0;
+        self.registers[2] = stack;
+        self.registers[10] = arg;
+    }
+
+    /// Restore registers that were saved in a signal frame. The status and the trap information
+    /// are kept.
+    pub fn restore_user(&mut self, saved: &InterruptStack) {
+        self.registers = saved.registers;
+        self.registers[0] = 0;
+        self.sepc = saved.sepc;
+    }
+}
+
+impl fmt::Debug for InterruptStack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "sepc {:016x} sstatus {:016x} scause {:016x} stval {:016x}",
+            self.sepc, self.sstatus, self.scause, self.stval
+        )?;
+
+        for (index, chunk) in self.registers.chunks(4).enumerate() {
+            for (offset, value) in chunk.iter().enumerate() {
+                write!(f, "x{:<2} {:016x} ", index * 4 + offset, value)?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Trap vector. While user code runs, sscratch holds the address of the per-CPU area of the hart
+// (see `machine::percpu`), and it is zero while the kernel runs, in which case `tp` holds the
+// address instead. This tells the vector whether `tp` and the stack have to be switched.
+global_asm!(
+    r#"
+.equ TRAP_FRAME_SIZE, 36 * 8
+
+# Offsets of the fields of `machine::percpu::Area`.
+.equ PERCPU_KERNEL_STACK, 0 * 8
+.equ PERCPU_SCRATCH, 1 * 8
+
+.section .text
+.global trap_vector
+.align 4
+trap_vector:
+    csrrw tp, sscratch, tp
+    bnez tp, 1f
+    # Trapped from the kernel: take back tp, and stay on the same stack.
+    csrrw tp, sscratch, tp
+    sd sp, PERCPU_SCRATCH(tp)
+    j 2f
+1:
+    # Trapped from user-mode: the user's tp is now in sscratch. Switch to the kernel stack.
+    sd sp, PERCPU_SCRATCH(tp)
+    ld sp, PERCPU_KERNEL_STACK(tp)
+2:
+    addi sp, sp, -TRAP_FRAME_SIZE
+    sd x1, 1 * 8(sp)
+    sd x3, 3 * 8(sp)
+    sd x5, 5 * 8(sp)
+    sd x6, 6 * 8(sp)
+    sd x7, 7 * 8(sp)
+    sd x8, 8 * 8(sp)
+    sd x9, 9 * 8(sp)
+    sd x10, 10 * 8(sp)
+    sd x11, 11 * 8(sp)
+    sd x12, 12 * 8(sp)
+    sd x13, 13 * 8(sp)
+    sd x14, 14 * 8(sp)
+    sd x15, 15 * 8(sp)
+    sd x16, 16 * 8(sp)
+    sd x17, 17 * 8(sp)
+    sd x18, 18 * 8(sp)
+    sd x19, 19 * 8(sp)
+    sd x20, 20 * 8(sp)
+    sd x21, 21 * 8(sp)
+    sd x22, 22 * 8(sp)
+    sd x23, 23 * 8(sp)
+    sd x24, 24 * 8(sp)
+    sd x25, 25 * 8(sp)
+    sd x26, 26 * 8(sp)
+    sd x27, 27 * 8(sp)
+    sd x28, 28 * 8(sp)
+    sd x29, 29 * 8(sp)
+    sd x30, 30 * 8(sp)
+    sd x31, 31 * 8(sp)
+
+    # Save the interrupted stack pointer and tp. The user's tp is in sscratch, which is zeroed,
+    # since the kernel is now running.
+    ld t0, PERCPU_SCRATCH(tp)
+    sd t0, 2 * 8(sp)
+    csrrw t0, sscratch, zero
+    csrr t1, sstatus
+    andi t1, t1, 1 << 8
+    beqz t1, 3f
+    mv t0, tp
+3:
+    sd t0, 4 * 8(sp)
+
+    csrr t0, sepc
+    sd t0, 32 * 8(sp)
+    csrr t0, sstatus
+    sd t0, 33 * 8(sp)
+    csrr t0, scause
+    sd t0, 34 * 8(sp)
+    csrr t0, stval
+    sd t0, 35 * 8(sp)
+
+    mv a0, sp
+    call {handler}
+
+.global trap_return
+trap_return:
+    ld t0, 32 * 8(sp)
+    csrw sepc, t0
+    ld t0, 33 * 8(sp)
+    csrw sstatus, t0
+
+    # Returning to user-mode: leave the kernel stack pointer in the per-CPU area and the area in
+    # sscratch for the next trap, and give the user's tp back. Returning to the kernel, tp is kept,
+    # since the context may have been moved to another hart in the meantime.
+    andi t0, t0, 1 << 8
+    bnez t0, 4f
+    addi t0, sp, TRAP_FRAME_SIZE
+    sd t0, PERCPU_KERNEL_STACK(tp)
+    csrw sscratch, tp
+    ld x4, 4 * 8(sp)
+4:
+    ld x1, 1 * 8(sp)
+    ld x3, 3 * 8(sp)
+    ld x5, 5 * 8(sp)
+    ld x6, 6 * 8(sp)
+    ld x7, 7 * 8(sp)
+    ld x8, 8 * 8(sp)
+    ld x9, 9 * 8(sp)
+    ld x10, 10 * 8(sp)
+    ld x11, 11 * 8(sp)
+    ld x12, 12 * 8(sp)
+    ld x13, 13 * 8(sp)
+    ld x14, 14 * 8(sp)
+    ld x15, 15 * 8(sp)
+    ld x16, 16 * 8(sp)
+    ld x17, 17 * 8(sp)
+    ld x18, 18 * 8(sp)
+    ld x19, 19 * 8(sp)
+    ld x20, 20 * 8(sp)
+    ld x21, 21 * 8(sp)
+    ld x22, 22 * 8(sp)
+    ld x23, 23 * 8(sp)
+    ld x24, 24 * 8(sp)
+    ld x25, 25 * 8(sp)
+    ld x26, 26 * 8(sp)
+    ld x27, 27 * 8(sp)
+    ld x28, 28 * 8(sp)
+    ld x29, 29 * 8(sp)
+    ld x30, 30 * 8(sp)
+    ld x31, 31 * 8(sp)
+    ld x2, 2 * 8(sp)
+    sret
+"#,
+    handler = sym crate::machine::trap::trap,
+);
+
+extern "C" {
+    /// Entry point of every trap.
+    pub fn trap_vector();
+}
+
+/// Resume user-space with the given registers, as if returning from a trap. Used to start new user
+/// contexts.
+///
+/// # Safety
+/// The registers must be at the top of the kernel stack of the current context (which is where
+/// the next trap from user-mode saves them), and describe valid user-mode state. Interrupts must be
+/// disabled.
+pub unsafe fn return_to_user(stack: *const InterruptStack) -> ! {
+    asm!(
+        "mv sp, {0}",
+        "j trap_return",
+        in(reg) stack,
+        options(noreturn)
+    );
+}
@@ -0,0 +1,47 @@
+use core::arch::asm;
+
+use crate::machine;
+
+/// Extension ID of the legacy SBI send-IPI call.
+const SBI_SEND_IPI: usize = 4;
+
+/// Bit of the sie and sip registers for supervisor software interrupts, which IPIs raise.
+const SSIP: usize = 1 << 1;
+
+/// Enable the interrupts that other harts send to this one.
+///
+/// # Safety
+/// The trap vector must be set up.
+pub unsafe fn init() {
+    machine::set_sie(SSIP);
+}
+
+/// Interrupt a hart so that it switches to a more urgent context that was queued on it.
+pub fn reschedule(cpu: usize) {
+    send(cpu);
+}
+
+/// Interrupt a hart so that it flushes its TLB (see [`crate::memory::tlb::shootdown`]). Both
+/// requests share the supervisor software interrupt, so every IPI checks for a shootdown.
+pub fn shootdown(cpu: usize) {
+    send(cpu);
+}
+
+/// Send a supervisor software interrupt to a hart.
+fn send(cpu: usize) {
+    // The legacy call takes the address of a mask of the harts to interrupt.
+    let mask: usize = 1 << cpu;
+    unsafe {
+        asm!(
+            "ecall",
+            inout("a0") &mask as *const usize => _,
+            in("a7") SBI_SEND_IPI,
+            options(nostack)
+        );
+    }
+}
+
+/// Acknowledge an IPI. The switch that it asks for happens on the way out of the interrupt.
+pub fn acknowledge() {
+    unsafe { machine::clear_sip(SSIP) };
+}
@@ -1 +1,30 @@
+use core::arch::asm;
 
+/// Supervisor interrupt enable bit of the sstatus register.
+const SSTATUS_SIE: usize = 1 << 1;
+
+/// Enable interrupts.
+pub unsafe fn enable() {
+    asm!("csrs sstatus, {0}", in(reg) SSTATUS_SIE, options(nostack));
+}
+
+/// Disable interrupts.
+pub unsafe fn disable() {
+    asm!("csrc sstatus, {0}", in(reg) SSTATUS_SIE, options(nostack));
+}
+
+/// Whether interrupts are enabled.
+#[inline(always)]
+pub fn enabled() -> bool {
+    let status: usize;
+    unsafe {
+        asm!("csrr {0}, sstatus", out(reg) status, options(nomem, nostack));
+    }
+    status & SSTATUS_SIE != 0
+}
+
+/// Enable interrupts and wait for the next one. `wfi` also returns when an interrupt is pending
+/// while interrupts are disabled, so nothing is missed between the two instructions.
+pub unsafe fn enable_and_halt() {
+    asm!("wfi", "csrs sstatus, {0}", in(reg) SSTATUS_SIE, options(nostack));
+}
@@ -1,5 +1,28 @@
 pub use self::asm::*;
+pub use self::context::{KERNFX_ALIGN, KERNFX_SIZE};
 
+pub mod asm;
+pub mod backtrace;
+pub mod context;
+pub mod debug;
+pub mod interrupt;
+pub mod ipi;
 pub mod irq;
+pub mod paging;
+pub mod percpu;
+pub mod switch;
+pub mod timer;
+pub mod tlb;
 pub mod trap;
-pub mod asm;
+pub mod usercopy;
+
+/// Offset of the kernel's linear mapping of all physical memory. This is the start of the upper
+/// half of the Sv39 address space, which is also canonical under Sv48.
+pub const PHYS_OFFSET: usize = 0xFFFF_FFC0_0000_0000;
+
+/// Start of the window of kernel memory where DMA buffers that are not write-back cached are
+/// mapped. It is covered by a single root table entry in both Sv39 and Sv48.
+pub const DMA_OFFSET: usize = 0xFFFF_FFE0_0000_0000;
+
+/// Size of the DMA window, in bytes.
+pub const DMA_SIZE: usize = 1 << 30;
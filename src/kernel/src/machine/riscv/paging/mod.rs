@@ -0,0 +1,565 @@
+pub mod sv39;
+pub mod sv48;
+
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::machine::asm;
+use crate::memory::paging::{MapError, PageFlags, PageMapper, PageSize, Translation};
+use crate::memory::{self, Frame};
+
+/// Number of entries in every page table.
+pub const ENTRY_COUNT: usize = 512;
+
+/// Index of the first root table entry in the kernel's (upper) half of the address space. These
+/// entries are shared between every address space.
+pub const KERNEL_ROOT_INDEX: usize = 256;
+
+/// Value of the MODE field of satp that the kernel runs with, which every address space uses. Sv39
+/// until [`init`] switches to Sv48.
+static MODE: AtomicUsize = AtomicUsize::new(sv39::SATP_MODE);
+
+/// Whether the Svpbmt extension is supported, so that pages may be mapped without caching.
+static SVPBMT: AtomicBool = AtomicBool::new(false);
+
+/// Paging modes supported by the kernel. Both use the same page table entry format, and only
+/// differ in the number of levels.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Mode {
+    /// Three-level paging with 39-bit virtual addresses.
+    Sv39,
+    /// Four-level paging with 48-bit virtual addresses.
+    Sv48,
+}
+
+impl Mode {
+    /// Retrieve the number of page table levels.
+    pub const fn levels(self) -> usize {
+        match self {
+            Mode::Sv39 => sv39::LEVELS,
+            Mode::Sv48 => sv48::LEVELS,
+        }
+    }
+
+    /// Retrieve the value of the MODE field of the satp register.
+    pub const fn satp_mode(self) -> usize {
+        match self {
+            Mode::Sv39 => sv39::SATP_MODE,
+            Mode::Sv48 => sv48::SATP_MODE,
+        }
+    }
+
+    /// Retrieve the mode from the MODE field of the satp register.
+    pub const fn from_satp_mode(value: usize) -> Option<Self> {
+        match value {
+            sv39::SATP_MODE => Some(Mode::Sv39),
+            sv48::SATP_MODE => Some(Mode::Sv48),
+            _ => None,
+        }
+    }
+
+    /// Retrieve the end of the user's (lower) half of the address space. Sv39 only has 256 GiB of
+    /// user addresses.
+    pub const fn user_end(self) -> usize {
+        1 << (12 + 9 * self.levels() - 1)
+    }
+}
+
+/// Retrieve the paging mode that the kernel runs with, which every address space uses.
+pub fn mode() -> Mode {
+    Mode::from_satp_mode(MODE.load(Ordering::Relaxed)).expect("Unknown paging mode")
+}
+
+/// Retrieve the end of the user's (lower) half of the address space, which depends on the paging
+/// mode.
+pub fn user_end() -> usize {
+    mode().user_end()
+}
+
+bitflags::bitflags! {
+    /// Flags of a page table entry. An entry with none of `READ`, `WRITE` and `EXECUTE` set points
+    /// to the next level of the page table instead of mapping a page.
+    pub struct TableEntryFlags: usize {
+        /// The entry is valid. Must be set for the entry to be used.
+        const VALID = 1 << 0;
+        /// The page can be read from.
+        const READ = 1 << 1;
+        /// The page can be written to. Requires `READ`.
+        const WRITE = 1 << 2;
+        /// Instructions can be fetched from the page.
+        const EXECUTE = 1 << 3;
+        /// The page can be accessed from user-mode.
+        const USER = 1 << 4;
+        /// The mapping exists in every address space.
+        const GLOBAL = 1 << 5;
+        /// The page has been accessed.
+        const ACCESSED = 1 << 6;
+        /// The page has been written to.
+        const DIRTY = 1 << 7;
+        /// Non-cacheable memory (requires the Svpbmt extension, and must not be set otherwise).
+        const NO_CACHE = 1 << 61;
+        /// Non-cacheable, strongly-ordered memory for I/O (requires the Svpbmt extension, and must
+        /// not be set otherwise).
+        const IO = 1 << 62;
+    }
+}
+
+impl From<PageFlags> for TableEntryFlags {
+    fn from(flags: PageFlags) -> Self {
+        // The accessed and dirty bits are set up front, so that implementations that do not update
+        // them in hardware do not raise page faults.
+        let mut entry_flags = TableEntryFlags::VALID | TableEntryFlags::ACCESSED;
+        entry_flags.set(
+            TableEntryFlags::READ,
+            flags.intersects(PageFlags::READ | PageFlags::WRITE),
+        );
+        entry_flags.set(
+            TableEntryFlags::WRITE | TableEntryFlags::DIRTY,
+            flags.contains(PageFlags::WRITE),
+        );
+        entry_flags.set(TableEntryFlags::EXECUTE, flags.contains(PageFlags::EXECUTE));
+        entry_flags.set(TableEntryFlags::USER, flags.contains(PageFlags::USER));
+        entry_flags.set(TableEntryFlags::GLOBAL, flags.contains(PageFlags::GLOBAL));
+        // The bits are reserved without Svpbmt, and memory is then cached as the platform decides.
+        entry_flags.set(
+            TableEntryFlags::NO_CACHE,
+            flags.contains(PageFlags::NO_CACHE) && SVPBMT.load(Ordering::Relaxed),
+        );
+        entry_flags
+    }
+}
+
+impl From<TableEntryFlags> for PageFlags {
+    fn from(entry_flags: TableEntryFlags) -> Self {
+        let mut flags = PageFlags::empty();
+        flags.set(PageFlags::READ, entry_flags.contains(TableEntryFlags::READ));
+        flags.set(
+            PageFlags::WRITE,
+            entry_flags.contains(TableEntryFlags::WRITE),
+        );
+        flags.set(
+            PageFlags::EXECUTE,
+            entry_flags.contains(TableEntryFlags::EXECUTE),
+        );
+        flags.set(PageFlags::USER, entry_flags.contains(TableEntryFlags::USER));
+        flags.set(
+            PageFlags::GLOBAL,
+            entry_flags.contains(TableEntryFlags::GLOBAL),
+        );
+        flags.set(
+            PageFlags::NO_CACHE,
+            entry_flags.intersects(TableEntryFlags::NO_CACHE | TableEntryFlags::IO),
+        );
+        flags.set(
+            PageFlags::ACCESSED,
+            entry_flags.contains(TableEntryFlags::ACCESSED),
+        );
+        flags.set(
+            PageFlags::DIRTY,
+            entry_flags.contains(TableEntryFlags::DIRTY),
+        );
+        flags
+    }
+}
+
+/// Like virtual addresses, page table entries contain 9-bit indices called physical page numbers
+/// (PPNs), starting at bit 10. The rest of the page table entry contains flags and bits reserved
+/// for software. The format is the same for Sv39 and Sv48.
+#[derive(Copy, Clone, Debug)]
+#[repr(transparent)]
+pub struct PageTableEntry(usize);
+
+impl PageTableEntry {
+    /// Bits of the entry that hold the physical page number.
+    const PPN_MASK: usize = 0x003F_FFFF_FFFF_FC00;
+
+    /// Construct an entry from a physical address and flags.
+    pub const fn new(address: usize, flags: TableEntryFlags) -> Self {
+        Self(((address >> 2) & Self::PPN_MASK) | flags.bits())
+    }
+
+    /// Retrieve the 0th PPN.
+    pub const fn ppn0(&self) -> usize {
+        (self.0 >> 10) & 0x1FF
+    }
+
+    /// Retrieve the 1st PPN.
+    pub const fn ppn1(&self) -> usize {
+        (self.0 >> 19) & 0x1FF
+    }
+
+    /// Retrieve the 2nd PPN (which holds the rest of the PPN in Sv39).
+    pub const fn ppn2(&self) -> usize {
+        (self.0 >> 28) & 0x3FF_FFFF
+    }
+
+    /// Retrieve the physical address held by the entry.
+    pub const fn address(&self) -> usize {
+        (self.0 & Self::PPN_MASK) << 2
+    }
+
+    /// Retrieve the flags of the entry.
+    pub const fn flags(&self) -> TableEntryFlags {
+        TableEntryFlags::from_bits_truncate(self.0)
+    }
+
+    /// Whether the entry is valid.
+    pub const fn is_valid(&self) -> bool {
+        self.flags().contains(TableEntryFlags::VALID)
+    }
+
+    /// Whether the entry maps a page instead of pointing to the next level.
+    pub const fn is_leaf(&self) -> bool {
+        self.0 & (TableEntryFlags::READ.bits() | TableEntryFlags::EXECUTE.bits()) != 0
+    }
+
+    /// Whether the entry is completely empty.
+    pub const fn is_unused(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Clear the entry.
+    pub fn clear(&mut self) {
+        self.0 = 0;
+    }
+}
+
+/// Page-table structure. Every level has the same layout.
+#[repr(C, align(4096))]
+pub struct PageTable {
+    pub entries: [PageTableEntry; ENTRY_COUNT],
+}
+
+impl PageTable {
+    /// Retrieve the table stored in the given frame, through the kernel's physical mapping.
+    ///
+    /// # Safety
+    /// The frame must hold a page table.
+    unsafe fn from_frame<'a>(frame: Frame) -> &'a mut PageTable {
+        &mut *(frame.virt_address() as *mut PageTable)
+    }
+
+    /// Whether no entries of the table are in use.
+    fn is_empty(&self) -> bool {
+        self.entries.iter().all(PageTableEntry::is_unused)
+    }
+}
+
+/// Set up paging on the boot hart, before any address space is created. `svpbmt` tells whether the
+/// harts support the Svpbmt extension, which the device tree lists in their ISA string.
+///
+/// With the `sv48` feature, the kernel switches to Sv48 if the hart supports it. The root table
+/// that it was booted with becomes the table under both the lowest and the highest entries of a
+/// new Sv48 root table, which maps the same addresses in both halves: an Sv39 root table covers
+/// the 512 GiB that an Sv48 root entry does, and the upper half of Sv39 is at the top of it.
+///
+/// # Safety
+/// Must be called once, before anything else is mapped.
+pub unsafe fn init(svpbmt: bool) {
+    SVPBMT.store(svpbmt, Ordering::Relaxed);
+
+    #[cfg(feature = "sv48")]
+    {
+        let current = Mapper::current();
+        if current.mode != Mode::Sv39 {
+            return;
+        }
+
+        let root = match allocate_table() {
+            Ok(root) => root,
+            Err(_) => return,
+        };
+        let table = PageTable::from_frame(root);
+        let entry = PageTableEntry::new(current.root.start_address(), TableEntryFlags::VALID);
+        table.entries[0] = entry;
+        table.entries[ENTRY_COUNT - 1] = entry;
+
+        // satp is WARL: a mode that the hart does not support leaves it as it was.
+        let sv48 = Mapper::from_root(root, Mode::Sv48);
+        asm::write_satp(sv48.satp());
+        asm::sfence_vma_all();
+
+        if asm::read_satp() == sv48.satp() {
+            MODE.store(sv48::SATP_MODE, Ordering::Relaxed);
+        } else {
+            memory::deallocate_frame(root);
+        }
+    }
+}
+
+/// Whether a virtual address can be mapped in the given mode: it is canonical, so that the indices
+/// of the root table do not alias other addresses.
+fn is_canonical(virt: usize, mode: Mode) -> bool {
+    match mode {
+        Mode::Sv39 => sv39::VirtAddr(virt).is_canonical(),
+        Mode::Sv48 => sv48::VirtAddr(virt).is_canonical(),
+    }
+}
+
+/// Retrieve the index into the table at the given level (0 for the last level) for a virtual
+/// address. This is the VPN of that level.
+const fn table_index(virt: usize, level: usize) -> usize {
+    (virt >> (12 + 9 * level)) & (ENTRY_COUNT - 1)
+}
+
+/// Retrieve the level whose entries map pages of the given size.
+const fn page_level(size: PageSize) -> usize {
+    match size {
+        PageSize::Small => 0,
+        PageSize::Large => 1,
+        PageSize::Huge => 2,
+    }
+}
+
+/// Retrieve the size of the pages mapped by entries at the given level.
+const fn level_size(level: usize) -> PageSize {
+    match level {
+        0 => PageSize::Small,
+        1 => PageSize::Large,
+        _ => PageSize::Huge,
+    }
+}
+
+/// Allocate a zeroed frame for a page table.
+fn allocate_table() -> Result<Frame, MapError> {
+    let frame = memory::allocate_frame().ok_or(MapError::OutOfMemory)?;
+    unsafe {
+        ptr::write_bytes(frame.virt_address() as *mut u8, 0, memory::PAGE_SIZE);
+    }
+    Ok(frame)
+}
+
+/// Manages an Sv39 or Sv48 page table hierarchy.
+pub struct Mapper {
+    /// Frame containing the root table.
+    root: Frame,
+    /// Paging mode of the hierarchy.
+    mode: Mode,
+}
+
+impl Mapper {
+    /// Create a new page table hierarchy that shares the kernel's half of the currently active
+    /// page table. The new hierarchy uses the same mode as the kernel.
+    pub fn new() -> Result<Self, MapError> {
+        let current = unsafe { Self::current() };
+        let root = allocate_table()?;
+
+        unsafe {
+            let table = PageTable::from_frame(root);
+            table.entries[KERNEL_ROOT_INDEX..]
+                .copy_from_slice(&PageTable::from_frame(current.root).entries[KERNEL_ROOT_INDEX..]);
+        }
+
+        Ok(Self {
+            root,
+            mode: current.mode,
+        })
+    }
+
+    /// Retrieve the page table hierarchy that is currently in use by the CPU.
+    ///
+    /// # Safety
+    /// The mapper must not be used to free the tables while the CPU is using them.
+    pub unsafe fn current() -> Self {
+        let satp = asm::read_satp();
+        let mode = Mode::from_satp_mode(satp >> 60).unwrap_or_else(self::mode);
+        Self::from_root(Frame::from_number(satp & Self::SATP_PPN_MASK), mode)
+    }
+
+    /// Manage an existing page table hierarchy.
+    ///
+    /// # Safety
+    /// The frame must hold a root table for the given mode.
+    pub unsafe fn from_root(root: Frame, mode: Mode) -> Self {
+        Self { root, mode }
+    }
+
+    /// Bits of the satp register that hold the PPN of the root table.
+    const SATP_PPN_MASK: usize = (1 << 44) - 1;
+
+    /// Retrieve the paging mode of the hierarchy.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Retrieve the value that must be written to satp to use this page table.
+    pub fn satp(&self) -> usize {
+        (self.mode.satp_mode() << 60) | self.root.number()
+    }
+
+    /// Walk down to the entry that maps the given address at the given level. Returns `None` if
+    /// an intermediate table is missing, or if a larger page is mapped above the requested level.
+    fn entry(&mut self, virt: usize, level: usize) -> Option<&mut PageTableEntry> {
+        let mut table = unsafe { PageTable::from_frame(self.root) };
+
+        for current in (level + 1..self.mode.levels()).rev() {
+            let entry = &table.entries[table_index(virt, current)];
+            if !entry.is_valid() || entry.is_leaf() {
+                return None;
+            }
+
+            table = unsafe { PageTable::from_frame(Frame::containing_address(entry.address())) };
+        }
+
+        Some(&mut table.entries[table_index(virt, level)])
+    }
+
+    /// Find the entry that maps the page containing the given address, along with its level.
+    fn leaf(&self, virt: usize) -> Option<(PageTableEntry, usize)> {
+        let mut table = unsafe { PageTable::from_frame(self.root) };
+
+        for level in (0..self.mode.levels()).rev() {
+            let entry = table.entries[table_index(virt, level)];
+            if !entry.is_valid() {
+                return None;
+            }
+
+            if entry.is_leaf() {
+                return Some((entry, level));
+            }
+
+            table = unsafe { PageTable::from_frame(Frame::containing_address(entry.address())) };
+        }
+
+        None
+    }
+
+    /// Find the entry that maps the page containing the given address, to change it.
+    fn leaf_mut(&mut self, virt: usize) -> Option<(&mut PageTableEntry, usize)> {
+        let (_, level) = self.leaf(virt)?;
+        Some((self.entry(virt, level)?, level))
+    }
+
+    /// Flush the TLB entry for the given address, if this page table is in use.
+    fn flush(&self, virt: usize) {
+        if self.is_active() {
+            unsafe { asm::sfence_vma(virt) };
+        }
+    }
+
+    /// Free the intermediate tables on the path to the given address that have become empty. The
+    /// kernel's half of the address space is shared, so its tables are never freed.
+    unsafe fn free_empty_tables(&mut self, virt: usize) {
+        if table_index(virt, self.mode.levels() - 1) >= KERNEL_ROOT_INDEX {
+            return;
+        }
+
+        for level in 1..self.mode.levels() {
+            let entry = match self.entry(virt, level) {
+                Some(entry) if entry.is_valid() && !entry.is_leaf() => entry,
+                _ => continue,
+            };
+
+            let frame = Frame::containing_address(entry.address());
+            if !PageTable::from_frame(frame).is_empty() {
+                break;
+            }
+
+            entry.clear();
+            memory::deallocate_frame(frame);
+        }
+    }
+}
+
+impl PageMapper for Mapper {
+    fn root(&self) -> Frame {
+        self.root
+    }
+
+    unsafe fn map(
+        &mut self,
+        virt: usize,
+        phys: usize,
+        size: PageSize,
+        flags: PageFlags,
+    ) -> Result<(), MapError> {
+        if virt % size.bytes() != 0 || phys % size.bytes() != 0 {
+            return Err(MapError::Misaligned);
+        }
+        if !is_canonical(virt, self.mode) {
+            return Err(MapError::InvalidAddress);
+        }
+
+        let level = page_level(size);
+        let mut table = PageTable::from_frame(self.root);
+
+        for current in (level + 1..self.mode.levels()).rev() {
+            let entry = &mut table.entries[table_index(virt, current)];
+            if entry.is_leaf() {
+                return Err(MapError::AlreadyMapped);
+            }
+
+            // Non-leaf entries must not have any permission or status bits set.
+            if !entry.is_valid() {
+                *entry =
+                    PageTableEntry::new(allocate_table()?.start_address(), TableEntryFlags::VALID);
+            }
+
+            table = PageTable::from_frame(Frame::containing_address(entry.address()));
+        }
+
+        let entry = &mut table.entries[table_index(virt, level)];
+        if entry.is_valid() {
+            return Err(MapError::AlreadyMapped);
+        }
+
+        *entry = PageTableEntry::new(phys, flags.into());
+        self.flush(virt);
+        Ok(())
+    }
+
+    unsafe fn unmap(&mut self, virt: usize) -> Result<(usize, PageSize), MapError> {
+        let (entry, level) = self.leaf_mut(virt).ok_or(MapError::NotMapped)?;
+        let phys = entry.address();
+        entry.clear();
+
+        self.flush(virt);
+        self.free_empty_tables(virt);
+        Ok((phys, level_size(level)))
+    }
+
+    fn translate(&self, virt: usize) -> Option<Translation> {
+        let (entry, level) = self.leaf(virt)?;
+        let size = level_size(level);
+
+        Some(Translation {
+            phys: entry.address() + virt % size.bytes(),
+            flags: entry.flags().into(),
+            size,
+        })
+    }
+
+    unsafe fn protect(&mut self, virt: usize, flags: PageFlags) -> Result<PageSize, MapError> {
+        let (entry, level) = self.leaf_mut(virt).ok_or(MapError::NotMapped)?;
+
+        // A page that was written to stays dirty when it is made read-only, so that swapping
+        // still writes it out.
+        let entry_flags = TableEntryFlags::from(flags)
+            | (entry.flags() & (TableEntryFlags::ACCESSED | TableEntryFlags::DIRTY));
+        *entry = PageTableEntry::new(entry.address(), entry_flags);
+
+        self.flush(virt);
+        Ok(level_size(level))
+    }
+
+    unsafe fn clear_accessed(&mut self, virt: usize) -> Result<bool, MapError> {
+        let (entry, _) = self.leaf_mut(virt).ok_or(MapError::NotMapped)?;
+        let flags = entry.flags();
+        if !flags.contains(TableEntryFlags::ACCESSED) {
+            return Ok(false);
+        }
+
+        *entry = PageTableEntry::new(entry.address(), flags - TableEntryFlags::ACCESSED);
+        self.flush(virt);
+        Ok(true)
+    }
+
+    fn is_active(&self) -> bool {
+        asm::read_satp() == self.satp()
+    }
+
+    unsafe fn activate(&self) {
+        asm::write_satp(self.satp());
+        asm::sfence_vma_all();
+    }
+}
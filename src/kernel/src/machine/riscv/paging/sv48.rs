@@ -0,0 +1,48 @@
+/// Number of page table levels in Sv48.
+pub const LEVELS: usize = 4;
+
+/// Value of the MODE field of the satp register that selects Sv48.
+pub const SATP_MODE: usize = 9;
+
+/// Sv48 virtual addresses are like Sv39 addresses, but with a fourth VPN.
+/// Bits 0-11: Page offset
+/// Bits 12-20: VPN 0
+/// Bits 21-29: VPN 1
+/// Bits 30-38: VPN 2
+/// Bits 39-47: VPN 3
+#[derive(Copy, Clone)]
+#[repr(transparent)]
+pub struct VirtAddr(pub usize);
+
+impl VirtAddr {
+    /// Retrieve the page offset.
+    pub fn page_offset(&self) -> usize {
+        self.0 & 0xFFF
+    }
+
+    /// Retrieve the 0th VPN.
+    pub fn vpn0(&self) -> usize {
+        (self.0 >> 12) & 0x1FF
+    }
+
+    /// Retrieve the 1st VPN.
+    pub fn vpn1(&self) -> usize {
+        (self.0 >> 21) & 0x1FF
+    }
+
+    /// Retrieve the 2nd VPN.
+    pub fn vpn2(&self) -> usize {
+        (self.0 >> 30) & 0x1FF
+    }
+
+    /// Retrieve the 3rd VPN.
+    pub fn vpn3(&self) -> usize {
+        (self.0 >> 39) & 0x1FF
+    }
+
+    /// Whether the address is canonical (bits 48-63 are copies of bit 47).
+    pub fn is_canonical(&self) -> bool {
+        let upper = self.0 >> 47;
+        upper == 0 || upper == (usize::MAX >> 47)
+    }
+}
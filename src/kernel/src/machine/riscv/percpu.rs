@@ -0,0 +1,43 @@
+use core::sync::atomic::AtomicUsize;
+
+use crate::machine;
+
+/// Part of a per-CPU area that the machine code uses, which comes first in the area. The kernel
+/// keeps the address of the area of its hart in `tp`. While user-mode runs, `tp` belongs to it and
+/// the address waits in sscratch instead, for the trap vector to swap back.
+///
+/// The trap vector uses the fields by their offsets, so they must not be moved.
+#[repr(C)]
+pub struct Area {
+    /// Top of the kernel stack of the context that runs in user-mode, which the trap vector
+    /// switches to.
+    kernel_stack: AtomicUsize,
+    /// Where the trap vector keeps the interrupted stack pointer while it saves the registers.
+    scratch: AtomicUsize,
+}
+
+impl Area {
+    /// Construct the area of a hart that is not started yet.
+    pub(crate) const fn new() -> Self {
+        Self {
+            kernel_stack: AtomicUsize::new(0),
+            scratch: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// Make `tp` point to the per-CPU area of this hart. sscratch is cleared, since the kernel runs.
+///
+/// # Safety
+/// Must be called once per hart, before anything can trap, and with an area that no other hart
+/// uses.
+pub unsafe fn init(area: &'static Area) {
+    machine::write_tp(area as *const Area as usize);
+    machine::write_sscratch(0);
+}
+
+/// Retrieve the address of the per-CPU area of the running hart.
+#[inline(always)]
+pub fn base() -> usize {
+    machine::read_tp()
+}
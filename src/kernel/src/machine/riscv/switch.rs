@@ -1,7 +1,31 @@
-/// Perform hardware switch from one context to another.
-extern "C" fn _switch(from: &mut Context, to: &Context);
+use core::arch::global_asm;
 
-/// Switch from one context to another. Acts as an interface over assembly code.
-pub fn switch(from: &mut Context, to: Context) {
-    unsafe { _switch(from, &to); }
+use crate::machine::context::Context;
+
+global_asm!(include_str!("switch.S"));
+
+global_asm!(
+    r#"
+.globl context_entry
+context_entry:
+        mv a0, s0
+        call {entry}
+        unimp
+"#,
+    entry = sym crate::context::switch::kernel_entry,
+);
+
+extern "C" {
+    /// Perform hardware switch from one context to another.
+    fn context_switch(prev: *mut Context, next: *const Context);
+}
+
+/// Switch from one context to another. Acts as an interface over assembly code, and returns once
+/// something switches back to `prev`.
+///
+/// # Safety
+/// Interrupts must be disabled, and both contexts must stay alive (and untouched by anything
+/// else) until the switch is over.
+pub unsafe fn switch(prev: *mut Context, next: *const Context) {
+    context_switch(prev, next);
 }
@@ -0,0 +1,77 @@
+use core::arch::asm;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::machine;
+use crate::time::{self, Clock};
+
+/// Extension ID of the legacy SBI set-timer call.
+const SBI_SET_TIMER: usize = 0;
+
+/// Bit of the sie register that enables supervisor timer interrupts.
+const SIE_STIE: usize = 1 << 5;
+
+/// Number of timebase ticks between two timer interrupts.
+static INTERVAL: AtomicUsize = AtomicUsize::new(0);
+
+/// Clock that reads the time CSR, which every hart shares.
+struct Timebase {
+    /// Frequency of the timebase, in hertz.
+    frequency: AtomicU64,
+}
+
+/// Timebase of the harts.
+static TIMEBASE: Timebase = Timebase {
+    frequency: AtomicU64::new(0),
+};
+
+impl Clock for Timebase {
+    fn name(&self) -> &'static str {
+        "timebase"
+    }
+
+    fn rating(&self) -> u32 {
+        300
+    }
+
+    fn frequency(&self) -> u64 {
+        self.frequency.load(Ordering::Relaxed)
+    }
+
+    fn read(&self) -> u64 {
+        machine::read_time() as u64
+    }
+}
+
+/// Ask the firmware to raise a timer interrupt once the time CSR reaches the given value. This
+/// also clears the pending timer interrupt.
+fn set_timer(time: usize) {
+    unsafe {
+        asm!(
+            "ecall",
+            inout("a0") time => _,
+            in("a7") SBI_SET_TIMER,
+            options(nostack)
+        );
+    }
+}
+
+/// Start raising timer interrupts at the given frequency, and keep the time with the timebase. The
+/// SBI timer only fires once, so it is armed again by every interrupt.
+///
+/// # Safety
+/// The trap vector must be set up.
+pub unsafe fn init(timebase_frequency: usize, frequency: usize) {
+    TIMEBASE
+        .frequency
+        .store(timebase_frequency as u64, Ordering::Relaxed);
+    time::register(&TIMEBASE);
+
+    INTERVAL.store((timebase_frequency / frequency).max(1), Ordering::Relaxed);
+    set_timer(machine::read_time() + INTERVAL.load(Ordering::Relaxed));
+    machine::set_sie(SIE_STIE);
+}
+
+/// Acknowledge a timer interrupt and arm the timer for the next one.
+pub fn interrupt() {
+    set_timer(machine::read_time() + INTERVAL.load(Ordering::Relaxed));
+}
@@ -0,0 +1,11 @@
+use crate::machine::asm;
+
+/// Invalidate the given address in the TLB through the `sfence.vma` instruction.
+pub unsafe fn flush(addr: usize) {
+    asm::sfence_vma(addr);
+}
+
+/// Invalidate the entire TLB.
+pub unsafe fn flush_all() {
+    asm::sfence_vma_all();
+}
@@ -1,4 +1,11 @@
-use crate::machine::{self, plic, hart};
+use crate::context::memory::{self, AccessKind, PageFault};
+use crate::context::{scheduler, signal, switch};
+use crate::machine::interrupt::{self, InterruptStack, SCAUSE_INTERRUPT, SSTATUS_SUM};
+use crate::machine::{self, hart, ipi, plic, timer, usercopy};
+use crate::memory::tlb;
+#[cfg(feature = "lockdep")]
+use crate::sync::lockdep;
+use crate::syscall;
 
 /// Each interrupt handler is provided the interrupt ID of the interrupt, and must return whether
 /// the interrupt was processed (which is used to notify the PLIC).
@@ -14,13 +21,151 @@ pub fn surpious(interrupt: u8) -> bool {
 /// numeric indices and there only a maximum of 255 interrupt IDs (so space is not an issue).
 static mut INTERRUPT_HANDLERS: [InterruptHandler; 255] = [surpious; 255];
 
-/// Main interrupt handling routine. Called by assembly-routine in between saving and restoring
-/// context. Responsible for calling any registered interrupt-routines.
-#[no_mangle]
-pub extern "C" fn trap() {
+/// Exception codes (the value of scause for exceptions).
+pub mod cause {
+    pub const INSTRUCTION_MISALIGNED: usize = 0;
+    pub const INSTRUCTION_ACCESS_FAULT: usize = 1;
+    pub const ILLEGAL_INSTRUCTION: usize = 2;
+    pub const BREAKPOINT: usize = 3;
+    pub const LOAD_MISALIGNED: usize = 4;
+    pub const LOAD_ACCESS_FAULT: usize = 5;
+    pub const STORE_MISALIGNED: usize = 6;
+    pub const STORE_ACCESS_FAULT: usize = 7;
+    pub const USER_ECALL: usize = 8;
+    pub const SUPERVISOR_ECALL: usize = 9;
+    pub const INSTRUCTION_PAGE_FAULT: usize = 12;
+    pub const LOAD_PAGE_FAULT: usize = 13;
+    pub const STORE_PAGE_FAULT: usize = 15;
+
+    // Interrupt codes (the value of scause for interrupts, without the interrupt bit).
+    pub const SUPERVISOR_SOFTWARE: usize = 1;
+    pub const SUPERVISOR_TIMER: usize = 5;
+    pub const SUPERVISOR_EXTERNAL: usize = 9;
+}
+
+/// Point the CPU to the trap vector.
+///
+/// # Safety
+/// Must be called before anything can trap.
+pub unsafe fn init() {
+    machine::write_stvec(interrupt::trap_vector as usize);
+    // Let the kernel access user memory on behalf of system calls.
+    machine::set_sstatus(SSTATUS_SUM);
+    ipi::init();
+}
+
+/// Main trap handling routine. Called by the trap vector in between saving and restoring context.
+/// Responsible for calling any registered interrupt-routines and for handling exceptions.
+pub extern "C" fn trap(stack: &mut InterruptStack) {
+    if stack.scause & SCAUSE_INTERRUPT != 0 {
+        irq(stack.scause & !SCAUSE_INTERRUPT);
+    } else {
+        match stack.scause {
+            cause::INSTRUCTION_PAGE_FAULT | cause::LOAD_PAGE_FAULT | cause::STORE_PAGE_FAULT => {
+                page_fault(stack)
+            }
+            cause::USER_ECALL => syscall(stack),
+            _ => exception(stack),
+        }
+    }
+
+    // Pending signals are handled on the way back to user-space.
+    if stack.is_user() {
+        signal::deliver(stack);
+    }
+}
+
+/// Handle an interrupt. Once it is handled, the CPU is given to a more urgent context if the
+/// interrupt made one runnable.
+fn irq(code: usize) {
+    #[cfg(feature = "lockdep")]
+    lockdep::enter_interrupt();
+
+    match code {
+        cause::SUPERVISOR_TIMER => {
+            timer::interrupt();
+            scheduler::tick();
+        }
+        cause::SUPERVISOR_SOFTWARE => {
+            ipi::acknowledge();
+            tlb::poll();
+        }
+        cause::SUPERVISOR_EXTERNAL => external_interrupt(),
+        _ => log::warn!("Unhandled interrupt {}", code),
+    }
+
+    #[cfg(feature = "lockdep")]
+    lockdep::leave_interrupt();
+
+    unsafe { switch::preempt() };
+}
+
+/// Handle an interrupt coming from the PLIC.
+fn external_interrupt() {
     if let Some(interrupt) = plic::next() {
         if INTERRUPT_HANDLERS[interrupt](interrupt) {
             plic::complete(interrupt);
         }
     }
 }
+
+/// Handle a system call. The number is passed in `a7` and the arguments in `a0` to `a4`, and the
+/// result is returned in `a0`.
+fn syscall(stack: &mut InterruptStack) {
+    // Return past the `ecall` instruction.
+    stack.sepc += 4;
+    syscall::syscall(stack);
+
+    unsafe { switch::preempt() };
+}
+
+/// Handle an exception other than a page fault. Faults caused by user code are reported to the
+/// context, but the kernel cannot recover from its own.
+fn exception(stack: &mut InterruptStack) {
+    let signal = match stack.scause {
+        cause::ILLEGAL_INSTRUCTION => signal::SIGILL,
+        cause::BREAKPOINT => signal::SIGTRAP,
+        cause::INSTRUCTION_MISALIGNED | cause::LOAD_MISALIGNED | cause::STORE_MISALIGNED => {
+            signal::SIGBUS
+        }
+        _ => signal::SIGSEGV,
+    };
+
+    if stack.is_user() {
+        return signal::raise(signal);
+    }
+
+    panic!(
+        "Exception {} in kernel mode at {:#x} (stval {:#x})\n{:?}",
+        stack.scause, stack.sepc, stack.stval, stack
+    );
+}
+
+/// Handle a page fault. Faults on user grants are resolved by the current address space, and
+/// user-mode faults that cannot be resolved send `SIGSEGV` to the current context.
+fn page_fault(stack: &mut InterruptStack) {
+    let access = match stack.scause {
+        cause::INSTRUCTION_PAGE_FAULT => AccessKind::Execute,
+        cause::STORE_PAGE_FAULT => AccessKind::Write,
+        _ => AccessKind::Read,
+    };
+
+    let fault = PageFault {
+        address: stack.stval,
+        access,
+        user: stack.is_user(),
+    };
+
+    match memory::page_fault(&fault) {
+        Ok(()) => (),
+        Err(_) if fault.user => signal::raise(signal::SIGSEGV),
+        // A copy from or to user memory reports the fault to its caller instead.
+        Err(reason) => match usercopy::fixup(stack.sepc) {
+            Some(fixup) => stack.sepc = fixup,
+            None => panic!(
+                "Page fault in kernel mode at {:#x} ({:?}): {:?}\n{:?}",
+                fault.address, access, reason, stack
+            ),
+        },
+    }
+}
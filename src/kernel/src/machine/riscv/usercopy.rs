@@ -0,0 +1,58 @@
+use core::arch::global_asm;
+use core::ptr::addr_of;
+
+// Copy `a2` bytes from `a1` to `a0`, returning zero. If the copy hits a page fault that cannot be
+// resolved, the trap handler resumes at `user_copy_fault` instead, which returns one. User memory
+// is accessible to the kernel since `trap::init` set SSTATUS.SUM.
+global_asm!(
+    r#"
+.globl user_copy
+user_copy:
+        beqz a2, 2f
+.globl user_copy_start
+user_copy_start:
+1:      lb t0, 0(a1)
+        sb t0, 0(a0)
+        addi a0, a0, 1
+        addi a1, a1, 1
+        addi a2, a2, -1
+        bnez a2, 1b
+.globl user_copy_end
+user_copy_end:
+2:      li a0, 0
+        ret
+
+.globl user_copy_fault
+user_copy_fault:
+        li a0, 1
+        ret
+"#
+);
+
+extern "C" {
+    fn user_copy(dst: *mut u8, src: *const u8, len: usize) -> usize;
+
+    static user_copy_start: u8;
+    static user_copy_end: u8;
+    static user_copy_fault: u8;
+}
+
+/// Copy bytes between user and kernel memory. Returns whether the copy went through; it stops
+/// at the first page fault that the kernel cannot resolve.
+///
+/// # Safety
+/// The kernel side must be valid for the copy, and the user side must lie in the user part of the
+/// address space.
+pub unsafe fn copy(dst: *mut u8, src: *const u8, len: usize) -> bool {
+    user_copy(dst, src, len) == 0
+}
+
+/// Retrieve the address to resume at after a page fault at the given instruction that could not
+/// be resolved, if the instruction belongs to [`copy`].
+pub fn fixup(instruction: usize) -> Option<usize> {
+    let start = addr_of!(user_copy_start) as usize;
+    let end = addr_of!(user_copy_end) as usize;
+    let fault = addr_of!(user_copy_fault) as usize;
+
+    (start..end).contains(&instruction).then_some(fault)
+}
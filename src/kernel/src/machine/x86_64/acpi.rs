@@ -0,0 +1,175 @@
+use core::ptr::read_unaligned;
+use core::{mem, slice};
+
+use crate::memory;
+
+/// Signature of the root system description pointer (RSDP).
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+
+/// Signature of the multiple APIC description table (MADT).
+const MADT_SIGNATURE: &[u8; 4] = b"APIC";
+
+/// Physical memory range of the BIOS that the RSDP is looked for in when the bootloader did not
+/// pass it. The RSDP is aligned to 16 bytes.
+const BIOS_START: usize = 0xE_0000;
+const BIOS_END: usize = 0x10_0000;
+
+/// Types of the MADT entries that describe a CPU, by its local APIC or its local x2APIC.
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_LOCAL_X2APIC: u8 = 9;
+
+/// Flag of the MADT entries that describe a CPU, which tells that the CPU can be started. CPUs
+/// that the firmware may only enable later on are left out.
+const MADT_ENABLED: u32 = 1 << 0;
+
+/// Root system description pointer, which points to the RSDT (and to the XSDT since revision 2).
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+/// Header of every system description table.
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+struct SdtHeader {
+    signature: [u8; 4],
+    length: u32,
+    revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: u32,
+    creator_revision: u32,
+}
+
+/// Size of the fields of the MADT between its header and its entries: the address of the local
+/// APICs and the flags.
+const MADT_FIELDS_SIZE: usize = 8;
+
+/// Whether the bytes of a structure add up to zero, as the checksums of ACPI require.
+fn checksum(address: usize, length: usize) -> bool {
+    let bytes = unsafe { slice::from_raw_parts(address as *const u8, length) };
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+/// Read the RSDP at the given virtual address, if it has a valid signature and checksum.
+fn rsdp_at(address: usize) -> Option<Rsdp> {
+    let rsdp = unsafe { read_unaligned(address as *const Rsdp) };
+    if &rsdp.signature != RSDP_SIGNATURE || !checksum(address, 20) {
+        return None;
+    }
+    Some(rsdp)
+}
+
+/// Find the RSDP among the ones that the bootloader passed, at the given physical address and
+/// size. Each of them is preceded by its size as a 32-bit integer, and aligned to 8 bytes.
+fn passed_rsdp(base: usize, size: usize) -> Option<Rsdp> {
+    let start = memory::phys_to_virt(base);
+    let mut offset = 0;
+
+    while base != 0 && offset + 4 <= size {
+        let length = unsafe { read_unaligned((start + offset) as *const u32) } as usize;
+        if offset + 4 + length > size {
+            break;
+        }
+        if length >= 20 {
+            if let Some(rsdp) = rsdp_at(start + offset + 4) {
+                return Some(rsdp);
+            }
+        }
+        offset = (offset + 4 + length).next_multiple_of(8);
+    }
+    None
+}
+
+/// Look for the RSDP in the memory of the BIOS.
+fn bios_rsdp() -> Option<Rsdp> {
+    (BIOS_START..BIOS_END)
+        .step_by(16)
+        .find_map(|address| rsdp_at(memory::phys_to_virt(address)))
+}
+
+/// Find the table with the given signature, returning the virtual address and the length of the
+/// table (header included).
+fn find_table(rsdp: &Rsdp, signature: &[u8; 4]) -> Option<(usize, usize)> {
+    // The XSDT holds 64-bit addresses, and the RSDT 32-bit ones.
+    let (root, entry_size) = match rsdp.revision {
+        0 => (rsdp.rsdt_address as usize, 4),
+        _ => (rsdp.xsdt_address as usize, 8),
+    };
+    let root = memory::phys_to_virt(root);
+    let header = unsafe { read_unaligned(root as *const SdtHeader) };
+    if !checksum(root, header.length as usize) {
+        return None;
+    }
+
+    let entries = (header.length as usize).saturating_sub(mem::size_of::<SdtHeader>()) / entry_size;
+    (0..entries).find_map(|index| {
+        let entry = root + mem::size_of::<SdtHeader>() + index * entry_size;
+        let address = match entry_size {
+            4 => unsafe { read_unaligned(entry as *const u32) as usize },
+            _ => unsafe { read_unaligned(entry as *const u64) as usize },
+        };
+
+        let table = memory::phys_to_virt(address);
+        let header = unsafe { read_unaligned(table as *const SdtHeader) };
+        let length = header.length as usize;
+        (&header.signature == signature && checksum(table, length)).then_some((table, length))
+    })
+}
+
+/// Call `found` with the APIC ID of every CPU that the MADT lists, in the order of the table
+/// (which is the order that the firmware wants them started in). The bootloader may pass the RSDP
+/// at the given physical address and size; it is looked for in the memory of the BIOS otherwise.
+/// Returns whether the MADT was found.
+pub fn for_each_apic_id(rsdps_base: usize, rsdps_size: usize, mut found: impl FnMut(u32)) -> bool {
+    let rsdp = match passed_rsdp(rsdps_base, rsdps_size).or_else(bios_rsdp) {
+        Some(rsdp) => rsdp,
+        None => return false,
+    };
+    let (madt, length) = match find_table(&rsdp, MADT_SIGNATURE) {
+        Some(madt) => madt,
+        None => return false,
+    };
+
+    let end = madt + length;
+    let mut entry = madt + mem::size_of::<SdtHeader>() + MADT_FIELDS_SIZE;
+    while entry + 2 <= end {
+        let (kind, size) = unsafe { (*(entry as *const u8), *((entry + 1) as *const u8)) };
+        if size < 2 || entry + size as usize > end {
+            break;
+        }
+
+        let (id, flags) = unsafe {
+            match kind {
+                MADT_LOCAL_APIC if size >= 8 => (
+                    Some(*((entry + 3) as *const u8) as u32),
+                    read_unaligned((entry + 4) as *const u32),
+                ),
+                MADT_LOCAL_X2APIC if size >= 16 => (
+                    Some(read_unaligned((entry + 4) as *const u32)),
+                    read_unaligned((entry + 8) as *const u32),
+                ),
+                _ => (None, 0),
+            }
+        };
+        if let Some(id) = id {
+            if flags & MADT_ENABLED != 0 {
+                found(id);
+            }
+        }
+
+        entry += size as usize;
+    }
+    true
+}
@@ -0,0 +1,182 @@
+use core::hint;
+use core::ptr::{read_volatile, write_volatile};
+
+use crate::machine::msr::{self, IA32_APIC_BASE};
+use crate::machine::paging::Mapper;
+use crate::machine::time;
+use crate::memory;
+use crate::memory::paging::{MapError, PageFlags, PageMapper, PageSize};
+
+/// Physical address of the local APIC's registers.
+const PHYS_BASE: usize = 0xFEE0_0000;
+
+/// Bit of the `IA32_APIC_BASE` register that enables the local APIC.
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+
+/// Offsets of the registers.
+const ID: usize = 0x20;
+const EOI: usize = 0xB0;
+const SPURIOUS: usize = 0xF0;
+const ICR_LOW: usize = 0x300;
+const ICR_HIGH: usize = 0x310;
+const LVT_TIMER: usize = 0x320;
+const TIMER_INITIAL: usize = 0x380;
+const TIMER_CURRENT: usize = 0x390;
+const TIMER_DIVIDE: usize = 0x3E0;
+
+/// Bit of the spurious interrupt vector register that enables the local APIC.
+const SPURIOUS_ENABLE: u32 = 1 << 8;
+
+/// Bits of the local vector table entries.
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+
+/// Bit of the low interrupt command register that is set while an IPI is being delivered.
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+
+/// Delivery modes of the low interrupt command register, which start other CPUs.
+const ICR_INIT: u32 = 0b101 << 8;
+const ICR_STARTUP: u32 = 0b110 << 8;
+
+/// Bit of the low interrupt command register that asserts the INIT signal, rather than
+/// deasserting it.
+const ICR_ASSERT: u32 = 1 << 14;
+
+/// Value of the divide configuration register that divides the timer's clock by 16.
+const DIVIDE_BY_16: u32 = 0b0011;
+
+/// Vector of the local APIC timer's interrupt.
+pub const TIMER_VECTOR: u8 = 48;
+
+/// Vector of the inter-processor interrupt that makes a CPU reschedule.
+pub const RESCHEDULE_VECTOR: u8 = 49;
+
+/// Vector of the inter-processor interrupt that makes a CPU flush its TLB.
+pub const SHOOTDOWN_VECTOR: u8 = 50;
+
+/// Vector of spurious interrupts. The low four bits must be set on older CPUs.
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+/// Each CPU has a local Advanced Programmable Interrupt Controller, which receives interrupts
+/// for the CPU and has a timer of its own. The registers are memory-mapped at the same address
+/// for every CPU, and always refer to the local APIC of the CPU accessing them.
+pub struct LocalApic {
+    /// Virtual address of the registers.
+    base: usize,
+}
+
+impl LocalApic {
+    /// Retrieve the local APIC of the current CPU.
+    pub fn get() -> Self {
+        Self {
+            base: memory::phys_to_virt(PHYS_BASE),
+        }
+    }
+
+    /// Read a register.
+    fn read(&self, register: usize) -> u32 {
+        unsafe { read_volatile((self.base + register) as *const u32) }
+    }
+
+    /// Write to a register.
+    fn write(&mut self, register: usize, value: u32) {
+        unsafe { write_volatile((self.base + register) as *mut u32, value) }
+    }
+
+    /// Map the registers (uncached) and enable the local APIC.
+    ///
+    /// # Safety
+    /// Must be called once per CPU, after the IDT has entries for [`SPURIOUS_VECTOR`] and
+    /// [`TIMER_VECTOR`].
+    pub unsafe fn init(&mut self) {
+        let result = Mapper::current().map(
+            self.base,
+            PHYS_BASE,
+            PageSize::Small,
+            PageFlags::READ | PageFlags::WRITE | PageFlags::GLOBAL | PageFlags::NO_CACHE,
+        );
+        match result {
+            // Another CPU already mapped the registers.
+            Ok(()) | Err(MapError::AlreadyMapped) => (),
+            Err(error) => panic!("Unable to map the local APIC: {:?}", error),
+        }
+
+        msr::wrmsr(
+            IA32_APIC_BASE,
+            msr::rdmsr(IA32_APIC_BASE) | APIC_BASE_ENABLE,
+        );
+        self.write(SPURIOUS, SPURIOUS_ENABLE | SPURIOUS_VECTOR as u32);
+    }
+
+    /// Retrieve the identifier of the local APIC (and therefore of the CPU).
+    pub fn id(&self) -> u32 {
+        self.read(ID) >> 24
+    }
+
+    /// Signal the end of the interrupt that is being handled.
+    pub fn eoi(&mut self) {
+        self.write(EOI, 0);
+    }
+
+    /// Send a fixed inter-processor interrupt with the given vector to the CPU whose local APIC
+    /// has the given identifier. Waits for the previous one to be delivered first.
+    ///
+    /// # Safety
+    /// Interrupts must be disabled, so that the two halves of the command are not split.
+    pub unsafe fn send_ipi(&mut self, id: u32, vector: u8) {
+        self.send_command(id, vector as u32);
+    }
+
+    /// Send an INIT signal to the CPU whose local APIC has the given identifier, which resets it
+    /// to wait for a startup IPI.
+    ///
+    /// # Safety
+    /// Interrupts must be disabled, and the CPU must not run anything that is still needed.
+    pub unsafe fn send_init(&mut self, id: u32) {
+        self.send_command(id, ICR_INIT | ICR_ASSERT);
+    }
+
+    /// Send a startup IPI to the CPU whose local APIC has the given identifier, which makes it
+    /// start in real mode at the beginning of the given page (below 1 MiB).
+    ///
+    /// # Safety
+    /// Interrupts must be disabled, and the page must hold code that the CPU can start with.
+    pub unsafe fn send_startup(&mut self, id: u32, page: usize) {
+        self.send_command(id, ICR_STARTUP | (page >> 12) as u32);
+    }
+
+    /// Write the interrupt command register, once the previous command is delivered.
+    unsafe fn send_command(&mut self, id: u32, command: u32) {
+        while self.read(ICR_LOW) & ICR_DELIVERY_PENDING != 0 {
+            hint::spin_loop();
+        }
+
+        // Writing the low half sends the interrupt.
+        self.write(ICR_HIGH, id << 24);
+        self.write(ICR_LOW, command);
+    }
+
+    /// Measure how many timer ticks pass in a second, against the PIT (see [`time::calibrate`]).
+    fn calibrate(&mut self) -> u32 {
+        self.write(TIMER_DIVIDE, DIVIDE_BY_16);
+        self.write(LVT_TIMER, LVT_MASKED);
+        self.write(TIMER_INITIAL, u32::MAX);
+
+        // The timer counts down.
+        let ticks_per_second = time::calibrate(|| (u32::MAX - self.read(TIMER_CURRENT)) as u64);
+        self.write(TIMER_INITIAL, 0);
+        ticks_per_second as u32
+    }
+
+    /// Make the timer fire [`TIMER_VECTOR`] periodically, at the given frequency.
+    ///
+    /// # Safety
+    /// Interrupts must be disabled, since the calibration is timing-sensitive.
+    pub unsafe fn start_timer(&mut self, frequency: u32) {
+        let ticks_per_second = self.calibrate();
+
+        self.write(TIMER_DIVIDE, DIVIDE_BY_16);
+        self.write(LVT_TIMER, LVT_TIMER_PERIODIC | TIMER_VECTOR as u32);
+        self.write(TIMER_INITIAL, (ticks_per_second / frequency).max(1));
+    }
+}
@@ -1 +1 @@
-
+pub mod local;
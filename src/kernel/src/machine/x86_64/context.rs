@@ -0,0 +1,93 @@
+use core::mem;
+
+use crate::machine::interrupt::InterruptStack;
+
+/// Size of the area that SIMD and FPU registers are saved to by `fxsave`, in bytes.
+pub const KERNFX_SIZE: usize = 512;
+
+/// Alignment that `fxsave` requires for the area.
+pub const KERNFX_ALIGN: usize = 16;
+
+/// Initial value of the RFLAGS register: interrupts disabled, and bit 1, which is always set.
+const INITIAL_RFLAGS: usize = 1 << 1;
+
+/// Registers of a context that are saved when switching away from it. Only callee-saved registers
+/// need to be saved, since switching is a function call as far as the compiler is concerned.
+#[derive(Clone, Debug)]
+#[repr(C)]
+pub struct Context {
+    /// Address of the area that SIMD and FPU registers are saved to.
+    pub fx: usize,
+    pub rflags: usize,
+    pub rbx: usize,
+    pub r12: usize,
+    pub r13: usize,
+    pub r14: usize,
+    pub r15: usize,
+    pub rbp: usize,
+    pub rsp: usize,
+    /// Base of the FS segment, which user-space uses to find its thread-local storage. It is
+    /// loaded whenever the context is switched to.
+    pub fs_base: usize,
+}
+
+impl Context {
+    /// Construct an empty context.
+    pub const fn new() -> Self {
+        Self {
+            fx: 0,
+            rflags: INITIAL_RFLAGS,
+            rbx: 0,
+            r12: 0,
+            r13: 0,
+            r14: 0,
+            r15: 0,
+            rbp: 0,
+            rsp: 0,
+            fs_base: 0,
+        }
+    }
+
+    /// Set the area that SIMD and FPU registers are saved to, and give the registers their
+    /// initial values (every exception masked).
+    pub fn set_fx(&mut self, fx: &mut [u8; KERNFX_SIZE]) {
+        fx.fill(0);
+        // x87 control word.
+        fx[0..2].copy_from_slice(&0x037Fu16.to_le_bytes());
+        // MXCSR.
+        fx[24..28].copy_from_slice(&0x1F80u32.to_le_bytes());
+        self.fx = fx.as_mut_ptr() as usize;
+    }
+
+    /// Make the context start running the given function on the given stack the first time it is
+    /// switched to. The function is called through [`crate::context::switch::kernel_entry`].
+    ///
+    /// # Safety
+    /// The stack must be valid for as long as the context runs, and the top must be 16-byte
+    /// aligned.
+    pub unsafe fn set_entry(&mut self, stack_top: usize, entry: extern "C" fn()) {
+        // Switching "returns" to the trampoline, which leaves the stack aligned for a call.
+        let rsp = stack_top - mem::size_of::<usize>();
+        (rsp as *mut usize).write(context_entry as *const () as usize);
+
+        self.rsp = rsp;
+        self.rbx = entry as usize;
+        self.rflags = INITIAL_RFLAGS;
+    }
+
+    /// Set the thread-local storage pointer of the context, which is the base of the FS segment.
+    /// It takes effect the next time the context is switched to.
+    pub fn set_tls(&mut self, _registers: &mut InterruptStack, tls: usize) {
+        self.fs_base = tls;
+    }
+
+    /// Retrieve the thread-local storage pointer of the context.
+    pub fn tls(&self, _registers: &InterruptStack) -> usize {
+        self.fs_base
+    }
+}
+
+extern "C" {
+    /// Trampoline that new contexts start in. Calls the entry function that was left in `rbx`.
+    fn context_entry();
+}
@@ -84,7 +84,7 @@ bitflags::bitflags! {
 pub fn cr0() -> Cr0 {
     let value: usize;
     unsafe {
-        asm!("mov cr0, {0}", in(reg) value);
+        asm!("mov {0}, cr0", out(reg) value);
     }
     Cr0::from_bits_truncate(value)
 }
@@ -94,11 +94,20 @@ pub unsafe fn write_cr0(value: Cr0) {
     asm!("mov cr0, {0}", in(reg) value.bits());
 }
 
+/// Read from the CR2 control-register. Holds the address that caused the last page fault.
+pub fn cr2() -> usize {
+    let value: usize;
+    unsafe {
+        asm!("mov {0}, cr2", out(reg) value);
+    }
+    value
+}
+
 /// Read from the CR3 control-register.
 pub fn cr3() -> usize {
     let value: usize;
     unsafe {
-        asm!("mov cr3, {0}", in(reg) value);
+        asm!("mov {0}, cr3", out(reg) value);
     }
     value
 }
@@ -107,3 +116,12 @@ pub fn cr3() -> usize {
 pub unsafe fn write_cr3(value: usize) {
     asm!("mov cr3, {0}", in(reg) value);
 }
+
+/// Read from the CR4 control-register.
+pub fn cr4() -> Cr4 {
+    let value: usize;
+    unsafe {
+        asm!("mov {0}, cr4", out(reg) value);
+    }
+    Cr4::from_bits_truncate(value)
+}
@@ -0,0 +1,40 @@
+use core::fmt::{self, Write};
+
+use crate::device::base::char::CharDeviceSwitch;
+use crate::device::serial::uart_16550::SerialPort;
+use crate::io::PortIo;
+
+/// Port of the serial port used for debugging output (COM1).
+const DEBUG_PORT: u16 = 0x3F8;
+
+/// Writes debugging output to the serial port. The port is not locked, so that output can still be
+/// written while panicking, no matter what state the kernel is in.
+pub struct DebugWriter {
+    port: SerialPort<PortIo<u8>>,
+}
+
+impl DebugWriter {
+    /// Create a writer for the debugging serial port.
+    pub const fn new() -> Self {
+        Self {
+            port: SerialPort::<PortIo<u8>>::new(DEBUG_PORT),
+        }
+    }
+}
+
+impl Write for DebugWriter {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        for byte in string.bytes() {
+            if byte == b'\n' {
+                self.port.put_char(b'\r').map_err(|_| fmt::Error)?;
+            }
+            self.port.put_char(byte).map_err(|_| fmt::Error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write formatted debugging output.
+pub fn print(args: fmt::Arguments) {
+    let _ = DebugWriter::new().write_fmt(args);
+}
@@ -22,7 +22,8 @@ impl<T> Default for DescriptorTablePointer<T> {
 }
 
 impl<T> DescriptorTablePointer<T> {
-    fn new(table: &T) -> Self {
+    /// Create a pointer to the given descriptor table.
+    pub fn new(table: &T) -> Self {
         let length = size_of::<T>() - 1;
         assert!(length < 0x10000);
         Self {
@@ -31,9 +32,10 @@ impl<T> DescriptorTablePointer<T> {
         }
     }
 
-    fn from_slice(table: &[T]) -> Self {
-        let length = table.len() - 1;
-        assert!(length < 0x100000);
+    /// Create a pointer to a descriptor table made of the given entries.
+    pub fn from_slice(table: &[T]) -> Self {
+        let length = table.len() * size_of::<T>() - 1;
+        assert!(length < 0x10000);
         Self {
             base: table.as_ptr(),
             limit: length as u16,
@@ -52,11 +54,23 @@ pub unsafe fn store_ldtr(selector: SegmentSelector) {
 pub unsafe fn load_ldtr() -> SegmentSelector {
     let selector: u16;
     unsafe {
-        asm!("sldt {0}", out(reg) selector);
+        asm!("sldt {0:x}", out(reg) selector);
     }
     SegmentSelector::from_raw(selector)
 }
 
+/// Load the segment selector of a TSS descriptor into the task register.
+#[inline(always)]
+pub unsafe fn load_tr(selector: SegmentSelector) {
+    asm!("ltr {0:x}", in(reg) selector.bits(), options(nostack, preserves_flags));
+}
+
+/// Load the IDTR register with the specified descriptor table pointer.
+#[inline(always)]
+pub unsafe fn load_idt<T>(idt: &DescriptorTablePointer<T>) {
+    asm!("lidt [{0}]", in(reg) idt, options(nostack));
+}
+
 /// Load the GDTR register with the specified descriptor table pointer.
 #[inline(always)]
 pub unsafe fn load_gdt<T>(gdt: &DescriptorTablePointer<T>) {
@@ -13,7 +13,7 @@ pub unsafe fn memory() {
 /// Orders processor execution relative to all memory stores prior to the SFENCE instruction. The
 /// processor ensures that every store prior to SFENCE is globally visible before any store after
 /// SFENCE becomes globally visible.
-pub fn store() {
+pub unsafe fn store() {
     asm!("sfence");
 }
 
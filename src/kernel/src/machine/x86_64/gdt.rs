@@ -1,12 +1,34 @@
 use core::mem;
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
+use crate::context::MAX_CPUS;
 use crate::machine::dtables::{self, DescriptorTablePointer};
-use crate::machine::segmentation::{self, Descriptor as SegmentDescriptor, SegmentSelector};
+use crate::machine::segmentation::{self, SegmentSelector};
 use crate::machine::Ring;
 
-/// One allocated per entry in the global descriptor table (GDT).
+/// Number of entries in the global descriptor table. The TSS descriptor takes two.
+const GDT_ENTRIES: usize = 9;
+
+/// Size of each interrupt stack, in bytes.
+const IST_STACK_SIZE: usize = 8 * 1024;
+
+/// Number of interrupt stacks of each CPU.
+const IST_STACKS: usize = 3;
+
+/// Interrupt stack table entries (starting at one, since zero means no stack switch) of the
+/// exceptions that may happen while the kernel's stack cannot be used. A double fault is usually
+/// caused by a stack overflow, and non-maskable interrupts and machine checks can happen anywhere,
+/// even before the stack pointer was switched in an entry stub. Page faults do not get one, since
+/// the stack would be overwritten by a nested page fault.
+pub const DOUBLE_FAULT_IST: u8 = 1;
+pub const NMI_IST: u8 = 2;
+pub const MACHINE_CHECK_IST: u8 = 3;
+
+/// One allocated per entry in the global descriptor table (GDT). Only the CPU reads the fields.
 #[derive(Copy, Clone, Debug)]
 #[repr(packed)]
+#[allow(dead_code)]
 pub struct GdtEntry {
     limit_low: u16,
     base_low: u16,
@@ -34,40 +56,122 @@ impl GdtEntry {
             base_high: (base >> 24) as u8,
         }
     }
+
+    /// Create the two GDT entries of a TSS descriptor. Its base does not fit in one entry, so the
+    /// second one holds the upper half of it.
+    fn tss(tss: &TaskStateSegment) -> [Self; 2] {
+        let base = tss as *const TaskStateSegment as u64;
+        let limit = mem::size_of::<TaskStateSegment>() as u32 - 1;
+        let access = GdtAccessFlags::PRESENT | GdtAccessFlags::RING_0 | GdtAccessFlags::TSS_AVAIL;
+
+        [
+            GdtEntry::from_raw(base as u32, limit, access.bits(), 0),
+            GdtEntry::from_raw((base >> 48) as u32, (base >> 32) as u32, 0, 0),
+        ]
+    }
 }
 
-static mut INIT_GDT: [GdtEntry; 4] = [
+/// The task state segment, which only holds the stacks that the CPU switches to in 64-bit mode.
+#[derive(Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct TaskStateSegment {
+    reserved0: u32,
+    /// Stacks that are loaded when an interrupt raises the privilege level to ring 0, 1 or 2.
+    rsp: [u64; 3],
+    reserved1: u64,
+    /// Stacks that IDT entries can switch to, whatever the privilege level (see
+    /// [`crate::machine::idt::IdtEntry`]).
+    ist: [u64; 7],
+    reserved2: u64,
+    reserved3: u16,
+    /// Offset of the I/O permission bitmap. Pointing it past the end of the TSS denies every port
+    /// to user-mode.
+    iomap_base: u16,
+}
+
+impl TaskStateSegment {
+    /// Create a TSS with no stacks.
+    const fn new() -> Self {
+        Self {
+            reserved0: 0,
+            rsp: [0; 3],
+            reserved1: 0,
+            ist: [0; 7],
+            reserved2: 0,
+            reserved3: 0,
+            iomap_base: mem::size_of::<TaskStateSegment>() as u16,
+        }
+    }
+}
+
+/// Stack of an interrupt stack table entry.
+#[derive(Copy, Clone)]
+#[repr(C, align(16))]
+struct IstStack([u8; IST_STACK_SIZE]);
+
+/// GDT of the kernel, which each CPU gets a copy of, so that each can have its own TSS.
+const GDT: [GdtEntry; GDT_ENTRIES] = [
     // Null
     GdtEntry::new(GdtAccessFlags::NULL, GdtEntryFlags::NULL),
     // Kernel code
     GdtEntry::new(
         GdtAccessFlags::PRESENT
-            | GdtAccessFlags::RING_0
-            | GdtAccessFlags::SYSTEM
-            | GdtAccessFlags::EXECUTABLE
-            | GdtAccessFlags::PRIVILEGE,
+            .union(GdtAccessFlags::RING_0)
+            .union(GdtAccessFlags::SYSTEM)
+            .union(GdtAccessFlags::EXECUTABLE)
+            .union(GdtAccessFlags::PRIVILEGE),
         GdtEntryFlags::LONG_MODE,
     ),
     // Kernel data
     GdtEntry::new(
         GdtAccessFlags::PRESENT
-            | GdtAccessFlags::RING_0
-            | GdtAccessFlags::SYSTEM
-            | GdtAccessFlags::EXECUTABLE
-            | GdtAccessFlags::PRIVILEGE,
-        GdtEntryFlags::LONG_MODE,
+            .union(GdtAccessFlags::RING_0)
+            .union(GdtAccessFlags::SYSTEM)
+            .union(GdtAccessFlags::PRIVILEGE),
+        GdtEntryFlags::NULL,
     ),
     // Kernel TLS
     GdtEntry::new(
         GdtAccessFlags::PRESENT
-            | GdtAccessFlags::RING_0
-            | GdtAccessFlags::SYSTEM
-            | GdtAccessFlags::EXECUTABLE
-            | GdtAccessFlags::PRIVILEGE,
+            .union(GdtAccessFlags::RING_0)
+            .union(GdtAccessFlags::SYSTEM)
+            .union(GdtAccessFlags::PRIVILEGE),
+        GdtEntryFlags::NULL,
+    ),
+    // User code (32-bit, unused)
+    GdtEntry::new(GdtAccessFlags::NULL, GdtEntryFlags::NULL),
+    // User data
+    GdtEntry::new(
+        GdtAccessFlags::PRESENT
+            .union(GdtAccessFlags::RING_3)
+            .union(GdtAccessFlags::SYSTEM)
+            .union(GdtAccessFlags::PRIVILEGE),
+        GdtEntryFlags::NULL,
+    ),
+    // User code
+    GdtEntry::new(
+        GdtAccessFlags::PRESENT
+            .union(GdtAccessFlags::RING_3)
+            .union(GdtAccessFlags::SYSTEM)
+            .union(GdtAccessFlags::EXECUTABLE)
+            .union(GdtAccessFlags::PRIVILEGE),
         GdtEntryFlags::LONG_MODE,
     ),
+    // TSS, which is filled in by `init`
+    GdtEntry::new(GdtAccessFlags::NULL, GdtEntryFlags::NULL),
+    GdtEntry::new(GdtAccessFlags::NULL, GdtEntryFlags::NULL),
 ];
 
+/// Number of CPUs that set up their GDT, which is also the index of the next CPU's tables. The
+/// tables are handed out in the order that the CPUs start, since they are needed before the CPUs
+/// know their identifiers.
+static CPUS: AtomicUsize = AtomicUsize::new(0);
+
+static mut GDTS: [[GdtEntry; GDT_ENTRIES]; MAX_CPUS] = [GDT; MAX_CPUS];
+static mut TSSS: [TaskStateSegment; MAX_CPUS] = [TaskStateSegment::new(); MAX_CPUS];
+static mut STACKS: [[IstStack; IST_STACKS]; MAX_CPUS] =
+    [[IstStack([0; IST_STACK_SIZE]); IST_STACKS]; MAX_CPUS];
+
 bitflags::bitflags! {
     struct GdtAccessFlags: u8 {
         const NULL = 0;
@@ -96,16 +200,10 @@ bitflags::bitflags! {
 
 pub struct GdtEntryType;
 impl GdtEntryType {
-    const NULL: u16 = 0;
     const KERNEL_CODE: u16 = 1;
     const KERNEL_DATA: u16 = 2;
-    const KERNEL_TLS: u16 = 3;
-    const USER_CODE32_UNUSED: u16 = 4;
-    const USER_DATA: u16 = 5;
-    const USER_CODE: u16 = 6;
     const TSS: u16 = 7;
     const TSS_HIGH: u16 = 8;
-    const CPUID_CONTAINER: u16 = 9;
 }
 
 bitflags::bitflags! {
@@ -116,25 +214,36 @@ bitflags::bitflags! {
     }
 }
 
-/// Initialize the global descriptor table.
+/// Load a GDT and a TSS of this CPU's own, whose interrupt stack table has the stacks of
+/// [`DOUBLE_FAULT_IST`], [`NMI_IST`] and [`MACHINE_CHECK_IST`]. The GS base is left alone, since
+/// the per-CPU area may already be reached through it.
+///
+/// # Safety
+/// Must be called once per CPU, before [`crate::machine::idt::init`], which uses the code
+/// segment that is loaded here.
 pub unsafe fn init() {
-    // Set up the initial GDT so that we can set up the actual GDT with a TLS. This means each CPU
-    // will be assigned its own GDT.
-    let limit = (INIT_GDT.len() * mem::size_of::<GdtEntry>() - 1)
-        .try_into()
-        .expect("Initial GDT is too large");
-    let base = INIT_GDT.as_ptr() as *const SegmentDescriptor;
-    let init_gdtr: DescriptorTablePointer<SegmentDescriptor> =
-        DescriptorTablePointer { limit, base };
-
-    // Load the initial GDT.
-    dtables::load_gdt(&init_gdtr);
-
-    // Load the segment descriptors.
-    segmentation::load_cs(SegmentSelector::new(GdtEntryType::KERNEL_DATA, Ring::Ring0));
+    let cpu = CPUS.fetch_add(1, Ordering::Relaxed);
+    assert!(cpu < MAX_CPUS, "Too many CPUs to give each a GDT");
+
+    let tss = &mut *addr_of_mut!(TSSS[cpu]);
+    let stacks = &*addr_of!(STACKS[cpu]);
+    for (index, stack) in stacks.iter().enumerate() {
+        // Stacks grow down, from their end.
+        tss.ist[index] = stack.0.as_ptr_range().end as u64;
+    }
+
+    let gdt = &mut *addr_of_mut!(GDTS[cpu]);
+    let [low, high] = GdtEntry::tss(tss);
+    gdt[GdtEntryType::TSS as usize] = low;
+    gdt[GdtEntryType::TSS_HIGH as usize] = high;
+
+    dtables::load_gdt(&DescriptorTablePointer::from_slice(gdt));
+
+    // Loading the GS and FS selectors would clear their bases, so they are not reloaded.
+    segmentation::load_cs(SegmentSelector::new(GdtEntryType::KERNEL_CODE, Ring::Ring0));
     segmentation::load_ds(SegmentSelector::new(GdtEntryType::KERNEL_DATA, Ring::Ring0));
     segmentation::load_es(SegmentSelector::new(GdtEntryType::KERNEL_DATA, Ring::Ring0));
-    segmentation::load_fs(SegmentSelector::new(GdtEntryType::KERNEL_DATA, Ring::Ring0));
-    segmentation::load_gs(SegmentSelector::new(GdtEntryType::KERNEL_DATA, Ring::Ring0));
     segmentation::load_ss(SegmentSelector::new(GdtEntryType::KERNEL_DATA, Ring::Ring0));
+
+    dtables::load_tr(SegmentSelector::new(GdtEntryType::TSS, Ring::Ring0));
 }
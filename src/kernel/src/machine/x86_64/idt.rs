@@ -1,2 +1,108 @@
-/// Holder function.
-pub fn init() {}
+use core::ptr::addr_of;
+
+use crate::machine::dtables::{self, DescriptorTablePointer};
+use crate::machine::gdt::{DOUBLE_FAULT_IST, MACHINE_CHECK_IST, NMI_IST};
+use crate::machine::interrupt;
+use crate::machine::segmentation;
+
+/// Number of entries in the interrupt descriptor table.
+pub const IDT_ENTRIES: usize = 256;
+
+bitflags::bitflags! {
+    /// Type and attribute field of an IDT entry.
+    pub struct IdtFlags: u8 {
+        /// Present bit. Must be set for every valid entry.
+        const PRESENT = 1 << 7;
+        /// Descriptor privilege level field. Contains the lowest CPU privilege level that can
+        /// trigger the gate with the `int` instruction.
+        const RING_0 = 0 << 5;
+        const RING_1 = 1 << 5;
+        const RING_2 = 2 << 5;
+        const RING_3 = 3 << 5;
+        /// Interrupt gate. Interrupts are disabled while the handler runs.
+        const INTERRUPT = 0xE;
+        /// Trap gate. Interrupts are left enabled while the handler runs.
+        const TRAP = 0xF;
+    }
+}
+
+/// One allocated per entry in the interrupt descriptor table (IDT).
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C, packed)]
+pub struct IdtEntry {
+    offset_low: u16,
+    selector: u16,
+    /// Index of the interrupt stack table entry to switch to (zero to stay on the current stack).
+    ist: u8,
+    flags: u8,
+    offset_middle: u16,
+    offset_high: u32,
+    reserved: u32,
+}
+
+impl IdtEntry {
+    /// Create an entry that is not present.
+    pub const fn new() -> Self {
+        Self {
+            offset_low: 0,
+            selector: 0,
+            ist: 0,
+            flags: 0,
+            offset_middle: 0,
+            offset_high: 0,
+            reserved: 0,
+        }
+    }
+
+    /// Point the entry to a handler.
+    pub fn set_handler(&mut self, handler: usize, selector: u16, flags: IdtFlags) {
+        self.offset_low = handler as u16;
+        self.offset_middle = (handler >> 16) as u16;
+        self.offset_high = (handler >> 32) as u32;
+        self.selector = selector;
+        self.flags = flags.bits();
+    }
+
+    /// Make the entry switch to the stack of the given interrupt stack table entry of the TSS (see
+    /// [`crate::machine::gdt::TaskStateSegment`]).
+    pub fn set_ist(&mut self, index: u8) {
+        self.ist = index;
+    }
+}
+
+/// Vectors of the exceptions that run on a stack of their own, and the interrupt stack table
+/// entries of the stacks.
+const IST_VECTORS: [(usize, u8); 3] =
+    [(2, NMI_IST), (8, DOUBLE_FAULT_IST), (18, MACHINE_CHECK_IST)];
+
+static mut IDT: [IdtEntry; IDT_ENTRIES] = [IdtEntry::new(); IDT_ENTRIES];
+
+/// Initialize the interrupt descriptor table with the exception and interrupt handlers, and load
+/// it.
+///
+/// # Safety
+/// The GDT and TSS must be loaded first (see [`crate::machine::gdt::init`]).
+pub unsafe fn init() {
+    let selector = segmentation::cs().bits();
+
+    for vector in 0..IDT_ENTRIES {
+        // Only the system call vector may be raised by user-space.
+        let ring = if vector == interrupt::SYSCALL_VECTOR {
+            IdtFlags::RING_3
+        } else {
+            IdtFlags::RING_0
+        };
+
+        IDT[vector].set_handler(
+            interrupt::interrupt_stubs[vector],
+            selector,
+            IdtFlags::PRESENT | ring | IdtFlags::INTERRUPT,
+        );
+    }
+
+    for (vector, ist) in IST_VECTORS {
+        IDT[vector].set_ist(ist);
+    }
+
+    dtables::load_idt(&DescriptorTablePointer::new(&*addr_of!(IDT)));
+}
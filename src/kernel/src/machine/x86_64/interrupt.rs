@@ -0,0 +1,484 @@
+use core::arch::{asm, global_asm};
+use core::fmt;
+
+use crate::context::memory::{self, AccessKind, PageFault};
+use crate::context::{scheduler, signal, switch};
+use crate::machine::apic::local::{
+    LocalApic, RESCHEDULE_VECTOR, SHOOTDOWN_VECTOR, SPURIOUS_VECTOR, TIMER_VECTOR,
+};
+use crate::machine::idt::IDT_ENTRIES;
+use crate::machine::{ctrlregs, usercopy};
+use crate::memory::tlb;
+#[cfg(feature = "lockdep")]
+use crate::sync::lockdep;
+use crate::syscall;
+
+/// Number of exception vectors reserved by the CPU.
+pub const EXCEPTION_COUNT: usize = 32;
+
+/// Vector of the page fault exception.
+pub const PAGE_FAULT: usize = 14;
+
+/// Vector that user-space raises with `int` to make a system call.
+pub const SYSCALL_VECTOR: usize = 0x80;
+
+/// Names of the exceptions, indexed by their vectors.
+const EXCEPTION_NAMES: [&str; EXCEPTION_COUNT] = [
+    "Divide error",
+    "Debug",
+    "Non-maskable interrupt",
+    "Breakpoint",
+    "Overflow",
+    "Bound range exceeded",
+    "Invalid opcode",
+    "Device not available",
+    "Double fault",
+    "Coprocessor segment overrun",
+    "Invalid TSS",
+    "Segment not present",
+    "Stack-segment fault",
+    "General protection fault",
+    "Page fault",
+    "Reserved",
+    "x87 floating-point exception",
+    "Alignment check",
+    "Machine check",
+    "SIMD floating-point exception",
+    "Virtualization exception",
+    "Control protection exception",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Hypervisor injection exception",
+    "VMM communication exception",
+    "Security exception",
+    "Reserved",
+];
+
+bitflags::bitflags! {
+    /// Error code pushed by the CPU on a page fault.
+    pub struct PageFaultError: usize {
+        /// The page was present (and the fault was caused by its permissions).
+        const PRESENT = 1 << 0;
+        /// The access was a write.
+        const WRITE = 1 << 1;
+        /// The access happened in user-mode.
+        const USER = 1 << 2;
+        /// A reserved bit was set in one of the page table entries.
+        const RESERVED = 1 << 3;
+        /// The access was an instruction fetch.
+        const INSTRUCTION_FETCH = 1 << 4;
+    }
+}
+
+/// Flags of the RFLAGS register that user-space may change: the arithmetic flags, the trap flag and
+/// the direction flag.
+const RFLAGS_USER: usize = 0b1101_1101_0101;
+
+/// Size of the area below the stack pointer that code may use without moving it, which signal
+/// frames must not overwrite.
+const RED_ZONE: usize = 128;
+
+/// Registers saved when an exception or interrupt occurs. The general purpose registers are
+/// pushed by the entry stubs, and everything after the error code is pushed by the CPU.
+#[derive(Clone, Default)]
+#[repr(C)]
+pub struct InterruptStack {
+    pub r15: usize,
+    pub r14: usize,
+    pub r13: usize,
+    pub r12: usize,
+    pub r11: usize,
+    pub r10: usize,
+    pub r9: usize,
+    pub r8: usize,
+    pub rbp: usize,
+    pub rdi: usize,
+    pub rsi: usize,
+    pub rdx: usize,
+    pub rcx: usize,
+    pub rbx: usize,
+    pub rax: usize,
+    /// Vector of the interrupt.
+    pub vector: usize,
+    /// Error code pushed by the CPU, or zero for vectors that do not have one.
+    pub error_code: usize,
+    pub rip: usize,
+    pub cs: usize,
+    pub rflags: usize,
+    pub rsp: usize,
+    pub ss: usize,
+}
+
+impl InterruptStack {
+    /// Whether the interrupted code was running in user-mode.
+    pub fn is_user(&self) -> bool {
+        self.cs & 0b11 == 0b11
+    }
+
+    /// Retrieve the address of the interrupted instruction.
+    pub fn instruction_pointer(&self) -> usize {
+        self.rip
+    }
+
+    /// Retrieve the stack pointer of the interrupted code.
+    pub fn stack_pointer(&self) -> usize {
+        self.rsp
+    }
+
+    /// Retrieve the number and the arguments of a system call.
+    pub fn syscall_args(&self) -> (usize, [usize; 5]) {
+        (self.rax, [self.rdi, self.rsi, self.rdx, self.r10, self.r8])
+    }
+
+    /// Set the value that a system call returns.
+    pub fn set_syscall_result(&mut self, value: usize) {
+        self.rax = value;
+    }
+
+    /// Make the system call be made again once the interrupted code resumes, by going back to the
+    /// `int 0x80` instruction. The number and arguments are still in their registers.
+    pub fn restart_syscall(&mut self) {
+        self.rip -= 2;
+    }
+
+    /// Retrieve the address where a signal frame of the given size is pushed on the stack. The
+    /// frame starts with the return address of the handler, so it is placed like a call would.
+    pub fn signal_frame(&self, size: usize) -> usize {
+        (self.rsp.wrapping_sub(RED_ZONE + size) & !0xF).wrapping_sub(8)
+    }
+
+    /// Retrieve the address of the signal frame once its handler returned, which popped the
+    /// return address.
+    pub fn returned_signal_frame(&self) -> usize {
+        self.rsp.wrapping_sub(8)
+    }
+
+    /// Make the interrupted code resume in a signal handler, which is passed the signal, with the
+    /// signal frame at the top of the stack. The handler returns to the address at the start of
+    /// the frame.
+    pub fn enter_signal_handler(
+        &mut self,
+        handler: usize,
+        signal: usize,
+        frame: usize,
+        _restorer: usize,
+    ) {
+        self.rip = handler;
+        self.rsp = frame;
+        self.rdi = signal;
+    }
+
+    /// Make the interrupted code resume at the entry point of a new thread, which is passed `arg`,
+    /// with the given stack pointer.
+    pub fn enter_thread(&mut self, entry: usize, stack: usize, arg: usize) {
+        self.rip = entry;
+        self.rsp = stack;
+        self.rdi = arg;
+    }
+
+    /// Restore registers that were saved in a signal frame. The segments and the flags that
+    /// user-space cannot change are kept.
+    pub fn restore_user(&mut self, saved: &InterruptStack) {
+        *self = InterruptStack {
+            vector: self.vector,
+            error_code: self.error_code,
+            cs: self.cs,
+            ss: self.ss,
+            rflags: (self.rflags & !RFLAGS_USER) | (saved.rflags & RFLAGS_USER),
+            ..saved.clone()
+        };
+    }
+}
+
+impl fmt::Debug for InterruptStack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "RIP {:016x} CS {:04x} RFLAGS {:016x}",
+            self.rip, self.cs, self.rflags
+        )?;
+        writeln!(f, "RSP {:016x} SS {:04x}", self.rsp, self.ss)?;
+        writeln!(
+            f,
+            "RAX {:016x} RBX {:016x} RCX {:016x} RDX {:016x}",
+            self.rax, self.rbx, self.rcx, self.rdx
+        )?;
+        writeln!(
+            f,
+            "RSI {:016x} RDI {:016x} RBP {:016x} R8  {:016x}",
+            self.rsi, self.rdi, self.rbp, self.r8
+        )?;
+        writeln!(
+            f,
+            "R9  {:016x} R10 {:016x} R11 {:016x} R12 {:016x}",
+            self.r9, self.r10, self.r11, self.r12
+        )?;
+        write!(
+            f,
+            "R13 {:016x} R14 {:016x} R15 {:016x}",
+            self.r13, self.r14, self.r15
+        )
+    }
+}
+
+// Entry stubs for the exceptions and interrupts. Vectors that do not come with an error code push a
+// zero in its place, so that every handler sees the same stack layout.
+global_asm!(
+    r#"
+.macro interrupt_stub vector, error_code
+.align 16
+interrupt_stub_\vector:
+.if \error_code == 0
+    push 0
+.endif
+    push \vector
+    jmp interrupt_common
+.endm
+
+interrupt_stub 0, 0
+interrupt_stub 1, 0
+interrupt_stub 2, 0
+interrupt_stub 3, 0
+interrupt_stub 4, 0
+interrupt_stub 5, 0
+interrupt_stub 6, 0
+interrupt_stub 7, 0
+interrupt_stub 8, 1
+interrupt_stub 9, 0
+interrupt_stub 10, 1
+interrupt_stub 11, 1
+interrupt_stub 12, 1
+interrupt_stub 13, 1
+interrupt_stub 14, 1
+interrupt_stub 15, 0
+interrupt_stub 16, 0
+interrupt_stub 17, 1
+interrupt_stub 18, 0
+interrupt_stub 19, 0
+interrupt_stub 20, 0
+interrupt_stub 21, 1
+interrupt_stub 22, 0
+interrupt_stub 23, 0
+interrupt_stub 24, 0
+interrupt_stub 25, 0
+interrupt_stub 26, 0
+interrupt_stub 27, 0
+interrupt_stub 28, 0
+interrupt_stub 29, 1
+interrupt_stub 30, 1
+interrupt_stub 31, 0
+
+.irp vector, 32,33,34,35,36,37,38,39,40,41,42,43,44,45,46,47,48,49,50,51,52,53,54,55,56,57,58,59,60,61,62,63,64,65,66,67,68,69,70,71,72,73,74,75,76,77,78,79,80,81,82,83,84,85,86,87,88,89,90,91,92,93,94,95,96,97,98,99,100,101,102,103,104,105,106,107,108,109,110,111,112,113,114,115,116,117,118,119,120,121,122,123,124,125,126,127,128,129,130,131,132,133,134,135,136,137,138,139,140,141,142,143,144,145,146,147,148,149,150,151,152,153,154,155,156,157,158,159,160,161,162,163,164,165,166,167,168,169,170,171,172,173,174,175,176,177,178,179,180,181,182,183,184,185,186,187,188,189,190,191,192,193,194,195,196,197,198,199,200,201,202,203,204,205,206,207,208,209,210,211,212,213,214,215,216,217,218,219,220,221,222,223,224,225,226,227,228,229,230,231,232,233,234,235,236,237,238,239,240,241,242,243,244,245,246,247,248,249,250,251,252,253,254,255
+interrupt_stub \vector, 0
+.endr
+
+interrupt_common:
+    // Coming from user-mode, the GS base is the one of user-mode, and the one of the kernel (which
+    // points to the per-CPU area) waits in IA32_KERNEL_GSBASE.
+    test qword ptr [rsp + 24], 3
+    jz 1f
+    swapgs
+1:
+    push rax
+    push rbx
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    push rbp
+    push r8
+    push r9
+    push r10
+    push r11
+    push r12
+    push r13
+    push r14
+    push r15
+
+    mov rdi, rsp
+    cld
+    call {handler}
+
+.global interrupt_return
+interrupt_return:
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rbp
+    pop rdi
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rbx
+    pop rax
+
+    // Skip the vector and the error code.
+    add rsp, 16
+
+    // Going back to user-mode, give it its GS base back.
+    test qword ptr [rsp + 8], 3
+    jz 2f
+    swapgs
+2:
+    iretq
+
+.section .rodata
+.align 8
+.global interrupt_stubs
+interrupt_stubs:
+.irp vector, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31,32,33,34,35,36,37,38,39,40,41,42,43,44,45,46,47,48,49,50,51,52,53,54,55,56,57,58,59,60,61,62,63,64,65,66,67,68,69,70,71,72,73,74,75,76,77,78,79,80,81,82,83,84,85,86,87,88,89,90,91,92,93,94,95,96,97,98,99,100,101,102,103,104,105,106,107,108,109,110,111,112,113,114,115,116,117,118,119,120,121,122,123,124,125,126,127,128,129,130,131,132,133,134,135,136,137,138,139,140,141,142,143,144,145,146,147,148,149,150,151,152,153,154,155,156,157,158,159,160,161,162,163,164,165,166,167,168,169,170,171,172,173,174,175,176,177,178,179,180,181,182,183,184,185,186,187,188,189,190,191,192,193,194,195,196,197,198,199,200,201,202,203,204,205,206,207,208,209,210,211,212,213,214,215,216,217,218,219,220,221,222,223,224,225,226,227,228,229,230,231,232,233,234,235,236,237,238,239,240,241,242,243,244,245,246,247,248,249,250,251,252,253,254,255
+    .quad interrupt_stub_\vector
+.endr
+.text
+"#,
+    handler = sym interrupt,
+);
+
+extern "C" {
+    /// Addresses of the entry stubs of the exceptions and interrupts, indexed by their vectors.
+    pub static interrupt_stubs: [usize; IDT_ENTRIES];
+}
+
+/// Resume user-space with the given registers, as if returning from an interrupt. Used to start
+/// new user contexts.
+///
+/// # Safety
+/// The registers must be at the top of the kernel stack of the current context, and describe valid
+/// user-mode state. Interrupts must be disabled.
+pub unsafe fn return_to_user(stack: *const InterruptStack) -> ! {
+    asm!(
+        "mov rsp, {0}",
+        "jmp interrupt_return",
+        in(reg) stack,
+        options(noreturn)
+    );
+}
+
+/// Main interrupt handler. Called by the entry stubs with the saved registers.
+extern "C" fn interrupt(stack: &mut InterruptStack) {
+    match stack.vector {
+        PAGE_FAULT => page_fault(stack),
+        vector if vector < EXCEPTION_COUNT => exception(stack),
+        SYSCALL_VECTOR => syscall(stack),
+        _ => irq(stack),
+    }
+
+    // Pending signals are handled on the way back to user-space.
+    if stack.is_user() {
+        signal::deliver(stack);
+    }
+}
+
+/// Handle an interrupt request. Once it is handled, the CPU is given to a more urgent context if
+/// the interrupt made one runnable.
+fn irq(stack: &mut InterruptStack) {
+    #[cfg(feature = "lockdep")]
+    lockdep::enter_interrupt();
+
+    match stack.vector as u8 {
+        TIMER_VECTOR => {
+            LocalApic::get().eoi();
+            scheduler::tick();
+        }
+        // Another CPU requested a switch, which happens below.
+        RESCHEDULE_VECTOR => LocalApic::get().eoi(),
+        SHOOTDOWN_VECTOR => {
+            tlb::poll();
+            LocalApic::get().eoi();
+        }
+        // Spurious interrupts must not be acknowledged.
+        SPURIOUS_VECTOR => (),
+        vector => {
+            log::warn!("Unhandled interrupt {}", vector);
+            LocalApic::get().eoi();
+        }
+    }
+
+    #[cfg(feature = "lockdep")]
+    lockdep::leave_interrupt();
+
+    unsafe { switch::preempt() };
+}
+
+/// Handle a system call. The number is passed in `rax` and the arguments in `rdi`, `rsi`, `rdx`,
+/// `r10` and `r8`, and the result is returned in `rax`.
+fn syscall(stack: &mut InterruptStack) {
+    syscall::syscall(stack);
+
+    unsafe { switch::preempt() };
+}
+
+/// Handle an exception other than a page fault.
+fn exception(stack: &mut InterruptStack) {
+    let signal = match stack.vector {
+        0 | 16 | 19 => signal::SIGFPE,
+        1 | 3 => signal::SIGTRAP,
+        6 => signal::SIGILL,
+        17 => signal::SIGBUS,
+        _ => signal::SIGSEGV,
+    };
+
+    // Faults caused by user code are reported to the context, but the kernel cannot recover from
+    // its own.
+    if stack.is_user() && stack.vector != 2 && stack.vector != 8 && stack.vector != 18 {
+        signal::raise(signal);
+        return;
+    }
+
+    panic!(
+        "{} (vector {}, error code {:#x})\n{:?}",
+        EXCEPTION_NAMES[stack.vector], stack.vector, stack.error_code, stack
+    );
+}
+
+/// Handle a page fault. Faults on user grants are resolved by the current address space, and
+/// user-mode faults that cannot be resolved send `SIGSEGV` to the current context.
+fn page_fault(stack: &mut InterruptStack) {
+    let address = ctrlregs::cr2();
+    let error = PageFaultError::from_bits_truncate(stack.error_code);
+
+    let access = if error.contains(PageFaultError::INSTRUCTION_FETCH) {
+        AccessKind::Execute
+    } else if error.contains(PageFaultError::WRITE) {
+        AccessKind::Write
+    } else {
+        AccessKind::Read
+    };
+
+    let fault = PageFault {
+        address,
+        access,
+        user: stack.is_user(),
+    };
+
+    // The no-execute bit is only set once EFER.NXE made it valid (see `paging::init`), so an entry
+    // with a reserved bit set must have been corrupted, or point past the physical address width.
+    if error.contains(PageFaultError::RESERVED) {
+        panic!(
+            "Reserved bit set in a page table entry for {:#x} (error code {:?})\n{:?}",
+            address, error, stack
+        );
+    }
+
+    match memory::page_fault(&fault) {
+        Ok(()) => (),
+        Err(_) if fault.user => signal::raise(signal::SIGSEGV),
+        // A copy from or to user memory reports the fault to its caller instead.
+        Err(reason) => match usercopy::fixup(stack.rip) {
+            Some(fixup) => stack.rip = fixup,
+            None => panic!(
+                "Page fault in kernel mode at {:#x} ({:?}, error code {:?}): {:?}\n{:?}",
+                address, access, error, reason, stack
+            ),
+        },
+    }
+}
@@ -0,0 +1,24 @@
+use crate::machine::apic::local::{LocalApic, RESCHEDULE_VECTOR, SHOOTDOWN_VECTOR};
+use crate::machine::{irq, smp};
+
+/// Interrupt a CPU so that it switches to a more urgent context that was queued on it.
+pub fn reschedule(cpu: usize) {
+    send(cpu, RESCHEDULE_VECTOR);
+}
+
+/// Interrupt a CPU so that it flushes its TLB (see [`crate::memory::tlb::shootdown`]).
+pub fn shootdown(cpu: usize) {
+    send(cpu, SHOOTDOWN_VECTOR);
+}
+
+/// Send an IPI with the given vector to a CPU.
+fn send(cpu: usize, vector: u8) {
+    let enabled = irq::enabled();
+    unsafe {
+        irq::disable();
+        LocalApic::get().send_ipi(smp::apic_id(cpu), vector);
+        if enabled {
+            irq::enable();
+        }
+    }
+}
@@ -1,5 +1,8 @@
 use core::arch::asm;
 
+/// Interrupt flag of the RFLAGS register.
+const RFLAGS_IF: usize = 1 << 9;
+
 /// Enable interrupts.
 pub unsafe fn enable() {
     asm!("sti");
@@ -9,3 +12,19 @@ pub unsafe fn enable() {
 pub unsafe fn disable() {
     asm!("cli");
 }
+
+/// Whether interrupts are enabled.
+#[inline(always)]
+pub fn enabled() -> bool {
+    let flags: usize;
+    unsafe {
+        asm!("pushfq; pop {0}", out(reg) flags, options(nomem, preserves_flags));
+    }
+    flags & RFLAGS_IF != 0
+}
+
+/// Enable interrupts and wait for the next one. Interrupts are only enabled once `hlt` starts
+/// executing, so no interrupt can slip in between the two instructions.
+pub unsafe fn enable_and_halt() {
+    asm!("sti; hlt", options(nomem, nostack));
+}
@@ -1,21 +1,43 @@
 use core::arch::asm;
 
+pub use self::context::{KERNFX_ALIGN, KERNFX_SIZE};
 pub use self::start::*;
 
+pub mod acpi;
 pub mod apic;
+pub mod backtrace;
+pub mod context;
 pub mod ctrlregs;
+pub mod debug;
 pub mod dtables;
 pub mod fence;
 pub mod gdt;
 pub mod idt;
+pub mod interrupt;
+pub mod ipi;
 //pub mod io;
 pub mod irq;
 pub mod msr;
+pub mod paging;
+pub mod percpu;
 pub mod segmentation;
+pub mod smp;
 //pub mod task;
 pub mod start;
+pub mod switch;
 pub mod time;
 pub mod tlb;
+pub mod usercopy;
+
+/// Offset of the kernel's linear mapping of all physical memory. Set up by the bootloader.
+pub const PHYS_OFFSET: usize = 0xFFFF_8000_0000_0000;
+
+/// Start of the window of kernel memory where DMA buffers that are not write-back cached are
+/// mapped. It is covered by a single PML4 entry.
+pub const DMA_OFFSET: usize = 0xFFFF_FE00_0000_0000;
+
+/// Size of the DMA window, in bytes.
+pub const DMA_SIZE: usize = 1 << 30;
 
 /// x86 Protection levels
 ///
@@ -1,3 +1,11 @@
+use core::arch::x86_64::__cpuid;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::machine::{ctrlregs, msr, tlb};
+use crate::memory::paging::{MapError, PageFlags, PageMapper, PageSize, Translation};
+use crate::memory::{self, Frame};
+
 /// A wrapper for physical addresses.
 #[repr(transparent)]
 #[derive(Copy, Clone, Eq, Ord, PartialEq, PartialOrd)]
@@ -14,3 +22,456 @@ impl PhysAddr {
         (self.0 as u32, (self.0 >> 32) as u32)
     }
 }
+
+/// Number of entries in every page table.
+pub const ENTRY_COUNT: usize = 512;
+
+/// End of the user's (lower) half of the address space.
+pub const USER_END: usize = 0x0000_8000_0000_0000;
+
+/// Retrieve the end of the user's (lower) half of the address space. It is the same for every
+/// address space on x86_64, but depends on the paging mode on other architectures.
+pub fn user_end() -> usize {
+    USER_END
+}
+
+/// Index of the first PML4 entry in the kernel's (upper) half of the address space. These entries
+/// are shared between every address space.
+pub const KERNEL_PML4_INDEX: usize = 256;
+
+/// Leaf of the CPUID instruction that tells whether the CPU supports the no-execute bit, in the
+/// bit [`CPUID_NX`] of EDX.
+const CPUID_EXTENDED_FEATURES: u32 = 0x8000_0001;
+const CPUID_NX: u32 = 1 << 20;
+
+/// Bit of `IA32_EFER` that enables the no-execute bit of page table entries.
+const EFER_NXE: u64 = 1 << 11;
+
+/// Whether `EFER.NXE` is enabled, so that [`PageTableFlags::NO_EXECUTE`] may be set. The bit is
+/// reserved otherwise, and entries that have it set raise page faults.
+static NO_EXECUTE: AtomicBool = AtomicBool::new(false);
+
+bitflags::bitflags! {
+    /// Flags of a page table entry.
+    pub struct PageTableFlags: u64 {
+        /// The entry is in use.
+        const PRESENT = 1 << 0;
+        /// The page can be written to.
+        const WRITABLE = 1 << 1;
+        /// The page can be accessed from ring 3.
+        const USER = 1 << 2;
+        /// Writes go straight through the cache to memory.
+        const WRITE_THROUGH = 1 << 3;
+        /// The page is not cached.
+        const NO_CACHE = 1 << 4;
+        /// Set by the CPU when the page is accessed.
+        const ACCESSED = 1 << 5;
+        /// Set by the CPU when the page is written to.
+        const DIRTY = 1 << 6;
+        /// The entry maps a 2 MiB page (in a PD) or a 1 GiB page (in a PDPT) instead of pointing
+        /// to a table.
+        const HUGE_PAGE = 1 << 7;
+        /// The mapping is not flushed from the TLB when CR3 is written to (requires
+        /// `Cr4::GLOBAL_PAGES`).
+        const GLOBAL = 1 << 8;
+        /// Instructions cannot be fetched from the page (requires `EFER.NXE`).
+        const NO_EXECUTE = 1 << 63;
+    }
+}
+
+impl From<PageFlags> for PageTableFlags {
+    fn from(flags: PageFlags) -> Self {
+        let mut entry_flags = PageTableFlags::PRESENT;
+        entry_flags.set(PageTableFlags::WRITABLE, flags.contains(PageFlags::WRITE));
+        entry_flags.set(PageTableFlags::USER, flags.contains(PageFlags::USER));
+        entry_flags.set(PageTableFlags::GLOBAL, flags.contains(PageFlags::GLOBAL));
+        entry_flags.set(
+            PageTableFlags::NO_CACHE,
+            flags.contains(PageFlags::NO_CACHE),
+        );
+        entry_flags.set(
+            PageTableFlags::WRITE_THROUGH,
+            flags.contains(PageFlags::WRITE_THROUGH),
+        );
+        entry_flags.set(
+            PageTableFlags::NO_EXECUTE,
+            !flags.contains(PageFlags::EXECUTE) && NO_EXECUTE.load(Ordering::Relaxed),
+        );
+        entry_flags
+    }
+}
+
+impl From<PageTableFlags> for PageFlags {
+    fn from(entry_flags: PageTableFlags) -> Self {
+        // Every present page on x86_64 can be read from.
+        let mut flags = PageFlags::READ;
+        flags.set(
+            PageFlags::WRITE,
+            entry_flags.contains(PageTableFlags::WRITABLE),
+        );
+        flags.set(
+            PageFlags::EXECUTE,
+            !entry_flags.contains(PageTableFlags::NO_EXECUTE),
+        );
+        flags.set(PageFlags::USER, entry_flags.contains(PageTableFlags::USER));
+        flags.set(
+            PageFlags::GLOBAL,
+            entry_flags.contains(PageTableFlags::GLOBAL),
+        );
+        flags.set(
+            PageFlags::NO_CACHE,
+            entry_flags.contains(PageTableFlags::NO_CACHE),
+        );
+        flags.set(
+            PageFlags::WRITE_THROUGH,
+            entry_flags.contains(PageTableFlags::WRITE_THROUGH),
+        );
+        flags.set(
+            PageFlags::ACCESSED,
+            entry_flags.contains(PageTableFlags::ACCESSED),
+        );
+        flags.set(
+            PageFlags::DIRTY,
+            entry_flags.contains(PageTableFlags::DIRTY),
+        );
+        flags
+    }
+}
+
+/// A single entry in a page table. Holds the physical address of the next table or of the page,
+/// along with its flags.
+#[derive(Copy, Clone, Debug)]
+#[repr(transparent)]
+pub struct PageTableEntry(u64);
+
+impl PageTableEntry {
+    /// Bits of the entry that hold the physical address.
+    const ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+    /// Construct an entry from a physical address and flags.
+    pub const fn new(address: usize, flags: PageTableFlags) -> Self {
+        Self((address as u64 & Self::ADDRESS_MASK) | flags.bits())
+    }
+
+    /// Retrieve the physical address held by the entry.
+    pub const fn address(&self) -> usize {
+        (self.0 & Self::ADDRESS_MASK) as usize
+    }
+
+    /// Retrieve the flags of the entry.
+    pub const fn flags(&self) -> PageTableFlags {
+        PageTableFlags::from_bits_truncate(self.0)
+    }
+
+    /// Whether the entry is in use.
+    pub const fn is_present(&self) -> bool {
+        self.flags().contains(PageTableFlags::PRESENT)
+    }
+
+    /// Whether the entry maps a page instead of pointing to a table.
+    pub const fn is_huge(&self) -> bool {
+        self.flags().contains(PageTableFlags::HUGE_PAGE)
+    }
+
+    /// Whether the entry is completely empty.
+    pub const fn is_unused(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Clear the entry.
+    pub fn clear(&mut self) {
+        self.0 = 0;
+    }
+}
+
+/// A page table. Every level (PML4, PDPT, PD and PT) has the same layout.
+#[repr(C, align(4096))]
+pub struct PageTable {
+    pub entries: [PageTableEntry; ENTRY_COUNT],
+}
+
+impl PageTable {
+    /// Retrieve the table stored in the given frame, through the kernel's physical mapping.
+    ///
+    /// # Safety
+    /// The frame must hold a page table.
+    unsafe fn from_frame<'a>(frame: Frame) -> &'a mut PageTable {
+        &mut *(frame.virt_address() as *mut PageTable)
+    }
+
+    /// Whether no entries of the table are in use.
+    fn is_empty(&self) -> bool {
+        self.entries.iter().all(PageTableEntry::is_unused)
+    }
+}
+
+/// Retrieve the index into the table at the given level (4 for the PML4, 1 for the PT) for a
+/// virtual address.
+const fn table_index(virt: usize, level: usize) -> usize {
+    (virt >> (12 + 9 * (level - 1))) & (ENTRY_COUNT - 1)
+}
+
+/// Retrieve the level whose entries map pages of the given size.
+const fn page_level(size: PageSize) -> usize {
+    match size {
+        PageSize::Small => 1,
+        PageSize::Large => 2,
+        PageSize::Huge => 3,
+    }
+}
+
+/// Enable the no-execute bit of page table entries on this CPU if it supports it. Must be called by
+/// every CPU before it uses page tables that forbid execution.
+///
+/// # Safety
+/// Must be called in ring 0.
+// `__cpuid` only became safe to call in later versions of Rust.
+#[allow(unused_unsafe)]
+pub unsafe fn init() {
+    let max_leaf = unsafe { __cpuid(0x8000_0000) }.eax;
+    let supported = max_leaf >= CPUID_EXTENDED_FEATURES
+        && unsafe { __cpuid(CPUID_EXTENDED_FEATURES) }.edx & CPUID_NX != 0;
+    if !supported {
+        return;
+    }
+
+    msr::wrmsr(msr::IA32_EFER, msr::rdmsr(msr::IA32_EFER) | EFER_NXE);
+    NO_EXECUTE.store(true, Ordering::Relaxed);
+}
+
+/// Retrieve the size of the pages mapped by entries at the given level.
+const fn level_size(level: usize) -> PageSize {
+    match level {
+        1 => PageSize::Small,
+        2 => PageSize::Large,
+        _ => PageSize::Huge,
+    }
+}
+
+/// Allocate a zeroed frame for a page table.
+fn allocate_table() -> Result<Frame, MapError> {
+    let frame = memory::allocate_frame().ok_or(MapError::OutOfMemory)?;
+    unsafe {
+        ptr::write_bytes(frame.virt_address() as *mut u8, 0, memory::PAGE_SIZE);
+    }
+    Ok(frame)
+}
+
+/// Manages a four-level page table hierarchy, starting from a PML4.
+pub struct Mapper {
+    /// Frame containing the PML4.
+    root: Frame,
+}
+
+impl Mapper {
+    /// Create a new page table hierarchy that shares the kernel's half of the currently active
+    /// page table.
+    pub fn new() -> Result<Self, MapError> {
+        let root = allocate_table()?;
+
+        unsafe {
+            let current = PageTable::from_frame(Frame::containing_address(ctrlregs::cr3()));
+            let table = PageTable::from_frame(root);
+            table.entries[KERNEL_PML4_INDEX..]
+                .copy_from_slice(&current.entries[KERNEL_PML4_INDEX..]);
+        }
+
+        Ok(Self { root })
+    }
+
+    /// Retrieve the page table hierarchy that is currently in use by the CPU.
+    ///
+    /// # Safety
+    /// The mapper must not be used to free the tables while the CPU is using them.
+    pub unsafe fn current() -> Self {
+        Self::from_root(Frame::containing_address(ctrlregs::cr3()))
+    }
+
+    /// Manage an existing page table hierarchy.
+    ///
+    /// # Safety
+    /// The frame must hold a PML4.
+    pub unsafe fn from_root(root: Frame) -> Self {
+        Self { root }
+    }
+
+    /// Walk down to the entry that maps the given address at the given level. Returns `None` if
+    /// an intermediate table is missing, or if a larger page is mapped above the requested level.
+    fn entry(&mut self, virt: usize, level: usize) -> Option<&mut PageTableEntry> {
+        let mut table = unsafe { PageTable::from_frame(self.root) };
+
+        for current in (level + 1..=4).rev() {
+            let entry = &table.entries[table_index(virt, current)];
+            if !entry.is_present() || entry.is_huge() {
+                return None;
+            }
+
+            table = unsafe { PageTable::from_frame(Frame::containing_address(entry.address())) };
+        }
+
+        Some(&mut table.entries[table_index(virt, level)])
+    }
+
+    /// Find the entry that maps the page containing the given address, along with its level.
+    fn leaf(&self, virt: usize) -> Option<(PageTableEntry, usize)> {
+        let mut table = unsafe { PageTable::from_frame(self.root) };
+
+        for level in (1..=4).rev() {
+            let entry = table.entries[table_index(virt, level)];
+            if !entry.is_present() {
+                return None;
+            }
+
+            if level == 1 || entry.is_huge() {
+                return Some((entry, level));
+            }
+
+            table = unsafe { PageTable::from_frame(Frame::containing_address(entry.address())) };
+        }
+
+        None
+    }
+
+    /// Find the entry that maps the page containing the given address, to change it.
+    fn leaf_mut(&mut self, virt: usize) -> Option<(&mut PageTableEntry, usize)> {
+        let (_, level) = self.leaf(virt)?;
+        Some((self.entry(virt, level)?, level))
+    }
+
+    /// Invalidate the TLB entry for the given address, if this page table is in use.
+    fn flush(&self, virt: usize) {
+        if self.is_active() {
+            unsafe { tlb::flush(virt) };
+        }
+    }
+
+    /// Free the intermediate tables on the path to the given address that have become empty. The
+    /// kernel's half of the address space is shared, so its tables are never freed.
+    unsafe fn free_empty_tables(&mut self, virt: usize) {
+        if table_index(virt, 4) >= KERNEL_PML4_INDEX {
+            return;
+        }
+
+        for level in 2..=4 {
+            let entry = match self.entry(virt, level) {
+                Some(entry) if entry.is_present() && !entry.is_huge() => entry,
+                _ => continue,
+            };
+
+            let frame = Frame::containing_address(entry.address());
+            if !PageTable::from_frame(frame).is_empty() {
+                break;
+            }
+
+            entry.clear();
+            memory::deallocate_frame(frame);
+        }
+    }
+}
+
+impl PageMapper for Mapper {
+    fn root(&self) -> Frame {
+        self.root
+    }
+
+    unsafe fn map(
+        &mut self,
+        virt: usize,
+        phys: usize,
+        size: PageSize,
+        flags: PageFlags,
+    ) -> Result<(), MapError> {
+        if virt % size.bytes() != 0 || phys % size.bytes() != 0 {
+            return Err(MapError::Misaligned);
+        }
+
+        // Intermediate tables are as permissive as possible, so that the leaf entry decides the
+        // permissions of the page.
+        let mut table_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        table_flags.set(PageTableFlags::USER, flags.contains(PageFlags::USER));
+
+        let level = page_level(size);
+        let mut table = PageTable::from_frame(self.root);
+
+        for current in (level + 1..=4).rev() {
+            let entry = &mut table.entries[table_index(virt, current)];
+            if entry.is_huge() {
+                return Err(MapError::AlreadyMapped);
+            }
+
+            if !entry.is_present() {
+                *entry = PageTableEntry::new(allocate_table()?.start_address(), table_flags);
+            } else if table_flags.contains(PageTableFlags::USER) {
+                *entry = PageTableEntry::new(entry.address(), entry.flags() | PageTableFlags::USER);
+            }
+
+            table = PageTable::from_frame(Frame::containing_address(entry.address()));
+        }
+
+        let entry = &mut table.entries[table_index(virt, level)];
+        if entry.is_present() {
+            return Err(MapError::AlreadyMapped);
+        }
+
+        let mut entry_flags = PageTableFlags::from(flags);
+        entry_flags.set(PageTableFlags::HUGE_PAGE, size != PageSize::Small);
+        *entry = PageTableEntry::new(phys, entry_flags);
+
+        self.flush(virt);
+        Ok(())
+    }
+
+    unsafe fn unmap(&mut self, virt: usize) -> Result<(usize, PageSize), MapError> {
+        let (entry, level) = self.leaf_mut(virt).ok_or(MapError::NotMapped)?;
+        let phys = entry.address();
+        entry.clear();
+
+        self.flush(virt);
+        self.free_empty_tables(virt);
+        Ok((phys, level_size(level)))
+    }
+
+    fn translate(&self, virt: usize) -> Option<Translation> {
+        let (entry, level) = self.leaf(virt)?;
+        let size = level_size(level);
+
+        Some(Translation {
+            phys: entry.address() + virt % size.bytes(),
+            flags: entry.flags().into(),
+            size,
+        })
+    }
+
+    unsafe fn protect(&mut self, virt: usize, flags: PageFlags) -> Result<PageSize, MapError> {
+        let (entry, level) = self.leaf_mut(virt).ok_or(MapError::NotMapped)?;
+
+        // The accessed and dirty bits are the hardware's, and swapping relies on them.
+        let mut entry_flags = PageTableFlags::from(flags)
+            | (entry.flags() & (PageTableFlags::ACCESSED | PageTableFlags::DIRTY));
+        entry_flags.set(PageTableFlags::HUGE_PAGE, level > 1);
+        *entry = PageTableEntry::new(entry.address(), entry_flags);
+
+        self.flush(virt);
+        Ok(level_size(level))
+    }
+
+    unsafe fn clear_accessed(&mut self, virt: usize) -> Result<bool, MapError> {
+        let (entry, _) = self.leaf_mut(virt).ok_or(MapError::NotMapped)?;
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::ACCESSED) {
+            return Ok(false);
+        }
+
+        *entry = PageTableEntry::new(entry.address(), flags - PageTableFlags::ACCESSED);
+        self.flush(virt);
+        Ok(true)
+    }
+
+    fn is_active(&self) -> bool {
+        Frame::containing_address(ctrlregs::cr3()) == self.root
+    }
+
+    unsafe fn activate(&self) {
+        ctrlregs::write_cr3(self.root.start_address());
+    }
+}
@@ -0,0 +1,50 @@
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::machine::msr::{self, IA32_GS_BASE, IA32_KERNEL_GSBASE};
+
+/// Part of a per-CPU area that the machine code uses, which comes first in the area. The kernel
+/// reaches the area of its CPU through the GS segment: the GS base is the address of the area
+/// while the kernel runs, and the entry stubs swap it with the one of user-mode (with `swapgs`)
+/// when they interrupt user-mode.
+#[repr(C)]
+pub struct Area {
+    /// Address of the area itself, since reading the GS base back takes a slow MSR access.
+    this: AtomicUsize,
+}
+
+impl Area {
+    /// Construct the area of a CPU that is not started yet.
+    pub(crate) const fn new() -> Self {
+        Self {
+            this: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// Make the GS base of this CPU point to its per-CPU area. User-mode starts with a null GS base.
+///
+/// # Safety
+/// Must be called once per CPU, while no user-mode GS base is loaded, and with an area that no
+/// other CPU uses.
+pub unsafe fn init(area: &'static Area) {
+    let address = area as *const Area as usize;
+    area.this.store(address, Ordering::Relaxed);
+
+    msr::wrmsr(IA32_GS_BASE, address as u64);
+    msr::wrmsr(IA32_KERNEL_GSBASE, 0);
+}
+
+/// Retrieve the address of the per-CPU area of the running CPU.
+#[inline(always)]
+pub fn base() -> usize {
+    let base: usize;
+    unsafe {
+        asm!(
+            "mov {0}, gs:[0]",
+            out(reg) base,
+            options(nostack, preserves_flags, readonly)
+        );
+    }
+    base
+}
@@ -106,9 +106,19 @@ impl Descriptor {
     }
 }
 
-/// Reload the code segment register.
+/// Reload the code segment register. It cannot be written to directly, so a far return pops it
+/// along with the address of the next instruction.
 pub unsafe fn load_cs(selector: SegmentSelector) {
-    asm!("mov cs, {0:x}", in(reg) selector.bits(), options(nomem, nostack));
+    asm!(
+        "push {0}",
+        "lea {1}, [rip + 2f]",
+        "push {1}",
+        "retfq",
+        "2:",
+        in(reg) selector.bits() as u64,
+        lateout(reg) _,
+        options(preserves_flags)
+    );
 }
 
 /// Reload the data segment register.
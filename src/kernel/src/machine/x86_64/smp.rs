@@ -0,0 +1,284 @@
+use core::arch::global_asm;
+use core::hint;
+use core::mem::offset_of;
+use core::ptr::{self, addr_of};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+use crate::context::MAX_CPUS;
+use crate::machine::apic::local::LocalApic;
+use crate::machine::ctrlregs::{self, Cr4};
+use crate::machine::msr::{self, IA32_EFER};
+use crate::machine::paging::Mapper;
+use crate::machine::{acpi, start, time};
+use crate::memory::paging::{PageFlags, PageMapper, PageSize};
+use crate::memory::{self, PAGE_SIZE};
+
+/// Physical address of the page that the other CPUs start in, in real mode. It is identity-mapped
+/// while they start, and the first MiB of memory is kept from the frame allocator for it.
+pub const TRAMPOLINE: usize = 0x8000;
+
+/// Offset of the [`TrampolineArgs`] in the page of the trampoline, past its code.
+const ARGS_OFFSET: usize = 0xF00;
+
+/// Bit of `IA32_EFER` that tells whether long mode is active. It cannot be written.
+const EFER_LMA: u64 = 1 << 10;
+
+/// Order of the blocks of frames that the stacks of the other CPUs are allocated with (64 KiB,
+/// like the kernel stacks of contexts). The stacks become the ones of their idle contexts.
+const STACK_ORDER: usize = 4;
+
+/// How long the boot CPU waits for a CPU to start before giving up on it, in milliseconds.
+const START_TIMEOUT_MS: usize = 100;
+
+/// What the boot CPU passes to the CPU that it starts, in the page of the trampoline.
+#[repr(C)]
+struct TrampolineArgs {
+    /// Identifier of the CPU, passed to [`start::kstart_ap`].
+    cpu: u64,
+    /// End of the CPU's stack.
+    stack: u64,
+    /// Address of [`start::kstart_ap`].
+    entry: u64,
+    /// Physical address of the PML4 to start with, which must be below 4 GiB.
+    page_table: u64,
+    /// Values of the control registers and of `IA32_EFER` on the boot CPU, which the trampoline
+    /// loads (but for the bits that can only be set once long mode is active).
+    cr0: u64,
+    cr4: u64,
+    efer: u64,
+}
+
+// Code that the other CPUs start with, which is copied to `TRAMPOLINE`. It switches from real mode
+// straight to long mode with the kernel's page table, using a GDT of its own until
+// `start::kstart_ap` loads the CPU's GDT, and calls `kstart_ap` on the stack that the boot CPU
+// allocated.
+global_asm!(
+    r#"
+.pushsection .rodata.ap_trampoline, "a"
+.global ap_trampoline
+.global ap_trampoline_end
+.code16
+ap_trampoline:
+    cli
+    cld
+    xorw %ax, %ax
+    movw %ax, %ds
+    movw %ax, %es
+    movw %ax, %ss
+
+    movl {args} + {cr4}, %eax
+    movl %eax, %cr4
+    movl {args} + {page_table}, %eax
+    movl %eax, %cr3
+
+    movl ${efer_msr}, %ecx
+    rdmsr
+    orl {args} + {efer}, %eax
+    wrmsr
+
+    movl {args} + {cr0}, %eax
+    movl %eax, %cr0
+
+    lgdtl {trampoline} + 3f - ap_trampoline
+    ljmpl $8, ${trampoline} + 1f - ap_trampoline
+
+.code64
+1:
+    movw $16, %ax
+    movw %ax, %ds
+    movw %ax, %es
+    movw %ax, %ss
+
+    movq {args} + {stack}, %rsp
+    movq {args} + {cpu}, %rdi
+    movq {args} + {entry}, %rax
+    callq *%rax
+    ud2
+
+.balign 8
+2:
+    .quad 0
+    .quad 0x00209A0000000000
+    .quad 0x0000920000000000
+3:
+    .word 3b - 2b - 1
+    .long {trampoline} + 2b - ap_trampoline
+ap_trampoline_end:
+.popsection
+"#,
+    trampoline = const TRAMPOLINE,
+    args = const TRAMPOLINE + ARGS_OFFSET,
+    cpu = const offset_of!(TrampolineArgs, cpu),
+    stack = const offset_of!(TrampolineArgs, stack),
+    entry = const offset_of!(TrampolineArgs, entry),
+    page_table = const offset_of!(TrampolineArgs, page_table),
+    cr0 = const offset_of!(TrampolineArgs, cr0),
+    cr4 = const offset_of!(TrampolineArgs, cr4),
+    efer = const offset_of!(TrampolineArgs, efer),
+    efer_msr = const IA32_EFER,
+    options(att_syntax)
+);
+
+extern "C" {
+    static ap_trampoline: u8;
+    static ap_trampoline_end: u8;
+}
+
+/// APIC IDs of the local APICs of the CPUs, indexed by CPU.
+static APIC_IDS: [AtomicU32; MAX_CPUS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NONE: AtomicU32 = AtomicU32::new(0);
+    [NONE; MAX_CPUS]
+};
+
+/// Identifier of the CPU that started last, which the boot CPU waits for.
+static ARRIVED: AtomicUsize = AtomicUsize::new(0);
+
+/// CPU that the boot CPU compares its TSC with, which waits for it once it arrived.
+static TSC_CHECK: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether the boot CPU set up scheduling, which the other CPUs wait for.
+static RELEASED: AtomicBool = AtomicBool::new(false);
+
+/// Retrieve the APIC ID of the local APIC of a CPU.
+pub fn apic_id(cpu: usize) -> u32 {
+    APIC_IDS[cpu].load(Ordering::Relaxed)
+}
+
+/// Start the CPUs that the MADT lists, one after the other, giving them consecutive identifiers
+/// after the boot CPU's (which is zero). CPUs that do not start in time are left out. Returns the
+/// number of CPUs that run, the boot CPU included. The bootloader may pass the RSDP at the given
+/// physical address and size.
+///
+/// The started CPUs wait in [`arrived`] until [`release`] is called.
+///
+/// # Safety
+/// Must be called once, by the boot CPU, with interrupts disabled.
+pub unsafe fn init(boot_apic_id: u32, rsdps_base: usize, rsdps_size: usize) -> usize {
+    APIC_IDS[0].store(boot_apic_id, Ordering::Relaxed);
+
+    // The page table is loaded before paging is enabled, when only 32-bit registers are there.
+    if ctrlregs::cr3() >= 1 << 32 {
+        log::warn!("Page table above 4 GiB, so only the boot CPU runs");
+        return 1;
+    }
+
+    // The trampoline enables paging, so it has to be mapped where it runs from.
+    let mut mapper = Mapper::current();
+    let mapped = match mapper.translate(TRAMPOLINE) {
+        Some(translation) if translation.phys == TRAMPOLINE => false,
+        Some(_) => {
+            log::warn!("Trampoline page in use, so only the boot CPU runs");
+            return 1;
+        }
+        None => {
+            let flags = PageFlags::READ | PageFlags::WRITE | PageFlags::EXECUTE;
+            mapper
+                .map(TRAMPOLINE, TRAMPOLINE, PageSize::Small, flags)
+                .expect("Unable to map the trampoline");
+            true
+        }
+    };
+
+    let start = addr_of!(ap_trampoline);
+    let size = addr_of!(ap_trampoline_end) as usize - start as usize;
+    assert!(size <= ARGS_OFFSET, "Trampoline too large");
+    ptr::copy_nonoverlapping(start, memory::phys_to_virt(TRAMPOLINE) as *mut u8, size);
+
+    let mut cpus = 1;
+    let found = acpi::for_each_apic_id(rsdps_base, rsdps_size, |id| {
+        if id == boot_apic_id {
+            return;
+        }
+
+        // The local APIC is used in xAPIC mode, where IPIs only reach 8-bit IDs.
+        if cpus == MAX_CPUS || id > 0xFF {
+            log::warn!("Leaving out the CPU with APIC ID {}", id);
+        } else if start_cpu(cpus, id) {
+            TSC_CHECK.store(cpus, Ordering::Release);
+            time::check_tsc();
+            cpus += 1;
+        }
+    });
+    if !found {
+        log::warn!("No MADT found, so only the boot CPU runs");
+    }
+
+    if mapped {
+        mapper
+            .unmap(TRAMPOLINE)
+            .expect("Unable to unmap the trampoline");
+    }
+    cpus
+}
+
+/// Start a CPU through the trampoline, returning whether it started. It is sent the INIT-SIPI-SIPI
+/// sequence, and reset again if it does not arrive in time.
+unsafe fn start_cpu(cpu: usize, id: u32) -> bool {
+    let stack = match memory::allocate_frames(STACK_ORDER) {
+        Some(stack) => stack,
+        None => {
+            log::warn!("No memory for the stack of the CPU with APIC ID {}", id);
+            return false;
+        }
+    };
+
+    let args = memory::phys_to_virt(TRAMPOLINE + ARGS_OFFSET) as *mut TrampolineArgs;
+    args.write_volatile(TrampolineArgs {
+        cpu: cpu as u64,
+        stack: (stack.virt_address() + (PAGE_SIZE << STACK_ORDER)) as u64,
+        entry: start::kstart_ap as *const () as u64,
+        page_table: ctrlregs::cr3() as u64,
+        cr0: ctrlregs::cr0().bits() as u64,
+        // PCIDs can only be enabled in long mode.
+        cr4: (ctrlregs::cr4() - Cr4::PCID).bits() as u64,
+        efer: msr::rdmsr(IA32_EFER) & !EFER_LMA,
+    });
+    APIC_IDS[cpu].store(id, Ordering::Relaxed);
+
+    let mut local_apic = LocalApic::get();
+    local_apic.send_init(id);
+    time::delay(10_000);
+
+    // A second startup IPI is sent if the first one was missed.
+    for _ in 0..2 {
+        local_apic.send_startup(id, TRAMPOLINE);
+        time::delay(200);
+        if ARRIVED.load(Ordering::Acquire) == cpu {
+            return true;
+        }
+    }
+
+    for _ in 0..START_TIMEOUT_MS {
+        if ARRIVED.load(Ordering::Acquire) == cpu {
+            return true;
+        }
+        time::delay(1000);
+    }
+
+    log::warn!("CPU with APIC ID {} did not start", id);
+    local_apic.send_init(id);
+    memory::deallocate_frames(stack, STACK_ORDER);
+    false
+}
+
+/// Tell the boot CPU that this CPU started, compare the TSCs of both (see [`time::check_tsc`]), and
+/// wait for the boot CPU to set up scheduling. Called by every other CPU once it called
+/// [`crate::context::percpu::init`].
+pub fn arrived(cpu: usize) {
+    ARRIVED.store(cpu, Ordering::Release);
+
+    while TSC_CHECK.load(Ordering::Acquire) != cpu {
+        hint::spin_loop();
+    }
+    time::check_tsc();
+
+    while !RELEASED.load(Ordering::Acquire) {
+        hint::spin_loop();
+    }
+}
+
+/// Let the started CPUs go on, once the boot CPU set up scheduling.
+pub fn release() {
+    RELEASED.store(true, Ordering::Release);
+}
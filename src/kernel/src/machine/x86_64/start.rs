@@ -1,6 +1,12 @@
+use core::{mem, slice};
+
+use crate::context::{self, percpu, scheduler};
+use crate::device::base::char::CharDeviceSwitch;
 use crate::device::serial::uart_16550::SerialPort;
 use crate::io::PortIo;
-use crate::machine::{gdt, idt};
+use crate::machine::apic::local::LocalApic;
+use crate::machine::{gdt, idt, irq, paging, smp, time};
+use crate::memory::{self, MemoryArea, MemoryAreaKind};
 
 /// Passed to the kernel entry-point. Same format as the bootloader for Redux OS.
 #[repr(packed)]
@@ -23,7 +29,9 @@ pub struct KernelArgs {
     /// The size of the RSDPs region.
     acpi_rsdps_size: u64,
 
+    /// The base pointer to an array of [`MemoryArea`]s describing the physical memory.
     areas_base: u64,
+    /// The size of the memory areas array, in bytes.
     areas_size: u64,
 
     /// The physical base 64-bit pointer to the contiguous bootstrap/initfs.
@@ -34,23 +42,116 @@ pub struct KernelArgs {
     bootstrap_entry: u64,
 }
 
+/// Highest number of memory areas that are taken from the bootloader.
+const MAX_AREAS: usize = 64;
+
+/// End of the memory that the other CPUs start in (see [`smp::TRAMPOLINE`]), which is kept from
+/// the frame allocator.
+const LOW_MEMORY_END: u64 = 0x10_0000;
+
+/// Copy the memory areas that the bootloader passed, leaving the low memory out of the free ones.
+/// Returns the number of areas that were copied.
+fn usable_areas(areas: &[MemoryArea], usable: &mut [MemoryArea; MAX_AREAS]) -> usize {
+    if areas.len() > MAX_AREAS {
+        log::warn!(
+            "Ignoring {} memory areas: too many areas",
+            areas.len() - MAX_AREAS
+        );
+    }
+
+    let mut count = 0;
+    for area in areas.iter().take(MAX_AREAS) {
+        let MemoryArea {
+            mut base,
+            mut size,
+            kind,
+        } = *area;
+        if kind == MemoryAreaKind::Free && base < LOW_MEMORY_END {
+            let end = base + size;
+            base = LOW_MEMORY_END.min(end);
+            size = end - base;
+        }
+
+        usable[count] = MemoryArea { base, size, kind };
+        count += 1;
+    }
+    count
+}
+
 /// Kernel entry-point for x86_64. Everything that is architecture-specific must be initialized
 /// here, before calling architecutre-independent kernel code.
-#[no_mangle]
-pub unsafe extern "C" fn _start(/*args_ptr: *const KernelArgs*/) -> ! {
-    // let args = args_ptr.read();
+// Host test binaries have their own `_start`.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn _start(args_ptr: *const KernelArgs) -> ! {
+    let args = args_ptr.read();
 
-    // Set up GDT and IDT before initializing paging.
-    // gdt::init();
-    // idt::init();
+    // Set up GDT and IDT before initializing paging, so that faults are reported from then on.
+    gdt::init();
+    idt::init();
 
     // Set up serial communication.
     let mut serial_port = SerialPort::<PortIo<u8>>::new(0x3F8);
     for character in "Hello world".as_bytes().iter() {
-        serial_port.put_char(*character);
+        let _ = serial_port.put_char(*character);
     }
 
+    // Hand the physical memory to the frame allocator. Entries that are mapped from now on may
+    // forbid execution.
+    paging::init();
+    let areas = slice::from_raw_parts(
+        args.areas_base as usize as *const MemoryArea,
+        args.areas_size as usize / mem::size_of::<MemoryArea>(),
+    );
+    let mut usable = [MemoryArea {
+        base: 0,
+        size: 0,
+        kind: MemoryAreaKind::Null,
+    }; MAX_AREAS];
+    let count = usable_areas(areas, &mut usable);
+    memory::init(&usable[..count]);
+
+    // Start the other CPUs, which wait for scheduling to be set up, and start scheduling, with
+    // this becoming the idle context. CPUs are numbered in the order that they started, from zero
+    // for this one, rather than by the IDs of their local APICs.
+    let mut local_apic = LocalApic::get();
+    local_apic.init();
+    percpu::init(0);
+    let cpus = smp::init(
+        local_apic.id(),
+        args.acpi_rsdps_base as usize,
+        args.acpi_rsdps_size as usize,
+    );
+    context::init(cpus);
+    smp::release();
+    local_apic.start_timer(scheduler::TICK_RATE as u32);
+    time::init();
+
     // TODO: this is temporary.
-    loop {}
+    loop {
+        irq::enable_and_halt();
+    }
     // crate::kmain(1, bootstrap)
 }
+
+/// Entry point of the other CPUs, which the trampoline calls with the identifier that the boot CPU
+/// gave the CPU (see [`smp::init`]).
+#[no_mangle]
+pub unsafe extern "C" fn kstart_ap(cpu: usize) -> ! {
+    // Per-CPU data (like the preemption count that the locks below use) is reached through the GS
+    // base since the boot CPU set up its area, so this CPU points it to its own area first. Loading
+    // the GDT leaves the GS base alone.
+    percpu::init(cpu);
+    gdt::init();
+    idt::init();
+    paging::init();
+
+    let mut local_apic = LocalApic::get();
+    local_apic.init();
+    smp::arrived(cpu);
+    context::init_cpu();
+    local_apic.start_timer(scheduler::TICK_RATE as u32);
+
+    loop {
+        irq::enable_and_halt();
+    }
+}
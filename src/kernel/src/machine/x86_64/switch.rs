@@ -0,0 +1,71 @@
+use core::arch::global_asm;
+use core::mem::offset_of;
+
+use crate::machine::context::Context;
+use crate::machine::msr::{self, IA32_FS_BASE};
+
+// Save the callee-saved registers and the SIMD state of the current context, and load those of
+// the next one. Returning from here returns into the next context, wherever it last called this
+// (or into `context_entry` for a new context).
+global_asm!(
+    r#"
+.global context_switch
+context_switch:
+    mov rax, [rdi + {fx}]
+    fxsave64 [rax]
+    mov rax, [rsi + {fx}]
+    fxrstor64 [rax]
+
+    pushfq
+    pop qword ptr [rdi + {rflags}]
+    mov [rdi + {rbx}], rbx
+    mov [rdi + {r12}], r12
+    mov [rdi + {r13}], r13
+    mov [rdi + {r14}], r14
+    mov [rdi + {r15}], r15
+    mov [rdi + {rbp}], rbp
+    mov [rdi + {rsp}], rsp
+
+    mov rbx, [rsi + {rbx}]
+    mov r12, [rsi + {r12}]
+    mov r13, [rsi + {r13}]
+    mov r14, [rsi + {r14}]
+    mov r15, [rsi + {r15}]
+    mov rbp, [rsi + {rbp}]
+    mov rsp, [rsi + {rsp}]
+    push qword ptr [rsi + {rflags}]
+    popfq
+    ret
+
+.global context_entry
+context_entry:
+    mov rdi, rbx
+    call {entry}
+    ud2
+"#,
+    fx = const offset_of!(Context, fx),
+    rflags = const offset_of!(Context, rflags),
+    rbx = const offset_of!(Context, rbx),
+    r12 = const offset_of!(Context, r12),
+    r13 = const offset_of!(Context, r13),
+    r14 = const offset_of!(Context, r14),
+    r15 = const offset_of!(Context, r15),
+    rbp = const offset_of!(Context, rbp),
+    rsp = const offset_of!(Context, rsp),
+    entry = sym crate::context::switch::kernel_entry,
+);
+
+extern "C" {
+    fn context_switch(prev: *mut Context, next: *const Context);
+}
+
+/// Switch from one context to another. Returns once something switches back to `prev`. The FS
+/// base of the next context is loaded first; the kernel does not use the FS segment.
+///
+/// # Safety
+/// Interrupts must be disabled, and both contexts must stay alive (and untouched by anything
+/// else) until the switch is over.
+pub unsafe fn switch(prev: *mut Context, next: *const Context) {
+    msr::wrmsr(IA32_FS_BASE, (*next).fs_base as u64);
+    context_switch(prev, next);
+}
@@ -1 +1,156 @@
+use core::arch::x86_64::{__cpuid, _mm_lfence, _rdtsc};
+use core::hint;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
+use crate::io::{IoVec, PortIo};
+use crate::sync::Mutex;
+use crate::time::{self, Clock};
+
+/// Frequency of the programmable interval timer (PIT), in hertz.
+const PIT_FREQUENCY: u64 = 1_193_182;
+
+/// How long counters are measured against the PIT for, in milliseconds.
+const CALIBRATION_MS: u64 = 10;
+
+/// Leaf of the CPUID instruction that tells whether the time-stamp counter is invariant, in the
+/// bit [`INVARIANT_TSC`] of EDX.
+const CPUID_ADVANCED_POWER: u32 = 0x8000_0007;
+const INVARIANT_TSC: u32 = 1 << 8;
+
+/// Number of times that each of two CPUs reads its TSC when they compare their TSCs.
+const TSC_CHECK_READS: usize = 100_000;
+
+/// Clock that reads the time-stamp counter (TSC) of the CPU. It is only used when the TSC is
+/// invariant, so that it counts at the same rate on every CPU whatever their power state, and when
+/// the TSCs of the CPUs agree (see [`check_tsc`]).
+struct Tsc {
+    /// Number of counts per second, measured once.
+    frequency: AtomicU64,
+}
+
+/// Time-stamp counter of the CPUs.
+static TSC: Tsc = Tsc {
+    frequency: AtomicU64::new(0),
+};
+
+impl Clock for Tsc {
+    fn name(&self) -> &'static str {
+        "tsc"
+    }
+
+    fn rating(&self) -> u32 {
+        300
+    }
+
+    fn frequency(&self) -> u64 {
+        self.frequency.load(Ordering::Relaxed)
+    }
+
+    fn read(&self) -> u64 {
+        unsafe { _rdtsc() }
+    }
+}
+
+/// Last TSC that was read while two CPUs compare their TSCs, which serializes their reads.
+static TSC_LAST: Mutex<u64> = Mutex::new(0);
+
+/// Whether a CPU read a TSC behind one that another CPU read before it, in which case the TSC
+/// cannot keep the time.
+static TSC_WARPED: AtomicBool = AtomicBool::new(false);
+
+/// Channel 2 of the PIT is shared by every CPU, so only one of them may count down with it at a
+/// time.
+static PIT: Mutex<()> = Mutex::new(());
+
+/// Make channel 2 of the PIT count down once from `count`, returning the register that holds its
+/// gate (and its output).
+fn start_countdown(count: u64) -> PortIo<u8> {
+    let mut gate = PortIo::<u8>::new(0x61);
+    let mut command = PortIo::<u8>::new(0x43);
+    let mut data = PortIo::<u8>::new(0x42);
+
+    // Disconnect the speaker, and program channel 2 to count down once (mode 0).
+    let control = gate.read() & !0b10;
+    gate.write(control & !0b01);
+    command.write(0b1011_0000);
+
+    data.write(count as u8);
+    data.write((count >> 8) as u8);
+
+    // Counting starts when the gate goes high.
+    gate.write(control | 0b01);
+    gate
+}
+
+/// Wait for the countdown of channel 2 of the PIT to reach zero, at which point its output goes
+/// high.
+fn wait_countdown(gate: &mut PortIo<u8>) {
+    while gate.read() & 0b10_0000 == 0 {
+        hint::spin_loop();
+    }
+}
+
+/// Measure how fast a counter goes up, in counts per second, using channel 2 of the PIT (whose
+/// frequency is fixed) as a reference.
+pub fn calibrate(mut read: impl FnMut() -> u64) -> u64 {
+    let _pit = PIT.lock();
+
+    let mut gate = start_countdown(PIT_FREQUENCY / (1000 / CALIBRATION_MS));
+    let start = read();
+    wait_countdown(&mut gate);
+
+    read().wrapping_sub(start) * (1000 / CALIBRATION_MS)
+}
+
+/// Spin for at least the given number of microseconds, which must not exceed 50 milliseconds (the
+/// longest countdown of the PIT).
+pub fn delay(micros: u64) {
+    let _pit = PIT.lock();
+
+    let count = (PIT_FREQUENCY * micros).div_ceil(1_000_000);
+    wait_countdown(&mut start_countdown(count.clamp(1, u16::MAX as u64)));
+}
+
+/// Whether the time-stamp counter is invariant.
+// `__cpuid` only became safe to call in later versions of Rust.
+#[allow(unused_unsafe)]
+fn has_invariant_tsc() -> bool {
+    let max_leaf = unsafe { __cpuid(0x8000_0000) }.eax;
+    max_leaf >= CPUID_ADVANCED_POWER
+        && unsafe { __cpuid(CPUID_ADVANCED_POWER) }.edx & INVARIANT_TSC != 0
+}
+
+/// Compare the TSC of this CPU with the one of another CPU that calls this at the same time: each
+/// read has to be ahead of the reads of the other CPU that came before. The boot CPU does this with
+/// every CPU that it starts (see [`crate::machine::smp`]).
+pub fn check_tsc() {
+    for _ in 0..TSC_CHECK_READS {
+        let mut last = TSC_LAST.lock();
+        // The TSC is not read before the lock is taken.
+        let now = unsafe {
+            _mm_lfence();
+            _rdtsc()
+        };
+
+        if now < *last {
+            TSC_WARPED.store(true, Ordering::Relaxed);
+        }
+        *last = now;
+    }
+}
+
+/// Register the clocks of the machine that are good enough to keep the time. Called once, by the
+/// boot CPU, once the other CPUs started.
+pub fn init() {
+    if !has_invariant_tsc() {
+        return;
+    }
+    if TSC_WARPED.load(Ordering::Relaxed) {
+        log::warn!("The TSCs of the CPUs do not agree, so they do not keep the time");
+        return;
+    }
+
+    TSC.frequency
+        .store(calibrate(|| unsafe { _rdtsc() }), Ordering::Relaxed);
+    time::register(&TSC);
+}
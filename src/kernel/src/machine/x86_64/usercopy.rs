@@ -0,0 +1,52 @@
+use core::arch::global_asm;
+use core::ptr::addr_of;
+
+// Copy `rdx` bytes from `rsi` to `rdi`, returning zero. If the copy hits a page fault that cannot
+// be resolved, the fault handler resumes at `user_copy_fault` instead, which returns one.
+global_asm!(
+    r#"
+.global user_copy
+user_copy:
+    mov rcx, rdx
+.global user_copy_start
+user_copy_start:
+    rep movsb
+.global user_copy_end
+user_copy_end:
+    xor eax, eax
+    ret
+
+.global user_copy_fault
+user_copy_fault:
+    mov eax, 1
+    ret
+"#
+);
+
+extern "C" {
+    fn user_copy(dst: *mut u8, src: *const u8, len: usize) -> usize;
+
+    static user_copy_start: u8;
+    static user_copy_end: u8;
+    static user_copy_fault: u8;
+}
+
+/// Copy bytes between user and kernel memory. Returns whether the copy went through; it stops
+/// at the first page fault that the kernel cannot resolve.
+///
+/// # Safety
+/// The kernel side must be valid for the copy, and the user side must lie in the user part of the
+/// address space.
+pub unsafe fn copy(dst: *mut u8, src: *const u8, len: usize) -> bool {
+    user_copy(dst, src, len) == 0
+}
+
+/// Retrieve the address to resume at after a page fault at the given instruction that could not
+/// be resolved, if the instruction belongs to [`copy`].
+pub fn fixup(instruction: usize) -> Option<usize> {
+    let start = addr_of!(user_copy_start) as usize;
+    let end = addr_of!(user_copy_end) as usize;
+    let fault = addr_of!(user_copy_fault) as usize;
+
+    (start..end).contains(&instruction).then_some(fault)
+}
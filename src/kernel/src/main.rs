@@ -1,16 +1,23 @@
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
+#![feature(alloc_error_handler)]
+#![feature(ptr_internals)]
 
 extern crate alloc;
 extern crate core;
 
+mod context;
 mod device;
 mod filesys;
 mod io;
+// Still a draft that does not build, so keep it out of host test builds.
+#[cfg(not(test))]
 mod ipc;
 mod machine;
 mod memory;
 mod sync;
+mod syscall;
+mod time;
 mod unwind;
 mod utils;
 
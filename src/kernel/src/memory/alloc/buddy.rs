@@ -0,0 +1,506 @@
+use core::mem;
+use core::slice;
+
+use crate::memory::{phys_to_virt, Frame, MemoryArea, MemoryAreaKind, PAGE_SIZE};
+
+/// Largest order that can be allocated. A block of order `n` is `2^n` frames long, so with 4 KiB
+/// pages the largest block is 4 MiB.
+pub const MAX_ORDER: usize = 10;
+
+/// Number of free-lists that each zone keeps (one for every order).
+const ORDER_COUNT: usize = MAX_ORDER + 1;
+
+/// Maximum number of memory areas that can be handed to the allocator.
+const MAX_ZONES: usize = 32;
+
+/// Marks the end of a free-list.
+const NIL: u32 = u32::MAX;
+
+/// Book-keeping data for a single physical frame. One of these is allocated for every frame in a
+/// zone and is stored at the start of the zone itself.
+#[derive(Copy, Clone, Debug)]
+struct PageInfo {
+    /// Index of the next free block in the free-list (only valid on the head of a free block).
+    next: u32,
+    /// Index of the previous free block in the free-list (only valid on the head of a free block).
+    prev: u32,
+    /// Order of the block that starts at this frame.
+    order: u8,
+    /// Whether the block that starts at this frame is free.
+    free: bool,
+    /// Number of references to the block that starts at this frame (only valid on the head of an
+    /// allocated block). Frames shared by copy-on-write mappings have more than one.
+    refs: u32,
+}
+
+impl PageInfo {
+    const fn new() -> Self {
+        Self {
+            next: NIL,
+            prev: NIL,
+            order: 0,
+            free: false,
+            refs: 0,
+        }
+    }
+}
+
+/// A zone is a physically contiguous region of memory that is managed by the buddy allocator. Each
+/// zone is created from one of the memory areas passed by the bootloader.
+struct Zone {
+    /// Frame number of the first frame in the zone.
+    base: usize,
+    /// Book-keeping data for every frame in the zone.
+    pages: &'static mut [PageInfo],
+    /// Heads of the free-lists for every order.
+    heads: [u32; ORDER_COUNT],
+    /// Number of frames that are free.
+    free: usize,
+}
+
+impl Zone {
+    /// Create a zone that manages the frames in the given physical range. The book-keeping data
+    /// is placed at the start of the range, so the zone manages slightly fewer frames than the
+    /// range contains. Returns `None` if the range is too small to be worth managing.
+    unsafe fn new(start: usize, end: usize) -> Option<Self> {
+        let start = align_up(start, PAGE_SIZE);
+        let end = end & !(PAGE_SIZE - 1);
+        if end <= start {
+            return None;
+        }
+
+        let total = (end - start) / PAGE_SIZE;
+        let info_pages = align_up(total * mem::size_of::<PageInfo>(), PAGE_SIZE) / PAGE_SIZE;
+        if total <= info_pages {
+            return None;
+        }
+
+        let count = total - info_pages;
+        let pages = slice::from_raw_parts_mut(phys_to_virt(start) as *mut PageInfo, count);
+        Some(Self::with_pages(start / PAGE_SIZE + info_pages, pages))
+    }
+
+    /// Create a zone that manages the frames starting at frame number `base`, with one entry of
+    /// `pages` for every frame. Every frame is free.
+    fn with_pages(base: usize, pages: &'static mut [PageInfo]) -> Self {
+        pages.fill(PageInfo::new());
+        let count = pages.len();
+
+        let mut zone = Self {
+            base,
+            pages,
+            heads: [NIL; ORDER_COUNT],
+            free: 0,
+        };
+
+        // Hand the frames to the free-lists in the largest naturally-aligned blocks that fit, so
+        // that no coalescing has to be done.
+        let mut index = 0;
+        while index < count {
+            let frame = zone.base + index;
+            let mut order = MAX_ORDER;
+            while order > 0 && (frame % (1 << order) != 0 || index + (1 << order) > count) {
+                order -= 1;
+            }
+
+            zone.push(index, order);
+            zone.free += 1 << order;
+            index += 1 << order;
+        }
+
+        zone
+    }
+
+    /// Whether the given frame is managed by this zone.
+    fn contains(&self, frame: Frame) -> bool {
+        frame.number() >= self.base && frame.number() < self.base + self.pages.len()
+    }
+
+    /// Add a free block to the front of the free-list of the given order.
+    fn push(&mut self, index: usize, order: usize) {
+        let head = self.heads[order];
+        if head != NIL {
+            self.pages[head as usize].prev = index as u32;
+        }
+
+        self.pages[index] = PageInfo {
+            next: head,
+            prev: NIL,
+            order: order as u8,
+            free: true,
+            refs: 0,
+        };
+        self.heads[order] = index as u32;
+    }
+
+    /// Remove a free block from the free-list of its order.
+    fn remove(&mut self, index: usize) {
+        let PageInfo {
+            next, prev, order, ..
+        } = self.pages[index];
+
+        if prev != NIL {
+            self.pages[prev as usize].next = next;
+        } else {
+            self.heads[order as usize] = next;
+        }
+
+        if next != NIL {
+            self.pages[next as usize].prev = prev;
+        }
+
+        self.pages[index] = PageInfo {
+            order,
+            ..PageInfo::new()
+        };
+    }
+
+    /// Allocate a block of the given order, splitting larger blocks if needed.
+    fn allocate(&mut self, order: usize) -> Option<Frame> {
+        let mut current = (order..ORDER_COUNT).find(|&order| self.heads[order] != NIL)?;
+        let index = self.heads[current] as usize;
+        self.remove(index);
+
+        // Give the upper halves back to the free-lists until the block is the requested size.
+        while current > order {
+            current -= 1;
+            self.push(index + (1 << current), current);
+        }
+
+        self.pages[index].order = order as u8;
+        self.pages[index].refs = 1;
+        self.free -= 1 << order;
+        Some(Frame::from_number(self.base + index))
+    }
+
+    /// Free a block of the given order, merging it with its buddy as long as the buddy is free.
+    fn deallocate(&mut self, frame: Frame, order: usize) {
+        let mut index = frame.number() - self.base;
+        let mut current = order;
+
+        // Only the head of an allocated block has references. The head of a block that was merged
+        // into a larger one has none left, and neither has any other frame.
+        let info = self.pages[index];
+        assert!(
+            !info.free && info.refs > 0,
+            "double free of frame {:#x}",
+            frame.start_address()
+        );
+        assert_eq!(
+            info.order as usize,
+            order,
+            "frame {:#x} freed with the wrong order",
+            frame.start_address()
+        );
+        self.pages[index].refs = 0;
+
+        while current < MAX_ORDER {
+            let buddy_frame = (self.base + index) ^ (1 << current);
+            if buddy_frame < self.base || buddy_frame >= self.base + self.pages.len() {
+                break;
+            }
+
+            let buddy = buddy_frame - self.base;
+            let info = self.pages[buddy];
+            if !info.free || info.order as usize != current {
+                break;
+            }
+
+            self.remove(buddy);
+            index = index.min(buddy);
+            current += 1;
+        }
+
+        self.push(index, current);
+        self.free += 1 << order;
+    }
+
+    /// Retrieve the book-keeping data of an allocated block.
+    fn allocated(&mut self, frame: Frame) -> &mut PageInfo {
+        let info = &mut self.pages[frame.number() - self.base];
+        assert!(
+            !info.free && info.refs > 0,
+            "frame {:#x} is not allocated",
+            frame.start_address()
+        );
+        info
+    }
+}
+
+/// Statistics about the state of the physical memory.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FrameStats {
+    /// Total number of frames managed by the allocator.
+    pub total: usize,
+    /// Number of frames that are free.
+    pub free: usize,
+    /// Number of free blocks of each order.
+    pub free_blocks: [usize; ORDER_COUNT],
+}
+
+impl FrameStats {
+    /// Number of frames that are in use.
+    pub fn used(&self) -> usize {
+        self.total - self.free
+    }
+}
+
+/// A binary buddy allocator for physical frames. Memory is split into blocks of `2^order` frames,
+/// and every block has a "buddy" of the same size next to it. When a block is freed and its buddy
+/// is also free, the two are merged back into a block of the next order, which keeps external
+/// fragmentation low.
+pub struct BuddyAllocator {
+    /// Zones that are managed by the allocator.
+    zones: [Option<Zone>; MAX_ZONES],
+    /// Number of zones in use.
+    zone_count: usize,
+}
+
+impl BuddyAllocator {
+    /// Construct an allocator that does not manage any memory.
+    pub const fn new() -> Self {
+        const EMPTY: Option<Zone> = None;
+        Self {
+            zones: [EMPTY; MAX_ZONES],
+            zone_count: 0,
+        }
+    }
+
+    /// Hand all of the free memory areas to the allocator.
+    ///
+    /// # Safety
+    /// The free areas must not be in use by anything else, and must be accessible through the
+    /// kernel's physical memory mapping.
+    pub unsafe fn seed(&mut self, areas: &[MemoryArea]) {
+        for area in areas {
+            let MemoryArea { base, size, kind } = *area;
+            if kind != MemoryAreaKind::Free {
+                continue;
+            }
+
+            if self.zone_count == MAX_ZONES {
+                log::warn!("Ignoring memory area at {:#x}: too many areas", base);
+                continue;
+            }
+
+            if let Some(zone) = Zone::new(base as usize, (base + size) as usize) {
+                self.zones[self.zone_count] = Some(zone);
+                self.zone_count += 1;
+            }
+        }
+    }
+
+    /// Iterate through the zones in use.
+    fn zones(&self) -> impl Iterator<Item = &Zone> {
+        self.zones[..self.zone_count].iter().flatten()
+    }
+
+    /// Iterate mutably through the zones in use.
+    fn zones_mut(&mut self) -> impl Iterator<Item = &mut Zone> {
+        self.zones[..self.zone_count].iter_mut().flatten()
+    }
+
+    /// Allocate `2^order` physically contiguous frames. The first frame is aligned to the size of
+    /// the block.
+    pub fn allocate(&mut self, order: usize) -> Option<Frame> {
+        if order > MAX_ORDER {
+            return None;
+        }
+
+        self.zones_mut().find_map(|zone| zone.allocate(order))
+    }
+
+    /// Free a block that was returned by [`BuddyAllocator::allocate`] with the same order, no
+    /// matter how many references to it are left.
+    ///
+    /// # Safety
+    /// The block must no longer be in use.
+    pub unsafe fn deallocate(&mut self, frame: Frame, order: usize) {
+        self.zone_of(frame).deallocate(frame, order);
+    }
+
+    /// Find the zone that manages the given frame.
+    fn zone_of(&mut self, frame: Frame) -> &mut Zone {
+        self.zones_mut()
+            .find(|zone| zone.contains(frame))
+            .expect("Frame is not managed by the frame allocator")
+    }
+
+    /// Retrieve the number of references to an allocated block.
+    pub fn refcount(&mut self, frame: Frame) -> usize {
+        self.zone_of(frame).allocated(frame).refs as usize
+    }
+
+    /// Add a reference to an allocated block, and retrieve the new number of references.
+    pub fn share(&mut self, frame: Frame) -> usize {
+        let info = self.zone_of(frame).allocated(frame);
+        info.refs += 1;
+        info.refs as usize
+    }
+
+    /// Drop a reference to a block of the given order, freeing it once no references are left.
+    /// Returns whether the block was freed.
+    ///
+    /// # Safety
+    /// The caller's reference to the block must no longer be in use.
+    pub unsafe fn release(&mut self, frame: Frame, order: usize) -> bool {
+        let zone = self.zone_of(frame);
+        let info = zone.allocated(frame);
+        if info.refs > 1 {
+            info.refs -= 1;
+            return false;
+        }
+
+        zone.deallocate(frame, order);
+        true
+    }
+
+    /// Retrieve statistics about the physical memory.
+    pub fn stats(&self) -> FrameStats {
+        let mut stats = FrameStats::default();
+
+        for zone in self.zones() {
+            stats.total += zone.pages.len();
+            stats.free += zone.free;
+
+            for (order, &head) in zone.heads.iter().enumerate() {
+                let mut index = head;
+                while index != NIL {
+                    stats.free_blocks[order] += 1;
+                    index = zone.pages[index as usize].next;
+                }
+            }
+        }
+
+        stats
+    }
+}
+
+/// Round the value up to the given alignment, which must be a power of two.
+const fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use alloc::boxed::Box;
+    use alloc::vec;
+
+    /// Frame number of the first frame of the test zones, which is aligned for every order.
+    const BASE: usize = 1 << 20;
+
+    /// Create a zone of `count` frames. Only the book-keeping data is in memory, since the
+    /// allocator never touches the frames themselves.
+    fn zone(count: usize) -> Zone {
+        let pages = Box::leak(vec![PageInfo::new(); count].into_boxed_slice());
+        Zone::with_pages(BASE, pages)
+    }
+
+    /// Create an allocator that manages a single zone of `count` frames.
+    fn allocator(count: usize) -> BuddyAllocator {
+        let mut allocator = BuddyAllocator::new();
+        allocator.zones[0] = Some(zone(count));
+        allocator.zone_count = 1;
+        allocator
+    }
+
+    #[test]
+    fn seeds_largest_aligned_blocks() {
+        let stats = allocator(3 << MAX_ORDER | 5).stats();
+        assert_eq!(stats.total, 3 << MAX_ORDER | 5);
+        assert_eq!(stats.free, stats.total);
+        assert_eq!(stats.free_blocks[MAX_ORDER], 3);
+        assert_eq!(stats.free_blocks[2], 1);
+        assert_eq!(stats.free_blocks[0], 1);
+    }
+
+    #[test]
+    fn splits_and_merges_buddies() {
+        let mut allocator = allocator(16);
+
+        let frame = allocator.allocate(0).unwrap();
+        assert_eq!(frame.number(), BASE);
+        let stats = allocator.stats();
+        assert_eq!(stats.free, 15);
+        assert_eq!(stats.free_blocks[..5], [1, 1, 1, 1, 0]);
+
+        unsafe { allocator.deallocate(frame, 0) };
+        let stats = allocator.stats();
+        assert_eq!(stats.free, 16);
+        assert_eq!(stats.free_blocks[..5], [0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn aligns_blocks_to_their_size() {
+        let mut allocator = allocator(64);
+
+        let small = allocator.allocate(0).unwrap();
+        let large = allocator.allocate(3).unwrap();
+        assert_ne!(small, large);
+        assert_eq!(large.number() % 8, 0);
+    }
+
+    #[test]
+    fn runs_out_of_frames() {
+        let mut allocator = allocator(4);
+
+        assert!(allocator.allocate(3).is_none());
+        assert!(allocator.allocate(MAX_ORDER + 1).is_none());
+        for _ in 0..4 {
+            assert!(allocator.allocate(0).is_some());
+        }
+        assert!(allocator.allocate(0).is_none());
+        assert_eq!(allocator.stats().used(), 4);
+    }
+
+    #[test]
+    fn frees_shared_frames_with_their_last_reference() {
+        let mut allocator = allocator(4);
+        let frame = allocator.allocate(0).unwrap();
+
+        assert_eq!(allocator.share(frame), 2);
+        assert_eq!(allocator.refcount(frame), 2);
+        assert!(!unsafe { allocator.release(frame, 0) });
+        assert_eq!(allocator.stats().free, 3);
+        assert!(unsafe { allocator.release(frame, 0) });
+        assert_eq!(allocator.stats().free, 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "double free")]
+    fn catches_double_frees() {
+        let mut allocator = allocator(4);
+        let frame = allocator.allocate(1).unwrap();
+
+        unsafe {
+            allocator.deallocate(frame, 1);
+            allocator.deallocate(frame, 1);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "double free")]
+    fn catches_double_frees_of_merged_blocks() {
+        let mut allocator = allocator(4);
+        let first = allocator.allocate(0).unwrap();
+        let second = allocator.allocate(0).unwrap();
+
+        unsafe {
+            allocator.deallocate(first, 0);
+            // Merged with the first frame, so it is no longer the head of a block.
+            allocator.deallocate(second, 0);
+            allocator.deallocate(second, 0);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "wrong order")]
+    fn catches_frees_with_the_wrong_order() {
+        let mut allocator = allocator(4);
+        let frame = allocator.allocate(1).unwrap();
+
+        unsafe { allocator.deallocate(frame, 0) };
+    }
+}
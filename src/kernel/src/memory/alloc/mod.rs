@@ -1 +1,104 @@
+pub use self::buddy::{BuddyAllocator, FrameStats, MAX_ORDER};
 
+pub mod buddy;
+
+use crate::io::dma;
+use crate::memory::{Frame, MemoryArea, PAGE_SIZE};
+use crate::sync::IrqMutex;
+
+/// The physical frame allocator used by the whole kernel. Interrupts are disabled while it is
+/// locked, since the heap takes frames from it with its own lock held.
+static FRAME_ALLOCATOR: IrqMutex<BuddyAllocator> = IrqMutex::new(BuddyAllocator::new());
+
+/// Initialize the physical frame allocator with the memory areas provided by the bootloader, and
+/// create the DMA window (see [`dma::init`]), which needs frames for its page table.
+///
+/// # Safety
+/// Must only be called once, before any frames are allocated and before any address space is
+/// created.
+pub unsafe fn init(areas: &[MemoryArea]) {
+    let stats = {
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        allocator.seed(areas);
+        allocator.stats()
+    };
+
+    log::info!(
+        "Frame allocator: {} KiB free of {} KiB",
+        stats.free * PAGE_SIZE / 1024,
+        stats.total * PAGE_SIZE / 1024
+    );
+
+    dma::init();
+}
+
+/// Allocate `2^order` physically contiguous frames.
+pub fn allocate_frames(order: usize) -> Option<Frame> {
+    FRAME_ALLOCATOR.lock().allocate(order)
+}
+
+/// Allocate a single frame.
+pub fn allocate_frame() -> Option<Frame> {
+    allocate_frames(0)
+}
+
+/// Free `2^order` frames that were allocated by [`allocate_frames`].
+///
+/// # Safety
+/// The frames must no longer be in use.
+pub unsafe fn deallocate_frames(frame: Frame, order: usize) {
+    FRAME_ALLOCATOR.lock().deallocate(frame, order)
+}
+
+/// Free a single frame that was allocated by [`allocate_frame`].
+///
+/// # Safety
+/// The frame must no longer be in use.
+pub unsafe fn deallocate_frame(frame: Frame) {
+    deallocate_frames(frame, 0)
+}
+
+/// Retrieve the number of references to an allocated frame.
+pub fn frame_refcount(frame: Frame) -> usize {
+    FRAME_ALLOCATOR.lock().refcount(frame)
+}
+
+/// Add a reference to an allocated frame, so that it is only freed once every user has released
+/// it. Retrieves the new number of references.
+pub fn share_frame(frame: Frame) -> usize {
+    FRAME_ALLOCATOR.lock().share(frame)
+}
+
+/// Drop a reference to a single frame, freeing it if it was the last one. Returns whether the
+/// frame was freed.
+///
+/// # Safety
+/// The caller's reference to the frame must no longer be in use.
+pub unsafe fn release_frame(frame: Frame) -> bool {
+    FRAME_ALLOCATOR.lock().release(frame, 0)
+}
+
+/// Retrieve statistics about the physical memory.
+pub fn frame_stats() -> FrameStats {
+    FRAME_ALLOCATOR.lock().stats()
+}
+
+/// Number of frames that are free.
+pub fn free_frames() -> usize {
+    frame_stats().free
+}
+
+/// Number of frames that are in use.
+pub fn used_frames() -> usize {
+    frame_stats().used()
+}
+
+/// Retrieve the smallest order whose blocks can hold the given number of bytes.
+pub const fn order_for_size(size: usize) -> usize {
+    let pages = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+    if pages <= 1 {
+        0
+    } else {
+        (usize::BITS - (pages - 1).leading_zeros()) as usize
+    }
+}
@@ -0,0 +1,167 @@
+pub use self::slab::SlabHeap;
+#[cfg(feature = "tlsf")]
+pub use self::tlsf::TlsfHeap;
+
+pub mod slab;
+#[cfg(feature = "tlsf")]
+pub mod tlsf;
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
+
+use crate::sync::IrqMutex;
+
+/// Maximum number of size classes that a heap can report statistics for.
+pub const MAX_SIZE_CLASSES: usize = 32;
+
+/// Statistics for a single size class of a heap.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SizeClassStats {
+    /// Largest allocation (in bytes) that is served by this class.
+    pub size: usize,
+    /// Number of live allocations in this class.
+    pub allocations: usize,
+    /// Number of free blocks that are held by this class.
+    pub free_blocks: usize,
+    /// Number of bytes handed out to live allocations in this class.
+    pub used_bytes: usize,
+    /// Number of bytes that are held by this class but not in use.
+    pub free_bytes: usize,
+}
+
+/// Statistics for a kernel heap. All heap implementations report the same statistics so that
+/// they can be compared on identical workloads.
+#[derive(Copy, Clone, Debug)]
+pub struct HeapStats {
+    /// Per size-class statistics. Only the first `class_count` entries are valid.
+    pub classes: [SizeClassStats; MAX_SIZE_CLASSES],
+    /// Number of valid entries in `classes`.
+    pub class_count: usize,
+    /// Number of bytes that were requested by live allocations.
+    pub requested_bytes: usize,
+    /// Number of bytes that the heap has taken from the frame allocator.
+    pub reserved_bytes: usize,
+    /// Number of allocations that failed.
+    pub failures: usize,
+}
+
+impl HeapStats {
+    /// Construct empty statistics.
+    pub const fn new() -> Self {
+        Self {
+            classes: [SizeClassStats {
+                size: 0,
+                allocations: 0,
+                free_blocks: 0,
+                used_bytes: 0,
+                free_bytes: 0,
+            }; MAX_SIZE_CLASSES],
+            class_count: 0,
+            requested_bytes: 0,
+            reserved_bytes: 0,
+            failures: 0,
+        }
+    }
+
+    /// Retrieve the statistics of the valid size classes.
+    pub fn classes(&self) -> &[SizeClassStats] {
+        &self.classes[..self.class_count]
+    }
+
+    /// Number of bytes that are held by the heap but not handed out to allocations (which
+    /// includes both free blocks and internal fragmentation).
+    pub fn wasted_bytes(&self) -> usize {
+        self.reserved_bytes.saturating_sub(self.requested_bytes)
+    }
+}
+
+impl Default for HeapStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The interface that every kernel heap implements. The heap is wrapped in a [`LockedHeap`] that
+/// serializes access to it, so its methods can take `&mut self`.
+pub trait Heap {
+    /// Allocate memory for the given layout.
+    fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>>;
+    /// Free memory that was returned by [`Heap::allocate`] with the same layout.
+    ///
+    /// # Safety
+    /// The memory must no longer be in use.
+    unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout);
+    /// Retrieve the statistics of the heap.
+    fn stats(&self) -> HeapStats;
+}
+
+/// A heap behind a lock, so that it can be registered as the global allocator. Interrupts are
+/// disabled while it is locked, since interrupt handlers allocate too: one that interrupted the
+/// code holding the lock would spin forever.
+pub struct LockedHeap<H> {
+    inner: IrqMutex<H>,
+}
+
+impl<H: Heap> LockedHeap<H> {
+    /// Wrap the given heap.
+    pub const fn new(heap: H) -> Self {
+        Self {
+            inner: IrqMutex::new(heap),
+        }
+    }
+
+    /// Retrieve the statistics of the heap.
+    pub fn stats(&self) -> HeapStats {
+        self.inner.lock().stats()
+    }
+}
+
+unsafe impl<H: Heap> GlobalAlloc for LockedHeap<H> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.inner
+            .lock()
+            .allocate(layout)
+            .map_or(ptr::null_mut(), NonNull::as_ptr)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Some(ptr) = NonNull::new(ptr) {
+            self.inner.lock().deallocate(ptr, layout)
+        }
+    }
+}
+
+/// The kernel heap. Small allocations are served from slab caches and anything else is served
+/// with whole pages from the frame allocator.
+#[cfg(not(feature = "tlsf"))]
+#[cfg_attr(not(test), global_allocator)]
+static HEAP: LockedHeap<SlabHeap> = LockedHeap::new(SlabHeap::new());
+
+/// The kernel heap for real-time builds. Allocation and freeing take a bounded amount of time.
+#[cfg(feature = "tlsf")]
+#[cfg_attr(not(test), global_allocator)]
+static HEAP: LockedHeap<TlsfHeap> = LockedHeap::new(TlsfHeap::new());
+
+/// Retrieve the statistics of the kernel heap.
+pub fn stats() -> HeapStats {
+    HEAP.stats()
+}
+
+/// Reserve memory for the kernel heap up front, so that allocations never have to wait for the
+/// frame allocator. Returns whether the memory could be reserved.
+#[cfg(feature = "tlsf")]
+pub fn reserve(size: usize) -> bool {
+    HEAP.inner.lock().add_pool(size)
+}
+
+/// Called when an allocation on the kernel heap fails.
+#[cfg(not(test))]
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    log::error!(
+        "Kernel heap allocation of {} bytes (aligned to {}) failed",
+        layout.size(),
+        layout.align()
+    );
+    panic!("Out of kernel heap memory");
+}
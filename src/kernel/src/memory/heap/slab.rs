@@ -0,0 +1,293 @@
+use core::alloc::Layout;
+use core::ptr::{self, NonNull};
+
+use crate::memory::heap::{Heap, HeapStats, SizeClassStats};
+use crate::memory::{self, Frame, PAGE_SIZE};
+
+/// Object sizes of the slab caches. Allocations larger than the last class are served with whole
+/// pages from the frame allocator.
+const CLASS_SIZES: [usize; 9] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// A free object in a slab cache. Free objects form a singly-linked list through their own memory.
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+/// A cache of equally-sized objects. Objects are carved out of whole pages, and because the pages
+/// are page-aligned and every size is a power of two, each object is aligned to its own size.
+struct SlabCache {
+    /// Size of every object in the cache.
+    size: usize,
+    /// Head of the list of free objects.
+    free: *mut FreeObject,
+    /// Number of free objects.
+    free_count: usize,
+    /// Number of objects handed out.
+    allocations: usize,
+    /// Number of pages taken from the frame allocator.
+    pages: usize,
+}
+
+impl SlabCache {
+    const fn new(size: usize) -> Self {
+        Self {
+            size,
+            free: ptr::null_mut(),
+            free_count: 0,
+            allocations: 0,
+            pages: 0,
+        }
+    }
+
+    /// Take a page from the frame allocator and split it into free objects.
+    fn grow(&mut self) -> Option<()> {
+        let page = memory::allocate_frame()?.virt_address();
+        unsafe { self.add_page(page) };
+        Some(())
+    }
+
+    /// Split the page at the given address into free objects.
+    ///
+    /// # Safety
+    /// The page must be page-aligned, and belong to the cache from now on.
+    unsafe fn add_page(&mut self, page: usize) {
+        for offset in (0..PAGE_SIZE).step_by(self.size).rev() {
+            let object = (page + offset) as *mut FreeObject;
+            object.write(FreeObject { next: self.free });
+            self.free = object;
+        }
+
+        self.free_count += PAGE_SIZE / self.size;
+        self.pages += 1;
+    }
+
+    fn allocate(&mut self) -> Option<NonNull<u8>> {
+        if self.free.is_null() {
+            self.grow()?;
+        }
+
+        let object = self.free;
+        self.free = unsafe { (*object).next };
+        self.free_count -= 1;
+        self.allocations += 1;
+        NonNull::new(object.cast())
+    }
+
+    unsafe fn deallocate(&mut self, ptr: NonNull<u8>) {
+        let object = ptr.as_ptr().cast::<FreeObject>();
+        object.write(FreeObject { next: self.free });
+        self.free = object;
+        self.free_count += 1;
+        self.allocations -= 1;
+    }
+}
+
+/// A slab heap. Small allocations are rounded up to the nearest size class and taken from that
+/// class's cache, while large allocations are given their own block of pages from the frame
+/// allocator. Slab pages are kept by their cache once they are allocated, so that the memory can
+/// be reused for objects of the same size.
+pub struct SlabHeap {
+    /// Caches for every size class.
+    caches: [SlabCache; CLASS_SIZES.len()],
+    /// Number of live allocations served with whole pages.
+    large_allocations: usize,
+    /// Number of pages used by live large allocations.
+    large_pages: usize,
+    /// Number of bytes that were requested by large allocations.
+    large_bytes: usize,
+    /// Number of bytes that were requested by live allocations.
+    requested_bytes: usize,
+    /// Number of allocations that failed.
+    failures: usize,
+}
+
+unsafe impl Send for SlabHeap {}
+
+impl SlabHeap {
+    /// Construct an empty heap. Memory is only taken from the frame allocator once it is needed.
+    pub const fn new() -> Self {
+        Self {
+            caches: [
+                SlabCache::new(CLASS_SIZES[0]),
+                SlabCache::new(CLASS_SIZES[1]),
+                SlabCache::new(CLASS_SIZES[2]),
+                SlabCache::new(CLASS_SIZES[3]),
+                SlabCache::new(CLASS_SIZES[4]),
+                SlabCache::new(CLASS_SIZES[5]),
+                SlabCache::new(CLASS_SIZES[6]),
+                SlabCache::new(CLASS_SIZES[7]),
+                SlabCache::new(CLASS_SIZES[8]),
+            ],
+            large_allocations: 0,
+            large_pages: 0,
+            large_bytes: 0,
+            requested_bytes: 0,
+            failures: 0,
+        }
+    }
+
+    /// Retrieve the index of the size class that serves the given layout, or `None` if it must be
+    /// served with whole pages.
+    fn class_of(layout: Layout) -> Option<usize> {
+        let size = layout.size().max(layout.align());
+        CLASS_SIZES.iter().position(|&class| class >= size)
+    }
+
+    /// Retrieve the order of the block of pages that serves the given layout.
+    fn large_order(layout: Layout) -> usize {
+        memory::order_for_size(layout.size().max(layout.align()))
+    }
+}
+
+impl Heap for SlabHeap {
+    fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        let result = match Self::class_of(layout) {
+            Some(class) => self.caches[class].allocate(),
+            None => {
+                let order = Self::large_order(layout);
+                memory::allocate_frames(order).and_then(|frame| {
+                    self.large_allocations += 1;
+                    self.large_pages += 1 << order;
+                    self.large_bytes += layout.size();
+                    NonNull::new(frame.virt_address() as *mut u8)
+                })
+            }
+        };
+
+        match result {
+            Some(_) => self.requested_bytes += layout.size(),
+            None => self.failures += 1,
+        }
+
+        result
+    }
+
+    unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        match Self::class_of(layout) {
+            Some(class) => self.caches[class].deallocate(ptr),
+            None => {
+                let order = Self::large_order(layout);
+                let frame = Frame::containing_address(memory::virt_to_phys(ptr.as_ptr() as usize));
+                memory::deallocate_frames(frame, order);
+
+                self.large_allocations -= 1;
+                self.large_pages -= 1 << order;
+                self.large_bytes -= layout.size();
+            }
+        }
+
+        self.requested_bytes -= layout.size();
+    }
+
+    fn stats(&self) -> HeapStats {
+        let mut stats = HeapStats::new();
+
+        for cache in self.caches.iter() {
+            stats.classes[stats.class_count] = SizeClassStats {
+                size: cache.size,
+                allocations: cache.allocations,
+                free_blocks: cache.free_count,
+                used_bytes: cache.allocations * cache.size,
+                free_bytes: cache.free_count * cache.size,
+            };
+            stats.class_count += 1;
+            stats.reserved_bytes += cache.pages * PAGE_SIZE;
+        }
+
+        // Large allocations are reported as one extra class with no upper bound.
+        stats.classes[stats.class_count] = SizeClassStats {
+            size: usize::MAX,
+            allocations: self.large_allocations,
+            free_blocks: 0,
+            used_bytes: self.large_bytes,
+            free_bytes: self.large_pages * PAGE_SIZE - self.large_bytes,
+        };
+        stats.class_count += 1;
+        stats.reserved_bytes += self.large_pages * PAGE_SIZE;
+
+        stats.requested_bytes = self.requested_bytes;
+        stats.failures = self.failures;
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use alloc::alloc::alloc;
+    use alloc::vec::Vec;
+
+    /// Allocate a page for a cache from the host's heap. It is never freed.
+    fn page() -> usize {
+        let layout = Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap();
+        let page = unsafe { alloc(layout) };
+        assert!(!page.is_null());
+        page as usize
+    }
+
+    #[test]
+    fn maps_layouts_to_size_classes() {
+        let class = |size, align| SlabHeap::class_of(Layout::from_size_align(size, align).unwrap());
+
+        assert_eq!(class(1, 1), Some(0));
+        assert_eq!(class(8, 8), Some(0));
+        assert_eq!(class(9, 1), Some(1));
+        assert_eq!(class(24, 64), Some(3));
+        assert_eq!(class(2048, 8), Some(CLASS_SIZES.len() - 1));
+        assert_eq!(class(2049, 8), None);
+        assert_eq!(class(8, 4096), None);
+    }
+
+    #[test]
+    fn carves_aligned_objects_out_of_pages() {
+        let mut cache = SlabCache::new(64);
+        unsafe { cache.add_page(page()) };
+        assert_eq!(cache.free_count, PAGE_SIZE / 64);
+
+        let objects: Vec<usize> = (0..PAGE_SIZE / 64)
+            .map(|_| cache.allocate().unwrap().as_ptr() as usize)
+            .collect();
+        assert!(objects.iter().all(|&object| object % 64 == 0));
+        assert_eq!(cache.free_count, 0);
+        assert_eq!(cache.allocations, PAGE_SIZE / 64);
+
+        let mut sorted = objects.clone();
+        sorted.sort_unstable();
+        sorted.dedup();
+        assert_eq!(sorted.len(), objects.len());
+    }
+
+    #[test]
+    fn reuses_freed_objects_first() {
+        let mut cache = SlabCache::new(32);
+        unsafe { cache.add_page(page()) };
+
+        let first = cache.allocate().unwrap();
+        let second = cache.allocate().unwrap();
+        unsafe { cache.deallocate(first) };
+        assert_eq!(cache.allocate(), Some(first));
+        assert_ne!(cache.allocate(), Some(second));
+        assert_eq!(cache.allocations, 3);
+    }
+
+    #[test]
+    fn reports_small_allocations() {
+        let mut heap = SlabHeap::new();
+        unsafe { heap.caches[2].add_page(page()) };
+
+        let layout = Layout::from_size_align(20, 4).unwrap();
+        let ptr = heap.allocate(layout).unwrap();
+        let stats = heap.stats();
+        assert_eq!(stats.class_count, CLASS_SIZES.len() + 1);
+        assert_eq!(stats.classes[2].allocations, 1);
+        assert_eq!(stats.classes[2].used_bytes, 32);
+        assert_eq!(stats.requested_bytes, 20);
+        assert_eq!(stats.reserved_bytes, PAGE_SIZE);
+
+        unsafe { heap.deallocate(ptr, layout) };
+        let stats = heap.stats();
+        assert_eq!(stats.classes[2].allocations, 0);
+        assert_eq!(stats.requested_bytes, 0);
+    }
+}
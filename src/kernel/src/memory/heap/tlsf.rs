@@ -0,0 +1,543 @@
+use core::alloc::Layout;
+use core::mem;
+use core::ptr::{self, NonNull};
+
+use crate::memory::heap::{Heap, HeapStats, SizeClassStats};
+use crate::memory::{self, MAX_ORDER, PAGE_SIZE};
+
+/// Every block size is a multiple of this alignment, and so is every payload address.
+const ALIGN_LOG2: usize = 3;
+const ALIGN: usize = 1 << ALIGN_LOG2;
+
+/// Number of second-level lists for every first-level class.
+const SL_LOG2: usize = 4;
+const SL_COUNT: usize = 1 << SL_LOG2;
+
+/// Blocks smaller than this are all kept in the first first-level class, which is split linearly
+/// instead of logarithmically.
+const FL_SHIFT: usize = SL_LOG2 + ALIGN_LOG2;
+const SMALL_BLOCK: usize = 1 << FL_SHIFT;
+
+/// Blocks must be smaller than `2^FL_MAX` bytes.
+const FL_MAX: usize = 30;
+const FL_COUNT: usize = FL_MAX - FL_SHIFT + 1;
+
+/// Status flags stored in the low bits of a block's size.
+const FREE: usize = 1 << 0;
+const PREV_FREE: usize = 1 << 1;
+const FLAGS: usize = FREE | PREV_FREE;
+
+/// Size of the header in front of every block.
+const HEADER: usize = mem::size_of::<BlockHeader>();
+
+/// Smallest payload a block can have, so that a free block can hold its free-list links.
+const MIN_BLOCK: usize = mem::size_of::<FreeLinks>();
+
+/// Order of the pools taken from the frame allocator.
+const POOL_ORDER: usize = MAX_ORDER;
+
+/// Header in front of every block, whether it is free or in use.
+#[repr(C)]
+struct BlockHeader {
+    /// The block physically before this one (only valid if `PREV_FREE` is set).
+    prev_phys: *mut BlockHeader,
+    /// Size of the payload, with the status flags in the low bits.
+    size: usize,
+}
+
+/// Links of a free block, stored in the block's payload.
+#[repr(C)]
+struct FreeLinks {
+    next: *mut BlockHeader,
+    prev: *mut BlockHeader,
+}
+
+/// Retrieve the size of the payload of a block.
+unsafe fn block_size(block: *mut BlockHeader) -> usize {
+    (*block).size & !FLAGS
+}
+
+/// Set the size of the payload of a block, preserving its flags.
+unsafe fn set_size(block: *mut BlockHeader, size: usize) {
+    (*block).size = size | ((*block).size & FLAGS);
+}
+
+unsafe fn set_flag(block: *mut BlockHeader, flag: usize, value: bool) {
+    if value {
+        (*block).size |= flag;
+    } else {
+        (*block).size &= !flag;
+    }
+}
+
+unsafe fn has_flag(block: *mut BlockHeader, flag: usize) -> bool {
+    (*block).size & flag != 0
+}
+
+/// Retrieve the address of the payload of a block.
+fn payload(block: *mut BlockHeader) -> usize {
+    block as usize + HEADER
+}
+
+/// Retrieve the block that owns the given payload.
+fn from_payload(payload: usize) -> *mut BlockHeader {
+    (payload - HEADER) as *mut BlockHeader
+}
+
+/// Retrieve the block physically after the given one.
+unsafe fn next_phys(block: *mut BlockHeader) -> *mut BlockHeader {
+    (payload(block) + block_size(block)) as *mut BlockHeader
+}
+
+/// Retrieve the free-list links of a free block.
+fn links(block: *mut BlockHeader) -> *mut FreeLinks {
+    payload(block) as *mut FreeLinks
+}
+
+/// Index of the most significant set bit.
+fn fls(value: usize) -> usize {
+    (usize::BITS - 1 - value.leading_zeros()) as usize
+}
+
+/// Retrieve the first and second-level indices of the list that a block of the given size
+/// belongs in.
+fn mapping(size: usize) -> (usize, usize) {
+    if size < SMALL_BLOCK {
+        (0, size / (SMALL_BLOCK / SL_COUNT))
+    } else {
+        let fl = fls(size);
+        let sl = (size >> (fl - SL_LOG2)) ^ SL_COUNT;
+        (fl - (FL_SHIFT - 1), sl)
+    }
+}
+
+/// Retrieve the indices of the first list whose blocks are all at least the given size. This
+/// rounds the size up to the next list boundary, so that any block in the list can be used
+/// without searching through it.
+fn mapping_search(size: usize) -> (usize, usize) {
+    if size < SMALL_BLOCK {
+        mapping(size)
+    } else {
+        mapping(size + (1 << (fls(size) - SL_LOG2)) - 1)
+    }
+}
+
+/// Round the value up to the given alignment, which must be a power of two.
+const fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+/// A Two-Level Segregated Fit allocator. Free blocks are kept in lists segregated first by the
+/// power of two of their size and then linearly within it, and two levels of bitmaps tell which
+/// lists are non-empty. Finding, splitting and merging blocks only takes a bounded number of bit
+/// operations, so allocation and freeing run in constant time regardless of the state of the heap.
+///
+/// Memory is taken from the frame allocator in pools. Adding a pool is the only operation that is
+/// not bounded, so real-time builds should reserve enough memory with [`TlsfHeap::add_pool`] while
+/// booting.
+pub struct TlsfHeap {
+    /// Bitmap of first-level classes that have a non-empty list.
+    fl_bitmap: usize,
+    /// Bitmaps of non-empty second-level lists for every first-level class.
+    sl_bitmaps: [usize; FL_COUNT],
+    /// Heads of the free-lists.
+    blocks: [[*mut BlockHeader; SL_COUNT]; FL_COUNT],
+    /// Number of live allocations for every first-level class.
+    allocations: [usize; FL_COUNT],
+    /// Number of bytes in live allocations for every first-level class.
+    used_bytes: [usize; FL_COUNT],
+    /// Number of free blocks for every first-level class.
+    free_blocks: [usize; FL_COUNT],
+    /// Number of bytes in free blocks for every first-level class.
+    free_bytes: [usize; FL_COUNT],
+    /// Number of bytes that were requested by live allocations.
+    requested_bytes: usize,
+    /// Number of bytes taken from the frame allocator.
+    reserved_bytes: usize,
+    /// Number of allocations that failed.
+    failures: usize,
+}
+
+unsafe impl Send for TlsfHeap {}
+
+impl TlsfHeap {
+    /// Construct an empty heap. The first pool is added once memory is first needed.
+    pub const fn new() -> Self {
+        Self {
+            fl_bitmap: 0,
+            sl_bitmaps: [0; FL_COUNT],
+            blocks: [[ptr::null_mut(); SL_COUNT]; FL_COUNT],
+            allocations: [0; FL_COUNT],
+            used_bytes: [0; FL_COUNT],
+            free_blocks: [0; FL_COUNT],
+            free_bytes: [0; FL_COUNT],
+            requested_bytes: 0,
+            reserved_bytes: 0,
+            failures: 0,
+        }
+    }
+
+    /// Take a pool that can hold at least `size` bytes from the frame allocator and add it to the
+    /// heap. Returns whether the pool could be allocated.
+    pub fn add_pool(&mut self, size: usize) -> bool {
+        let order = memory::order_for_size(size + 2 * HEADER).max(POOL_ORDER);
+        if order > MAX_ORDER {
+            return false;
+        }
+
+        let frame = match memory::allocate_frames(order) {
+            Some(frame) => frame,
+            None => return false,
+        };
+
+        unsafe { self.insert_pool(frame.virt_address(), PAGE_SIZE << order) };
+        true
+    }
+
+    /// Add the memory at the given address to the heap as a pool of `size` bytes.
+    ///
+    /// # Safety
+    /// The memory must be aligned to [`ALIGN`], and belong to the heap from now on.
+    unsafe fn insert_pool(&mut self, start: usize, size: usize) {
+        self.reserved_bytes += size;
+
+        // One block spans the whole pool, and a zero-sized block that is always in use marks the
+        // end of the pool so that blocks are never merged past it.
+        let block = start as *mut BlockHeader;
+        block.write(BlockHeader {
+            prev_phys: ptr::null_mut(),
+            size: (size - 2 * HEADER) | FREE,
+        });
+
+        next_phys(block).write(BlockHeader {
+            prev_phys: block,
+            size: PREV_FREE,
+        });
+
+        self.insert_free(block);
+    }
+
+    /// Add a free block to the front of the list it belongs in.
+    unsafe fn insert_free(&mut self, block: *mut BlockHeader) {
+        let size = block_size(block);
+        let (fl, sl) = mapping(size);
+        let head = self.blocks[fl][sl];
+
+        links(block).write(FreeLinks {
+            next: head,
+            prev: ptr::null_mut(),
+        });
+        if !head.is_null() {
+            (*links(head)).prev = block;
+        }
+
+        self.blocks[fl][sl] = block;
+        self.fl_bitmap |= 1 << fl;
+        self.sl_bitmaps[fl] |= 1 << sl;
+
+        self.free_blocks[fl] += 1;
+        self.free_bytes[fl] += size;
+    }
+
+    /// Remove a free block from the list it is in.
+    unsafe fn remove_free(&mut self, block: *mut BlockHeader) {
+        let size = block_size(block);
+        let (fl, sl) = mapping(size);
+        let FreeLinks { next, prev } = links(block).read();
+
+        if !next.is_null() {
+            (*links(next)).prev = prev;
+        }
+
+        if !prev.is_null() {
+            (*links(prev)).next = next;
+        } else {
+            self.blocks[fl][sl] = next;
+            if next.is_null() {
+                self.sl_bitmaps[fl] &= !(1 << sl);
+                if self.sl_bitmaps[fl] == 0 {
+                    self.fl_bitmap &= !(1 << fl);
+                }
+            }
+        }
+
+        self.free_blocks[fl] -= 1;
+        self.free_bytes[fl] -= size;
+    }
+
+    /// Find a free block that is at least the given size, without removing it.
+    fn find_free(&self, size: usize) -> Option<*mut BlockHeader> {
+        let (mut fl, sl) = mapping_search(size);
+        if fl >= FL_COUNT {
+            return None;
+        }
+
+        let mut sl_map = self.sl_bitmaps[fl] & (!0 << sl);
+        if sl_map == 0 {
+            let fl_map = self.fl_bitmap & (!0usize).checked_shl(fl as u32 + 1).unwrap_or(0);
+            if fl_map == 0 {
+                return None;
+            }
+
+            fl = fl_map.trailing_zeros() as usize;
+            sl_map = self.sl_bitmaps[fl];
+        }
+
+        Some(self.blocks[fl][sl_map.trailing_zeros() as usize])
+    }
+
+    /// Split the end of a block off into a new free block, if there is enough room for one.
+    unsafe fn split(&mut self, block: *mut BlockHeader, size: usize) {
+        let total = block_size(block);
+        if total < size + HEADER + MIN_BLOCK {
+            return;
+        }
+
+        let remaining = (payload(block) + size) as *mut BlockHeader;
+        remaining.write(BlockHeader {
+            prev_phys: block,
+            size: (total - size - HEADER) | FREE,
+        });
+        set_size(block, size);
+
+        let next = next_phys(remaining);
+        (*next).prev_phys = remaining;
+        set_flag(next, PREV_FREE, true);
+
+        self.insert_free(remaining);
+    }
+
+    /// Split the start of a free block off into a new free block, so that the payload of the rest
+    /// starts at the given address. Returns the rest of the block.
+    unsafe fn trim_leading(&mut self, block: *mut BlockHeader, start: usize) -> *mut BlockHeader {
+        let rest = from_payload(start);
+        let leading_size = rest as usize - payload(block);
+
+        rest.write(BlockHeader {
+            prev_phys: block,
+            size: (block_size(block) - leading_size - HEADER) | PREV_FREE,
+        });
+        (*next_phys(rest)).prev_phys = rest;
+
+        set_size(block, leading_size);
+        set_flag(block, FREE, true);
+        self.insert_free(block);
+
+        rest
+    }
+
+    /// Find, remove and carve out a block for the given (adjusted) size and alignment.
+    unsafe fn take_block(&mut self, size: usize, align: usize) -> Option<*mut BlockHeader> {
+        // Over-aligned blocks need room in front of the payload for a free block to fill the gap.
+        let gap = HEADER + MIN_BLOCK;
+        let search_size = if align > ALIGN {
+            size + align + gap
+        } else {
+            size
+        };
+        if search_size >= 1 << FL_MAX {
+            return None;
+        }
+
+        let mut block = self.find_free(search_size)?;
+        self.remove_free(block);
+
+        if align > ALIGN {
+            let mut start = align_up(payload(block), align);
+            if start != payload(block) {
+                if start - payload(block) < gap {
+                    start = align_up(payload(block) + gap, align);
+                }
+
+                block = self.trim_leading(block, start);
+            }
+        }
+
+        self.split(block, size);
+        set_flag(block, FREE, false);
+        set_flag(next_phys(block), PREV_FREE, false);
+        Some(block)
+    }
+}
+
+impl Heap for TlsfHeap {
+    fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        let size = align_up(layout.size(), ALIGN).max(MIN_BLOCK);
+        let align = layout.align();
+
+        let block = unsafe {
+            match self.take_block(size, align) {
+                Some(block) => Some(block),
+                None if self.add_pool(size + align + HEADER + MIN_BLOCK) => {
+                    self.take_block(size, align)
+                }
+                None => None,
+            }
+        };
+
+        match block {
+            Some(block) => {
+                let size = unsafe { block_size(block) };
+                let (fl, _) = mapping(size);
+                self.allocations[fl] += 1;
+                self.used_bytes[fl] += size;
+                self.requested_bytes += layout.size();
+                NonNull::new(payload(block) as *mut u8)
+            }
+            None => {
+                self.failures += 1;
+                None
+            }
+        }
+    }
+
+    unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let mut block = from_payload(ptr.as_ptr() as usize);
+        let size = block_size(block);
+        let (fl, _) = mapping(size);
+        self.allocations[fl] -= 1;
+        self.used_bytes[fl] -= size;
+        self.requested_bytes -= layout.size();
+
+        // Merge with the previous block if it is free.
+        if has_flag(block, PREV_FREE) {
+            let prev = (*block).prev_phys;
+            self.remove_free(prev);
+            set_size(prev, block_size(prev) + HEADER + block_size(block));
+            block = prev;
+        }
+
+        // Merge with the next block if it is free.
+        let next = next_phys(block);
+        if has_flag(next, FREE) {
+            self.remove_free(next);
+            set_size(block, block_size(block) + HEADER + block_size(next));
+        }
+
+        set_flag(block, FREE, true);
+        let next = next_phys(block);
+        (*next).prev_phys = block;
+        set_flag(next, PREV_FREE, true);
+
+        self.insert_free(block);
+    }
+
+    fn stats(&self) -> HeapStats {
+        let mut stats = HeapStats::new();
+
+        for fl in 0..FL_COUNT {
+            stats.classes[fl] = SizeClassStats {
+                size: (1 << (fl + FL_SHIFT)) - 1,
+                allocations: self.allocations[fl],
+                free_blocks: self.free_blocks[fl],
+                used_bytes: self.used_bytes[fl],
+                free_bytes: self.free_bytes[fl],
+            };
+        }
+
+        stats.class_count = FL_COUNT;
+        stats.requested_bytes = self.requested_bytes;
+        stats.reserved_bytes = self.reserved_bytes;
+        stats.failures = self.failures;
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use alloc::alloc::alloc;
+
+    /// Size of the pools of the tests.
+    const POOL: usize = 64 * 1024;
+
+    /// Create a heap with one pool, allocated from the host's heap. It is never freed.
+    fn heap() -> TlsfHeap {
+        let layout = Layout::from_size_align(POOL, PAGE_SIZE).unwrap();
+        let pool = unsafe { alloc(layout) };
+        assert!(!pool.is_null());
+
+        let mut heap = TlsfHeap::new();
+        unsafe { heap.insert_pool(pool as usize, POOL) };
+        heap
+    }
+
+    /// Total number of free blocks in the heap.
+    fn free_blocks(heap: &TlsfHeap) -> usize {
+        heap.free_blocks.iter().sum()
+    }
+
+    #[test]
+    fn maps_sizes_to_lists() {
+        assert_eq!(mapping(0), (0, 0));
+        assert_eq!(mapping(SMALL_BLOCK - ALIGN), (0, SL_COUNT - 1));
+        assert_eq!(mapping(SMALL_BLOCK), (1, 0));
+        assert_eq!(mapping(2 * SMALL_BLOCK - 1), (1, SL_COUNT - 1));
+        assert_eq!(mapping(2 * SMALL_BLOCK), (2, 0));
+
+        // Searching rounds up to the next list, whose blocks are all large enough.
+        let step = SMALL_BLOCK / SL_COUNT;
+        assert_eq!(mapping_search(SMALL_BLOCK + 1), (1, 1));
+        assert_eq!(mapping_search(SMALL_BLOCK + step), (1, 1));
+        assert_eq!(mapping_search(2 * SMALL_BLOCK - 1), (2, 0));
+    }
+
+    #[test]
+    fn merges_freed_blocks() {
+        let mut heap = heap();
+        let layout = Layout::from_size_align(100, 8).unwrap();
+
+        let first = heap.allocate(layout).unwrap();
+        let second = heap.allocate(layout).unwrap();
+        let third = heap.allocate(layout).unwrap();
+        assert_eq!(free_blocks(&heap), 1);
+
+        unsafe {
+            heap.deallocate(first, layout);
+            heap.deallocate(third, layout);
+            assert_eq!(free_blocks(&heap), 2);
+            heap.deallocate(second, layout);
+        }
+
+        assert_eq!(free_blocks(&heap), 1);
+        let stats = heap.stats();
+        assert_eq!(stats.requested_bytes, 0);
+        assert_eq!(stats.reserved_bytes, POOL);
+        assert_eq!(
+            stats
+                .classes()
+                .iter()
+                .map(|class| class.free_bytes)
+                .sum::<usize>(),
+            POOL - 2 * HEADER
+        );
+    }
+
+    #[test]
+    fn aligns_payloads() {
+        let mut heap = heap();
+
+        for align in [8, 16, 64, 256, 4096] {
+            let layout = Layout::from_size_align(24, align).unwrap();
+            let ptr = heap.allocate(layout).unwrap();
+            assert_eq!(ptr.as_ptr() as usize % align, 0);
+        }
+    }
+
+    #[test]
+    fn reports_allocations_by_class() {
+        let mut heap = heap();
+        let layout = Layout::from_size_align(1000, 8).unwrap();
+
+        let ptr = heap.allocate(layout).unwrap();
+        let stats = heap.stats();
+        let (fl, _) = mapping(1000);
+        assert_eq!(stats.classes[fl].allocations, 1);
+        assert_eq!(stats.classes[fl].used_bytes, 1000);
+        assert_eq!(stats.requested_bytes, 1000);
+
+        unsafe { heap.deallocate(ptr, layout) };
+        assert_eq!(heap.stats().classes[fl].allocations, 0);
+    }
+}
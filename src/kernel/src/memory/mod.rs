@@ -1,2 +1,91 @@
 pub mod alloc;
+pub mod heap;
+pub mod paging;
+#[cfg(feature = "swap")]
+pub mod swap;
+pub mod tlb;
+
 pub use self::alloc::*;
+
+use crate::machine;
+
+/// Size of the smallest page (and therefore of a physical frame), in bytes.
+pub const PAGE_SIZE: usize = 4096;
+
+/// Kinds of memory areas reported by the bootloader.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(u32)]
+pub enum MemoryAreaKind {
+    /// Unused entry.
+    Null = 0,
+    /// Usable memory that is not in use by anything.
+    Free = 1,
+    /// Memory that is used by the bootloader, but can be reclaimed once the kernel has started.
+    Reclaim = 2,
+    /// Memory that must never be touched (firmware, memory-mapped devices, the kernel image).
+    Reserved = 3,
+}
+
+/// Describes a region of physical memory. Passed to the kernel through `KernelArgs::areas_base`
+/// and `KernelArgs::areas_size`, in the same format as the bootloader for Redox OS.
+#[derive(Copy, Clone, Debug)]
+#[repr(packed)]
+pub struct MemoryArea {
+    /// Physical address of the start of the area.
+    pub base: u64,
+    /// Size of the area, in bytes.
+    pub size: u64,
+    /// What the area can be used for.
+    pub kind: MemoryAreaKind,
+}
+
+/// A physical page frame, identified by its frame number (its physical address divided by the
+/// page size).
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Frame {
+    number: usize,
+}
+
+impl Frame {
+    /// Retrieve the frame that contains the given physical address.
+    pub const fn containing_address(address: usize) -> Self {
+        Self {
+            number: address / PAGE_SIZE,
+        }
+    }
+
+    /// Retrieve the frame with the given frame number.
+    pub const fn from_number(number: usize) -> Self {
+        Self { number }
+    }
+
+    /// Retrieve the frame number.
+    pub const fn number(&self) -> usize {
+        self.number
+    }
+
+    /// Retrieve the physical address of the start of the frame.
+    pub const fn start_address(&self) -> usize {
+        self.number * PAGE_SIZE
+    }
+
+    /// Retrieve the address that the frame can be accessed at through the kernel's physical
+    /// memory mapping.
+    pub const fn virt_address(&self) -> usize {
+        phys_to_virt(self.start_address())
+    }
+}
+
+/// Convert a physical address into the virtual address that it is mapped at in the kernel's
+/// linear mapping of physical memory.
+#[inline(always)]
+pub const fn phys_to_virt(address: usize) -> usize {
+    address + machine::PHYS_OFFSET
+}
+
+/// Convert a virtual address in the kernel's linear mapping of physical memory back into a
+/// physical address.
+#[inline(always)]
+pub const fn virt_to_phys(address: usize) -> usize {
+    address - machine::PHYS_OFFSET
+}
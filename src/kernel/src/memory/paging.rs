@@ -0,0 +1,127 @@
+use crate::memory::Frame;
+
+bitflags::bitflags! {
+    /// Architecture-independent permissions and attributes of a page. Every architecture's page
+    /// table translates these into its own entry flags.
+    pub struct PageFlags: usize {
+        /// The page can be read from.
+        const READ = 1 << 0;
+        /// The page can be written to.
+        const WRITE = 1 << 1;
+        /// Instructions can be fetched from the page.
+        const EXECUTE = 1 << 2;
+        /// The page can be accessed from user-mode.
+        const USER = 1 << 3;
+        /// The mapping is shared between all address spaces and is not flushed from the TLB when
+        /// switching between them.
+        const GLOBAL = 1 << 4;
+        /// Accesses to the page are not cached.
+        const NO_CACHE = 1 << 5;
+        /// Writes to the page go straight through the cache to memory.
+        const WRITE_THROUGH = 1 << 6;
+        /// The page has been accessed since this flag was last cleared.
+        const ACCESSED = 1 << 7;
+        /// The page has been written to since this flag was last cleared.
+        const DIRTY = 1 << 8;
+    }
+}
+
+/// Sizes of the pages that can be mapped.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PageSize {
+    /// 4 KiB page.
+    Small,
+    /// 2 MiB page.
+    Large,
+    /// 1 GiB page.
+    Huge,
+}
+
+impl PageSize {
+    /// Retrieve the size of the page, in bytes.
+    pub const fn bytes(self) -> usize {
+        match self {
+            PageSize::Small => 0x1000,
+            PageSize::Large => 0x20_0000,
+            PageSize::Huge => 0x4000_0000,
+        }
+    }
+}
+
+/// Representation of an error as the result of an operation on a page table.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MapError {
+    /// The address is already mapped.
+    AlreadyMapped,
+    /// The address is not mapped.
+    NotMapped,
+    /// An address is not aligned to the size of the page.
+    Misaligned,
+    /// An address cannot be mapped by the page table.
+    InvalidAddress,
+    /// A frame for an intermediate page table could not be allocated.
+    OutOfMemory,
+}
+
+/// The result of translating a virtual address.
+#[derive(Copy, Clone, Debug)]
+pub struct Translation {
+    /// Physical address that the virtual address maps to.
+    pub phys: usize,
+    /// Flags of the page that contains the address.
+    pub flags: PageFlags,
+    /// Size of the page that contains the address.
+    pub size: PageSize,
+}
+
+/// The interface that every architecture's page table implements. This lets address spaces be
+/// managed without knowing which architecture they are running on.
+pub trait PageMapper {
+    /// Retrieve the frame containing the root page table.
+    fn root(&self) -> Frame;
+
+    /// Map a page at the given virtual address to the given physical address. Intermediate tables
+    /// are allocated from the frame allocator when needed.
+    ///
+    /// # Safety
+    /// The physical memory must be safe to expose at the virtual address.
+    unsafe fn map(
+        &mut self,
+        virt: usize,
+        phys: usize,
+        size: PageSize,
+        flags: PageFlags,
+    ) -> Result<(), MapError>;
+
+    /// Unmap the page containing the given virtual address, returning the physical address it was
+    /// mapped to and its size. The caller is responsible for freeing the frame.
+    ///
+    /// # Safety
+    /// The page must no longer be in use.
+    unsafe fn unmap(&mut self, virt: usize) -> Result<(usize, PageSize), MapError>;
+
+    /// Translate a virtual address into the physical address it is mapped to.
+    fn translate(&self, virt: usize) -> Option<Translation>;
+
+    /// Change the flags of the page containing the given virtual address, returning its size.
+    ///
+    /// # Safety
+    /// Removing permissions from a page that is still in use will cause page faults.
+    unsafe fn protect(&mut self, virt: usize, flags: PageFlags) -> Result<PageSize, MapError>;
+
+    /// Clear the accessed bit of the page containing the given virtual address, returning whether
+    /// it was set. Used to find pages that have not been used recently.
+    ///
+    /// # Safety
+    /// The CPU may fault on the next access to the page (which must set the bit again).
+    unsafe fn clear_accessed(&mut self, virt: usize) -> Result<bool, MapError>;
+
+    /// Whether this page table is the one in use by the CPU.
+    fn is_active(&self) -> bool;
+
+    /// Make the CPU use this page table.
+    ///
+    /// # Safety
+    /// The kernel must be mapped in the page table.
+    unsafe fn activate(&self);
+}
@@ -0,0 +1,220 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use core::slice;
+
+use crate::device::base::block::BlockDeviceSwitch;
+use crate::device::DeviceError;
+use crate::memory::{Frame, PAGE_SIZE};
+use crate::sync::{mutex, Mutex};
+
+/// Representation of an error as the result of a swap operation.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SwapError {
+    /// No swap device is in use.
+    NoDevice,
+    /// A swap device is already in use.
+    AlreadyEnabled,
+    /// Every slot of the swap device is in use.
+    Full,
+    /// The block size of the device does not divide the page size.
+    InvalidBlockSize,
+    /// The device failed to read or write a page.
+    Device(DeviceError),
+    /// A slot has as many references as its counter can hold.
+    TooManyReferences,
+}
+
+impl From<DeviceError> for SwapError {
+    fn from(error: DeviceError) -> Self {
+        SwapError::Device(error)
+    }
+}
+
+/// A page-sized slot on the swap device.
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct SwapSlot(u32);
+
+impl SwapSlot {
+    /// Retrieve the index of the slot on the swap device.
+    pub fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Statistics about the swap device.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SwapStats {
+    /// Number of slots on the device.
+    pub total: usize,
+    /// Number of slots in use.
+    pub used: usize,
+}
+
+/// A block device used to hold pages that were evicted from memory.
+struct SwapDevice {
+    /// The device itself.
+    device: Box<dyn BlockDeviceSwitch + Send>,
+    /// Number of blocks that make up one slot.
+    blocks_per_slot: usize,
+}
+
+/// Which slots of the swap device are in use.
+struct SwapSlots {
+    /// Number of references to every slot (zero for free slots). A slot is shared when an address
+    /// space holding swapped pages is forked.
+    map: Vec<u32>,
+    /// Number of slots in use.
+    used: usize,
+    /// Where to start looking for a free slot. Slots are handed out in order, which keeps pages
+    /// that were evicted together close to each other on the device.
+    hint: usize,
+}
+
+impl SwapSlots {
+    /// Find a free slot and take a reference to it.
+    fn allocate(&mut self) -> Option<SwapSlot> {
+        let count = self.map.len();
+        let index = (0..count)
+            .map(|offset| (self.hint + offset) % count)
+            .find(|&index| self.map[index] == 0)?;
+
+        self.map[index] = 1;
+        self.used += 1;
+        self.hint = index + 1;
+        Some(SwapSlot(index as u32))
+    }
+
+    /// Drop a reference to a slot, freeing it once no references are left.
+    fn release(&mut self, slot: SwapSlot) {
+        let refs = &mut self.map[slot.index()];
+        assert!(*refs > 0, "swap slot {} is not in use", slot.index());
+
+        *refs -= 1;
+        if *refs == 0 {
+            self.used -= 1;
+        }
+    }
+}
+
+/// The swap device in use, if any. Reading or writing a page can take a while, so this is a
+/// mutex that blocks, which must not be locked with a spin lock held (such as the one of an
+/// address space).
+static DEVICE: mutex::Mutex<Option<SwapDevice>> = mutex::Mutex::new(None);
+
+/// The slots of the swap device in use, if any. They are kept apart from the device, so that
+/// references can be taken and dropped quickly, even with an address space locked.
+static SLOTS: Mutex<Option<SwapSlots>> = Mutex::new(None);
+
+/// Start swapping to the given block device. `blocks` is the number of blocks of the device that
+/// can be used, starting from the first one. Fails if a swap device is already in use.
+pub fn enable(device: Box<dyn BlockDeviceSwitch + Send>, blocks: usize) -> Result<(), SwapError> {
+    let block_size = device.block_size();
+    if block_size == 0 || PAGE_SIZE % block_size != 0 {
+        return Err(SwapError::InvalidBlockSize);
+    }
+
+    let mut guard = DEVICE.lock();
+    if guard.is_some() {
+        return Err(SwapError::AlreadyEnabled);
+    }
+
+    let blocks_per_slot = PAGE_SIZE / block_size;
+    let slots = (blocks / blocks_per_slot).min(u32::MAX as usize);
+    log::info!("Swap: {} KiB", slots * PAGE_SIZE / 1024);
+
+    *guard = Some(SwapDevice {
+        device,
+        blocks_per_slot,
+    });
+    *SLOTS.lock() = Some(SwapSlots {
+        map: vec![0; slots],
+        used: 0,
+        hint: 0,
+    });
+
+    Ok(())
+}
+
+/// Whether a swap device is in use.
+pub fn is_enabled() -> bool {
+    SLOTS.lock().is_some()
+}
+
+/// Retrieve statistics about the swap device.
+pub fn stats() -> SwapStats {
+    SLOTS
+        .lock()
+        .as_ref()
+        .map_or(SwapStats::default(), |slots| SwapStats {
+            total: slots.map.len(),
+            used: slots.used,
+        })
+}
+
+/// Write the contents of a frame to a free slot. The slot has a single reference. This blocks
+/// until the device is done.
+pub fn write(frame: Frame) -> Result<SwapSlot, SwapError> {
+    let mut guard = DEVICE.lock();
+    let swap = guard.as_mut().ok_or(SwapError::NoDevice)?;
+    let slot = SLOTS
+        .lock()
+        .as_mut()
+        .and_then(SwapSlots::allocate)
+        .ok_or(SwapError::Full)?;
+
+    let page = unsafe { slice::from_raw_parts(frame.virt_address() as *const u8, PAGE_SIZE) };
+    let block_size = PAGE_SIZE / swap.blocks_per_slot;
+    let first = slot.index() * swap.blocks_per_slot;
+
+    for (block, buffer) in page.chunks(block_size).enumerate() {
+        if let Err(error) = swap.device.write_block(first + block, buffer) {
+            release(slot);
+            return Err(error.into());
+        }
+    }
+
+    Ok(slot)
+}
+
+/// Read the contents of a slot into a frame. The slot stays in use. This blocks until the device
+/// is done.
+pub fn read(slot: SwapSlot, frame: Frame) -> Result<(), SwapError> {
+    let mut guard = DEVICE.lock();
+    let swap = guard.as_mut().ok_or(SwapError::NoDevice)?;
+
+    let page = unsafe { slice::from_raw_parts_mut(frame.virt_address() as *mut u8, PAGE_SIZE) };
+    let block_size = PAGE_SIZE / swap.blocks_per_slot;
+    let first = slot.index() * swap.blocks_per_slot;
+
+    for (block, buffer) in page.chunks_mut(block_size).enumerate() {
+        swap.device.read_block(first + block, buffer)?;
+    }
+
+    Ok(())
+}
+
+/// Add a reference to a slot.
+pub fn share(slot: SwapSlot) -> Result<(), SwapError> {
+    let mut guard = SLOTS.lock();
+    let slots = guard.as_mut().ok_or(SwapError::NoDevice)?;
+    let refs = &mut slots.map[slot.index()];
+    *refs = refs.checked_add(1).ok_or(SwapError::TooManyReferences)?;
+    Ok(())
+}
+
+/// Retrieve the number of references to a slot.
+pub fn refcount(slot: SwapSlot) -> usize {
+    SLOTS
+        .lock()
+        .as_ref()
+        .map_or(0, |slots| slots.map[slot.index()] as usize)
+}
+
+/// Drop a reference to a slot, freeing it once no references are left.
+pub fn release(slot: SwapSlot) {
+    if let Some(slots) = SLOTS.lock().as_mut() {
+        slots.release(slot);
+    }
+}
@@ -0,0 +1,65 @@
+use core::hint;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::context::{self, percpu, MAX_CPUS};
+use crate::machine::{ipi, tlb};
+
+/// Number of shootdowns that were requested from each CPU so far.
+static REQUESTED: [AtomicU64; MAX_CPUS] = {
+    const ZERO: AtomicU64 = AtomicU64::new(0);
+    [ZERO; MAX_CPUS]
+};
+
+/// Latest request that each CPU flushed its TLB for.
+static FLUSHED: [AtomicU64; MAX_CPUS] = {
+    const ZERO: AtomicU64 = AtomicU64::new(0);
+    [ZERO; MAX_CPUS]
+};
+
+/// Make the other CPUs flush their TLBs, and wait until they all did. Must be called once page
+/// table entries lost permissions or were removed, and before the frames that they pointed to are
+/// reused, since the other CPUs may still be using the old entries until then.
+///
+/// The CPUs that are asked may be spinning on a lock that the caller holds, with interrupts
+/// disabled, so every spin lock of the kernel answers shootdowns while it waits (see
+/// [`crate::sync::Spin`]).
+pub fn shootdown() {
+    let count = context::cpu_count();
+    if count == 1 {
+        return;
+    }
+
+    let current = percpu::cpu_id();
+    let mut tickets = [0; MAX_CPUS];
+    for cpu in (0..count).filter(|&cpu| cpu != current) {
+        tickets[cpu] = REQUESTED[cpu].fetch_add(1, Ordering::SeqCst) + 1;
+        ipi::shootdown(cpu);
+    }
+
+    for cpu in (0..count).filter(|&cpu| cpu != current) {
+        while FLUSHED[cpu].load(Ordering::Acquire) < tickets[cpu] {
+            // The other CPU may be waiting for this one to answer a shootdown of its own.
+            poll();
+            hint::spin_loop();
+        }
+    }
+}
+
+/// Flush the TLB if another CPU requested a shootdown since the last flush. Called when the
+/// shootdown's IPI arrives, and while spinning with interrupts disabled.
+pub fn poll() {
+    if context::cpu_count() == 1 {
+        return;
+    }
+
+    let cpu = percpu::cpu_id();
+    // Reading the request before flushing makes sure that the flush comes after the changes that
+    // the request was made for.
+    let requested = REQUESTED[cpu].load(Ordering::SeqCst);
+    if FLUSHED[cpu].load(Ordering::Relaxed) >= requested {
+        return;
+    }
+
+    unsafe { tlb::flush_all() };
+    FLUSHED[cpu].fetch_max(requested, Ordering::Release);
+}
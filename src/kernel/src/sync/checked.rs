@@ -0,0 +1,395 @@
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+#[cfg(feature = "lockdep")]
+use core::panic::Location;
+
+use spin::RelaxStrategy;
+
+use crate::context::percpu;
+#[cfg(feature = "lockdep")]
+use crate::sync::lockdep::{self, Class, Kind};
+use crate::sync::Spin;
+
+/// Spin lock that gives exclusive access to the data that it protects. It wraps [`spin::Mutex`],
+/// and disables preemption while it is locked (see [`percpu::preempt_disable`]). With the
+/// `lockdep` feature, it also reports how it is used to [`lockdep`].
+pub struct Mutex<T: ?Sized> {
+    #[cfg(feature = "lockdep")]
+    class: Class,
+    inner: spin::mutex::Mutex<T, Spin>,
+}
+
+/// Guard of a locked [`Mutex`], which unlocks it when it is dropped.
+pub struct MutexGuard<'a, T: ?Sized + 'a> {
+    #[cfg(feature = "lockdep")]
+    lock: usize,
+    /// Guard of the lock, which has to be dropped before preemption is enabled again.
+    inner: ManuallyDrop<spin::MutexGuard<'a, T>>,
+    /// Preemption has to be enabled on the CPU that disabled it.
+    _not_send: PhantomData<*const ()>,
+}
+
+/// Spin lock that gives shared access to readers and exclusive access to writers. It wraps
+/// [`spin::RwLock`] like [`Mutex`], and waits for the lock with the relax strategy `R`.
+pub struct RwLock<T: ?Sized, R = Spin> {
+    #[cfg(feature = "lockdep")]
+    class: Class,
+    inner: spin::rwlock::RwLock<T, R>,
+}
+
+/// Guard of an [`RwLock`] locked for reading, which unlocks it when it is dropped.
+pub struct RwLockReadGuard<'a, T: ?Sized + 'a> {
+    #[cfg(feature = "lockdep")]
+    lock: usize,
+    inner: ManuallyDrop<spin::RwLockReadGuard<'a, T>>,
+    _not_send: PhantomData<*const ()>,
+}
+
+/// Guard of an [`RwLock`] locked for writing, which unlocks it when it is dropped.
+pub struct RwLockWriteGuard<'a, T: ?Sized + 'a, R = Spin> {
+    #[cfg(feature = "lockdep")]
+    lock: usize,
+    inner: ManuallyDrop<spin::rwlock::RwLockWriteGuard<'a, T, R>>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> Mutex<T> {
+    /// Construct an unlocked [`Mutex`]. Its class is where it is constructed.
+    #[track_caller]
+    pub const fn new(data: T) -> Self {
+        Self {
+            #[cfg(feature = "lockdep")]
+            class: Location::caller(),
+            inner: spin::mutex::Mutex::new(data),
+        }
+    }
+
+    /// Consume the mutex and retrieve the data that it protects.
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Lock the mutex, spinning until it is free.
+    #[track_caller]
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        #[cfg(feature = "lockdep")]
+        let lock = self.address();
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(lock, self.class, Kind::Spin, Location::caller());
+        // Preemption is only disabled once the lock is taken, so that the context can be switched
+        // away from while it waits (see [`crate::sync::Yield`]).
+        let inner = loop {
+            percpu::preempt_disable();
+            match self.inner.try_lock() {
+                Some(inner) => break inner,
+                None => percpu::preempt_enable(),
+            }
+            Spin::relax();
+        };
+        MutexGuard {
+            #[cfg(feature = "lockdep")]
+            lock,
+            inner: ManuallyDrop::new(inner),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Lock the mutex if it is free.
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        percpu::preempt_disable();
+        let inner = match self.inner.try_lock() {
+            Some(inner) => inner,
+            None => {
+                percpu::preempt_enable();
+                return None;
+            }
+        };
+        #[cfg(feature = "lockdep")]
+        let lock = self.address();
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire_try(lock, self.class, Kind::Spin, Location::caller());
+        Some(MutexGuard {
+            #[cfg(feature = "lockdep")]
+            lock,
+            inner: ManuallyDrop::new(inner),
+            _not_send: PhantomData,
+        })
+    }
+
+    /// Whether the mutex is locked.
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    /// Retrieve the data without locking the mutex, which borrowing it mutably makes safe.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+
+    /// Retrieve the address of the mutex, which tells it apart from the others of its class.
+    #[cfg(feature = "lockdep")]
+    fn address(&self) -> usize {
+        self as *const Self as *const () as usize
+    }
+}
+
+impl<T, R> RwLock<T, R> {
+    /// Construct an unlocked [`RwLock`]. Its class is where it is constructed.
+    #[track_caller]
+    pub const fn new(data: T) -> Self {
+        Self {
+            #[cfg(feature = "lockdep")]
+            class: Location::caller(),
+            inner: spin::rwlock::RwLock::new(data),
+        }
+    }
+
+    /// Consume the lock and retrieve the data that it protects.
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+
+    /// Retrieve a pointer to the data without locking. See [`spin::RwLock::as_mut_ptr`].
+    pub fn as_mut_ptr(&self) -> *mut T {
+        self.inner.as_mut_ptr()
+    }
+}
+
+impl<T: ?Sized, R: RelaxStrategy> RwLock<T, R> {
+    /// Lock the lock for reading, spinning until there is no writer.
+    #[track_caller]
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        #[cfg(feature = "lockdep")]
+        let lock = self.address();
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(lock, self.class, Kind::Spin, Location::caller());
+        // See `Mutex::lock`.
+        let inner = loop {
+            percpu::preempt_disable();
+            match self.inner.try_read() {
+                Some(inner) => break inner,
+                None => percpu::preempt_enable(),
+            }
+            R::relax();
+        };
+        RwLockReadGuard {
+            #[cfg(feature = "lockdep")]
+            lock,
+            inner: ManuallyDrop::new(inner),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Lock the lock for writing, spinning until there is no reader or writer.
+    #[track_caller]
+    pub fn write(&self) -> RwLockWriteGuard<'_, T, R> {
+        #[cfg(feature = "lockdep")]
+        let lock = self.address();
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(lock, self.class, Kind::Spin, Location::caller());
+        // See `Mutex::lock`.
+        let inner = loop {
+            percpu::preempt_disable();
+            match self.inner.try_write() {
+                Some(inner) => break inner,
+                None => percpu::preempt_enable(),
+            }
+            R::relax();
+        };
+        RwLockWriteGuard {
+            #[cfg(feature = "lockdep")]
+            lock,
+            inner: ManuallyDrop::new(inner),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Lock the lock for reading if there is no writer.
+    #[track_caller]
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        percpu::preempt_disable();
+        let inner = match self.inner.try_read() {
+            Some(inner) => inner,
+            None => {
+                percpu::preempt_enable();
+                return None;
+            }
+        };
+        #[cfg(feature = "lockdep")]
+        let lock = self.address();
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire_try(lock, self.class, Kind::Spin, Location::caller());
+        Some(RwLockReadGuard {
+            #[cfg(feature = "lockdep")]
+            lock,
+            inner: ManuallyDrop::new(inner),
+            _not_send: PhantomData,
+        })
+    }
+
+    /// Lock the lock for writing if there is no reader or writer.
+    #[track_caller]
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T, R>> {
+        percpu::preempt_disable();
+        let inner = match self.inner.try_write() {
+            Some(inner) => inner,
+            None => {
+                percpu::preempt_enable();
+                return None;
+            }
+        };
+        #[cfg(feature = "lockdep")]
+        let lock = self.address();
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire_try(lock, self.class, Kind::Spin, Location::caller());
+        Some(RwLockWriteGuard {
+            #[cfg(feature = "lockdep")]
+            lock,
+            inner: ManuallyDrop::new(inner),
+            _not_send: PhantomData,
+        })
+    }
+}
+
+impl<T: ?Sized, R> RwLock<T, R> {
+    /// Retrieve the data without locking the lock, which borrowing it mutably makes safe.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+
+    /// Retrieve the address of the lock, which tells it apart from the others of its class.
+    #[cfg(feature = "lockdep")]
+    fn address(&self) -> usize {
+        self as *const Self as *const () as usize
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    #[track_caller]
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: Default, R> Default for RwLock<T, R> {
+    #[track_caller]
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug, R> fmt::Debug for RwLock<T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display> fmt::Display for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockReadGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display> fmt::Display for RwLockReadGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug, R> fmt::Debug for RwLockWriteGuard<'_, T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display, R> fmt::Display for RwLockWriteGuard<'_, T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        unsafe { ManuallyDrop::drop(&mut self.inner) };
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.lock);
+        percpu::preempt_enable();
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        unsafe { ManuallyDrop::drop(&mut self.inner) };
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.lock);
+        percpu::preempt_enable();
+    }
+}
+
+impl<T: ?Sized, R> Deref for RwLockWriteGuard<'_, T, R> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: ?Sized, R> DerefMut for RwLockWriteGuard<'_, T, R> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: ?Sized, R> Drop for RwLockWriteGuard<'_, T, R> {
+    fn drop(&mut self) {
+        unsafe { ManuallyDrop::drop(&mut self.inner) };
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.lock);
+        percpu::preempt_enable();
+    }
+}
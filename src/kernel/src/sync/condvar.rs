@@ -0,0 +1,62 @@
+use crate::context::{SleepQueue, WaitError, WakeupPolicy};
+use crate::sync::mutex::MutexGuard;
+
+/// Condition variables let contexts wait for a condition on data protected by a blocking
+/// [`Mutex`] (or a [`CeilingMutex`]) to become true. Waiting unlocks the mutex and blocks the
+/// context on a [`SleepQueue`] in one step, so that a notification sent after the mutex is unlocked
+/// cannot be missed, and locks it again once the context is woken up. Contexts can be woken up
+/// without the condition being true, so they should check it again.
+///
+/// [`Mutex`]: crate::sync::mutex::Mutex
+/// [`CeilingMutex`]: crate::sync::mutex::CeilingMutex
+pub struct Condvar {
+    /// Contexts that wait on the condition variable.
+    queue: SleepQueue,
+}
+
+impl Condvar {
+    /// Construct a new [`Condvar`].
+    pub const fn new() -> Self {
+        Self {
+            queue: SleepQueue::new(WakeupPolicy::Front(1)),
+        }
+    }
+
+    /// Unlock the mutex that `guard` locks, and block the current context until it is notified or
+    /// a signal that it does not block is sent to it. The mutex is locked again in both cases.
+    #[track_caller]
+    pub fn wait<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+    ) -> (MutexGuard<'a, T>, Result<(), WaitError>) {
+        guard.wait_on(&self.queue, None)
+    }
+
+    /// Like [`Condvar::wait`], but give up waiting after `timeout` scheduler ticks, in which case
+    /// [`WaitError::TimedOut`] is returned.
+    #[track_caller]
+    pub fn wait_timeout<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: u64,
+    ) -> (MutexGuard<'a, T>, Result<(), WaitError>) {
+        guard.wait_on(&self.queue, Some(timeout))
+    }
+
+    /// Wake up the context that has waited on the condition variable for the longest, if there is
+    /// one.
+    pub fn notify_one(&self) {
+        self.queue.wake(&WakeupPolicy::Front(1));
+    }
+
+    /// Wake up every context that waits on the condition variable.
+    pub fn notify_all(&self) {
+        self.queue.wake(&WakeupPolicy::All);
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
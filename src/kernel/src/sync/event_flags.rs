@@ -0,0 +1,103 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::context::{SleepQueue, WaitError, WakeupPolicy};
+
+/// Condition that [`EventFlags::wait`] waits for.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WaitMode {
+    /// Any flag of the mask is set.
+    Any,
+    /// Every flag of the mask is set.
+    All,
+}
+
+/// Group of event flags, which contexts can wait for any or all of. Flags stay set until they
+/// are cleared, either explicitly or by the context that waited for them.
+pub struct EventFlags {
+    /// Flags that are set.
+    flags: AtomicUsize,
+    /// Contexts that wait for flags. They wait for different flags, so they are all woken up
+    /// whenever flags are set.
+    waiters: SleepQueue,
+}
+
+impl EventFlags {
+    /// Construct an [`EventFlags`] group with every flag clear.
+    pub const fn new() -> Self {
+        Self {
+            flags: AtomicUsize::new(0),
+            waiters: SleepQueue::new(WakeupPolicy::All),
+        }
+    }
+
+    /// Retrieve the flags that are set.
+    pub fn get(&self) -> usize {
+        self.flags.load(Ordering::SeqCst)
+    }
+
+    /// Whether the given flags satisfy the condition.
+    fn satisfied(flags: usize, mask: usize, mode: WaitMode) -> bool {
+        match mode {
+            WaitMode::Any => flags & mask != 0,
+            WaitMode::All => flags & mask == mask,
+        }
+    }
+
+    /// Retrieve the flags if the condition is satisfied, clearing the flags of the mask if `clear`
+    /// is set.
+    pub fn try_wait(&self, mask: usize, mode: WaitMode, clear: bool) -> Option<usize> {
+        let new = |flags| match clear {
+            true => flags & !mask,
+            false => flags,
+        };
+
+        self.flags
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |flags| {
+                Self::satisfied(flags, mask, mode).then(|| new(flags))
+            })
+            .ok()
+    }
+
+    /// Wait until any or all of the flags of the mask are set, for at most `timeout` scheduler
+    /// ticks (if there is a timeout). Returns the flags as they were when the condition was
+    /// satisfied, and clears the flags of the mask if `clear` is set.
+    pub fn wait(
+        &self,
+        mask: usize,
+        mode: WaitMode,
+        clear: bool,
+        timeout: Option<u64>,
+    ) -> Result<usize, WaitError> {
+        self.waiters.wait_until(
+            timeout,
+            || self.try_wait(mask, mode, clear),
+            || Self::satisfied(self.get(), mask, mode),
+        )
+    }
+
+    /// Set flags, waking up the contexts that wait for flags. Returns the flags that were set
+    /// before.
+    pub fn set(&self, flags: usize) -> usize {
+        let old = self.flags.fetch_or(flags, Ordering::SeqCst);
+        self.waiters.wake_up();
+        old
+    }
+
+    /// Set flags from an interrupt handler, which never blocks. See [`EventFlags::set`].
+    pub fn set_from_interrupt(&self, flags: usize) -> usize {
+        let old = self.flags.fetch_or(flags, Ordering::SeqCst);
+        self.waiters.wake_up_from_interrupt();
+        old
+    }
+
+    /// Clear flags. Returns the flags that were set before.
+    pub fn clear(&self, flags: usize) -> usize {
+        self.flags.fetch_and(!flags, Ordering::SeqCst)
+    }
+}
+
+impl Default for EventFlags {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,129 @@
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+
+use crate::machine::irq;
+use crate::sync::{Mutex, MutexGuard};
+
+/// Spin lock that disables interrupts on the CPU while it is locked, for data that interrupt
+/// handlers use too: an interrupt handler that wants the lock while the code that it interrupted
+/// holds it would spin forever. Whether interrupts were enabled is restored once it is unlocked, so
+/// it may be locked with interrupts disabled, and nested.
+pub struct IrqMutex<T: ?Sized> {
+    inner: Mutex<T>,
+}
+
+/// Guard of a locked [`IrqMutex`], which unlocks it and restores interrupts when it is dropped.
+pub struct IrqMutexGuard<'a, T: ?Sized + 'a> {
+    /// Guard of the lock, which has to be dropped before interrupts are restored.
+    inner: ManuallyDrop<MutexGuard<'a, T>>,
+    /// Whether interrupts were enabled before the lock was locked.
+    enabled: bool,
+    /// Interrupts have to be restored on the CPU that disabled them.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> IrqMutex<T> {
+    /// Construct an unlocked [`IrqMutex`].
+    #[track_caller]
+    pub const fn new(data: T) -> Self {
+        Self {
+            inner: Mutex::new(data),
+        }
+    }
+
+    /// Consume the mutex and retrieve the data that it protects.
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> IrqMutex<T> {
+    /// Disable interrupts and lock the mutex, spinning until it is free.
+    #[track_caller]
+    pub fn lock(&self) -> IrqMutexGuard<'_, T> {
+        let enabled = irq::enabled();
+        unsafe { irq::disable() };
+
+        IrqMutexGuard {
+            inner: ManuallyDrop::new(self.inner.lock()),
+            enabled,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Disable interrupts and lock the mutex if it is free. Interrupts are left alone if it is not.
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<IrqMutexGuard<'_, T>> {
+        let enabled = irq::enabled();
+        unsafe { irq::disable() };
+
+        match self.inner.try_lock() {
+            Some(inner) => Some(IrqMutexGuard {
+                inner: ManuallyDrop::new(inner),
+                enabled,
+                _not_send: PhantomData,
+            }),
+            None => {
+                if enabled {
+                    unsafe { irq::enable() };
+                }
+                None
+            }
+        }
+    }
+
+    /// Whether the mutex is locked.
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    /// Retrieve the data without locking the mutex, which borrowing it mutably makes safe.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+}
+
+impl<T: Default> Default for IrqMutex<T> {
+    #[track_caller]
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for IrqMutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for IrqMutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized> Deref for IrqMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: ?Sized> DerefMut for IrqMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: ?Sized> Drop for IrqMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        unsafe { ManuallyDrop::drop(&mut self.inner) };
+
+        if self.enabled {
+            unsafe { irq::enable() };
+        }
+    }
+}
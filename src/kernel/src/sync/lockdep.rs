@@ -0,0 +1,556 @@
+use core::fmt;
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::context::percpu::percpu;
+use crate::context::scheduler::without_interrupts;
+use crate::context::{self, ContextId};
+use crate::machine::{self, irq};
+
+/// Highest number of lock classes that are told apart.
+const MAX_CLASSES: usize = 1024;
+/// Highest number of orders between two lock classes that are remembered.
+const MAX_EDGES: usize = 8192;
+/// Highest number of locks that are held at once, by every context together.
+const MAX_HELD: usize = 256;
+/// Highest number of callers that are remembered where a lock is taken.
+const MAX_FRAMES: usize = 8;
+
+/// Class of a lock, which is where it was constructed. Locks constructed at the same place (like
+/// the locks of the contexts) are used the same way, so they are validated together.
+pub type Class = &'static Location<'static>;
+
+/// Return addresses of the callers of the code that took a lock, innermost first, followed by
+/// zeroes (see [`machine::backtrace::trace`]).
+type Trace = [usize; MAX_FRAMES];
+
+/// Where a lock was taken, and how the code that took it was reached.
+#[derive(Copy, Clone)]
+struct Site {
+    at: Class,
+    trace: Trace,
+}
+
+/// Whether locks are validated. Set once the kernel can tell which context is running.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+percpu! {
+    /// Number of interrupt handlers that each CPU is running.
+    static INTERRUPT_DEPTH: AtomicUsize = AtomicUsize::new(0);
+}
+
+/// What the validator knows. Interrupts are disabled while it is locked, and nothing is allocated,
+/// since the heap has locks of its own.
+static STATE: spin::Mutex<State> = spin::Mutex::new(State::new());
+
+/// Kinds of locks, which are validated differently.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Kind {
+    /// Lock that is spun on, which must not be held across a context switch.
+    Spin,
+    /// Lock that blocks the context that waits for it, which must not be taken in interrupt
+    /// context.
+    Sleep,
+}
+
+/// Who holds a lock: a context, or a CPU that does not run contexts yet.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Owner {
+    Context(ContextId),
+    Cpu(usize),
+}
+
+/// What is known about a lock class.
+#[derive(Copy, Clone)]
+struct ClassState {
+    class: Class,
+    /// Where a lock of the class was first taken with interrupts enabled.
+    irq_enabled: Option<Site>,
+    /// Where a lock of the class was first taken in interrupt context.
+    in_interrupt: Option<Site>,
+    /// Whether a problem with the class was reported already, so that it is reported once.
+    reported: bool,
+}
+
+/// Lock class `to` was taken while lock class `from` was held.
+#[derive(Copy, Clone)]
+struct Edge {
+    from: usize,
+    to: usize,
+    /// Where the lock of class `from` was taken.
+    from_at: Site,
+    /// Where the lock of class `to` was taken.
+    to_at: Site,
+    /// Whether an inversion of the order was reported already.
+    reported: bool,
+}
+
+/// Lock that is held (or that is being waited for).
+#[derive(Copy, Clone)]
+struct Held {
+    /// Address of the lock.
+    lock: usize,
+    class: usize,
+    kind: Kind,
+    owner: Owner,
+    /// Where the lock was taken.
+    at: Site,
+}
+
+/// Lock classes, the orders that their locks were taken in, and the locks that are held.
+struct State {
+    classes: [Option<ClassState>; MAX_CLASSES],
+    class_count: usize,
+    edges: [Option<Edge>; MAX_EDGES],
+    edge_count: usize,
+    /// Locks that are held, in the order that they were taken.
+    held: [Option<Held>; MAX_HELD],
+    held_count: usize,
+    /// Edge through which each class was reached by the last search, if it was.
+    reached: [Option<usize>; MAX_CLASSES],
+    /// Classes that the last search has yet to go through.
+    pending: [usize; MAX_CLASSES],
+}
+
+impl State {
+    /// Construct an empty [`State`].
+    const fn new() -> Self {
+        Self {
+            classes: [None; MAX_CLASSES],
+            class_count: 0,
+            edges: [None; MAX_EDGES],
+            edge_count: 0,
+            held: [None; MAX_HELD],
+            held_count: 0,
+            reached: [None; MAX_CLASSES],
+            pending: [0; MAX_CLASSES],
+        }
+    }
+
+    /// Retrieve the index of a class, registering it if it is new. Returns `None` if there is no
+    /// room for it.
+    fn class(&mut self, class: Class) -> Option<usize> {
+        let known = self.classes[..self.class_count]
+            .iter()
+            .position(|state| state.map_or(false, |state| state.class == class));
+        if known.is_some() {
+            return known;
+        }
+
+        let index = self.class_count;
+        *self.classes.get_mut(index)? = Some(ClassState {
+            class,
+            irq_enabled: None,
+            in_interrupt: None,
+            reported: false,
+        });
+        self.class_count += 1;
+        Some(index)
+    }
+
+    /// Retrieve what is known about a registered class.
+    fn class_state(&mut self, index: usize) -> &mut ClassState {
+        self.classes[index]
+            .as_mut()
+            .expect("Unregistered lock class")
+    }
+
+    /// Retrieve an edge that was remembered.
+    fn edge(&self, index: usize) -> &Edge {
+        self.edges[index].as_ref().expect("Unknown lock order")
+    }
+
+    /// Remember that class `to` was taken while class `from` was held, unless it is known already.
+    /// Returns `false` if there is no room for it.
+    fn add_edge(&mut self, from: &Held, to: usize, to_at: Site) -> bool {
+        let known = self.edges[..self.edge_count]
+            .iter()
+            .flatten()
+            .any(|edge| edge.from == from.class && edge.to == to);
+        if known {
+            return true;
+        }
+
+        match self.edges.get_mut(self.edge_count) {
+            Some(slot) => {
+                *slot = Some(Edge {
+                    from: from.class,
+                    to,
+                    from_at: from.at,
+                    to_at,
+                    reported: false,
+                })
+            }
+            None => return false,
+        }
+        self.edge_count += 1;
+        true
+    }
+
+    /// Look for a chain of orders from class `from` to class `to`. Returns the last edge of the
+    /// chain; the others are found through [`State::reached`].
+    fn path(&mut self, from: usize, to: usize) -> Option<usize> {
+        self.reached[..self.class_count].fill(None);
+        self.pending[0] = from;
+        let (mut next, mut end) = (0, 1);
+
+        while next < end {
+            let class = self.pending[next];
+            next += 1;
+
+            for index in 0..self.edge_count {
+                let edge = self.edge(index);
+                if edge.from != class || edge.to == from || self.reached[edge.to].is_some() {
+                    continue;
+                }
+
+                let reached = edge.to;
+                self.reached[reached] = Some(index);
+                if reached == to {
+                    return Some(index);
+                }
+                self.pending[end] = reached;
+                end += 1;
+            }
+        }
+        None
+    }
+
+    /// Iterate over the locks that an owner holds, in the order that it took them.
+    fn held_by(&self, owner: Owner) -> impl Iterator<Item = Held> + '_ {
+        self.held[..self.held_count]
+            .iter()
+            .flatten()
+            .copied()
+            .filter(move |held| held.owner == owner)
+    }
+
+    /// Record that a lock is held. Returns `false` if there is no room for it.
+    fn push(&mut self, held: Held) -> bool {
+        match self.held.get_mut(self.held_count) {
+            Some(slot) => *slot = Some(held),
+            None => return false,
+        }
+        self.held_count += 1;
+        true
+    }
+
+    /// Forget a lock that is no longer held by the owner, or else by anyone (if its guard was
+    /// passed on to another context).
+    fn pop(&mut self, lock: usize, owner: Owner) {
+        let held = &self.held[..self.held_count];
+        let index = held
+            .iter()
+            .rposition(|held| held.map_or(false, |held| held.lock == lock && held.owner == owner))
+            .or_else(|| {
+                held.iter()
+                    .rposition(|held| held.map_or(false, |held| held.lock == lock))
+            });
+
+        if let Some(index) = index {
+            self.held.copy_within(index + 1..self.held_count, index);
+            self.held_count -= 1;
+            self.held[self.held_count] = None;
+        }
+    }
+
+    /// Describe a class, for reports.
+    fn describe(&self, class: usize) -> ClassName {
+        ClassName(self.classes[class].expect("Unregistered lock class").class)
+    }
+
+    /// Print the locks that an owner holds.
+    fn print_held(&self, owner: Owner) {
+        for held in self.held_by(owner) {
+            print(format_args!(
+                "    holding {} (taken at {})\n",
+                self.describe(held.class),
+                held.at.at
+            ));
+            print_trace(&held.at.trace);
+        }
+    }
+}
+
+/// Name of a lock class in reports.
+struct ClassName(Class);
+
+impl fmt::Display for ClassName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "lock created at {}", self.0)
+    }
+}
+
+/// Write part of a report to the debugging serial port.
+fn print(args: fmt::Arguments) {
+    machine::debug::print(args);
+}
+
+/// Print the callers of the code that took a lock, which can be told apart with `addr2line`.
+fn print_trace(trace: &Trace) {
+    for &address in trace.iter().take_while(|&&address| address != 0) {
+        print(format_args!("        called from {:#x}\n", address));
+    }
+}
+
+/// Record where the running code takes a lock. Called before the state is locked, so that the trace
+/// ends in the code that takes the lock.
+#[inline(always)]
+fn site(at: Class) -> Site {
+    let mut trace = [0; MAX_FRAMES];
+    machine::backtrace::trace(&mut trace);
+    Site { at, trace }
+}
+
+/// Retrieve who would hold a lock taken by the running code.
+fn owner() -> Owner {
+    let id = context::context_id();
+    match id.get() {
+        0 => Owner::Cpu(context::cpu_id()),
+        _ => Owner::Context(id),
+    }
+}
+
+/// Whether the running code is an interrupt handler.
+fn in_interrupt() -> bool {
+    INTERRUPT_DEPTH.get().load(Ordering::Relaxed) > 0
+}
+
+/// Stop validating, because a table is full.
+fn overflow(what: &str) {
+    ENABLED.store(false, Ordering::SeqCst);
+    print(format_args!("lockdep: too many {}, turning off\n", what));
+}
+
+/// Run a function with the state locked, if locks are validated.
+fn with_state(function: impl FnOnce(&mut State)) {
+    if ENABLED.load(Ordering::Relaxed) {
+        without_interrupts(|| function(&mut STATE.lock()));
+    }
+}
+
+/// Start validating locks. Called once the running context can be told.
+pub fn enable() {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// Record that the CPU started running an interrupt handler.
+pub fn enter_interrupt() {
+    INTERRUPT_DEPTH.get().fetch_add(1, Ordering::Relaxed);
+}
+
+/// Record that the CPU is done with an interrupt handler.
+pub fn leave_interrupt() {
+    INTERRUPT_DEPTH.get().fetch_sub(1, Ordering::Relaxed);
+}
+
+/// Validate that the running code may wait for a lock while holding the locks that it holds, and
+/// record that it holds it. Called before waiting, so that a deadlock is reported before it hangs.
+pub fn acquire(lock: usize, class: Class, kind: Kind, at: Class) {
+    let irq_enabled = irq::enabled();
+    let in_interrupt = in_interrupt();
+    let at = site(at);
+
+    with_state(|state| {
+        let class = match state.class(class) {
+            Some(class) => class,
+            None => return overflow("lock classes"),
+        };
+        let owner = owner();
+        let held = Held {
+            lock,
+            class,
+            kind,
+            owner,
+            at,
+        };
+
+        check_order(state, &held);
+        check_interrupts(state, &held, irq_enabled, in_interrupt);
+
+        if !state.push(held) {
+            overflow("held locks");
+        }
+    });
+}
+
+/// Record that the running code took a lock without waiting for it, which cannot deadlock, so
+/// that the locks that it takes next are ordered after it.
+pub fn acquire_try(lock: usize, class: Class, kind: Kind, at: Class) {
+    let at = site(at);
+    with_state(|state| {
+        let class = match state.class(class) {
+            Some(class) => class,
+            None => return overflow("lock classes"),
+        };
+        let held = Held {
+            lock,
+            class,
+            kind,
+            owner: owner(),
+            at,
+        };
+
+        if !state.push(held) {
+            overflow("held locks");
+        }
+    });
+}
+
+/// Record that a lock was released.
+pub fn release(lock: usize) {
+    with_state(|state| state.pop(lock, owner()));
+}
+
+/// Check that the running context holds no spin lock, since it is about to switch away. Interrupt
+/// handlers switch away from the context that they interrupted, which may hold spin locks, so they
+/// are not checked.
+pub fn check_switch(at: Class) {
+    if in_interrupt() || !irq::enabled() {
+        return;
+    }
+    let at = site(at);
+
+    with_state(|state| {
+        let owner = owner();
+        let held = state.held_by(owner).find(|held| held.kind == Kind::Spin);
+        let held = match held {
+            Some(held) if !state.class_state(held.class).reported => held,
+            _ => return,
+        };
+        state.class_state(held.class).reported = true;
+
+        print(format_args!(
+            "lockdep: switching away while holding a spin lock\n  {:?} holding locks:\n",
+            owner
+        ));
+        state.print_held(owner);
+        print(format_args!("  switches away at {}\n", at.at));
+        print_trace(&at.trace);
+    });
+}
+
+/// Report a lock that is taken in the opposite order of a chain of locks that was taken before,
+/// which deadlocks if both happen at once, and remember the new order.
+fn check_order(state: &mut State, lock: &Held) {
+    let mut index = 0;
+    while index < state.held_count {
+        let held = state.held[index].expect("Missing held lock");
+        index += 1;
+
+        // Locks of one class are ordered among themselves by their users (like by CPU), which
+        // cannot be told from here.
+        if held.owner != lock.owner || held.class == lock.class {
+            continue;
+        }
+
+        if let Some(last) = state.path(lock.class, held.class) {
+            if !state.edge(last).reported {
+                report_inversion(state, lock, &held, last);
+            }
+        }
+        if !state.add_edge(&held, lock.class, lock.at) {
+            return overflow("lock orders");
+        }
+    }
+}
+
+/// Report an inversion, whose earlier chain ends with the edge `last`.
+fn report_inversion(state: &mut State, lock: &Held, held: &Held, last: usize) {
+    print(format_args!(
+        "lockdep: possible deadlock, {} is taken while holding {}, in the opposite order of \
+         before\n  {:?} taking {} at {}:\n",
+        state.describe(lock.class),
+        state.describe(held.class),
+        lock.owner,
+        state.describe(lock.class),
+        lock.at.at
+    ));
+    print_trace(&lock.at.trace);
+    state.print_held(lock.owner);
+
+    // The chain is followed backwards from its last edge, and kept where the search kept the
+    // classes that it had yet to go through, which it no longer needs.
+    let mut length = 0;
+    let mut edge = Some(last);
+    while let Some(index) = edge {
+        state.pending[length] = index;
+        length += 1;
+        let from = state.edge(index).from;
+        edge = match from == lock.class {
+            true => None,
+            false => state.reached[from],
+        };
+    }
+
+    print(format_args!("  earlier:\n"));
+    for position in (0..length).rev() {
+        let index = state.pending[position];
+        let edge = *state.edge(index);
+        print(format_args!(
+            "    holding {} (taken at {})\n",
+            state.describe(edge.from),
+            edge.from_at.at
+        ));
+        print_trace(&edge.from_at.trace);
+        print(format_args!(
+            "    took {} at {}\n",
+            state.describe(edge.to),
+            edge.to_at.at
+        ));
+        print_trace(&edge.to_at.trace);
+        state.edges[index]
+            .as_mut()
+            .expect("Unknown lock order")
+            .reported = true;
+    }
+}
+
+/// Report a spin lock class that is taken both in interrupt context and with interrupts enabled,
+/// which deadlocks if the interrupt comes while the lock is held on the same CPU, and a blocking
+/// lock that is taken in interrupt context.
+fn check_interrupts(state: &mut State, lock: &Held, irq_enabled: bool, in_interrupt: bool) {
+    let class = state.class_state(lock.class);
+    if in_interrupt {
+        class.in_interrupt.get_or_insert(lock.at);
+    } else if irq_enabled {
+        class.irq_enabled.get_or_insert(lock.at);
+    }
+
+    let problem = match (lock.kind, class.irq_enabled, class.in_interrupt) {
+        (Kind::Sleep, _, Some(_)) => "a blocking lock is taken in interrupt context",
+        (Kind::Spin, Some(_), Some(_)) => {
+            "a lock that is taken in interrupt context is also taken with interrupts enabled"
+        }
+        _ => return,
+    };
+    if class.reported {
+        return;
+    }
+    class.reported = true;
+    let class = *class;
+
+    let in_interrupt = class
+        .in_interrupt
+        .expect("Lock not taken in interrupt context");
+    print(format_args!(
+        "lockdep: {}\n  {} taken in interrupt context at {}\n",
+        problem,
+        state.describe(lock.class),
+        in_interrupt.at
+    ));
+    print_trace(&in_interrupt.trace);
+    if let Some(irq_enabled) = class.irq_enabled {
+        print(format_args!(
+            "  taken with interrupts enabled at {}\n",
+            irq_enabled.at
+        ));
+        print_trace(&irq_enabled.trace);
+    }
+    print(format_args!(
+        "  {:?} taking it at {}:\n",
+        lock.owner, lock.at.at
+    ));
+    print_trace(&lock.at.trace);
+    state.print_held(lock.owner);
+}
@@ -0,0 +1,165 @@
+use alloc::boxed::Box;
+
+use crate::context::{SleepQueue, WaitError, WakeupPolicy};
+use crate::sync::IrqMutex;
+
+/// Errors that can occur when posting a message to a [`Mailbox`].
+#[derive(Debug, Eq, PartialEq)]
+pub enum PostError<T> {
+    /// The mailbox was full, so the message is given back.
+    Full(T),
+    /// The wait for room in the mailbox ended before there was any, so the message is given back.
+    Wait(WaitError, T),
+}
+
+/// Ring of slots that holds the messages of a [`Mailbox`], which are all allocated when it is
+/// created.
+struct Ring<T> {
+    slots: Box<[Option<T>]>,
+    /// Index of the slot of the oldest message.
+    head: usize,
+    /// Number of messages in the ring.
+    len: usize,
+}
+
+impl<T> Ring<T> {
+    /// Construct an empty [`Ring`] with room for `capacity` messages.
+    fn new(capacity: usize) -> Self {
+        Self {
+            slots: (0..capacity).map(|_| None).collect(),
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Add a message after the newest one if there is room for it, or give it back.
+    fn push(&mut self, message: T) -> Result<(), T> {
+        if self.len == self.slots.len() {
+            return Err(message);
+        }
+
+        let index = (self.head + self.len) % self.slots.len();
+        self.slots[index] = Some(message);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Take the oldest message, if there is one.
+    fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let message = self.slots[self.head].take();
+        self.head = (self.head + 1) % self.slots.len();
+        self.len -= 1;
+        message
+    }
+}
+
+/// Queue of messages with a fixed capacity. Posting blocks while the mailbox is full, and
+/// receiving blocks while it is empty. Room for every message is allocated up-front, so interrupt
+/// handlers can post messages without allocating.
+pub struct Mailbox<T> {
+    /// Messages that were not received yet. Interrupts are disabled while it is locked, since
+    /// interrupt handlers post messages.
+    messages: IrqMutex<Ring<T>>,
+    /// Contexts that wait for room in the mailbox.
+    senders: SleepQueue,
+    /// Contexts that wait for a message.
+    receivers: SleepQueue,
+}
+
+impl<T> Mailbox<T> {
+    /// Construct an empty [`Mailbox`] that holds at most `capacity` messages.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            messages: IrqMutex::new(Ring::new(capacity)),
+            senders: SleepQueue::new(WakeupPolicy::Front(1)),
+            receivers: SleepQueue::new(WakeupPolicy::Front(1)),
+        }
+    }
+
+    /// Retrieve the highest number of messages in the mailbox.
+    pub fn capacity(&self) -> usize {
+        self.messages.lock().slots.len()
+    }
+
+    /// Retrieve the number of messages in the mailbox.
+    pub fn len(&self) -> usize {
+        self.messages.lock().len
+    }
+
+    /// Whether the mailbox has no message.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Add a message if there is room for it, or give it back.
+    fn push(&self, message: T) -> Result<(), T> {
+        self.messages.lock().push(message)
+    }
+
+    /// Post a message if there is room for it, waking up the context that has waited for a message
+    /// for the longest.
+    pub fn try_post(&self, message: T) -> Result<(), PostError<T>> {
+        self.push(message).map_err(PostError::Full)?;
+        self.receivers.wake_up();
+        Ok(())
+    }
+
+    /// Post a message, blocking the current context while the mailbox is full, for at most
+    /// `timeout` scheduler ticks (if there is a timeout).
+    pub fn post(&self, message: T, timeout: Option<u64>) -> Result<(), PostError<T>> {
+        let mut message = Some(message);
+        let result = self.senders.wait_until(
+            timeout,
+            || match self.push(message.take()?) {
+                Ok(()) => Some(()),
+                Err(back) => {
+                    message = Some(back);
+                    None
+                }
+            },
+            || self.len() < self.capacity(),
+        );
+
+        match result {
+            Ok(()) => {
+                self.receivers.wake_up();
+                Ok(())
+            }
+            Err(error) => Err(PostError::Wait(error, message.expect("Message lost"))),
+        }
+    }
+
+    /// Post a message from an interrupt handler, which never blocks. See [`Mailbox::try_post`].
+    pub fn post_from_interrupt(&self, message: T) -> Result<(), PostError<T>> {
+        self.push(message).map_err(PostError::Full)?;
+        self.receivers.wake_up_from_interrupt();
+        Ok(())
+    }
+
+    /// Take the oldest message, if there is one.
+    fn pop(&self) -> Option<T> {
+        self.messages.lock().pop()
+    }
+
+    /// Receive the oldest message if there is one, waking up the context that has waited for room
+    /// for the longest.
+    pub fn try_receive(&self) -> Option<T> {
+        let message = self.pop()?;
+        self.senders.wake_up();
+        Some(message)
+    }
+
+    /// Receive the oldest message, blocking the current context while the mailbox is empty, for at
+    /// most `timeout` scheduler ticks (if there is a timeout).
+    pub fn receive(&self, timeout: Option<u64>) -> Result<T, WaitError> {
+        let message = self
+            .receivers
+            .wait_until(timeout, || self.pop(), || !self.is_empty())?;
+        self.senders.wake_up();
+        Ok(message)
+    }
+}
@@ -1,4 +1,20 @@
-pub use self::condvar::*;
-pub use self::relax::Yield;
+pub use self::checked::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use self::event_flags::{EventFlags, WaitMode};
+pub use self::irq_mutex::IrqMutex;
+pub use self::mailbox::Mailbox;
+pub use self::relax::{Spin, Yield};
+pub use self::semaphore::Semaphore;
 
 pub use spin::*;
+
+mod checked;
+pub mod condvar;
+pub mod event_flags;
+pub mod irq_mutex;
+#[cfg(feature = "lockdep")]
+pub mod lockdep;
+pub mod mailbox;
+pub mod mutex;
+pub mod rcu;
+pub mod relax;
+pub mod semaphore;
@@ -0,0 +1,469 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+#[cfg(feature = "lockdep")]
+use core::panic::Location;
+
+use crate::context::scheduler::Urgency;
+use crate::context::{self, scheduler, switch, ContextId, ContextList, Priority, Status};
+use crate::context::{SleepQueue, WaitError};
+#[cfg(feature = "lockdep")]
+use crate::sync::lockdep::{self, Class, Kind};
+
+use spin::Mutex as SpinMutex;
+
+/// Who waits for whom, and which ceilings are held, for every mutex. Urgencies are inherited
+/// along it.
+static INHERITANCE: SpinMutex<Inheritance> = SpinMutex::new(Inheritance::new());
+
+/// Sources of the urgencies that contexts inherit.
+struct Inheritance {
+    /// Owner of the mutex that each blocked context waits for.
+    waits: BTreeMap<ContextId, ContextId>,
+    /// Ceilings of the mutexes that each context holds, for the ones that have a ceiling.
+    ceilings: BTreeMap<ContextId, Vec<Priority>>,
+}
+
+impl Inheritance {
+    /// Construct an empty [`Inheritance`].
+    const fn new() -> Self {
+        Self {
+            waits: BTreeMap::new(),
+            ceilings: BTreeMap::new(),
+        }
+    }
+
+    /// Compute the urgency that a context inherits: the highest of the urgencies of the contexts
+    /// that wait for it (including what they inherited, and their deadlines if they are of the
+    /// deadline class) and of the ceilings it holds.
+    fn boost(&self, contexts: &ContextList, id: ContextId) -> Option<Urgency> {
+        let waiters = self
+            .waits
+            .iter()
+            .filter(|&(_, &owner)| owner == id)
+            .filter_map(|(&waiter, _)| contexts.get(waiter))
+            .map(|waiter| Urgency::of(&waiter.read()));
+        let ceilings = self
+            .ceilings
+            .get(&id)
+            .into_iter()
+            .flatten()
+            .map(|&ceiling| Urgency::Fixed(ceiling));
+
+        waiters.chain(ceilings).max()
+    }
+
+    /// Give a context the urgency it inherits, and pass it on along the chain of the contexts
+    /// that it waits for, until a context's urgency does not change.
+    fn propagate(&self, mut id: ContextId) {
+        let contexts = context::contexts();
+
+        loop {
+            let boost = self.boost(&contexts, id);
+            let context = match contexts.get(id) {
+                Some(context) => context,
+                None => return,
+            };
+
+            let mut context = context.write();
+            if context.boost == boost {
+                return;
+            }
+            scheduler::set_boost(&mut context, boost);
+            drop(context);
+
+            id = match self.waits.get(&id) {
+                Some(&owner) => owner,
+                None => return,
+            };
+        }
+    }
+}
+
+/// Pass a change of the urgency of a context (through [`scheduler::set_priority`]) on to the owner
+/// of the mutex that it waits for, if it waits for one, and along the chain of the contexts that
+/// the owner waits for. It cannot be done while the context is locked, so it is called afterwards.
+pub fn urgency_changed(id: ContextId) {
+    let inheritance = INHERITANCE.lock();
+    if let Some(&owner) = inheritance.waits.get(&id) {
+        inheritance.propagate(owner);
+    }
+}
+
+/// State of a mutex.
+struct State {
+    /// Context that holds the mutex.
+    owner: Option<ContextId>,
+    /// Contexts that wait for the mutex, in the order that they started waiting.
+    waiters: Vec<ContextId>,
+}
+
+/// Mutex without the data that it protects, which is shared by the variants.
+struct RawMutex {
+    state: SpinMutex<State>,
+    /// Priority that the owner runs with at least, for mutexes with a ceiling.
+    ceiling: Option<Priority>,
+    /// Where the mutex was constructed, which is its class for [`lockdep`].
+    #[cfg(feature = "lockdep")]
+    class: Class,
+}
+
+impl RawMutex {
+    /// Construct an unlocked [`RawMutex`].
+    #[track_caller]
+    const fn new(ceiling: Option<Priority>) -> Self {
+        Self {
+            state: SpinMutex::new(State {
+                owner: None,
+                waiters: Vec::new(),
+            }),
+            ceiling,
+            #[cfg(feature = "lockdep")]
+            class: Location::caller(),
+        }
+    }
+
+    /// Retrieve the address of the mutex, which tells it apart from the others of its class.
+    #[cfg(feature = "lockdep")]
+    fn address(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    /// Make a context the owner of the mutex. The state must be locked.
+    fn acquire(&self, state: &mut State, inheritance: &mut Inheritance, id: ContextId) {
+        state.owner = Some(id);
+        if let Some(ceiling) = self.ceiling {
+            inheritance.ceilings.entry(id).or_default().push(ceiling);
+        }
+    }
+
+    /// Take the mutex if it is free, returning whether it was.
+    #[track_caller]
+    fn try_lock(&self) -> bool {
+        let id = context::context_id();
+        let mut state = self.state.lock();
+        if state.owner.is_some() {
+            return false;
+        }
+
+        let mut inheritance = INHERITANCE.lock();
+        self.acquire(&mut state, &mut inheritance, id);
+        inheritance.propagate(id);
+
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire_try(self.address(), self.class, Kind::Sleep, Location::caller());
+        true
+    }
+
+    /// Take the mutex, blocking the current context until it is handed the mutex if it is held.
+    /// The owner inherits the urgency of the context while it waits, and so do the contexts that
+    /// the owner waits for in turn.
+    #[track_caller]
+    fn lock(&self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self.address(), self.class, Kind::Sleep, Location::caller());
+
+        let current = context::current().expect("Locking a mutex outside of a context");
+        let mut state = self.state.lock();
+        let id = current.read().id;
+
+        let owner = match state.owner {
+            Some(owner) => owner,
+            None => {
+                let mut inheritance = INHERITANCE.lock();
+                self.acquire(&mut state, &mut inheritance, id);
+                inheritance.propagate(id);
+                return;
+            }
+        };
+        assert_ne!(owner, id, "Mutex locked twice by the same context");
+
+        state.waiters.push(id);
+        {
+            let mut inheritance = INHERITANCE.lock();
+            inheritance.waits.insert(id, owner);
+            inheritance.propagate(owner);
+        }
+
+        // The mutex is handed over by the owner when it unlocks it. The context is blocked before
+        // the state is unlocked, so that it cannot miss it, and blocked again if something else
+        // (like a signal) woke it up.
+        loop {
+            if state.owner == Some(id) {
+                return;
+            }
+
+            scheduler::block(&mut current.write(), "Waiting for a mutex");
+            drop(state);
+
+            while current.read().status == Status::Blocked {
+                unsafe { switch() };
+            }
+            state = self.state.lock();
+        }
+    }
+
+    /// Release the mutex, handing it over to the most urgent waiter (the one that waited for the
+    /// longest among equals), which then inherits the urgency of the others.
+    fn unlock(&self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.address());
+
+        let mut state = self.state.lock();
+        let mut inheritance = INHERITANCE.lock();
+        let id = state
+            .owner
+            .take()
+            .expect("Unlocking a mutex that is not locked");
+
+        if let Some(ceiling) = self.ceiling {
+            if let Some(ceilings) = inheritance.ceilings.get_mut(&id) {
+                if let Some(index) = ceilings.iter().position(|&held| held == ceiling) {
+                    ceilings.swap_remove(index);
+                }
+                if ceilings.is_empty() {
+                    inheritance.ceilings.remove(&id);
+                }
+            }
+        }
+
+        let next = {
+            let contexts = context::contexts();
+            let priority = |waiter: &ContextId| {
+                contexts
+                    .get(*waiter)
+                    .map(|context| Urgency::of(&context.read()))
+            };
+
+            // `max_by_key` picks the last of equals, so the waiters are searched from the back.
+            let index = state
+                .waiters
+                .iter()
+                .enumerate()
+                .rev()
+                .max_by_key(|(_, waiter)| priority(waiter))
+                .map(|(index, _)| index);
+            index.map(|index| state.waiters.remove(index))
+        };
+
+        if let Some(next) = next {
+            inheritance.waits.remove(&next);
+            for waiter in &state.waiters {
+                inheritance.waits.insert(*waiter, next);
+            }
+            self.acquire(&mut state, &mut inheritance, next);
+            inheritance.propagate(next);
+
+            if let Some(context) = context::contexts().get(next) {
+                scheduler::wake(&mut context.write());
+            }
+        }
+
+        // The context no longer inherits anything from the waiters of the mutex.
+        inheritance.propagate(id);
+    }
+}
+
+/// Mutual exclusion lock that blocks contexts that wait for it, instead of spinning. Its owner
+/// inherits the urgency of the contexts that wait for it while it holds it (their priority, or
+/// their deadline for contexts of the deadline class), so that contexts of intermediate urgency
+/// cannot keep the waiters from running (priority inversion); if the owner itself waits for another
+/// mutex, the urgency is passed on to the owner of that one, and so on.
+///
+/// The urgency that a context inherits is visible to the scheduler through [`Urgency::of`].
+/// Mutexes can only be locked by contexts, and not by interrupt handlers; do not lock them while
+/// holding spin locks.
+pub struct Mutex<T: ?Sized> {
+    raw: RawMutex,
+    data: UnsafeCell<T>,
+}
+
+/// Mutex that follows the (immediate) priority ceiling protocol: its owner runs with at least the
+/// ceiling priority while it holds it, which should be the highest priority of the contexts that
+/// lock it. The owner also inherits priorities like with [`Mutex`], in case the ceiling is too low.
+pub struct CeilingMutex<T: ?Sized> {
+    raw: RawMutex,
+    data: UnsafeCell<T>,
+}
+
+/// Guard of a locked [`Mutex`] or [`CeilingMutex`], which unlocks it when it is dropped.
+pub struct MutexGuard<'a, T: ?Sized> {
+    raw: &'a RawMutex,
+    data: &'a mut T,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Send for CeilingMutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for CeilingMutex<T> {}
+
+impl<T> Mutex<T> {
+    /// Construct an unlocked [`Mutex`] that protects the given data.
+    #[track_caller]
+    pub const fn new(data: T) -> Self {
+        Self {
+            raw: RawMutex::new(None),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Consume the mutex and retrieve the data that it protects.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Lock the mutex, blocking until it is free.
+    #[track_caller]
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.raw.lock();
+        MutexGuard {
+            raw: &self.raw,
+            data: unsafe { &mut *self.data.get() },
+        }
+    }
+
+    /// Lock the mutex if it is free.
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        match self.raw.try_lock() {
+            true => Some(MutexGuard {
+                raw: &self.raw,
+                data: unsafe { &mut *self.data.get() },
+            }),
+            false => None,
+        }
+    }
+
+    /// Whether the mutex is locked.
+    pub fn is_locked(&self) -> bool {
+        self.raw.state.lock().owner.is_some()
+    }
+
+    /// Retrieve the data without locking the mutex, which borrowing it mutably makes safe.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+impl<T> CeilingMutex<T> {
+    /// Construct an unlocked [`CeilingMutex`] with the given ceiling that protects the given data.
+    #[track_caller]
+    pub const fn new(data: T, ceiling: Priority) -> Self {
+        Self {
+            raw: RawMutex::new(Some(ceiling)),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Consume the mutex and retrieve the data that it protects.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> CeilingMutex<T> {
+    /// Retrieve the ceiling of the mutex.
+    pub fn ceiling(&self) -> Priority {
+        self.raw.ceiling.expect("Ceiling mutex without a ceiling")
+    }
+
+    /// Lock the mutex, blocking until it is free. The current context runs with at least the
+    /// ceiling priority until it unlocks it.
+    #[track_caller]
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.raw.lock();
+        MutexGuard {
+            raw: &self.raw,
+            data: unsafe { &mut *self.data.get() },
+        }
+    }
+
+    /// Lock the mutex if it is free.
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        match self.raw.try_lock() {
+            true => Some(MutexGuard {
+                raw: &self.raw,
+                data: unsafe { &mut *self.data.get() },
+            }),
+            false => None,
+        }
+    }
+
+    /// Whether the mutex is locked.
+    pub fn is_locked(&self) -> bool {
+        self.raw.state.lock().owner.is_some()
+    }
+
+    /// Retrieve the data without locking the mutex, which borrowing it mutably makes safe.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    #[track_caller]
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => write!(f, "Mutex {{ data: {:?} }}", &*guard),
+            None => write!(f, "Mutex {{ <locked> }}"),
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.data
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.data
+    }
+}
+
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    /// Unlock the mutex once the current context is in `queue`, block the context there (see
+    /// [`SleepQueue::wait_with`]), and lock the mutex again. This is how a [`Condvar`] waits.
+    ///
+    /// [`Condvar`]: crate::sync::condvar::Condvar
+    #[track_caller]
+    pub(super) fn wait_on(
+        self,
+        queue: &SleepQueue,
+        timeout: Option<u64>,
+    ) -> (Self, Result<(), WaitError>) {
+        let raw = self.raw;
+        let data: *mut T = &mut *self.data;
+
+        let result = queue.wait_with(timeout, move || drop(self));
+        raw.lock();
+
+        // The data is only reached through the guard, which is back.
+        let guard = MutexGuard {
+            raw,
+            data: unsafe { &mut *data },
+        };
+        (guard, result)
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.raw.unlock();
+    }
+}
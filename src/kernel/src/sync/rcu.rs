@@ -0,0 +1,235 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicPtr, Ordering};
+use core::{mem, ptr};
+
+use spin::RelaxStrategy;
+
+use crate::context::worker::{self, Work};
+use crate::context::{self, scheduler};
+use crate::machine::irq;
+use crate::sync::{IrqMutex, Yield};
+
+/// Callbacks passed to [`call_rcu`] that wait for the next grace period. Interrupt handlers may
+/// pass callbacks too.
+static PENDING: IrqMutex<HeadList> = IrqMutex::new(HeadList::new());
+
+/// Work that runs the callbacks in [`PENDING`] once a grace period is over.
+static RUN_CALLBACKS: Work = Work::new(run_callbacks);
+
+/// Read-copy-update (RCU) lets readers look at shared data without taking any lock or doing any
+/// atomic operation, which suits data that is read much more often than it is changed, and read by
+/// interrupt handlers. Writers never change data that readers may see: they publish an updated
+/// copy, and free the old one once every reader that could still see it is done, which is after a
+/// grace period (see [`synchronize`] and [`call_rcu`]).
+///
+/// Readers look at the data within a read-side critical section, which lasts as long as the
+/// [`RcuReadGuard`] returned by [`read_lock`]. Interrupts are disabled inside of it, so it must be
+/// short and must not block. Writers have to be serialized with a lock of their own if they update
+/// the data based on its current value.
+pub struct Rcu<T> {
+    /// Current version of the data, which is never null.
+    ptr: AtomicPtr<Version<T>>,
+}
+
+/// Guard of an RCU read-side critical section, which ends when it is dropped. Critical sections
+/// may be nested.
+pub struct RcuReadGuard {
+    /// Whether interrupts were enabled before the critical section started.
+    enabled: bool,
+    /// The critical section has to end on the CPU that it started on.
+    _not_send: PhantomData<*const ()>,
+}
+
+/// Callback that is embedded in an object, usually the one that it frees, so that it can be passed
+/// to [`call_rcu`] without allocating. It waits for one grace period at a time.
+pub struct RcuHead {
+    /// Function that runs once a grace period is over, which is given the head.
+    callback: unsafe fn(*mut RcuHead),
+    /// Next head that waits for the grace period, which is only touched with the list locked.
+    next: AtomicPtr<RcuHead>,
+}
+
+/// Intrusive list of [`RcuHead`] that waits for a grace period, oldest first.
+struct HeadList {
+    head: *mut RcuHead,
+    tail: *mut RcuHead,
+}
+
+// The heads belong to the list until their callback runs.
+unsafe impl Send for HeadList {}
+
+/// Version of the data of an [`Rcu`], with the head that drops it once it was replaced and no
+/// reader sees it anymore.
+#[repr(C)]
+struct Version<T> {
+    /// First, so that a pointer to the head is a pointer to the version.
+    head: RcuHead,
+    data: T,
+}
+
+impl RcuHead {
+    /// Construct an [`RcuHead`] that runs the given function, which is not waiting yet.
+    pub const fn new(callback: unsafe fn(*mut RcuHead)) -> Self {
+        Self {
+            callback,
+            next: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+}
+
+impl HeadList {
+    /// Construct an empty [`HeadList`].
+    const fn new() -> Self {
+        Self {
+            head: ptr::null_mut(),
+            tail: ptr::null_mut(),
+        }
+    }
+
+    /// Append a head, which must not be in a list.
+    unsafe fn push(&mut self, head: *mut RcuHead) {
+        (*head).next.store(ptr::null_mut(), Ordering::Relaxed);
+        match self.tail.is_null() {
+            true => self.head = head,
+            false => (*self.tail).next.store(head, Ordering::Relaxed),
+        }
+        self.tail = head;
+    }
+}
+
+/// Start an RCU read-side critical section.
+pub fn read_lock() -> RcuReadGuard {
+    let enabled = irq::enabled();
+    unsafe { irq::disable() };
+
+    RcuReadGuard {
+        enabled,
+        _not_send: PhantomData,
+    }
+}
+
+impl Drop for RcuReadGuard {
+    fn drop(&mut self) {
+        if self.enabled {
+            unsafe { irq::enable() };
+        }
+    }
+}
+
+/// Wait until every RCU read-side critical section that started before the call has ended (a grace
+/// period), so that data that readers could reach before may be freed. Must not be called inside of
+/// a read-side critical section.
+pub fn synchronize() {
+    assert!(
+        irq::enabled(),
+        "Waiting for a grace period with interrupts disabled"
+    );
+
+    // The CPU that the current context runs on is not inside a critical section, since interrupts
+    // are enabled. Every other CPU has to go through a quiescent state.
+    let cpu = context::cpu_id();
+    let seen: Vec<(usize, u64)> = (0..scheduler::cpu_count())
+        .filter(|&other| other != cpu)
+        .map(|other| (other, scheduler::quiescent_states(other)))
+        .collect();
+
+    for (other, seen) in seen {
+        while scheduler::quiescent_states(other) == seen {
+            Yield::relax();
+        }
+    }
+}
+
+/// Run the callback of a head once every RCU read-side critical section that started before the
+/// call has ended, without waiting for it. Nothing is allocated, so this may be called from
+/// interrupt handlers and from critical sections. The callbacks are run by a worker of
+/// [`worker::SYSTEM`], in batches that share a grace period.
+///
+/// # Safety
+/// The head must stay valid and must not be passed again until its callback runs.
+pub unsafe fn call_rcu(head: *mut RcuHead) {
+    PENDING.lock().push(head);
+
+    // The batch is taken by the worker, so the callbacks that are passed until then join it.
+    worker::SYSTEM.queue_work(&RUN_CALLBACKS);
+}
+
+/// Run the callbacks that wait for a grace period, once it is over.
+fn run_callbacks() {
+    let batch = mem::replace(&mut *PENDING.lock(), HeadList::new());
+    synchronize();
+
+    let mut head = batch.head;
+    while !head.is_null() {
+        // The callback may free the head, so the next one is found first.
+        unsafe {
+            let next = (*head).next.load(Ordering::Relaxed);
+            ((*head).callback)(head);
+            head = next;
+        }
+    }
+}
+
+/// Callback of the head of a [`Version`] that was replaced, which drops it.
+unsafe fn drop_version<T>(head: *mut RcuHead) {
+    drop(Box::from_raw(head as *mut Version<T>));
+}
+
+impl<T: Send + Sync + 'static> Rcu<T> {
+    /// Construct an [`Rcu`] that holds the given data.
+    pub fn new(data: T) -> Self {
+        Self {
+            ptr: AtomicPtr::new(Self::version(data)),
+        }
+    }
+
+    /// Retrieve the current version of the data, which stays valid until the read-side critical
+    /// section ends, even if a writer replaces it in the meantime.
+    pub fn read<'a>(&'a self, _guard: &'a RcuReadGuard) -> &'a T {
+        // Loads are not read-modify-write operations, so they are as cheap as plain ones.
+        unsafe { &(*self.ptr.load(Ordering::Acquire)).data }
+    }
+
+    /// Publish a new version of the data, and wait for a grace period before returning the old
+    /// version, which no reader sees anymore by then.
+    pub fn replace(&self, data: T) -> T {
+        let old = self.publish(data);
+        synchronize();
+        unsafe { Box::from_raw(old).data }
+    }
+
+    /// Publish a new version of the data, and drop the old version once a grace period is over,
+    /// without waiting for it. The new version is allocated, so this must not be called from
+    /// interrupt handlers.
+    pub fn replace_deferred(&self, data: T) {
+        let old = self.publish(data);
+        // The old version embeds the head, and is only freed by its callback.
+        unsafe { call_rcu(ptr::addr_of_mut!((*old).head)) };
+    }
+
+    /// Allocate a version of the data, which is not published yet.
+    fn version(data: T) -> *mut Version<T> {
+        Box::into_raw(Box::new(Version {
+            head: RcuHead::new(drop_version::<T>),
+            data,
+        }))
+    }
+
+    /// Make a new version of the data current, returning the old one, which readers may still see.
+    fn publish(&self, data: T) -> *mut Version<T> {
+        self.ptr.swap(Self::version(data), Ordering::AcqRel)
+    }
+}
+
+impl<T> Drop for Rcu<T> {
+    fn drop(&mut self) {
+        // References returned by `read` borrow `self`, so no reader is left.
+        drop(unsafe { Box::from_raw(*self.ptr.get_mut()) });
+    }
+}
+
+unsafe impl<T: Send + Sync> Send for Rcu<T> {}
+unsafe impl<T: Send + Sync> Sync for Rcu<T> {}
@@ -1,15 +1,50 @@
-use sync::RelaxStrategy;
+use core::hint;
+
+use spin::RelaxStrategy;
+
+use crate::context::{self, percpu, scheduler};
+use crate::machine::irq;
+use crate::memory::tlb;
+
+/// A strategy that spins like [`spin::Spin`], but answers the TLB shootdowns that other CPUs
+/// request in the meantime. The lock's holder may be waiting for this CPU to flush its TLB, which
+/// the shootdown's IPI cannot make it do while interrupts are disabled.
+///
+/// This is the strategy of the kernel's spin locks.
+pub struct Spin;
+
+impl RelaxStrategy for Spin {
+    #[inline(always)]
+    fn relax() {
+        tlb::poll();
+        hint::spin_loop();
+    }
+}
 
 /// A strategy that yields the current time slice to the scheduler in favour of other threads or
 /// processes.
 ///
 /// This is generally used as a strategy for minimising power consumption and for events that take
-/// a long time such as I/O or messages from other processes.
+/// a long time such as I/O or messages from other processes. The context gives up the rest of its
+/// time slice, so that the other contexts of its priority run first. It only switches away from
+/// contexts that hold no spin lock (see [`percpu::preempt_disable`]), and spins like [`Spin`]
+/// otherwise.
 pub struct Yield;
 
 impl RelaxStrategy for Yield {
     #[inline(always)]
     fn relax() {
-        // TODO: Implement this.
+        // Interrupt handlers (and code that disabled interrupts) cannot switch away, and neither can
+        // the code that runs before contexts exist, so they spin instead. Neither can code that
+        // holds a spin lock, which other contexts could then spin for until it runs again.
+        if !irq::enabled() || !percpu::preemptible() {
+            return Spin::relax();
+        }
+
+        // The contexts of the same priority run first, since the lock's holder may be one of them.
+        scheduler::expire_slice();
+        if !unsafe { context::switch() } {
+            Spin::relax();
+        }
     }
 }
@@ -0,0 +1,84 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::context::{SleepQueue, WaitError, WakeupPolicy};
+
+/// Counting semaphore. Taking it decrements its count, blocking while the count is zero, and
+/// giving it increments the count, up to a maximum. A semaphore with a maximum of one is a binary
+/// semaphore, which interrupt handlers can use to signal events to contexts.
+pub struct Semaphore {
+    /// Number of times that the semaphore can be taken without blocking.
+    count: AtomicUsize,
+    /// Highest value of the count.
+    max: usize,
+    /// Contexts that wait for the count to become positive.
+    waiters: SleepQueue,
+}
+
+impl Semaphore {
+    /// Construct a [`Semaphore`] with the given count and maximum count.
+    pub const fn new(count: usize, max: usize) -> Self {
+        Self {
+            count: AtomicUsize::new(if count < max { count } else { max }),
+            max,
+            waiters: SleepQueue::new(WakeupPolicy::Front(1)),
+        }
+    }
+
+    /// Retrieve the count of the semaphore.
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// Retrieve the highest value of the count.
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// Take the semaphore if its count is positive, returning whether it was.
+    pub fn try_take(&self) -> bool {
+        self.count
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                count.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    /// Take the semaphore, blocking the current context while its count is zero, for at most
+    /// `timeout` scheduler ticks (if there is a timeout).
+    pub fn take(&self, timeout: Option<u64>) -> Result<(), WaitError> {
+        self.waiters.wait_until(
+            timeout,
+            || self.try_take().then_some(()),
+            || self.count() > 0,
+        )
+    }
+
+    /// Increment the count, returning whether it was below its maximum.
+    fn increment(&self) -> bool {
+        let max = self.max;
+        self.count
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                (count < max).then_some(count + 1)
+            })
+            .is_ok()
+    }
+
+    /// Give the semaphore, waking up the context that has waited for it for the longest. Returns
+    /// whether the count was below its maximum (it is left as it is otherwise).
+    pub fn give(&self) -> bool {
+        let given = self.increment();
+        if given {
+            self.waiters.wake_up();
+        }
+        given
+    }
+
+    /// Give the semaphore from an interrupt handler, which never blocks. See [`Semaphore::give`].
+    pub fn give_from_interrupt(&self) -> bool {
+        let given = self.increment();
+        if given {
+            self.waiters.wake_up_from_interrupt();
+        }
+        given
+    }
+}
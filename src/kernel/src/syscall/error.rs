@@ -0,0 +1,145 @@
+use core::fmt;
+
+use crate::context::WaitError;
+use crate::filesys::error::FileSystemError;
+
+/// Error returned by a system call. User-space receives the negated error number in place of the
+/// result.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct Error {
+    pub errno: i32,
+}
+
+/// Result of a system call.
+pub type Result<T, E = Error> = core::result::Result<T, E>;
+
+impl Error {
+    /// Construct a new [`Error`].
+    pub const fn new(errno: i32) -> Self {
+        Self { errno }
+    }
+
+    /// Encode the result of a system call into the value that is returned to user-space.
+    pub fn mux(result: Result<usize>) -> usize {
+        match result {
+            Ok(value) => value,
+            Err(error) => -(error.errno as isize) as usize,
+        }
+    }
+
+    /// Retrieve a description of the error.
+    pub fn text(&self) -> &'static str {
+        match self.errno {
+            EPERM => "Operation not permitted",
+            ENOENT => "No such file or directory",
+            ESRCH => "No such process",
+            EINTR => "Interrupted system call",
+            EIO => "I/O error",
+            ECHILD => "No child processes",
+            EAGAIN => "Try again",
+            ENOMEM => "Out of memory",
+            EFAULT => "Bad address",
+            EBUSY => "Device or resource busy",
+            EEXIST => "File exists",
+            ENOTDIR => "Not a directory",
+            EISDIR => "Is a directory",
+            EINVAL => "Invalid argument",
+            ENOSPC => "No space left on device",
+            ESPIPE => "Illegal seek",
+            ENOSYS => "Function not implemented",
+            ENOTSOCK => "Socket operation on non-socket",
+            EOPNOTSUPP => "Operation not supported",
+            ENOTCONN => "Transport endpoint is not connected",
+            ETIMEDOUT => "Connection timed out",
+            ECONNREFUSED => "Connection refused",
+            _ => "Unknown error",
+        }
+    }
+}
+
+impl From<FileSystemError> for Error {
+    fn from(error: FileSystemError) -> Self {
+        Self::new(match error {
+            FileSystemError::NotSupported => EOPNOTSUPP,
+            FileSystemError::EntryExists => EEXIST,
+            FileSystemError::EntryNotFound => ENOENT,
+            FileSystemError::Busy => EBUSY,
+            FileSystemError::NotDirectory => ENOTDIR,
+            FileSystemError::IsPipe => ESPIPE,
+            FileSystemError::IsDirectory => EISDIR,
+            FileSystemError::Interrupted => EINTR,
+            FileSystemError::Device => EIO,
+            FileSystemError::TooSmall | FileSystemError::InvalidPath => EINVAL,
+            FileSystemError::NotSocket => ENOTSOCK,
+            FileSystemError::ConnectionRefused => ECONNREFUSED,
+            FileSystemError::NotConnected => ENOTCONN,
+            FileSystemError::WouldBlock => EAGAIN,
+        })
+    }
+}
+
+impl From<WaitError> for Error {
+    fn from(error: WaitError) -> Self {
+        Self::new(match error {
+            WaitError::Interrupted => EINTR,
+            WaitError::TimedOut => ETIMEDOUT,
+        })
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.text(), self.errno)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.text())
+    }
+}
+
+/// Operation not permitted.
+pub const EPERM: i32 = 1;
+/// No such file or directory.
+pub const ENOENT: i32 = 2;
+/// No such process.
+pub const ESRCH: i32 = 3;
+/// Interrupted system call.
+pub const EINTR: i32 = 4;
+/// I/O error.
+pub const EIO: i32 = 5;
+/// No child processes.
+pub const ECHILD: i32 = 10;
+/// Try again.
+pub const EAGAIN: i32 = 11;
+/// Out of memory.
+pub const ENOMEM: i32 = 12;
+/// Bad address.
+pub const EFAULT: i32 = 14;
+/// Device or resource busy.
+pub const EBUSY: i32 = 16;
+/// File exists.
+pub const EEXIST: i32 = 17;
+/// Not a directory.
+pub const ENOTDIR: i32 = 20;
+/// Is a directory.
+pub const EISDIR: i32 = 21;
+/// Invalid argument.
+pub const EINVAL: i32 = 22;
+/// No space left on device.
+pub const ENOSPC: i32 = 28;
+/// Illegal seek.
+pub const ESPIPE: i32 = 29;
+/// Function not implemented.
+pub const ENOSYS: i32 = 38;
+/// Socket operation on non-socket.
+pub const ENOTSOCK: i32 = 88;
+/// Operation not supported.
+pub const EOPNOTSUPP: i32 = 95;
+/// Transport endpoint is not connected.
+pub const ENOTCONN: i32 = 107;
+/// Connection timed out.
+pub const ETIMEDOUT: i32 = 110;
+/// Connection refused.
+pub const ECONNREFUSED: i32 = 111;
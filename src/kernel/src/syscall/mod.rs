@@ -0,0 +1,74 @@
+pub use self::error::*;
+pub use self::number::*;
+
+use crate::context;
+use crate::machine::interrupt::InterruptStack;
+
+pub mod error;
+pub mod number;
+pub mod process;
+pub mod sched;
+pub mod signal;
+pub mod sync;
+pub mod time;
+pub mod validate;
+
+/// Handle a system call made by user-space, whose number and arguments are in the saved registers.
+/// The result is encoded with [`Error::mux`] and returned in a register, unless the call was
+/// interrupted by a signal that lets it restart, in which case it is made again once the signal
+/// was handled.
+pub fn syscall(stack: &mut InterruptStack) {
+    let (number, [a, b, c, d, _e]) = stack.syscall_args();
+
+    let result = match number {
+        SYS_SCHED_STATS => sched::stats(a, b as *mut _),
+        SYS_SCHED_DUMP => sched::dump(),
+        SYS_KILL => self::signal::kill(a as isize, b),
+        SYS_SIGACTION => self::signal::sigaction(a, b as *const _, c as *mut _, d),
+        SYS_SIGPROCMASK => self::signal::sigprocmask(a, b as *const _, c as *mut _),
+        SYS_SIGRETURN => {
+            // The registers are the ones from before the signal, including the result register.
+            if self::signal::sigreturn(stack).is_err() {
+                context::signal::raise(context::signal::SIGSEGV);
+            }
+            return;
+        }
+        SYS_SPAWN => process::spawn(stack),
+        SYS_EXIT => process::exit(a),
+        SYS_WAITPID => process::waitpid(a as isize, b as *mut _, c),
+        SYS_GETPID => process::getpid(),
+        SYS_GETPPID => process::getppid(),
+        SYS_SETPGID => process::setpgid(a, b),
+        SYS_THREAD_SPAWN => process::thread_spawn(stack, a, b, c, d),
+        SYS_THREAD_EXIT => process::thread_exit(a),
+        SYS_GETTID => process::gettid(),
+        SYS_PIN_MEMORY => process::pin_memory(a),
+        SYS_SEM_CREATE => sync::sem_create(a, b),
+        SYS_SEM_TAKE => sync::sem_take(a, b),
+        SYS_SEM_GIVE => sync::sem_give(a),
+        SYS_EVENT_CREATE => sync::event_create(),
+        SYS_EVENT_SET => sync::event_set(a, b),
+        SYS_EVENT_CLEAR => sync::event_clear(a, b),
+        SYS_EVENT_WAIT => sync::event_wait(a, b, c, d),
+        SYS_MBOX_CREATE => sync::mbox_create(a, b),
+        SYS_MBOX_SEND => sync::mbox_send(a, b as *const _, c, d),
+        SYS_MBOX_RECEIVE => sync::mbox_receive(a, b as *mut _, c, d),
+        SYS_OBJECT_DESTROY => sync::object_destroy(a),
+        SYS_CLOCK_GETTIME => time::clock_gettime(a, b as *mut _),
+        SYS_CLOCK_SETTIME => time::clock_settime(a, b as *const _),
+        SYS_NANOSLEEP => time::nanosleep(a as *const _, b as *mut _),
+        _ => Err(Error::new(ENOSYS)),
+    };
+
+    // Sleeps are not made again, since they would start over: they report the time that was left.
+    match result {
+        Err(error)
+            if error.errno == EINTR
+                && number != SYS_NANOSLEEP
+                && context::signal::restart_syscall() =>
+        {
+            stack.restart_syscall()
+        }
+        result => stack.set_syscall_result(Error::mux(result)),
+    }
+}
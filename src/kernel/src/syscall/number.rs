@@ -0,0 +1,177 @@
+/// Copy the timing statistics of a context of the deadline class to user-space.
+///
+/// Arguments: context ID (zero for the calling context), pointer to a `TimingStats`.
+pub const SYS_SCHED_STATS: usize = 1;
+
+/// Write the timing statistics of every context of the deadline class to the serial console.
+pub const SYS_SCHED_DUMP: usize = 2;
+
+/// Send a signal to contexts.
+///
+/// Arguments: context ID (zero for the process group of the calling context, -1 for every user
+/// context, or the negated ID of a process group), signal number (zero to only check that the
+/// signal could be sent).
+pub const SYS_KILL: usize = 3;
+
+/// Change the action taken when a signal is received.
+///
+/// Arguments: signal number, pointer to the new `SigAction` (or null), pointer to where the old
+/// `SigAction` is copied (or null), address of the code that handlers return to, which calls
+/// `SYS_SIGRETURN`.
+pub const SYS_SIGACTION: usize = 4;
+
+/// Change the signal mask of the calling context.
+///
+/// Arguments: `SIG_BLOCK`, `SIG_UNBLOCK` or `SIG_SETMASK`, pointer to the mask (or null), pointer
+/// to where the old mask is copied (or null). Masks are arrays of two 64-bit words.
+pub const SYS_SIGPROCMASK: usize = 5;
+
+/// Return from a signal handler, restoring the registers and the signal mask from before it was
+/// called. Only meant to be called by the code that handlers return to.
+pub const SYS_SIGRETURN: usize = 6;
+
+/// Create a child of the calling context that is a copy of it, with a copy-on-write copy of its
+/// memory, and copies of its file table and signal actions.
+///
+/// Returns the ID of the child to the calling context, and zero to the child.
+pub const SYS_SPAWN: usize = 7;
+
+/// End the process of the calling context, killing every one of its threads.
+///
+/// Arguments: exit code (only the low 8 bits are kept).
+pub const SYS_EXIT: usize = 8;
+
+/// Wait for a child of the calling context to exit and collect its wait status.
+///
+/// Arguments: context ID (-1 for any child, zero for any child in the process group of the calling
+/// context, or the negated ID of a process group), pointer to where the wait status is copied (or
+/// null), options (`WNOHANG`).
+///
+/// Returns the ID of the child, or zero if `WNOHANG` is set and no child exited yet.
+pub const SYS_WAITPID: usize = 9;
+
+/// Retrieve the ID of the process of the calling context, which is the ID of its first thread.
+pub const SYS_GETPID: usize = 10;
+
+/// Retrieve the ID of the parent process of the calling context.
+pub const SYS_GETPPID: usize = 11;
+
+/// Move a process to a process group.
+///
+/// Arguments: process ID (zero for the process of the calling context), process group ID (zero for
+/// the ID of the process, which creates a new group).
+pub const SYS_SETPGID: usize = 12;
+
+/// Create a thread in the process of the calling context, which shares its memory, its file table
+/// and its signal actions.
+///
+/// Arguments: address that the thread starts at, stack pointer of the thread, thread-local storage
+/// pointer of the thread (the FS base on x86_64, `tp` on RISC-V), argument passed to the thread.
+///
+/// Returns the ID of the thread.
+pub const SYS_THREAD_SPAWN: usize = 13;
+
+/// Terminate the calling thread. The process ends if it was its last thread.
+///
+/// Arguments: exit code (only the low 8 bits are kept).
+pub const SYS_THREAD_EXIT: usize = 14;
+
+/// Retrieve the ID of the calling context, as opposed to the ID of its process.
+pub const SYS_GETTID: usize = 15;
+
+/// Create a counting semaphore, which is shared by every process that knows its ID.
+///
+/// Arguments: initial count, maximum count.
+///
+/// Returns the ID of the semaphore.
+pub const SYS_SEM_CREATE: usize = 16;
+
+/// Take a semaphore, waiting while its count is zero.
+///
+/// Arguments: semaphore ID, timeout in scheduler ticks (zero only tries, `usize::MAX` waits
+/// forever).
+pub const SYS_SEM_TAKE: usize = 17;
+
+/// Give a semaphore, waking up the context that has waited for it for the longest.
+///
+/// Arguments: semaphore ID.
+pub const SYS_SEM_GIVE: usize = 18;
+
+/// Create a group of event flags, which is shared by every process that knows its ID.
+///
+/// Returns the ID of the group.
+pub const SYS_EVENT_CREATE: usize = 19;
+
+/// Set event flags, waking up the contexts that wait for them.
+///
+/// Arguments: group ID, flags to set.
+///
+/// Returns the flags that were set before.
+pub const SYS_EVENT_SET: usize = 20;
+
+/// Clear event flags.
+///
+/// Arguments: group ID, flags to clear.
+///
+/// Returns the flags that were set before.
+pub const SYS_EVENT_CLEAR: usize = 21;
+
+/// Wait for any or all of the given event flags to be set.
+///
+/// Arguments: group ID, mask of flags, options (`EVENT_ALL` to wait for every flag of the mask,
+/// `EVENT_CLEAR` to clear them), timeout in scheduler ticks (like for `SYS_SEM_TAKE`).
+///
+/// Returns the flags as they were when the wait ended.
+pub const SYS_EVENT_WAIT: usize = 22;
+
+/// Create a mailbox with a fixed number of messages of a fixed size, which is shared by every
+/// process that knows its ID.
+///
+/// Arguments: highest number of messages, highest size of a message.
+///
+/// Returns the ID of the mailbox.
+pub const SYS_MBOX_CREATE: usize = 23;
+
+/// Post a message to a mailbox, waiting while it is full.
+///
+/// Arguments: mailbox ID, pointer to the message, size of the message, timeout in scheduler ticks
+/// (like for `SYS_SEM_TAKE`).
+pub const SYS_MBOX_SEND: usize = 24;
+
+/// Receive the oldest message of a mailbox, waiting while it is empty.
+///
+/// Arguments: mailbox ID, pointer to a buffer that can hold the largest message, size of the
+/// buffer, timeout in scheduler ticks (like for `SYS_SEM_TAKE`).
+///
+/// Returns the size of the message.
+pub const SYS_MBOX_RECEIVE: usize = 25;
+
+/// Destroy a semaphore, a group of event flags or a mailbox.
+///
+/// Arguments: object ID.
+pub const SYS_OBJECT_DESTROY: usize = 26;
+
+/// Retrieve the time of a clock.
+///
+/// Arguments: clock (`CLOCK_REALTIME` or `CLOCK_MONOTONIC`), pointer to where the `TimeSpec` is
+/// copied.
+pub const SYS_CLOCK_GETTIME: usize = 27;
+
+/// Set the time of a clock. Only the realtime clock can be set.
+///
+/// Arguments: clock (`CLOCK_REALTIME`), pointer to the `TimeSpec`.
+pub const SYS_CLOCK_SETTIME: usize = 28;
+
+/// Block the calling context for a time, measured with the monotonic clock. It fails with `EINTR`
+/// if a signal interrupts it, even if the signal lets system calls restart.
+///
+/// Arguments: pointer to the `TimeSpec` of the time, pointer to where the time that was left is
+/// copied if a signal interrupted it (or null).
+pub const SYS_NANOSLEEP: usize = 29;
+
+/// Keep every page of the calling process in memory, reading back the ones that were swapped out,
+/// or allow them to be swapped out again. Meant for real-time processes, which cannot wait for
+/// the swap device. Does nothing if the kernel has no swap support.
+///
+/// Arguments: nonzero to pin the pages, zero to unpin them.
+pub const SYS_PIN_MEMORY: usize = 30;
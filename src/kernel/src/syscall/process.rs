@@ -0,0 +1,154 @@
+use crate::context::process::{self, WaitPidError, WaitTarget};
+use crate::context::{self, thread, ContextId};
+use crate::machine::interrupt::InterruptStack;
+use crate::syscall::error::*;
+use crate::syscall::validate::write_user;
+
+/// Option of [`waitpid`] that makes it return right away if no child exited yet.
+pub const WNOHANG: usize = 1;
+
+/// Create a child of the process of the calling context, which resumes with the given registers.
+pub fn spawn(stack: &InterruptStack) -> Result<usize> {
+    process::spawn(stack)
+        .map(|id| id.get())
+        .map_err(|_| Error::new(ENOMEM))
+}
+
+/// End the process of the calling context with the given exit code.
+pub fn exit(code: usize) -> ! {
+    process::exit(process::exit_status(code))
+}
+
+/// Create a thread in the process of the calling context, which starts at `entry` with the given
+/// stack and thread-local storage pointers, and is passed `arg`.
+pub fn thread_spawn(
+    stack: &InterruptStack,
+    entry: usize,
+    stack_pointer: usize,
+    tls: usize,
+    arg: usize,
+) -> Result<usize> {
+    thread::spawn(stack, entry, stack_pointer, tls, arg)
+        .map(|id| id.get())
+        .map_err(|_| Error::new(ENOMEM))
+}
+
+/// Terminate the calling thread with the given exit code.
+pub fn thread_exit(code: usize) -> ! {
+    thread::exit(process::exit_status(code))
+}
+
+/// Wait for a child process of the calling process to end (if `pid` is positive), any child in the
+/// process group of the calling context (if it is zero), any child (if it is -1), or any child in
+/// the process group `-pid`. Its wait status is copied to `status` (if it is not null).
+pub fn waitpid(pid: isize, status: *mut usize, options: usize) -> Result<usize> {
+    if options & !WNOHANG != 0 {
+        return Err(Error::new(EINVAL));
+    }
+
+    let target = match pid {
+        0 => {
+            let current = context::current().ok_or(Error::new(ESRCH))?;
+            let group_id = current.read().group_id;
+            WaitTarget::Group(group_id)
+        }
+        -1 => WaitTarget::Any,
+        pid if pid < 0 => WaitTarget::Group(ContextId::new(-pid as usize)),
+        pid => WaitTarget::Child(ContextId::new(pid as usize)),
+    };
+
+    match process::wait(target, options & WNOHANG != 0) {
+        Ok(Some((id, code))) => {
+            if !status.is_null() {
+                write_user(status, &code)?;
+            }
+            Ok(id.get())
+        }
+        Ok(None) => Ok(0),
+        Err(WaitPidError::NoChild) => Err(Error::new(ECHILD)),
+        Err(WaitPidError::Interrupted) => Err(Error::new(EINTR)),
+    }
+}
+
+/// Retrieve the identifier of the process of the calling context.
+pub fn getpid() -> Result<usize> {
+    let current = context::current().ok_or(Error::new(ESRCH))?;
+    let leader = current.read().thread_group.leader();
+    Ok(leader.get())
+}
+
+/// Retrieve the identifier of the calling context.
+pub fn gettid() -> Result<usize> {
+    Ok(context::context_id().get())
+}
+
+/// Retrieve the identifier of the parent of the process of the calling context.
+pub fn getppid() -> Result<usize> {
+    let leader = ContextId::new(getpid()?);
+    let contexts = context::contexts();
+    let leader = contexts.get(leader).ok_or(Error::new(ESRCH))?;
+    let parent_id = leader.read().parent_id;
+    Ok(parent_id.get())
+}
+
+/// Move the process of the calling context or one of its children (`pid`, or the calling process if
+/// it is zero) to the process group `group` (or to a new group with the identifier of the process
+/// if it is zero). Groups other than a new one must already have a member. Every thread of the
+/// process is moved.
+pub fn setpgid(pid: usize, group: usize) -> Result<usize> {
+    let current = ContextId::new(getpid()?);
+    let id = match pid {
+        0 => current,
+        pid => ContextId::new(pid),
+    };
+    let group = match group {
+        0 => id,
+        group => ContextId::new(group),
+    };
+
+    let contexts = context::contexts();
+    let target = contexts.get(id).ok_or(Error::new(ESRCH))?;
+    let target = target.read();
+    let leader = target.thread_group.leader() == id;
+    if !leader || (id != current && target.parent_id != current) {
+        return Err(Error::new(ESRCH));
+    }
+    drop(target);
+
+    let exists = group == id
+        || contexts
+            .iter()
+            .any(|(_, context)| context.read().group_id == group);
+    if !exists {
+        return Err(Error::new(EPERM));
+    }
+
+    for (_, context) in contexts.iter() {
+        let mut context = context.write();
+        if context.thread_group.leader() == id {
+            context.group_id = group;
+        }
+    }
+    Ok(0)
+}
+
+/// Keep every page of the process of the calling context in memory if `pin` is nonzero, or allow
+/// them to be swapped out again if it is zero.
+pub fn pin_memory(pin: usize) -> Result<usize> {
+    let current = context::current().ok_or(Error::new(ESRCH))?;
+    let addr_space = current
+        .read()
+        .addr_space()
+        .cloned()
+        .ok_or(Error::new(EINVAL))?;
+
+    #[cfg(feature = "swap")]
+    match pin {
+        0 => addr_space.write().unpin(),
+        _ => context::memory::pin(&addr_space).map_err(|_| Error::new(ENOMEM))?,
+    }
+    #[cfg(not(feature = "swap"))]
+    let _ = (pin, addr_space);
+
+    Ok(0)
+}
@@ -0,0 +1,28 @@
+use crate::context::trace::{self, TimingStats};
+use crate::context::{self, ContextId};
+use crate::syscall::error::*;
+use crate::syscall::validate::write_user;
+
+/// Copy the timing statistics of a context of the deadline class to user-space. A context ID of
+/// zero stands for the calling context.
+pub fn stats(id: usize, buffer: *mut TimingStats) -> Result<usize> {
+    let id = match id {
+        0 => context::context_id(),
+        id => ContextId::new(id),
+    };
+
+    let stats = match trace::stats(id) {
+        Some(stats) => stats,
+        None if context::contexts().get(id).is_some() => return Err(Error::new(EINVAL)),
+        None => return Err(Error::new(ESRCH)),
+    };
+
+    write_user(buffer, &stats)?;
+    Ok(0)
+}
+
+/// Write the timing statistics of every context of the deadline class to the serial console.
+pub fn dump() -> Result<usize> {
+    trace::dump();
+    Ok(0)
+}
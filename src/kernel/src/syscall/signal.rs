@@ -0,0 +1,170 @@
+use alloc::vec::Vec;
+
+use crate::context::signal::{self, SigAction, SignalFrame, NSIG};
+use crate::context::{self, Context, ContextId};
+use crate::machine::interrupt::InterruptStack;
+use crate::syscall::error::*;
+use crate::syscall::validate::{read_user, write_user};
+
+/// Convert a signal number from user-space, which may be zero if `allow_zero` is set.
+fn signal_number(signal: usize, allow_zero: bool) -> Result<u8> {
+    match signal {
+        0 if allow_zero => Ok(0),
+        signal if signal > 0 && signal < NSIG => Ok(signal as u8),
+        _ => Err(Error::new(EINVAL)),
+    }
+}
+
+/// Context that sends a signal, as far as [`kill`] is concerned.
+struct Sender {
+    id: ContextId,
+    group_id: ContextId,
+    real_user_id: u32,
+    effective_user_id: u32,
+}
+
+impl Sender {
+    /// Whether the sender may send signals to a context: the super-user may send them to anyone,
+    /// and other users to the contexts that they own.
+    fn may_signal(&self, target: &Context) -> bool {
+        self.effective_user_id == 0
+            || self.real_user_id == target.real_user_id
+            || self.effective_user_id == target.real_user_id
+    }
+}
+
+/// Send a signal to a context (if `pid` is positive), to the processes in the process group of the
+/// calling context (if it is zero), to every user process but the calling one (if it is -1), or to
+/// the processes in the process group `-pid`. A signal number of zero only checks that the signal
+/// could be sent.
+pub fn kill(pid: isize, signal: usize) -> Result<usize> {
+    let signal = signal_number(signal, true)?;
+
+    let current = context::current().ok_or(Error::new(ESRCH))?;
+    let current = {
+        let context = current.read();
+        Sender {
+            id: context.thread_group.leader(),
+            group_id: context.group_id,
+            real_user_id: context.real_user_id,
+            effective_user_id: context.effective_user_id,
+        }
+    };
+
+    let contexts = context::contexts();
+    let targets: Vec<_> = contexts
+        .iter()
+        .filter(|(&id, context)| {
+            // Signals sent to groups go to the processes in them, which are their first threads.
+            let context = context.read();
+            let leader = context.thread_group.leader() == id;
+            match pid {
+                0 => leader && context.group_id == current.group_id,
+                -1 => leader && id != current.id && context.addr_space().is_some(),
+                pid if pid < 0 => leader && context.group_id == ContextId::new(-pid as usize),
+                pid => id == ContextId::new(pid as usize),
+            }
+        })
+        .map(|(_, context)| context.clone())
+        .collect();
+    drop(contexts);
+
+    if targets.is_empty() {
+        return Err(Error::new(ESRCH));
+    }
+
+    let mut sent = false;
+    for target in targets {
+        let mut target = target.write();
+        if !current.may_signal(&target) {
+            continue;
+        }
+
+        sent = true;
+        if signal != 0 {
+            signal::send(&mut target, signal);
+        }
+    }
+
+    if sent {
+        Ok(0)
+    } else {
+        Err(Error::new(EPERM))
+    }
+}
+
+/// Change the action of the calling context for a signal, after copying the old one to `old` (if
+/// it is not null). Handlers return to `restorer`, which calls `SYS_SIGRETURN`. The actions of
+/// [`signal::SIGKILL`] and [`signal::SIGSTOP`] cannot be changed.
+pub fn sigaction(
+    signal: usize,
+    action: *const SigAction,
+    old: *mut SigAction,
+    restorer: usize,
+) -> Result<usize> {
+    let signal = signal_number(signal, false)?;
+    let action = match action.is_null() {
+        true => None,
+        false => Some(read_user(action)?),
+    };
+
+    if action.is_some() && (signal == signal::SIGKILL || signal == signal::SIGSTOP) {
+        return Err(Error::new(EINVAL));
+    }
+
+    let current = context::current().ok_or(Error::new(ESRCH))?;
+    let context = current.read();
+    let previous = signal::action(&context, signal).0;
+    if let Some(action) = action {
+        signal::set_action(&context, signal, action, restorer);
+    }
+    drop(context);
+
+    if !old.is_null() {
+        write_user(old, &previous)?;
+    }
+    Ok(0)
+}
+
+/// Change the signal mask of the calling context according to `how`, after copying the old one to
+/// `old` (if it is not null). [`signal::SIGKILL`] and [`signal::SIGSTOP`] cannot be blocked.
+pub fn sigprocmask(how: usize, set: *const [u64; 2], old: *mut [u64; 2]) -> Result<usize> {
+    let set = match set.is_null() {
+        true => None,
+        false => Some(read_user(set)?),
+    };
+
+    let current = context::current().ok_or(Error::new(ESRCH))?;
+    let mut context = current.write();
+    let previous = context.signal_mask;
+
+    if let Some(set) = set {
+        let mask = context.signal_mask;
+        let mask = match how {
+            signal::SIG_BLOCK => [mask[0] | set[0], mask[1] | set[1]],
+            signal::SIG_UNBLOCK => [mask[0] & !set[0], mask[1] & !set[1]],
+            signal::SIG_SETMASK => set,
+            _ => return Err(Error::new(EINVAL)),
+        };
+        context.signal_mask = signal::sanitize_mask(mask);
+    }
+    drop(context);
+
+    if !old.is_null() {
+        write_user(old, &previous)?;
+    }
+    Ok(0)
+}
+
+/// Return from a signal handler: restore the registers and the signal mask from the signal frame
+/// at the top of the user stack. Fails if the frame cannot be read.
+pub fn sigreturn(stack: &mut InterruptStack) -> Result<()> {
+    let address = stack.returned_signal_frame();
+    let frame = read_user(address as *const SignalFrame)?;
+
+    stack.restore_user(&frame.registers);
+
+    let current = context::current().ok_or(Error::new(ESRCH))?;
+    current.write().signal_mask = signal::sanitize_mask(frame.mask);
+    Ok(())
+}
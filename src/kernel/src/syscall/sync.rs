@@ -0,0 +1,273 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::context::{self, ContextId, WaitError};
+use crate::sync::mutex::Mutex;
+use crate::sync::{EventFlags, Mailbox, RwLock, Semaphore, WaitMode};
+use crate::syscall::error::*;
+use crate::syscall::validate::{copy_from_user, copy_to_user};
+
+/// Timeout of the calls that wait, which makes them wait for as long as it takes.
+pub const TIMEOUT_FOREVER: usize = usize::MAX;
+
+/// Option of [`event_wait`] that makes it wait for every flag of the mask, instead of any.
+pub const EVENT_ALL: usize = 1;
+/// Option of [`event_wait`] that makes it clear the flags of the mask once they are set.
+pub const EVENT_CLEAR: usize = 2;
+
+/// Highest number of messages in a mailbox.
+pub const MBOX_MAX_CAPACITY: usize = 64;
+/// Highest size of the messages of a mailbox, in bytes.
+pub const MBOX_MAX_SIZE: usize = 4096;
+/// Highest number of objects that a process may have created at once.
+pub const MAX_OBJECTS: usize = 64;
+
+/// Synchronization objects created by user-space, by identifier, with the leader of the process
+/// that created them. They are shared by every process that knows their identifier, and live until
+/// their process destroys them or ends (and no call uses them).
+static OBJECTS: RwLock<BTreeMap<usize, (ContextId, Object)>> = RwLock::new(BTreeMap::new());
+
+/// Identifier of the next object that is created.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// Mailbox created by user-space, whose messages are copied in and out of buffers that are
+/// allocated when it is created.
+struct UserMailbox {
+    /// Buffers of the messages. A buffer belongs to whoever took its index out of `free` or `full`.
+    buffers: Box<[Mutex<Box<[u8]>>]>,
+    /// Highest size of the messages.
+    size: usize,
+    /// Indices of the buffers that hold no message.
+    free: Mailbox<usize>,
+    /// Indices of the buffers that hold a message with the size of the message, oldest first.
+    full: Mailbox<(usize, usize)>,
+}
+
+/// Synchronization object created by user-space.
+#[derive(Clone)]
+enum Object {
+    Semaphore(Arc<Semaphore>),
+    EventFlags(Arc<EventFlags>),
+    Mailbox(Arc<UserMailbox>),
+}
+
+/// Retrieve the leader of the current process, which objects are charged to.
+fn process_id() -> ContextId {
+    let current = context::current().expect("System call outside of a context");
+    let context = current.read();
+    context.thread_group.leader()
+}
+
+/// Add an object to the table on behalf of the current process, returning its identifier. Fails
+/// with [`ENOSPC`] if the process already has [`MAX_OBJECTS`].
+fn insert(object: Object) -> Result<usize> {
+    let owner = process_id();
+    let mut objects = OBJECTS.write();
+    if objects.values().filter(|(id, _)| *id == owner).count() >= MAX_OBJECTS {
+        return Err(Error::new(ENOSPC));
+    }
+
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    objects.insert(id, (owner, object));
+    Ok(id)
+}
+
+/// Retrieve an object from the table. The table is not kept locked, since the caller may block.
+fn get(id: usize) -> Result<Object> {
+    let objects = OBJECTS.read();
+    let (_, object) = objects.get(&id).ok_or(Error::new(EINVAL))?;
+    Ok(object.clone())
+}
+
+/// Convert a timeout from user-space, in scheduler ticks.
+fn timeout(ticks: usize) -> Option<u64> {
+    match ticks {
+        TIMEOUT_FOREVER => None,
+        ticks => Some(ticks as u64),
+    }
+}
+
+/// Convert the error of a wait. A wait without a timeout only tries, so it fails with [`EAGAIN`].
+fn wait_error(error: WaitError, ticks: usize) -> Error {
+    match error {
+        WaitError::TimedOut if ticks == 0 => Error::new(EAGAIN),
+        error => Error::from(error),
+    }
+}
+
+/// Create a counting semaphore with the given count and maximum count. Returns its identifier.
+pub fn sem_create(count: usize, max: usize) -> Result<usize> {
+    if max == 0 || count > max {
+        return Err(Error::new(EINVAL));
+    }
+    let semaphore = Semaphore::new(count, max);
+    insert(Object::Semaphore(Arc::new(semaphore)))
+}
+
+/// Take a semaphore, waiting for at most `ticks` scheduler ticks while its count is zero.
+pub fn sem_take(id: usize, ticks: usize) -> Result<usize> {
+    let semaphore = match get(id)? {
+        Object::Semaphore(semaphore) => semaphore,
+        _ => return Err(Error::new(EINVAL)),
+    };
+
+    semaphore
+        .take(timeout(ticks))
+        .map_err(|error| wait_error(error, ticks))?;
+    Ok(0)
+}
+
+/// Give a semaphore. Fails with [`EBUSY`] if its count is already at its maximum.
+pub fn sem_give(id: usize) -> Result<usize> {
+    let semaphore = match get(id)? {
+        Object::Semaphore(semaphore) => semaphore,
+        _ => return Err(Error::new(EINVAL)),
+    };
+
+    match semaphore.give() {
+        true => Ok(0),
+        false => Err(Error::new(EBUSY)),
+    }
+}
+
+/// Create a group of event flags, which are all clear. Returns its identifier.
+pub fn event_create() -> Result<usize> {
+    insert(Object::EventFlags(Arc::new(EventFlags::new())))
+}
+
+/// Retrieve a group of event flags.
+fn event_flags(id: usize) -> Result<Arc<EventFlags>> {
+    match get(id)? {
+        Object::EventFlags(flags) => Ok(flags),
+        _ => Err(Error::new(EINVAL)),
+    }
+}
+
+/// Set event flags. Returns the flags that were set before.
+pub fn event_set(id: usize, flags: usize) -> Result<usize> {
+    Ok(event_flags(id)?.set(flags))
+}
+
+/// Clear event flags. Returns the flags that were set before.
+pub fn event_clear(id: usize, flags: usize) -> Result<usize> {
+    Ok(event_flags(id)?.clear(flags))
+}
+
+/// Wait for any flag of the mask to be set (or every one, with [`EVENT_ALL`]), for at most
+/// `ticks` scheduler ticks. Returns the flags as they were when the wait ended.
+pub fn event_wait(id: usize, mask: usize, options: usize, ticks: usize) -> Result<usize> {
+    if mask == 0 || options & !(EVENT_ALL | EVENT_CLEAR) != 0 {
+        return Err(Error::new(EINVAL));
+    }
+    let mode = match options & EVENT_ALL != 0 {
+        true => WaitMode::All,
+        false => WaitMode::Any,
+    };
+
+    event_flags(id)?
+        .wait(mask, mode, options & EVENT_CLEAR != 0, timeout(ticks))
+        .map_err(|error| wait_error(error, ticks))
+}
+
+/// Create a mailbox that holds at most `capacity` messages of at most `size` bytes, which must
+/// not be above [`MBOX_MAX_CAPACITY`] and [`MBOX_MAX_SIZE`]. Returns its identifier.
+pub fn mbox_create(capacity: usize, size: usize) -> Result<usize> {
+    if capacity == 0 || capacity > MBOX_MAX_CAPACITY || size == 0 || size > MBOX_MAX_SIZE {
+        return Err(Error::new(EINVAL));
+    }
+
+    let free = Mailbox::new(capacity);
+    for index in 0..capacity {
+        let _ = free.try_post(index);
+    }
+    let mailbox = UserMailbox {
+        buffers: (0..capacity)
+            .map(|_| Mutex::new(vec![0; size].into_boxed_slice()))
+            .collect(),
+        size,
+        free,
+        full: Mailbox::new(capacity),
+    };
+    insert(Object::Mailbox(Arc::new(mailbox)))
+}
+
+/// Retrieve a mailbox.
+fn mailbox(id: usize) -> Result<Arc<UserMailbox>> {
+    match get(id)? {
+        Object::Mailbox(mailbox) => Ok(mailbox),
+        _ => Err(Error::new(EINVAL)),
+    }
+}
+
+/// Post a copy of a message to a mailbox, waiting for at most `ticks` scheduler ticks while it is
+/// full.
+pub fn mbox_send(id: usize, message: *const u8, len: usize, ticks: usize) -> Result<usize> {
+    let mailbox = mailbox(id)?;
+    if len > mailbox.size {
+        return Err(Error::new(EINVAL));
+    }
+
+    let index = mailbox
+        .free
+        .receive(timeout(ticks))
+        .map_err(|error| wait_error(error, ticks))?;
+    let copied = copy_from_user(&mut mailbox.buffers[index].lock()[..len], message);
+
+    // There is always room for the buffer that was taken out.
+    match copied {
+        Ok(()) => {
+            let _ = mailbox.full.try_post((index, len));
+        }
+        Err(_) => {
+            let _ = mailbox.free.try_post(index);
+        }
+    }
+    copied?;
+    Ok(0)
+}
+
+/// Receive the oldest message of a mailbox into a buffer, waiting for at most `ticks` scheduler
+/// ticks while it is empty. The buffer must be able to hold the largest message. Returns the size
+/// of the message.
+pub fn mbox_receive(id: usize, buffer: *mut u8, len: usize, ticks: usize) -> Result<usize> {
+    let mailbox = mailbox(id)?;
+    if len < mailbox.size {
+        return Err(Error::new(EINVAL));
+    }
+
+    let (index, size) = mailbox
+        .full
+        .receive(timeout(ticks))
+        .map_err(|error| wait_error(error, ticks))?;
+    let copied = copy_to_user(buffer, &mailbox.buffers[index].lock()[..size]);
+
+    // The message is dropped if it cannot be copied, like any message that was received.
+    let _ = mailbox.free.try_post(index);
+    copied?;
+    Ok(size)
+}
+
+/// Destroy a synchronization object that the current process created. Contexts that wait on it go
+/// on waiting until their timeout.
+pub fn object_destroy(id: usize) -> Result<usize> {
+    let process = process_id();
+    let mut objects = OBJECTS.write();
+    match objects.get(&id) {
+        Some(&(owner, _)) if owner == process => {
+            objects.remove(&id);
+            Ok(0)
+        }
+        Some(_) => Err(Error::new(EPERM)),
+        None => Err(Error::new(EINVAL)),
+    }
+}
+
+/// Destroy the synchronization objects that a process created, once it ends.
+pub fn destroy_objects(owner: ContextId) {
+    let mut objects = OBJECTS.write();
+    objects.retain(|_, (id, _)| *id != owner);
+}
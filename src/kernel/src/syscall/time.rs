@@ -0,0 +1,44 @@
+use core::time::Duration;
+
+use crate::syscall::error::*;
+use crate::syscall::validate::{read_user, write_user};
+use crate::time::{self, ClockId, TimeSpec};
+
+/// Clock that counts the time since the Unix epoch, which can be set.
+pub const CLOCK_REALTIME: usize = ClockId::Realtime as usize;
+/// Clock that counts the time since the kernel started keeping it, which never goes backwards.
+pub const CLOCK_MONOTONIC: usize = ClockId::Monotonic as usize;
+
+/// Copy the time of a clock to user-space.
+pub fn clock_gettime(clock: usize, time: *mut TimeSpec) -> Result<usize> {
+    let clock = ClockId::from_raw(clock).ok_or(Error::new(EINVAL))?;
+    write_user(time, &time::now(clock))?;
+    Ok(0)
+}
+
+/// Set the time of a clock from user-space. The monotonic clock cannot be set.
+pub fn clock_settime(clock: usize, time: *const TimeSpec) -> Result<usize> {
+    let time = read_user(time)?;
+
+    match ClockId::from_raw(clock) {
+        Some(ClockId::Realtime) if time::set_realtime(time) => Ok(0),
+        _ => Err(Error::new(EINVAL)),
+    }
+}
+
+/// Block the calling context for the time given by user-space. If a signal interrupts it, the time
+/// that was left is copied to `remaining` (unless it is null), and it fails with [`EINTR`].
+pub fn nanosleep(request: *const TimeSpec, remaining: *mut TimeSpec) -> Result<usize> {
+    let request = read_user(request)?;
+    let duration = Duration::try_from(request).map_err(|()| Error::new(EINVAL))?;
+
+    match time::sleep(duration) {
+        Ok(()) => Ok(0),
+        Err(left) => {
+            if !remaining.is_null() {
+                write_user(remaining, &TimeSpec::from(left))?;
+            }
+            Err(Error::new(EINTR))
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use core::mem;
+use core::slice;
+
+use crate::context;
+use crate::context::memory::USER_START;
+use crate::context::signal::{SigAction, SignalFrame};
+use crate::machine::{paging, usercopy};
+use crate::memory::paging::PageFlags;
+use crate::syscall::error::*;
+use crate::time::TimeSpec;
+
+/// Marker for types that can be read from user-space, which can put anything there.
+///
+/// # Safety
+/// Every bit pattern of the size of the type must be a valid value of it.
+pub unsafe trait FromUser {}
+
+unsafe impl FromUser for u8 {}
+unsafe impl FromUser for u64 {}
+unsafe impl<T: FromUser, const N: usize> FromUser for [T; N] {}
+
+// These only hold integers (and flags that keep unknown bits).
+unsafe impl FromUser for TimeSpec {}
+unsafe impl FromUser for SigAction {}
+unsafe impl FromUser for SignalFrame {}
+
+/// Check that user-space memory is covered by grants of the current address space (which are
+/// writable, if `write` is set). The pages do not have to be present, since the page fault
+/// handler brings them in when the kernel touches them.
+fn validate(address: usize, size: usize, write: bool) -> Result<()> {
+    if size == 0 {
+        return Ok(());
+    }
+
+    let end = address.checked_add(size).ok_or(Error::new(EFAULT))?;
+    if address < USER_START || end > paging::user_end() {
+        return Err(Error::new(EFAULT));
+    }
+
+    let addr_space = context::current()
+        .and_then(|context| context.read().addr_space().cloned())
+        .ok_or(Error::new(EFAULT))?;
+    let addr_space = addr_space.read();
+
+    let mut address = address;
+    while address < end {
+        let grant = addr_space.grant_at(address).ok_or(Error::new(EFAULT))?;
+        if write && !grant.flags().contains(PageFlags::WRITE) {
+            return Err(Error::new(EFAULT));
+        }
+        address = grant.end();
+    }
+
+    Ok(())
+}
+
+/// Copy values from user-space into a kernel buffer. The grants may still be unmapped by another
+/// thread once they are validated, so a page fault that cannot be resolved during the copy fails
+/// with [`EFAULT`] too, rather than bringing the kernel down.
+pub fn copy_from_user<T: FromUser>(dst: &mut [T], src: *const T) -> Result<()> {
+    let size = mem::size_of_val(dst);
+    validate(src as usize, size, false)?;
+
+    match unsafe { usercopy::copy(dst.as_mut_ptr().cast(), src.cast(), size) } {
+        true => Ok(()),
+        false => Err(Error::new(EFAULT)),
+    }
+}
+
+/// Copy values from a kernel buffer to user-space, like [`copy_from_user`].
+pub fn copy_to_user<T>(dst: *mut T, src: &[T]) -> Result<()> {
+    let size = mem::size_of_val(src);
+    validate(dst as usize, size, true)?;
+
+    match unsafe { usercopy::copy(dst.cast(), src.as_ptr().cast(), size) } {
+        true => Ok(()),
+        false => Err(Error::new(EFAULT)),
+    }
+}
+
+/// Read a value from user-space (see [`copy_from_user`]).
+pub fn read_user<T: FromUser>(src: *const T) -> Result<T> {
+    // Zeroes are as valid as anything else that user-space can put there.
+    let mut value = unsafe { mem::zeroed() };
+    copy_from_user(slice::from_mut(&mut value), src)?;
+    Ok(value)
+}
+
+/// Write a value to user-space (see [`copy_to_user`]).
+pub fn write_user<T>(dst: *mut T, value: &T) -> Result<()> {
+    copy_to_user(dst, slice::from_ref(value))
+}
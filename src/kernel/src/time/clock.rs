@@ -1,14 +1,53 @@
+use crate::context::scheduler::{self, TICK_RATE};
+
 /// A clock is the interface all timers must implement to interact with the kernel. A clock is
-/// something that allows you to access the time.
-pub trait Clock {
+/// something that allows you to access the time: it is a counter that goes up at a fixed frequency,
+/// which the kernel keeps the time with once it is registered (see [`super::register`]).
+pub trait Clock: Sync {
+    /// Retrieve the name of the clock, for diagnostics.
+    fn name(&self) -> &'static str;
+
+    /// Retrieve how good the clock is. The registered clock with the highest rating keeps the time.
+    /// Clocks that are only as precise as the scheduler's ticks are rated below 100, and clocks
+    /// that are precise and agree between the CPUs are rated 300 or more.
+    fn rating(&self) -> u32;
+
+    /// Retrieve the number of counts per second.
+    fn frequency(&self) -> u64;
+
+    /// Read the counter. It must never go backwards, whichever CPU reads it.
+    fn read(&self) -> u64;
 }
 
 /// Interrupt clocks are clocks that let you calculate the time by firing interrupts (called ticks)
 /// in a given frequency. The time can be calculated by the number of ticks.
 pub trait InterruptClock {
     /// Retrieve the number of ticks per second.
-    fn frequency(&self) -> usize,
+    fn frequency(&self) -> usize;
+
     /// Register a clock handler, that will be called upon each tick interrupt to update the time
     /// of the clock.
     fn register_handler(&mut self, handler: fn()) -> bool;
 }
+
+/// Clock that counts the ticks of the scheduler's timer. It is always there, so it keeps the time
+/// until a better clock is registered, but it is only as precise as a tick.
+pub struct TickClock;
+
+impl Clock for TickClock {
+    fn name(&self) -> &'static str {
+        "ticks"
+    }
+
+    fn rating(&self) -> u32 {
+        1
+    }
+
+    fn frequency(&self) -> u64 {
+        TICK_RATE as u64
+    }
+
+    fn read(&self) -> u64 {
+        scheduler::ticks()
+    }
+}
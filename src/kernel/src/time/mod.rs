@@ -0,0 +1,150 @@
+use core::sync::atomic::{AtomicI64, Ordering};
+use core::time::Duration;
+
+use crate::context::scheduler::{self, without_interrupts};
+use crate::context::{SleepQueue, WaitError, WakeupPolicy};
+use crate::sync::RwLock;
+
+pub use self::clock::{Clock, InterruptClock, TickClock};
+pub use self::spec::{TimeSpec, NANOS_PER_SEC};
+
+pub mod clock;
+pub mod spec;
+
+/// Clocks that can be read, numbered like on Linux for system calls.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ClockId {
+    /// Time since the Unix epoch. It can be set, so it may jump backwards.
+    Realtime = 0,
+    /// Time since the kernel started keeping the time, which never goes backwards.
+    Monotonic = 1,
+}
+
+impl ClockId {
+    /// Retrieve the clock with the given number.
+    pub fn from_raw(id: usize) -> Option<Self> {
+        match id {
+            0 => Some(Self::Realtime),
+            1 => Some(Self::Monotonic),
+            _ => None,
+        }
+    }
+}
+
+/// Clock that keeps the time, and where it took over from the previous one.
+struct Source {
+    clock: &'static dyn Clock,
+    /// Count of the clock when it took over.
+    start: u64,
+    /// Monotonic time when it took over, in nanoseconds.
+    base: u64,
+}
+
+impl Source {
+    /// Retrieve the monotonic time, in nanoseconds.
+    fn now(&self) -> u64 {
+        let counts = self.clock.read().wrapping_sub(self.start) as u128;
+        let nanos = counts * NANOS_PER_SEC as u128 / self.clock.frequency() as u128;
+        self.base + nanos as u64
+    }
+}
+
+/// Clock that is always there, which keeps the time until a better one is registered.
+static TICK_CLOCK: TickClock = TickClock;
+
+/// Registered clock with the highest rating. Interrupts are disabled while it is locked for
+/// writing, since interrupt handlers may read the time.
+static SOURCE: RwLock<Source> = RwLock::new(Source {
+    clock: &TICK_CLOCK,
+    start: 0,
+    base: 0,
+});
+
+/// Difference between the realtime clock and the monotonic clock, in nanoseconds. The realtime
+/// clock starts at the Unix epoch, until something sets it.
+static REALTIME_OFFSET: AtomicI64 = AtomicI64::new(0);
+
+/// Register a clock, which keeps the time from now on if its rating is higher than the one of the
+/// clock that keeps it. The monotonic time carries on from where the previous clock left it.
+pub fn register(clock: &'static dyn Clock) {
+    without_interrupts(|| {
+        let mut source = SOURCE.write();
+        if clock.rating() <= source.clock.rating() {
+            return;
+        }
+
+        let base = source.now();
+        *source = Source {
+            clock,
+            start: clock.read(),
+            base,
+        };
+    });
+}
+
+/// Retrieve the monotonic time, in nanoseconds.
+fn monotonic_nanos() -> u64 {
+    SOURCE.read().now()
+}
+
+/// Retrieve the time since the kernel started keeping the time.
+pub fn monotonic() -> Duration {
+    Duration::from_nanos(monotonic_nanos())
+}
+
+/// Retrieve the time since the Unix epoch.
+pub fn realtime() -> TimeSpec {
+    let offset = REALTIME_OFFSET.load(Ordering::Relaxed);
+    TimeSpec::from_nanos((monotonic_nanos() as i64).saturating_add(offset))
+}
+
+/// Set the realtime clock, returning whether the time was valid: it must be normalized, and in
+/// the range that the clock can hold (about 292 years around the Unix epoch). The monotonic clock
+/// is not affected.
+pub fn set_realtime(time: TimeSpec) -> bool {
+    let nanos = match time.as_nanos() {
+        Some(nanos) if time.is_normalized() => nanos,
+        _ => return false,
+    };
+
+    REALTIME_OFFSET.store(
+        nanos.wrapping_sub(monotonic_nanos() as i64),
+        Ordering::Relaxed,
+    );
+    true
+}
+
+/// Retrieve the time of a clock.
+pub fn now(clock: ClockId) -> TimeSpec {
+    match clock {
+        ClockId::Realtime => realtime(),
+        ClockId::Monotonic => TimeSpec::from(monotonic()),
+    }
+}
+
+/// Block the current context for at least the given time, or until a signal that it does not
+/// block is sent to it, in which case it fails with the time that was left.
+///
+/// The context is woken up by the scheduler's timer, so it may sleep for up to a tick longer.
+pub fn sleep(duration: Duration) -> Result<(), Duration> {
+    // Nothing wakes the queue up: the context only waits for its timeout.
+    let queue = SleepQueue::new(WakeupPolicy::All);
+    let deadline = monotonic().saturating_add(duration);
+
+    loop {
+        let left = match deadline.checked_sub(monotonic()) {
+            Some(left) if !left.is_zero() => left,
+            _ => return Ok(()),
+        };
+
+        // The ticks are counted from the last one, so a partial tick is rounded up. Very long
+        // sleeps wait again once the timeout expired.
+        let ticks = left.as_nanos() * scheduler::TICK_RATE as u128 / NANOS_PER_SEC as u128 + 1;
+        match queue.wait_timeout(ticks.min(u32::MAX as u128) as u64) {
+            Ok(()) | Err(WaitError::TimedOut) => (),
+            Err(WaitError::Interrupted) => {
+                return Err(deadline.saturating_sub(monotonic()));
+            }
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use core::time::Duration;
+
+/// Number of nanoseconds in a second.
+pub const NANOS_PER_SEC: i64 = 1_000_000_000;
+
+/// Point in time or span of time, in seconds and nanoseconds. It is laid out like the `timespec`
+/// structure of C, so that it can be copied to and from user-space as is.
+///
+/// It is normalized when the nanoseconds are at least zero and less than a second. Negative times
+/// have negative seconds (one and a half second before is `-2` seconds and `500_000_000`
+/// nanoseconds).
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[repr(C)]
+pub struct TimeSpec {
+    /// Whole seconds.
+    pub seconds: i64,
+    /// Nanoseconds on top of the seconds.
+    pub nanoseconds: i64,
+}
+
+impl TimeSpec {
+    /// The start of the clock, or an empty span of time.
+    pub const ZERO: Self = Self::new(0, 0);
+
+    /// Construct a [`TimeSpec`] from seconds and nanoseconds, as they are.
+    pub const fn new(seconds: i64, nanoseconds: i64) -> Self {
+        Self {
+            seconds,
+            nanoseconds,
+        }
+    }
+
+    /// Construct a normalized [`TimeSpec`] from a number of nanoseconds.
+    pub const fn from_nanos(nanos: i64) -> Self {
+        Self::new(
+            nanos.div_euclid(NANOS_PER_SEC),
+            nanos.rem_euclid(NANOS_PER_SEC),
+        )
+    }
+
+    /// Retrieve the number of nanoseconds, if it fits.
+    pub fn as_nanos(&self) -> Option<i64> {
+        self.seconds
+            .checked_mul(NANOS_PER_SEC)?
+            .checked_add(self.nanoseconds)
+    }
+
+    /// Whether the time is normalized.
+    pub fn is_normalized(&self) -> bool {
+        (0..NANOS_PER_SEC).contains(&self.nanoseconds)
+    }
+}
+
+impl From<Duration> for TimeSpec {
+    /// Convert a span of time. Spans too long to fit saturate.
+    fn from(duration: Duration) -> Self {
+        match i64::try_from(duration.as_secs()) {
+            Ok(seconds) => Self::new(seconds, duration.subsec_nanos() as i64),
+            Err(_) => Self::new(i64::MAX, NANOS_PER_SEC - 1),
+        }
+    }
+}
+
+impl TryFrom<TimeSpec> for Duration {
+    type Error = ();
+
+    /// Convert a span of time, which must be normalized and not negative.
+    fn try_from(time: TimeSpec) -> Result<Self, Self::Error> {
+        if time.seconds < 0 || !time.is_normalized() {
+            return Err(());
+        }
+        Ok(Duration::new(time.seconds as u64, time.nanoseconds as u32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_nanoseconds() {
+        assert_eq!(
+            TimeSpec::from_nanos(1_500_000_000),
+            TimeSpec::new(1, 500_000_000)
+        );
+        assert_eq!(
+            TimeSpec::from_nanos(-1_500_000_000),
+            TimeSpec::new(-2, 500_000_000)
+        );
+        assert_eq!(
+            TimeSpec::from_nanos(-1),
+            TimeSpec::new(-1, NANOS_PER_SEC - 1)
+        );
+        assert!(TimeSpec::from_nanos(i64::MIN).is_normalized());
+        assert!(!TimeSpec::new(0, NANOS_PER_SEC).is_normalized());
+        assert!(!TimeSpec::new(1, -1).is_normalized());
+    }
+
+    #[test]
+    fn converts_to_nanoseconds() {
+        assert_eq!(
+            TimeSpec::new(-2, 500_000_000).as_nanos(),
+            Some(-1_500_000_000)
+        );
+        assert_eq!(TimeSpec::from_nanos(i64::MAX).as_nanos(), Some(i64::MAX));
+        assert_eq!(TimeSpec::new(i64::MAX, 0).as_nanos(), None);
+    }
+
+    #[test]
+    fn orders_by_seconds_first() {
+        assert!(TimeSpec::new(1, 0) > TimeSpec::new(0, 999_999_999));
+        assert!(TimeSpec::new(-1, 999_999_999) < TimeSpec::ZERO);
+    }
+
+    #[test]
+    fn converts_durations() {
+        let duration = Duration::new(3, 250);
+        assert_eq!(TimeSpec::from(duration), TimeSpec::new(3, 250));
+        assert_eq!(Duration::try_from(TimeSpec::new(3, 250)), Ok(duration));
+
+        assert_eq!(
+            TimeSpec::from(Duration::MAX),
+            TimeSpec::new(i64::MAX, NANOS_PER_SEC - 1)
+        );
+        assert_eq!(Duration::try_from(TimeSpec::new(-1, 0)), Err(()));
+        assert_eq!(Duration::try_from(TimeSpec::new(0, NANOS_PER_SEC)), Err(()));
+    }
+}
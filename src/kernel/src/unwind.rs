@@ -1,11 +1,16 @@
 use core::panic::PanicInfo;
 
+use crate::machine;
+
+#[cfg(not(test))]
 #[panic_handler]
-extern "C" fn begin_unwind(_info: &PanicInfo) -> ! {
+extern "C" fn begin_unwind(info: &PanicInfo) -> ! {
+    machine::debug::print(format_args!("Kernel panic: {}\n", info));
     loop {}
 }
 
 /// This function is the entry point for the unwinding process.
+#[cfg(not(test))]
 #[lang = "eh_personality"]
 #[no_mangle]
 extern "C" fn rust_eh_personality() -> ! {
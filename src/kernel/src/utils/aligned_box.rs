@@ -0,0 +1,66 @@
+use alloc::alloc::{alloc_zeroed, dealloc, handle_alloc_error, Layout};
+
+use core::mem;
+use core::ops::{Deref, DerefMut};
+use core::ptr::Unique;
+
+/// Marker for types that are valid when all of their bytes are zero.
+///
+/// # Safety
+/// The all-zero bit pattern must be a valid value of the type.
+pub unsafe trait ValidForZero {}
+
+unsafe impl<const N: usize> ValidForZero for [u8; N] {}
+
+/// A heap allocation with a larger alignment than its type requires. Used for memory that the
+/// CPU accesses with alignment requirements of its own, such as the area that SIMD and FPU
+/// registers are saved to.
+pub struct AlignedBox<T, const ALIGN: usize> {
+    /// Pointer to the value.
+    inner: Unique<T>,
+}
+
+impl<T, const ALIGN: usize> AlignedBox<T, ALIGN> {
+    /// Retrieve the layout of the allocation.
+    fn layout() -> Layout {
+        Layout::from_size_align(mem::size_of::<T>(), ALIGN.max(mem::align_of::<T>()))
+            .expect("Invalid alignment")
+    }
+
+    /// Allocate a zeroed value.
+    pub fn zeroed() -> Self
+    where
+        T: ValidForZero,
+    {
+        let layout = Self::layout();
+        let pointer = unsafe { alloc_zeroed(layout) } as *mut T;
+
+        match Unique::new(pointer) {
+            Some(inner) => Self { inner },
+            None => handle_alloc_error(layout),
+        }
+    }
+}
+
+impl<T, const ALIGN: usize> Deref for AlignedBox<T, ALIGN> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.inner.as_ref() }
+    }
+}
+
+impl<T, const ALIGN: usize> DerefMut for AlignedBox<T, ALIGN> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.inner.as_mut() }
+    }
+}
+
+impl<T, const ALIGN: usize> Drop for AlignedBox<T, ALIGN> {
+    fn drop(&mut self) {
+        unsafe {
+            self.inner.as_ptr().drop_in_place();
+            dealloc(self.inner.as_ptr() as *mut u8, Self::layout());
+        }
+    }
+}
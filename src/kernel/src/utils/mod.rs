@@ -1,2 +1,3 @@
+pub mod aligned_box;
 pub mod bootstrap;
 pub mod msg_queue;
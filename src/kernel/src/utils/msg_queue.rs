@@ -29,7 +29,7 @@ impl MessageQueue {
 
     /// Reads a message from the message queue into the provided buffer, and returns the number of
     /// bytes that were read.
-    pub fn read(&mut self, buffer: &[u8]) -> usize {
+    pub fn read(&mut self, buffer: &mut [u8]) -> usize {
         if let Some(message) = self.messages.pop_front() {
             let message_len = message.data.len();
             assert!(buffer.len() >= message_len);